@@ -1,24 +1,73 @@
 
+import itertools
 import os
+import sys
 
 # supported targets are wasm, windows and unix
 
+FEATURES = [
+    "json_ser",
+    "bson_ser",
+    "postcard_ser",
+    "messagepack_ser",
+    "cbor_ser",
+    "ron_ser",
+    "flexbuffers_ser",
+    "proto",
+    "compression",
+    "checksum",
+    "text_safe",
+    "transcode",
+    "verifier",
+    "quic",
+    "tls",
+    "mdns",
+    "srv",
+    "socket_options",
+]
+
+# every combination of formats has to build too
+FORMATS = [
+    "format-json",
+    "format-bson",
+    "format-postcard",
+    "messagepack_ser",
+]
+
+failed = []
+
 def check(args):
-    try:
-        os.system(f"cargo check {args}")
-    except Exception as e:
-        print(e)
-        exit(1)
+    if os.system(f"cargo check {args}") != 0:
+        failed.append(args)
 
-for target in  [
+TARGETS = [
     "--target=wasm32-unknown-unknown",
     "--target=x86_64-pc-windows-msvc",
     "--target=x86_64-unknown-linux-gnu",
     "--target=x86_64-apple-darwin",
     "",
-]:
+]
+
+# `--host` only checks the host target, for ci
+if "--host" in sys.argv:
+    TARGETS = [""]
+
+for target in TARGETS:
     for feature in [
-        ""
+        "",
+        "--no-default-features",
+        "--all-features",
+        # every optional feature has to build on its own
+        *[f"--no-default-features --features {feature}" for feature in FEATURES],
+        *[
+            f"--no-default-features --features {','.join(formats)}"
+            for n in range(2, len(FORMATS) + 1)
+            for formats in itertools.combinations(FORMATS, n)
+        ],
     ]:
         print(target, feature)
         check(f'{target} {feature}')
+
+if failed:
+    print("failed:", *failed, sep="\n")
+    exit(1)
@@ -1,16 +1,376 @@
+#[cfg(feature = "verifier")]
+use crate::channel::channels::Transport;
 use crate::Result;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    channel::channels::{ReceiveBuffer, DEFAULT_RECEIVE_BUFFER_LIMIT},
+    channel::encrypted::snowwith::WithCipher,
+    io::{split, Read, ReadHalf, Write, WriteHalf},
+    serialization::{
+        formats::{ReadFormat, SendFormat},
+        is_control, rx_payload_until, tx_into, Framing,
+    },
+};
 use crate::{err, Channel};
-use snow::{params::*, StatelessTransportState};
+#[cfg(feature = "verifier")]
+use async_trait::async_trait;
+use rand::{rngs::OsRng, CryptoRng, Rng, RngCore};
+#[cfg(not(target_arch = "wasm32"))]
+use serde::{de::DeserializeOwned, Serialize};
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::types::{Cipher, Dh, Hash, Random};
+use snow::{params::*, HandshakeState, StatelessTransportState};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::Duration;
 
-const PACKET_LEN: u64 = 65519;
+pub use snow::{params::HandshakePattern, Keypair};
+
+// largest plaintext of a packet, so packets fit in the largest noise message
+pub(crate) const PACKET_LEN: u64 = 65519;
+// size of the authentication tag of each packet
+const TAG_LEN: usize = 16;
+// size of the length prefix of each packet
+const LEN_PREFIX: usize = 2;
+// time the peer has to complete the handshake by default
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// packets sent with a key by default before the sender replaces it
+const REKEY_INTERVAL: u32 = 1 << 20;
+
+// flags in the first byte of the plaintext of every message.
+// the message only carries control information, such as heartbeats
+const CONTROL: u8 = 1;
+// the sender replaces its key after the message, so the receiver does too
+const REKEY: u8 = 2;
+
+/// transport state shared by the send and receive halves of a channel.
+/// both halves encrypt and decrypt at the same time, only rekeying
+/// needs exclusive access
+pub type SharedTransport = Arc<RwLock<StatelessTransportState>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Packet counter of one direction of an encrypted channel
+pub struct Nonce {
+    /// Packets that used the current key
+    pub count: u32,
+    /// Packets sent with a key before the sender replaces it.
+    /// Receivers replace their key when the sender says so, so it's
+    /// only used when sending and peers don't need to agree on it
+    pub rekey_interval: u32,
+}
+
+impl Default for Nonce {
+    fn default() -> Self {
+        Nonce {
+            count: 0,
+            rekey_interval: REKEY_INTERVAL,
+        }
+    }
+}
 
 /// helper struct that can be used to encrypt messages.
 /// it contains the transport and a nonce.
+/// A nonce only counts the packets of one direction, so encrypting and
+/// decrypting need separate nonces, both starting at 0.
 pub struct RefDividedSnow<'a> {
     /// reference to transport state
-    pub transport: &'a StatelessTransportState,
-    /// external nonce
-    pub nonce: &'a mut u32,
+    pub transport: &'a RwLock<StatelessTransportState>,
+    /// external nonce, counts the packets that used the current key
+    pub nonce: &'a mut Nonce,
+}
+
+impl<'a> RefDividedSnow<'a> {
+    /// encrypt or decrypt the packets of one direction of `transport`
+    pub fn new(transport: &'a RwLock<StatelessTransportState>, nonce: &'a mut Nonce) -> Self {
+        RefDividedSnow { transport, nonce }
+    }
+    /// encrypt a message without payload, which receives skip.
+    /// With `rekey`, the key is replaced right after it on both sides
+    pub(crate) fn encrypt_control(&mut self, rekey: bool) -> Result<Vec<u8>> {
+        let flags = match rekey {
+            true => CONTROL | REKEY,
+            false => CONTROL,
+        };
+        self.encrypt_message(flags, &[])
+    }
+    // the flags take the first byte of the first packet, which is the only
+    // part of the message that's copied before it's encrypted
+    fn encrypt_message(&mut self, flags: u8, buf: &[u8]) -> Result<Vec<u8>> {
+        let (first, rest) = buf.split_at(buf.len().min(PACKET_LEN as usize - 1));
+        let packets = 1 + rest.len().div_ceil(PACKET_LEN as usize);
+        let flags = flags | self.rekey_flag(packets);
+        let mut head = Vec::with_capacity(1 + first.len());
+        head.push(flags);
+        head.extend_from_slice(first);
+        let mut total = Vec::with_capacity(1 + buf.len() + packets * (LEN_PREFIX + TAG_LEN));
+        let chunks = std::iter::once(head.as_slice()).chain(rest.chunks(PACKET_LEN as _));
+        self.write_packets(flags, chunks, &mut total)?;
+        Ok(total)
+    }
+    /// encrypt `bytes` at the end of `out`, reusing its capacity. The first
+    /// byte of `bytes` is left for the flags, so no part of it is copied
+    pub(crate) fn encrypt_flagged(&mut self, bytes: &mut [u8], out: &mut Vec<u8>) -> Result<()> {
+        let packets = bytes.len().div_ceil(PACKET_LEN as usize);
+        let flags = self.rekey_flag(packets);
+        bytes[0] = flags;
+        out.reserve(bytes.len() + packets * (LEN_PREFIX + TAG_LEN));
+        self.write_packets(flags, bytes.chunks(PACKET_LEN as _), out)
+    }
+    // the key is replaced after the message that reaches the interval
+    fn rekey_flag(&self, packets: usize) -> u8 {
+        match u64::from(self.nonce.count) + packets as u64 >= u64::from(self.nonce.rekey_interval) {
+            true => REKEY,
+            false => 0,
+        }
+    }
+    // encrypt the packets of a message sent with `flags` at the end of `out`.
+    // every packet is prefixed with its length and grows by the size of its tag
+    fn write_packets<'b>(
+        &mut self,
+        flags: u8,
+        packets: impl Iterator<Item = &'b [u8]>,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        for buf in packets {
+            let nonce = next_nonce(self.nonce)?;
+            // encrypt straight into the output buffer, after the length prefix
+            let start = out.len() + LEN_PREFIX;
+            out.resize(start + buf.len() + TAG_LEN, 0);
+            let len = read(self.transport)?
+                .write_message(nonce, buf, &mut out[start..])
+                .map_err(err!(@invalid_data))?;
+            // packets are at most `PACKET_LEN + TAG_LEN` bytes, which fits in a u16
+            out[start - LEN_PREFIX..start].copy_from_slice(&(len as u16).to_be_bytes());
+            out.truncate(start + len);
+        }
+        if flags & REKEY != 0 {
+            rekey(
+                self.transport,
+                self.nonce,
+                StatelessTransportState::rekey_outgoing,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// byte stream encrypted with the transport state of a handshake. Messages
+/// are framed and encrypted like the ones of encrypted channels, so either
+/// end can be a channel. It can be split into halves that send and receive
+/// at the same time.
+/// ```no_run
+/// # use canary::async_snow::{self, Snow};
+/// # use canary::serialization::formats::Format;
+/// # use snow::StatelessTransportState;
+/// # use tokio::net::TcpStream;
+/// # async fn run(stream: TcpStream, transport: StatelessTransportState) -> canary::Result<()> {
+/// let (mut sender, mut receiver) = Snow::new(stream, transport).split();
+/// sender.tx("hello!", &mut Format::Bincode).await?;
+/// let reply: String = receiver.rx(&mut Format::Bincode).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Snow<T> {
+    stream: T,
+    transport: SharedTransport,
+    send_nonce: Nonce,
+    receive_nonce: Nonce,
+    send_buffer: SendBuffer,
+    receive_buffer: ReceiveBuffer,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T> Snow<T> {
+    /// encrypt `stream` with `transport`, the result of a handshake with the peer
+    pub fn new(stream: T, transport: StatelessTransportState) -> Self {
+        Snow {
+            stream,
+            transport: Arc::new(RwLock::new(transport)),
+            send_nonce: Nonce::default(),
+            receive_nonce: Nonce::default(),
+            send_buffer: SendBuffer::default(),
+            receive_buffer: ReceiveBuffer::default(),
+        }
+    }
+    /// send an object through the stream serialized with `f`
+    pub async fn tx<O: Serialize, F: SendFormat>(&mut self, obj: O, f: &mut F) -> Result<usize>
+    where
+        T: Write + Unpin,
+    {
+        let snow = RefDividedSnow::new(&self.transport, &mut self.send_nonce);
+        snow_tx(&mut self.stream, snow, obj, f, &mut self.send_buffer).await
+    }
+    /// receive an object sent through the stream with `f`
+    pub async fn rx<O: DeserializeOwned, F: ReadFormat>(&mut self, f: &mut F) -> Result<O>
+    where
+        T: Read + Unpin,
+    {
+        let snow = RefDividedSnow::new(&self.transport, &mut self.receive_nonce);
+        snow_rx(&mut self.stream, snow, f, &mut self.receive_buffer).await
+    }
+    /// split the stream into a half that sends and a half that receives,
+    /// which can be used at the same time from different tasks
+    pub fn split(self) -> (SnowSender<WriteHalf<T>>, SnowReceiver<ReadHalf<T>>)
+    where
+        T: Read + Write,
+    {
+        let (read, write) = split(self.stream);
+        let sender = SnowSender {
+            stream: write,
+            transport: self.transport.clone(),
+            nonce: self.send_nonce,
+            buffer: self.send_buffer,
+        };
+        let receiver = SnowReceiver {
+            stream: read,
+            transport: self.transport,
+            nonce: self.receive_nonce,
+            buffer: self.receive_buffer,
+        };
+        (sender, receiver)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// half of a `Snow` stream that sends, see `Snow::split`
+pub struct SnowSender<T> {
+    stream: T,
+    transport: SharedTransport,
+    nonce: Nonce,
+    buffer: SendBuffer,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Write + Unpin> SnowSender<T> {
+    /// send an object through the stream serialized with `f`
+    pub async fn tx<O: Serialize, F: SendFormat>(&mut self, obj: O, f: &mut F) -> Result<usize> {
+        let snow = RefDividedSnow::new(&self.transport, &mut self.nonce);
+        snow_tx(&mut self.stream, snow, obj, f, &mut self.buffer).await
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// half of a `Snow` stream that receives, see `Snow::split`
+pub struct SnowReceiver<T> {
+    stream: T,
+    transport: SharedTransport,
+    nonce: Nonce,
+    buffer: ReceiveBuffer,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Read + Unpin> SnowReceiver<T> {
+    /// receive an object sent through the stream with `f`
+    pub async fn rx<O: DeserializeOwned, F: ReadFormat>(&mut self, f: &mut F) -> Result<O> {
+        let snow = RefDividedSnow::new(&self.transport, &mut self.nonce);
+        snow_rx(&mut self.stream, snow, f, &mut self.buffer).await
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+// buffers reused to frame and encrypt the messages a `Snow` stream sends.
+// like receive buffers, they are dropped once a message grows them past the limit
+struct SendBuffer {
+    frame: Vec<u8>,
+    plain: Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SendBuffer {
+    fn trim(&mut self) {
+        for buf in [&mut self.frame, &mut self.plain] {
+            if buf.capacity() > DEFAULT_RECEIVE_BUFFER_LIMIT {
+                *buf = Vec::new();
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+// format that serializes messages into a scratch buffer, leaving a byte for
+// the flags, and encrypts them straight into the frame
+struct Sealed<'a, 'b, F> {
+    snow: RefDividedSnow<'a>,
+    format: &'b mut F,
+    plain: &'b mut Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<F: SendFormat> SendFormat for Sealed<'_, '_, F> {
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.serialize_into(obj, &mut bytes)?;
+        Ok(bytes)
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> Result<usize> {
+        self.plain.clear();
+        self.plain.push(0);
+        self.format.serialize_into(obj, self.plain)?;
+        let start = buf.len();
+        self.snow.encrypt_flagged(self.plain, buf)?;
+        Ok(buf.len() - start)
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+// send an object through `stream`, encrypted with `snow` in the buffers of `buffer`
+async fn snow_tx<T: Write + Unpin, O: Serialize, F: SendFormat>(
+    stream: &mut T,
+    snow: RefDividedSnow<'_>,
+    obj: O,
+    format: &mut F,
+    buffer: &mut SendBuffer,
+) -> Result<usize> {
+    let mut sealed = Sealed {
+        snow,
+        format,
+        plain: &mut buffer.plain,
+    };
+    let sent = tx_into(stream, obj, &mut sealed, &mut buffer.frame).await;
+    buffer.trim();
+    sent
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+// receive an object through `stream`, decrypted with `snow` in the buffers of `buffer`
+async fn snow_rx<T: Read + Unpin, O: DeserializeOwned, F: ReadFormat>(
+    stream: &mut T,
+    mut snow: RefDividedSnow<'_>,
+    format: &mut F,
+    buffer: &mut ReceiveBuffer,
+) -> Result<O> {
+    let (state, plain) = buffer.parts();
+    let with = WithCipher::new(&mut snow, format);
+    let obj = loop {
+        if let Err(e) = rx_payload_until(stream, &with, state, None).await {
+            break Err(e);
+        }
+        match with.snow.decrypt_flagged(state.payload(), plain) {
+            // encrypted control messages are skipped like heartbeats
+            Err(e) if is_control(&e) => continue,
+            Err(e) => break Err(e),
+            Ok(()) => break with.format.deserialize(&plain[1..]),
+        }
+    };
+    buffer.trim();
+    obj
+}
+
+/// length of the encrypted message of `len` bytes of plaintext
+pub(crate) fn encrypted_len(len: usize) -> usize {
+    let rest = len.saturating_sub(PACKET_LEN as usize - 1);
+    let packets = 1 + rest.div_ceil(PACKET_LEN as usize);
+    packets
+        .saturating_mul(LEN_PREFIX + TAG_LEN)
+        .saturating_add(len)
+        .saturating_add(1)
 }
 
 /// helper trait used to encrypt
@@ -25,67 +385,645 @@ pub trait Decrypt {
     fn decrypt(&mut self, buf: &[u8]) -> Result<Vec<u8>>;
 }
 
-impl RefDividedSnow<'_> {
-    // returns an error if length of buf is greater than the packet length
-    fn encrypt_packet(&mut self, buf: &[u8]) -> Result<Vec<u8>> {
-        // create message buffer
-        let mut msg = vec![0u8; buf.len() + 16];
-        // encrypt into message buffer
-        self.encrypt_packet_raw(buf, &mut msg)?;
-        Ok(msg)
-    }
-    fn encrypt_packet_raw(&mut self, buf: &[u8], mut msg: &mut [u8]) -> Result<()> {
-        // encrypt into message buffer
-        let nonce = self.nonce.wrapping_add(1) as _;
-        self.transport
-            .write_message(nonce, buf, &mut msg)
-            .map_err(err!(@invalid_data))?;
-        Ok(())
-    }
+// locks are only held while a packet is encrypted or decrypted, never across an await
+fn read(
+    transport: &RwLock<StatelessTransportState>,
+) -> Result<RwLockReadGuard<'_, StatelessTransportState>> {
+    transport
+        .read()
+        .map_err(|_| err!(other, "transport state poisoned"))
+}
+
+// get the nonce of the next packet. keys are replaced long before the
+// counter runs out, unless a single message has more packets than it counts
+fn next_nonce(nonce: &mut Nonce) -> Result<u64> {
+    let current = nonce.count;
+    nonce.count = current
+        .checked_add(1)
+        .ok_or_else(|| err!(other, "nonce exhausted without rekeying"))?;
+    Ok(current as u64)
+}
+
+// replace the key of one direction, restarting its nonce
+fn rekey(
+    transport: &RwLock<StatelessTransportState>,
+    nonce: &mut Nonce,
+    rekey: fn(&mut StatelessTransportState),
+) -> Result<()> {
+    let mut transport = transport
+        .write()
+        .map_err(|_| err!(other, "transport state poisoned"))?;
+    rekey(&mut transport);
+    nonce.count = 0;
+    Ok(())
 }
 
 impl Encrypt for RefDividedSnow<'_> {
     fn encrypt_packets(&mut self, buf: Vec<u8>) -> Result<Vec<u8>> {
-        let mut total = Vec::with_capacity(buf.len() + 16);
-        for buf in buf.chunks(PACKET_LEN as _) {
-            let mut buf = self.encrypt_packet(buf)?;
-            total.append(&mut buf);
-        }
-        Ok(total)
+        self.encrypt_message(0, &buf)
     }
 }
 
 impl Decrypt for RefDividedSnow<'_> {
     fn decrypt(&mut self, buf: &[u8]) -> Result<Vec<u8>> {
-        let mut bytes = vec![];
-        for buf in buf.chunks(PACKET_LEN as usize + 16) {
-            let mut message = vec![0u8; buf.len()]; // move message outside the loop
-
-            let nonce = self.nonce.wrapping_add(1) as _;
+        let mut bytes = Vec::new();
+        self.decrypt_flagged(buf, &mut bytes)?;
+        bytes.remove(0);
+        Ok(bytes)
+    }
+}
 
-            self.transport
-                .read_message(nonce, &buf, &mut message)
+impl RefDividedSnow<'_> {
+    /// decrypt `buf` into `bytes`, reusing its capacity. The plaintext is
+    /// left after the flags byte, so receives can borrow it without moving it
+    pub(crate) fn decrypt_flagged(&mut self, mut buf: &[u8], bytes: &mut Vec<u8>) -> Result<()> {
+        // the plaintext is never longer than the ciphertext
+        bytes.clear();
+        bytes.resize(buf.len(), 0);
+        let mut len = 0;
+        while !buf.is_empty() {
+            let (packet, rest) = match buf {
+                [high, low, rest @ ..] => {
+                    let packet_len = u16::from_be_bytes([*high, *low]) as usize;
+                    if packet_len < TAG_LEN || packet_len > rest.len() {
+                        return err!((invalid_data, "truncated encrypted packet"));
+                    }
+                    rest.split_at(packet_len)
+                }
+                _ => return err!((invalid_data, "truncated encrypted packet")),
+            };
+            let nonce = next_nonce(self.nonce)?;
+            // decrypt straight into the output buffer
+            len += read(self.transport)?
+                .read_message(nonce, packet, &mut bytes[len..])
                 .map_err(|e| err!(other, e.to_string()))?;
-            bytes.append(&mut message);
+            buf = rest;
         }
-        Ok(bytes)
+        bytes.truncate(len);
+        let flags = match bytes[..] {
+            [flags, ..] if flags & !(CONTROL | REKEY) == 0 => flags,
+            [_, ..] => return err!((invalid_data, "unknown flags in encrypted message")),
+            [] => return err!((invalid_data, "empty encrypted message")),
+        };
+        if flags & REKEY != 0 {
+            rekey(
+                self.transport,
+                self.nonce,
+                StatelessTransportState::rekey_incoming,
+            )?;
+        }
+        if flags & CONTROL != 0 {
+            return Err(crate::serialization::control());
+        }
+        Ok(())
     }
 }
 
-/// Starts a new snow stream using the default noise parameters
-pub async fn new(stream: &mut Channel) -> Result<StatelessTransportState> {
-    let noise_params = NoiseParams::new(
+fn noise_params(pattern: HandshakePattern) -> NoiseParams {
+    NoiseParams::new(
         "".into(),
         BaseChoice::Noise,
         HandshakeChoice {
-            pattern: HandshakePattern::NN,
+            pattern,
             modifiers: HandshakeModifierList { list: vec![] },
         },
         DHChoice::Curve25519,
         CipherChoice::ChaChaPoly,
         HashChoice::Blake2s,
-    );
-    new_with_params(stream, noise_params).await
+    )
+}
+
+#[derive(Clone)]
+/// Configuration of the encryption handshake.
+/// The default configuration uses the `NN` pattern, with ephemeral keys only,
+/// which encrypts the channel but doesn't authenticate the peer.
+/// Setting a static key or pinning the key of the peer switches to the
+/// `XX` pattern, in which both peers send their static keys.
+/// Other patterns such as `IK` or `NK` can be picked with `pattern`, and a
+/// pre-shared key can be mixed into any pattern with `with_psk`.
+/// ```no_run
+/// # use canary::{async_snow::SnowConfig, providers::Addr};
+/// # async fn run(addr: Addr, server_public_key: Vec<u8>) -> canary::Result<()> {
+/// let keypair = SnowConfig::generate_keypair()?;
+/// let config = SnowConfig::new(keypair.private).pin(server_public_key);
+/// let chan = addr.connect_with(&config).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SnowConfig {
+    /// Noise parameters used by the handshake
+    pub params: NoiseParams,
+    /// Local static private key
+    pub private_key: Option<Vec<u8>>,
+    /// Expected static public key of the peer
+    pub remote_key: Option<Vec<u8>>,
+    /// Pre-shared key and the position of the handshake it's mixed in
+    pub psk: Option<(u8, [u8; 32])>,
+    /// Time the handshake has to complete in, not enforced on wasm
+    pub timeout: Option<Duration>,
+    #[cfg(feature = "verifier")]
+    /// Check run on the key of the peer once the handshake completes
+    pub verifier: Option<Arc<dyn Verifier>>,
+    /// Run the handshake even on channels their transport already encrypts
+    pub force_encryption: bool,
+    /// Packets this side sends with a key before replacing it
+    pub rekey_interval: u32,
+}
+
+impl fmt::Debug for SnowConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut config = f.debug_struct("SnowConfig");
+        config
+            .field("params", &self.params)
+            .field("private_key", &self.private_key.as_ref().map(|_| "..."))
+            .field("remote_key", &self.remote_key)
+            .field("psk", &self.psk.as_ref().map(|_| "..."))
+            .field("timeout", &self.timeout);
+        #[cfg(feature = "verifier")]
+        config.field("verifier", &self.verifier.as_ref().map(|_| "..."));
+        config
+            .field("force_encryption", &self.force_encryption)
+            .field("rekey_interval", &self.rekey_interval)
+            .finish()
+    }
+}
+
+impl Default for SnowConfig {
+    fn default() -> Self {
+        SnowConfig {
+            params: noise_params(HandshakePattern::NN),
+            private_key: None,
+            remote_key: None,
+            psk: None,
+            timeout: Some(HANDSHAKE_TIMEOUT),
+            #[cfg(feature = "verifier")]
+            verifier: None,
+            force_encryption: false,
+            rekey_interval: REKEY_INTERVAL,
+        }
+    }
+}
+
+impl SnowConfig {
+    /// Create a configuration that authenticates with a static private key
+    pub fn new(private_key: impl Into<Vec<u8>>) -> Self {
+        SnowConfig {
+            params: noise_params(HandshakePattern::XX),
+            private_key: Some(private_key.into()),
+            remote_key: None,
+            psk: None,
+            timeout: Some(HANDSHAKE_TIMEOUT),
+            #[cfg(feature = "verifier")]
+            verifier: None,
+            force_encryption: false,
+            rekey_interval: REKEY_INTERVAL,
+        }
+    }
+    /// Require the peer to have the given static public key.
+    /// The handshake fails with a `PermissionDenied` error before any
+    /// message is exchanged if the peer has a different key.
+    #[must_use]
+    pub fn pin(mut self, remote_key: impl Into<Vec<u8>>) -> Self {
+        if self.params.handshake.pattern == HandshakePattern::NN {
+            self.params.handshake.pattern = HandshakePattern::XX;
+        }
+        self.remote_key = Some(remote_key.into());
+        self
+    }
+    /// Use a different handshake pattern, both peers must use the same one.
+    /// The connecting peer initiates the handshake. On channels where
+    /// neither peer connected, in patterns where only the initiator knows
+    /// the static key of the peer beforehand, such as `NK` or `IK`, the peer
+    /// configured with `pin` initiates.
+    /// ```no_run
+    /// # use canary::async_snow::{HandshakePattern, SnowConfig};
+    /// # fn run() -> canary::Result<()> {
+    /// # let server_keypair = SnowConfig::generate_keypair()?;
+    /// let server = SnowConfig::new(server_keypair.private).pattern(HandshakePattern::NK);
+    /// let client = SnowConfig::default().pin(server_keypair.public).pattern(HandshakePattern::NK);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn pattern(mut self, pattern: HandshakePattern) -> Self {
+        self.params.handshake.pattern = pattern;
+        self
+    }
+    /// Mix a pre-shared key into the handshake, so only peers with the same
+    /// key can connect. Both peers must use the same key and position, a
+    /// mismatch makes the handshake fail with a `PermissionDenied` error.
+    /// The peer that sent the last message of the handshake can't notice it,
+    /// its channel fails on the first receive instead.
+    /// Position 0 mixes the key in the first message of the handshake.
+    /// ```no_run
+    /// # use canary::{async_snow::SnowConfig, providers::Addr};
+    /// # async fn run(addr: Addr, psk: [u8; 32]) -> canary::Result<()> {
+    /// let config = SnowConfig::default().with_psk(0, psk);
+    /// let chan = addr.connect_with(&config).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn with_psk(mut self, position: u8, psk: [u8; 32]) -> Self {
+        let modifiers = &mut self.params.handshake.modifiers.list;
+        modifiers.retain(|modifier| !matches!(modifier, HandshakeModifier::Psk(_)));
+        modifiers.push(HandshakeModifier::Psk(position));
+        self.psk = Some((position, psk));
+        self
+    }
+    /// Set the time the handshake has to complete in, ten seconds by default.
+    /// Peers that connect and stay silent make the handshake fail with a
+    /// `TimedOut` error instead of waiting forever. `None` waits indefinitely.
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::{async_snow::SnowConfig, providers::Addr};
+    /// # async fn run(addr: Addr) -> canary::Result<()> {
+    /// let config = SnowConfig::default().timeout(Duration::from_secs(3));
+    /// let chan = addr.connect_with(&config).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeout = timeout.into();
+        self
+    }
+    #[cfg(feature = "verifier")]
+    /// Run `verifier` on the key of the peer after the handshake, before the
+    /// channel is returned. A rejected peer has its channel closed and the
+    /// handshake fails with a `PermissionDenied` error.
+    /// ```no_run
+    /// # use std::collections::HashSet;
+    /// # use canary::{async_snow::{SnowConfig, Verifier}, channel::channels::Transport, providers::Addr};
+    /// # struct Allowlist(HashSet<Vec<u8>>);
+    /// # #[canary::async_trait]
+    /// # impl Verifier for Allowlist {
+    /// #     async fn verify(&self, _: &[u8], _: Transport) -> canary::Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn run(addr: Addr, keys: HashSet<Vec<u8>>) -> canary::Result<()> {
+    /// # let keypair = SnowConfig::generate_keypair()?;
+    /// let config = SnowConfig::new(keypair.private).verifier(Allowlist(keys));
+    /// let chan = addr.connect_with(&config).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn verifier(mut self, verifier: impl Verifier + 'static) -> Self {
+        self.verifier = Some(Arc::new(verifier));
+        self
+    }
+    /// Run the handshake on channels their transport already encrypts, such
+    /// as quic channels, which skip it by default unless the configuration
+    /// authenticates the peer. Both peers must use the same setting.
+    /// ```no_run
+    /// # use canary::{async_snow::SnowConfig, providers::Addr};
+    /// # async fn run() -> canary::Result<()> {
+    /// let config = SnowConfig::default().force_encryption();
+    /// let chan = "quic@example.com:4433".parse::<Addr>()?.connect_with(&config).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn force_encryption(mut self) -> Self {
+        self.force_encryption = true;
+        self
+    }
+    /// Set the number of packets sent with a key before it's replaced, about a
+    /// million by default. Messages are split in packets of 64KiB.
+    /// The message that reaches the interval tells the peer to replace its
+    /// key too, so peers don't need to use the same interval.
+    /// Keys can also be replaced at any time with `Channel::rekey_now`.
+    /// ```no_run
+    /// # use canary::{async_snow::SnowConfig, providers::Addr};
+    /// # async fn run(addr: Addr) -> canary::Result<()> {
+    /// let config = SnowConfig::default().rekey_interval(1 << 16);
+    /// let chan = addr.connect_with(&config).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn rekey_interval(mut self, packets: u32) -> Self {
+        self.rekey_interval = packets.max(1);
+        self
+    }
+    /// Whether the handshake authenticates the peer with keys or a verifier
+    pub(crate) fn authenticates(&self) -> bool {
+        #[cfg(feature = "verifier")]
+        if self.verifier.is_some() {
+            return true;
+        }
+        self.private_key.is_some() || self.remote_key.is_some() || self.psk.is_some()
+    }
+    /// Generate a static keypair usable with `SnowConfig::new`
+    pub fn generate_keypair() -> Result<Keypair> {
+        snow::Builder::new(noise_params(HandshakePattern::XX))
+            .generate_keypair()
+            .map_err(err!(@other))
+    }
+}
+
+#[cfg(feature = "verifier")]
+/// Check of the peer run after the encryption handshake, which can reject
+/// peers that authenticated correctly but aren't allowed to connect.
+/// `peer_key` is the static key of the peer, or its ephemeral key in
+/// patterns where the peer doesn't send a static key, such as `NN`.
+/// ```no_run
+/// # use std::collections::HashSet;
+/// # use canary::{async_snow::Verifier, async_trait, channel::channels::Transport, err, Result};
+/// struct Allowlist(HashSet<Vec<u8>>);
+///
+/// #[async_trait]
+/// impl Verifier for Allowlist {
+///     async fn verify(&self, peer_key: &[u8], _: Transport) -> Result<()> {
+///         match self.0.contains(peer_key) {
+///             true => Ok(()),
+///             false => err!((permission_denied, "peer isn't in the allowlist")),
+///         }
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Verifier: Send + Sync {
+    /// Accept the peer by returning `Ok`, or reject it by returning an error
+    async fn verify(&self, peer_key: &[u8], transport: Transport) -> Result<()>;
+}
+
+/// transport state and security context of a finished handshake,
+/// and the static key of the peer, or its ephemeral key if it has none
+pub(crate) type Established = (StatelessTransportState, SecurityContext, Vec<u8>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Values bound to the encryption handshake of a channel, which are the same
+/// on both peers and unique to the connection. Signing them ties a token to
+/// the connection, so it can't be replayed on another one.
+/// ```no_run
+/// # use canary::{err, Channel};
+/// # fn run(chan: Channel) -> canary::Result<()> {
+/// let context = chan.security_context().ok_or(err!("channel isn't encrypted"))?;
+/// let binding = context.export_keying_material(b"auth token", 32);
+/// # Ok(())
+/// # }
+/// ```
+pub struct SecurityContext {
+    handshake_hash: Vec<u8>,
+}
+
+impl SecurityContext {
+    /// Get the hash of the handshake
+    pub fn handshake_hash(&self) -> &[u8] {
+        &self.handshake_hash
+    }
+    /// Derive `len` bytes from the handshake hash and `label`.
+    /// Different labels give unrelated bytes, so each use should have its own.
+    pub fn export_keying_material(&self, label: &[u8], len: usize) -> Vec<u8> {
+        let mut material = Vec::with_capacity(len);
+        let mut counter = 0u32;
+        while material.len() < len {
+            // the blake2s of snow, so no other hash implementation is needed
+            let mut hash = DefaultResolver
+                .resolve_hash(&HashChoice::Blake2s)
+                .expect("snow implements blake2s");
+            hash.input(b"canary exporter");
+            hash.input(&(label.len() as u64).to_be_bytes());
+            hash.input(label);
+            hash.input(&(len as u64).to_be_bytes());
+            hash.input(&counter.to_be_bytes());
+            hash.input(&self.handshake_hash);
+            let mut block = [0u8; 32];
+            hash.result(&mut block);
+            material.extend_from_slice(&block);
+            counter += 1;
+        }
+        material.truncate(len);
+        material
+    }
+}
+
+/// Starts a new snow stream using the default noise parameters
+pub async fn new(stream: &mut Channel) -> Result<StatelessTransportState> {
+    new_with_params(stream, noise_params(HandshakePattern::NN)).await
+}
+
+/// starts a new snow stream using the provided configuration.
+/// Both peers exchange random numbers to pick the initiator of the handshake,
+/// use `new_with_role` if each side knows its role.
+/// If the handshake fails or times out, the channel may be left in the
+/// middle of a message and should be dropped.
+pub async fn new_with_config(
+    chan: &mut Channel,
+    config: &SnowConfig,
+) -> Result<StatelessTransportState> {
+    let (transport, ..) = establish(chan, config, None).await?;
+    Ok(transport)
+}
+
+/// starts a new snow stream using the provided configuration, initiating
+/// the handshake if `initiator` is true. The peer has to take the other role,
+/// which saves the round trip `new_with_config` needs to pick the roles.
+pub async fn new_with_role(
+    chan: &mut Channel,
+    config: &SnowConfig,
+    initiator: bool,
+) -> Result<StatelessTransportState> {
+    let (transport, ..) = establish(chan, config, Some(initiator)).await?;
+    Ok(transport)
+}
+
+#[doc(hidden)]
+/// starts a new snow stream drawing every random value of the handshake from
+/// `rng`, including the numbers that pick the roles, for tests that snapshot
+/// the wire format. Peers seeded the same way on every run produce the same
+/// transcript. They need different seeds, or the roles are never picked.
+/// A predictable generator makes the encryption worthless, never use it
+/// outside tests.
+pub async fn new_with_rng<R>(
+    chan: &mut Channel,
+    config: &SnowConfig,
+    rng: R,
+) -> Result<StatelessTransportState>
+where
+    R: RngCore + CryptoRng + Send + 'static,
+{
+    let rng = HandshakeRng(Some(Arc::new(Mutex::new(rng))));
+    let handshake = run_handshake(chan, config, None, rng);
+    let (transport, ..) = with_timeout(config, handshake).await?;
+    Ok(transport)
+}
+
+/// starts a new snow stream as the initiator, the peer must call `new_responder`
+pub async fn new_initiator(chan: &mut Channel) -> Result<StatelessTransportState> {
+    new_with_role(chan, &SnowConfig::default(), true).await
+}
+
+/// starts a new snow stream as the responder, the peer must call `new_initiator`
+pub async fn new_responder(chan: &mut Channel) -> Result<StatelessTransportState> {
+    new_with_role(chan, &SnowConfig::default(), false).await
+}
+
+/// run the handshake, initiating it if `role` is `Some(true)` and picking the
+/// roles with the peer if it's `None`
+pub(crate) async fn establish(
+    chan: &mut Channel,
+    config: &SnowConfig,
+    role: Option<bool>,
+) -> Result<Established> {
+    let handshake = run_handshake(chan, config, role, HandshakeRng::default());
+    with_timeout(config, handshake).await
+}
+
+// generator a handshake draws from, the os generator unless tests inject one
+#[derive(Clone, Default)]
+struct HandshakeRng(Option<Arc<Mutex<dyn InjectedRng>>>);
+
+trait InjectedRng: RngCore + CryptoRng + Send {}
+impl<R: RngCore + CryptoRng + Send> InjectedRng for R {}
+
+impl HandshakeRng {
+    // builder of a handshake state that draws from this generator
+    fn builder(&self, params: NoiseParams) -> snow::Builder<'static> {
+        match self.0 {
+            Some(_) => snow::Builder::with_resolver(params, Box::new(self.clone())),
+            None => snow::Builder::new(params),
+        }
+    }
+    fn with<T>(&self, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        match &self.0 {
+            // a panic can't leave the generator in an invalid state
+            Some(rng) => f(&mut *rng.lock().unwrap_or_else(|e| e.into_inner())),
+            None => f(&mut OsRng),
+        }
+    }
+}
+
+impl RngCore for HandshakeRng {
+    fn next_u32(&mut self) -> u32 {
+        self.with(|rng| rng.next_u32())
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.with(|rng| rng.next_u64())
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.with(|rng| rng.fill_bytes(dest))
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand::Error> {
+        self.with(|rng| rng.try_fill_bytes(dest))
+    }
+}
+
+impl CryptoRng for HandshakeRng {}
+impl Random for HandshakeRng {}
+
+// the default primitives of snow, drawing from the injected generator
+impl CryptoResolver for HandshakeRng {
+    fn resolve_rng(&self) -> Option<Box<dyn Random>> {
+        Some(Box::new(self.clone()))
+    }
+    fn resolve_dh(&self, choice: &DHChoice) -> Option<Box<dyn Dh>> {
+        DefaultResolver.resolve_dh(choice)
+    }
+    fn resolve_hash(&self, choice: &HashChoice) -> Option<Box<dyn Hash>> {
+        DefaultResolver.resolve_hash(choice)
+    }
+    fn resolve_cipher(&self, choice: &CipherChoice) -> Option<Box<dyn Cipher>> {
+        DefaultResolver.resolve_cipher(choice)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+async fn with_timeout<T>(
+    config: &SnowConfig,
+    handshake: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(timeout) = config.timeout {
+        return match crate::io::timeout(timeout, handshake).await {
+            Ok(transport) => transport,
+            Err(_) => err!((timeout, format!("handshake timed out after {:?}", timeout))),
+        };
+    }
+    handshake.await
+}
+
+// pick the role if it's not known and run the handshake described by the configuration
+async fn run_handshake(
+    chan: &mut Channel,
+    config: &SnowConfig,
+    role: Option<bool>,
+    mut rng: HandshakeRng,
+) -> Result<Established> {
+    let pattern = config.params.handshake.pattern;
+    let remote_key = config.remote_key.as_deref();
+    // NN keeps the original message layout so it stays compatible
+    if pattern == HandshakePattern::NN && config.params.handshake.modifiers.list.is_empty() {
+        if remote_key.is_some() {
+            return err!((
+                invalid_input,
+                "the NN pattern can't authenticate the static key of the peer"
+            ));
+        }
+        let initiator = match role {
+            Some(initiator) => initiator,
+            None => should_initiate(chan, &mut rng).await?,
+        };
+        return if initiator {
+            initialize_initiator(chan, config.params.clone(), rng).await
+        } else {
+            initialize_responder(chan, config.params.clone(), rng).await
+        };
+    }
+    // if only the initiator has to know the key of the peer, roles are fixed
+    let initiator = match role {
+        Some(initiator) => initiator,
+        None if pattern.need_known_remote_pubkey(true)
+            && !pattern.need_known_remote_pubkey(false) =>
+        {
+            remote_key.is_some()
+        }
+        None => should_initiate(chan, &mut rng).await?,
+    };
+    let mut builder = rng.builder(config.params.clone());
+    let throwaway;
+    if pattern.needs_local_static_key(initiator) {
+        // pinning without a static key of our own uses a throwaway one
+        let private_key = match &config.private_key {
+            Some(key) => key,
+            None => {
+                throwaway = builder.generate_keypair().map_err(err!(@other))?.private;
+                &throwaway
+            }
+        };
+        builder = builder.local_private_key(private_key);
+    }
+    if pattern.need_known_remote_pubkey(initiator) {
+        let remote_key = remote_key.ok_or_else(|| {
+            err!(
+                invalid_input,
+                "the handshake pattern requires the static key of the peer"
+            )
+        })?;
+        builder = builder.remote_public_key(remote_key);
+    }
+    if let Some((position, psk)) = &config.psk {
+        builder = builder.psk(*position, psk);
+    }
+    let state = if initiator {
+        builder.build_initiator()
+    } else {
+        builder.build_responder()
+    };
+    let state = state.map_err(err!(@other))?;
+    let dh_len = dh_len(&config.params);
+    handshake(chan, state, remote_key, dh_len).await
+}
+
+// size of the public keys of the handshake
+fn dh_len(params: &NoiseParams) -> usize {
+    match params.dh {
+        DHChoice::Curve25519 => 32,
+        DHChoice::Ed448 => 56,
+    }
 }
 
 /// starts a new snow stream using the provided parameters.
@@ -93,8 +1031,17 @@ pub async fn new_with_params(
     chan: &mut Channel,
     noise_params: NoiseParams,
 ) -> Result<StatelessTransportState> {
+    let config = SnowConfig {
+        params: noise_params,
+        ..Default::default()
+    };
+    new_with_config(chan, &config).await
+}
+
+// both peers send a random number, the one with the bigger number initiates
+async fn should_initiate(chan: &mut Channel, rng: &mut HandshakeRng) -> Result<bool> {
     let should_init = loop {
-        let local_num = rand::random::<u64>();
+        let local_num = rng.gen::<u64>();
 
         chan.send(local_num).await?;
         let peer_num: u64 = chan.receive().await?;
@@ -105,23 +1052,84 @@ pub async fn new_with_params(
             break local_num > peer_num;
         }
     };
-    if should_init {
-        initialize_initiator(chan, noise_params).await
-    } else {
-        initialize_responder(chan, noise_params).await
+    Ok(should_init)
+}
+
+// run a handshake of any pattern, checking the static key of the peer
+// as soon as it's known
+async fn handshake(
+    chan: &mut Channel,
+    mut state: HandshakeState,
+    remote_key: Option<&[u8]>,
+    dh_len: usize,
+) -> Result<Established> {
+    let mut buffer = vec![0u8; 65535];
+    let mut remote_ephemeral = Vec::new();
+    while !state.is_handshake_finished() {
+        if state.is_my_turn() {
+            let len = state
+                .write_message(&[], &mut buffer)
+                .map_err(err!(@other))?;
+            chan.send(&buffer[..len]).await?;
+        } else {
+            let msg: Vec<u8> = chan.receive().await?;
+            state.read_message(&msg, &mut buffer).map_err(|e| match e {
+                // the peer used different static or pre-shared keys
+                snow::Error::Decrypt => err!(
+                    permission_denied,
+                    "handshake failed, the peer couldn't be authenticated"
+                ),
+                e => err!(other, e),
+            })?;
+            // the first message of the peer starts with its ephemeral key
+            if remote_ephemeral.is_empty() {
+                remote_ephemeral = msg[..dh_len].to_vec();
+            }
+            match (remote_key, state.get_remote_static()) {
+                (Some(expected), Some(key)) if expected != key => {
+                    return err!((
+                        permission_denied,
+                        "static key of the peer doesn't match the pinned key"
+                    ));
+                }
+                _ => {}
+            }
+        }
     }
+    if remote_key.is_some() && state.get_remote_static().is_none() {
+        return err!((permission_denied, "peer didn't send a static key"));
+    }
+    into_transport(state, remote_ephemeral)
+}
+
+// switch to transport mode, keeping the handshake hash and the key of the peer
+fn into_transport(state: HandshakeState, remote_ephemeral: Vec<u8>) -> Result<Established> {
+    let context = SecurityContext {
+        handshake_hash: state.get_handshake_hash().to_vec(),
+    };
+    let peer_key = match state.get_remote_static() {
+        Some(key) => key.to_vec(),
+        None => remote_ephemeral,
+    };
+    let transport = state
+        .into_stateless_transport_mode()
+        .map_err(err!(@other))?;
+    Ok((transport, context, peer_key))
 }
 
 /// starts a new snow stream using the provided parameters.
-pub(crate) async fn initialize_initiator(
+async fn initialize_initiator(
     chan: &mut Channel,
     noise_params: NoiseParams,
-) -> Result<StatelessTransportState> {
-    let mut initiator = snow::Builder::new(noise_params)
+    mut rng: HandshakeRng,
+) -> Result<Established> {
+    let dh_len = dh_len(&noise_params);
+    let mut initiator = rng
+        .builder(noise_params)
         .build_initiator()
         .map_err(err!(@other))?;
     let mut buffer_msg = vec![0u8; 128];
-    let rand_payload: &[u8; 16] = &rand::random();
+    let rand_payload: &[u8; 16] = &rng.gen();
 
     let len = initiator
         .write_message(rand_payload, &mut buffer_msg)
@@ -134,34 +1142,538 @@ pub(crate) async fn initialize_initiator(
         .read_message(&buffer_msg, &mut buffer_out)
         .map_err(err!(@other))?;
 
-    initiator
-        .into_stateless_transport_mode()
-        .map_err(err!(@other))
+    let remote_ephemeral = buffer_msg[..dh_len].to_vec();
+    into_transport(initiator, remote_ephemeral)
 }
 
 /// starts a new snow stream using the provided parameters.
-pub(crate) async fn initialize_responder(
+async fn initialize_responder(
     chan: &mut Channel,
     noise_params: NoiseParams,
-) -> Result<StatelessTransportState> {
-    let mut responder = snow::Builder::new(noise_params)
+    mut rng: HandshakeRng,
+) -> Result<Established> {
+    let dh_len = dh_len(&noise_params);
+    let mut responder = rng
+        .builder(noise_params)
         .build_responder()
         .map_err(err!(@other))?;
     let mut buffer_out = vec![0u8; 128];
 
     let (mut buffer_msg, len): (Vec<u8>, u64) = chan.receive().await?;
+    // the length comes from the peer
+    let msg = usize::try_from(len)
+        .ok()
+        .and_then(|len| buffer_msg.get(..len))
+        .ok_or_else(|| err!(invalid_data, "handshake message shorter than its length"))?;
     responder
-        .read_message(&buffer_msg[..len as usize], &mut buffer_out)
+        .read_message(msg, &mut buffer_out)
         .map_err(err!(@other))?;
+    let remote_ephemeral = buffer_msg[..dh_len].to_vec();
 
-    let rand_payload: &[u8; 16] = &rand::random();
+    let rand_payload: &[u8; 16] = &rng.gen();
 
     let len = responder
         .write_message(rand_payload, &mut buffer_msg)
         .map_err(err!(@other))?;
     chan.send((&buffer_out, &buffer_msg[..len])).await?;
 
-    responder
-        .into_stateless_transport_mode()
-        .map_err(err!(@other))
+    into_transport(responder, remote_ephemeral)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::channel::raw::unified::unformatted::UnformattedRawUnifiedChannel;
+    use crate::serialization::formats::Format;
+    use tokio::io::DuplexStream;
+
+    // transport states of both peers, the same on every call since the
+    // ephemeral keys are fixed
+    fn transports() -> (
+        RwLock<StatelessTransportState>,
+        RwLock<StatelessTransportState>,
+    ) {
+        let params = noise_params(HandshakePattern::NN);
+        let mut initiator = snow::Builder::new(params.clone())
+            .fixed_ephemeral_key_for_testing_only(&[1; 32])
+            .build_initiator()
+            .unwrap();
+        let mut responder = snow::Builder::new(params)
+            .fixed_ephemeral_key_for_testing_only(&[2; 32])
+            .build_responder()
+            .unwrap();
+        let (mut msg, mut payload) = ([0u8; 128], [0u8; 128]);
+        let len = initiator.write_message(&[], &mut msg).unwrap();
+        responder.read_message(&msg[..len], &mut payload).unwrap();
+        let len = responder.write_message(&[], &mut msg).unwrap();
+        initiator.read_message(&msg[..len], &mut payload).unwrap();
+        (
+            RwLock::new(initiator.into_stateless_transport_mode().unwrap()),
+            RwLock::new(responder.into_stateless_transport_mode().unwrap()),
+        )
+    }
+
+    #[test]
+    fn rekey_replaces_keys_of_both_peers() {
+        let (sender, receiver) = transports();
+        let (_, stale) = transports();
+        let (mut send, mut receive) = (Nonce::default(), Nonce::default());
+        let mut old_nonce = Nonce::default();
+
+        let mut tx = RefDividedSnow::new(&sender, &mut send);
+        let before = tx.encrypt_packets(b"before".to_vec()).unwrap();
+        let rekey = tx.encrypt_control(true).unwrap();
+        let after = tx.encrypt_packets(b"after".to_vec()).unwrap();
+
+        let mut rx = RefDividedSnow::new(&receiver, &mut receive);
+        assert_eq!(rx.decrypt(&before).unwrap(), b"before");
+        // the control message is skipped, and replaces the key of the receiver
+        assert!(rx.decrypt(&rekey).is_err());
+        assert_eq!(rx.decrypt(&after).unwrap(), b"after");
+
+        // a peer that kept the old key decrypts what was sent before only
+        let mut old = RefDividedSnow::new(&stale, &mut old_nonce);
+        assert_eq!(old.decrypt(&before).unwrap(), b"before");
+        old.nonce.count += 1;
+        assert!(old.decrypt(&after).is_err());
+    }
+
+    #[test]
+    fn keys_are_replaced_every_interval() {
+        let (sender, receiver) = transports();
+        let interval = Nonce {
+            count: 0,
+            rekey_interval: 2,
+        };
+        let (mut send, mut receive) = (interval, Nonce::default());
+        for i in 0..10u8 {
+            let msg = RefDividedSnow::new(&sender, &mut send)
+                .encrypt_packets(vec![i; 10])
+                .unwrap();
+            let bytes = RefDividedSnow::new(&receiver, &mut receive)
+                .decrypt(&msg)
+                .unwrap();
+            assert_eq!(bytes, vec![i; 10]);
+            assert!(send.count < 2);
+            assert_eq!(send.count, receive.count);
+        }
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        let (sender, receiver) = transports();
+        let (mut send, mut receive) = (Nonce::default(), Nonce::default());
+        let msg = RefDividedSnow::new(&sender, &mut send)
+            .encrypt_message(0x80, b"hi")
+            .unwrap();
+        let e = RefDividedSnow::new(&receiver, &mut receive)
+            .decrypt(&msg)
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    // in-memory stream of a channel that has nothing buffered
+    fn stream(chan: Channel) -> DuplexStream {
+        match chan.into_inner().unwrap() {
+            UnformattedRawUnifiedChannel::Mem(stream) => stream,
+            _ => unreachable!(),
+        }
+    }
+
+    // streams encrypted with the result of a handshake between them
+    async fn snow_pair() -> (Snow<DuplexStream>, Snow<DuplexStream>) {
+        let (mut a, mut b) = Channel::pair();
+        let (initiator, responder) = tokio::join!(new_initiator(&mut a), new_responder(&mut b));
+        let a = Snow::new(stream(a), initiator.unwrap());
+        let b = Snow::new(stream(b), responder.unwrap());
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn split_snow_halves_send_and_receive_at_once() {
+        let (a, b) = snow_pair().await;
+        let (mut a_send, mut a_receive) = a.split();
+        let (mut b_send, mut b_receive) = b.split();
+        // both ends send more than the streams buffer before they receive,
+        // so the sends only finish if the receives run along with them
+        let message = vec![1u8; 2 * 1024 * 1024];
+        let (sent_a, sent_b, from_b, from_a) = tokio::join!(
+            async {
+                a_send.tx(&message, &mut Format::Bincode).await?;
+                a_send.tx("a", &mut Format::Bincode).await
+            },
+            async {
+                b_send.tx(&message, &mut Format::Bincode).await?;
+                b_send.tx("b", &mut Format::Bincode).await
+            },
+            async {
+                let bytes: Vec<u8> = a_receive.rx(&mut Format::Bincode).await?;
+                let name: String = a_receive.rx(&mut Format::Bincode).await?;
+                Ok::<_, crate::Error>((bytes, name))
+            },
+            async {
+                let bytes: Vec<u8> = b_receive.rx(&mut Format::Bincode).await?;
+                let name: String = b_receive.rx(&mut Format::Bincode).await?;
+                Ok::<_, crate::Error>((bytes, name))
+            },
+        );
+        sent_a.unwrap();
+        sent_b.unwrap();
+        assert_eq!(from_b.unwrap(), (message.clone(), "b".to_string()));
+        assert_eq!(from_a.unwrap(), (message, "a".to_string()));
+    }
+
+    #[tokio::test]
+    async fn snow_streams_talk_to_cipher_formats() {
+        use crate::channel::encrypted::snowwith::WithCipher;
+        use crate::serialization::{rx, tx};
+
+        let (mut a, mut b) = Channel::pair();
+        let (initiator, responder) = tokio::join!(new_initiator(&mut a), new_responder(&mut b));
+        let mut snow = Snow::new(stream(a), initiator.unwrap());
+        let (mut st, transport) = (stream(b), RwLock::new(responder.unwrap()));
+        let (mut send_nonce, mut receive_nonce) = (Nonce::default(), Nonce::default());
+        let mut send = RefDividedSnow::new(&transport, &mut send_nonce);
+        let mut receive = RefDividedSnow::new(&transport, &mut receive_nonce);
+        let mut format = Format::Bincode;
+        // a short message and one over several packets
+        for message in ["hello!".to_string(), "a".repeat(100_000)] {
+            tx(
+                &mut st,
+                &message,
+                &mut WithCipher::new(&mut send, &mut format),
+            )
+            .await
+            .unwrap();
+            let received: String = snow.rx(&mut Format::Bincode).await.unwrap();
+            assert_eq!(received, message);
+
+            snow.tx(&message, &mut Format::Bincode).await.unwrap();
+            let received: String = rx(&mut st, &mut WithCipher::new(&mut receive, &mut format))
+                .await
+                .unwrap();
+            assert_eq!(received, message);
+        }
+    }
+
+    // run a handshake between peers seeded with `seeds`, returning the bytes
+    // each of them sent along with a message encrypted by the first one
+    async fn seeded_transcript(seeds: [u64; 2]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        use rand::{rngs::StdRng, SeedableRng};
+        use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+        // copy the bytes of one direction, keeping a record of them
+        async fn relay(
+            mut from: impl AsyncRead + Unpin,
+            mut to: impl AsyncWrite + Unpin,
+        ) -> Vec<u8> {
+            let (mut sent, mut buf) = (Vec::new(), [0u8; 1024]);
+            loop {
+                match from.read(&mut buf).await.unwrap() {
+                    0 => return sent,
+                    len => {
+                        sent.extend_from_slice(&buf[..len]);
+                        to.write_all(&buf[..len]).await.unwrap();
+                    }
+                }
+            }
+        }
+
+        let (mut a, a_end) = Channel::pair();
+        let (b_end, mut b) = Channel::pair();
+        let (a_read, a_write) = tokio::io::split(stream(a_end));
+        let (b_read, b_write) = tokio::io::split(stream(b_end));
+        let a_sent = tokio::spawn(relay(a_read, b_write));
+        let b_sent = tokio::spawn(relay(b_read, a_write));
+        let config = SnowConfig::default();
+        let (transport, other) = tokio::join!(
+            new_with_rng(&mut a, &config, StdRng::seed_from_u64(seeds[0])),
+            new_with_rng(&mut b, &config, StdRng::seed_from_u64(seeds[1])),
+        );
+        other.unwrap();
+        let transport = RwLock::new(transport.unwrap());
+        let message = RefDividedSnow::new(&transport, &mut Nonce::default())
+            .encrypt_packets(b"hello".to_vec())
+            .unwrap();
+        drop((a, b));
+        (a_sent.await.unwrap(), b_sent.await.unwrap(), message)
+    }
+
+    #[tokio::test]
+    async fn seeded_handshakes_are_reproducible() {
+        let transcript = seeded_transcript([1, 2]).await;
+        assert_eq!(transcript, seeded_transcript([1, 2]).await);
+        // the seeds pick the roles and keys, so others change the transcript
+        assert_ne!(transcript, seeded_transcript([2, 1]).await);
+        assert_ne!(transcript, seeded_transcript([3, 4]).await);
+    }
+
+    #[tokio::test]
+    async fn channels_rekey_now() {
+        let (mut a, mut b) = Channel::encrypted_pair().await.unwrap();
+        for _ in 0..3 {
+            a.rekey_now().await.unwrap();
+            b.rekey_now().await.unwrap();
+            a.send("hello").await.unwrap();
+            b.send("world").await.unwrap();
+            assert_eq!(b.receive::<String>().await.unwrap(), "hello");
+            assert_eq!(a.receive::<String>().await.unwrap(), "world");
+        }
+        let (mut raw, _) = Channel::pair();
+        let e = raw.rekey_now().await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    // payloads at the edges of packets, the first of which also carries the flags
+    fn boundary_payloads() -> impl Iterator<Item = Vec<u8>> {
+        let len = PACKET_LEN as usize;
+        [len - 1, len, len + 1, 3 * len]
+            .into_iter()
+            .map(|size| (0..size).map(|i| i as u8).collect())
+    }
+
+    #[test]
+    fn packet_boundaries_round_trip() {
+        let (sender, receiver) = transports();
+        let (mut send, mut receive) = (Nonce::default(), Nonce::default());
+        for payload in boundary_payloads() {
+            let msg = RefDividedSnow::new(&sender, &mut send)
+                .encrypt_packets(payload.clone())
+                .unwrap();
+            let bytes = RefDividedSnow::new(&receiver, &mut receive)
+                .decrypt(&msg)
+                .unwrap();
+            assert_eq!(bytes, payload);
+        }
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected() {
+        let (sender, receiver) = transports();
+        let mut send = Nonce::default();
+        let payload = boundary_payloads().last().unwrap();
+        let msg = RefDividedSnow::new(&sender, &mut send)
+            .encrypt_packets(payload)
+            .unwrap();
+        for len in [1, msg.len() - 1] {
+            let e = RefDividedSnow::new(&receiver, &mut Nonce::default())
+                .decrypt(&msg[..len])
+                .unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_round_trip_packet_boundaries() {
+        let (mut a, mut b) = Channel::encrypted_pair().await.unwrap();
+        for payload in boundary_payloads() {
+            a.send_raw(&payload).await.unwrap();
+            assert_eq!(b.receive_raw().await.unwrap(), payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn websockets_round_trip_packet_boundaries() {
+        use crate::providers::WebSocket;
+
+        let ws = WebSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = ws.local_addr().unwrap();
+        let (a, b) = tokio::join!(
+            async { WebSocket::connect(addr).await?.encrypted().await },
+            async { ws.next().await?.encrypted().await },
+        );
+        let (mut a, mut b) = (a.unwrap(), b.unwrap());
+        for payload in boundary_payloads() {
+            a.send_raw(&payload).await.unwrap();
+            assert_eq!(b.receive_raw().await.unwrap(), payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn websocket_handshakes_encrypt_both_ends() {
+        use crate::providers::WebSocket;
+
+        let ws = WebSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = ws.local_addr().unwrap();
+        let (a, b) = tokio::join!(
+            async { WebSocket::connect(addr).await?.encrypted().await },
+            async { ws.next().await?.encrypted().await },
+        );
+        let (mut a, mut b) = (a.unwrap(), b.unwrap());
+        assert!(a.is_encrypted());
+        assert!(b.is_encrypted());
+        a.send("hello!").await.unwrap();
+        assert_eq!(b.receive::<String>().await.unwrap(), "hello!");
+        b.send("world!").await.unwrap();
+        assert_eq!(a.receive::<String>().await.unwrap(), "world!");
+    }
+
+    // run the handshake between a connector and an acceptor with their configurations
+    async fn handshake_pair(
+        connector: &SnowConfig,
+        acceptor: &SnowConfig,
+    ) -> (Result<Channel>, Result<Channel>) {
+        use crate::channel::handshake::Handshake;
+
+        let (a, b) = Channel::pair();
+        tokio::join!(
+            Handshake::connector(a).encrypted_with(connector),
+            Handshake::acceptor(b).encrypted_with(acceptor),
+        )
+    }
+
+    #[tokio::test]
+    async fn pinned_keys_authenticate_the_peer() {
+        let server = SnowConfig::generate_keypair().unwrap();
+        let acceptor = SnowConfig::new(server.private.clone());
+        let connector = SnowConfig::default().pin(server.public.clone());
+        let (a, b) = handshake_pair(&connector, &acceptor).await;
+        let (mut a, mut b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a.peer_static_key(), Some(server.public.clone()));
+        a.send("hello").await.unwrap();
+        assert_eq!(b.receive::<String>().await.unwrap(), "hello");
+
+        // the key of another server
+        let other = SnowConfig::generate_keypair().unwrap();
+        let connector = SnowConfig::default().pin(other.public);
+        let (a, b) = handshake_pair(&connector, &acceptor).await;
+        assert_eq!(a.unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(b.is_err());
+
+        // patterns where only the connector knows the key of the server
+        let acceptor = acceptor.pattern(HandshakePattern::NK);
+        let connector = SnowConfig::default()
+            .pin(server.public.clone())
+            .pattern(HandshakePattern::NK);
+        let (a, b) = handshake_pair(&connector, &acceptor).await;
+        assert_eq!(a.unwrap().peer_static_key(), Some(server.public));
+        assert_eq!(b.unwrap().peer_static_key(), None);
+    }
+
+    #[tokio::test]
+    async fn pre_shared_keys_must_match() {
+        for position in [0, 2] {
+            let config = SnowConfig::default().with_psk(position, [7; 32]);
+            let (a, b) = handshake_pair(&config, &config).await;
+            let (mut a, mut b) = (a.unwrap(), b.unwrap());
+            a.send("hello").await.unwrap();
+            assert_eq!(b.receive::<String>().await.unwrap(), "hello");
+
+            let other = SnowConfig::default().with_psk(position, [8; 32]);
+            // the peer that sent the last message of the handshake learns
+            // about the mismatch once the other one drops the channel
+            let (a, b) = handshake_pair(&config, &other).await;
+            let denied = |chan: &Result<Channel>| matches!(chan, Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied);
+            assert!(denied(&a) || denied(&b));
+            for mut chan in [a, b].into_iter().flatten() {
+                assert!(chan.receive::<String>().await.is_err());
+            }
+        }
+        // the key is only replaced, peers agree on a single position
+        let config = SnowConfig::default()
+            .with_psk(1, [9; 32])
+            .with_psk(0, [7; 32]);
+        assert_eq!(config.params.handshake.modifiers.list.len(), 1);
+        let (a, b) = handshake_pair(&config, &SnowConfig::default().with_psk(0, [7; 32])).await;
+        assert!(a.is_ok() && b.is_ok());
+    }
+
+    #[tokio::test]
+    async fn handshake_lengths_past_the_message_are_rejected() {
+        let (mut a, mut b) = Channel::pair();
+        let (responder, _) = tokio::join!(new_responder(&mut a), async {
+            b.send((vec![0u8; 32], u64::MAX)).await.unwrap();
+        });
+        let e = responder.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn silent_peers_time_out() {
+        use crate::channel::handshake::Handshake;
+
+        let timeout = Duration::from_millis(50);
+        for config in [
+            SnowConfig::default(),
+            SnowConfig::new(SnowConfig::generate_keypair().unwrap().private),
+        ] {
+            // the peer stays connected but never writes
+            let (a, _silent) = Channel::pair();
+            let started = std::time::Instant::now();
+            let e = Handshake::connector(a)
+                .encrypted_with(&config.timeout(timeout))
+                .await
+                .unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+            assert!(started.elapsed() >= timeout);
+        }
+    }
+
+    #[tokio::test]
+    async fn peers_export_the_same_keying_material() {
+        let (a, b) = Channel::encrypted_pair().await.unwrap();
+        let (a, b) = (a.security_context().unwrap(), b.security_context().unwrap());
+        assert_eq!(a, b);
+        // lengths that aren't a multiple of the hash use part of a block
+        for len in [0, 16, 32, 45, 100] {
+            let material = a.export_keying_material(b"token", len);
+            assert_eq!(material.len(), len);
+            assert_eq!(material, b.export_keying_material(b"token", len));
+        }
+        let token = a.export_keying_material(b"token", 32);
+        assert_ne!(token, a.export_keying_material(b"other", 32));
+        // the length is part of the derivation too
+        assert_ne!(token[..16], a.export_keying_material(b"token", 16));
+
+        // other connections have their own material
+        let (c, _) = Channel::encrypted_pair().await.unwrap();
+        let c = c.security_context().unwrap();
+        assert_ne!(token, c.export_keying_material(b"token", 32));
+        let (raw, _) = Channel::pair();
+        assert!(raw.security_context().is_none());
+    }
+
+    #[cfg(feature = "verifier")]
+    #[tokio::test]
+    async fn verifiers_accept_or_reject_peers() {
+        // accepts the key it was given, recording the transports it saw
+        struct Only(Vec<u8>, Arc<Mutex<Vec<Transport>>>);
+
+        #[async_trait]
+        impl Verifier for Only {
+            async fn verify(&self, peer_key: &[u8], transport: Transport) -> Result<()> {
+                self.1.lock().unwrap().push(transport);
+                match peer_key == self.0 {
+                    true => Ok(()),
+                    false => err!((other, "unknown peer")),
+                }
+            }
+        }
+
+        let client = SnowConfig::generate_keypair().unwrap();
+        let server = SnowConfig::generate_keypair().unwrap();
+        let connector = SnowConfig::new(client.private.clone());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let verifier = Only(client.public.clone(), seen.clone());
+        let acceptor = SnowConfig::new(server.private.clone()).verifier(verifier);
+        let (a, b) = handshake_pair(&connector, &acceptor).await;
+        let (mut a, mut b) = (a.unwrap(), b.unwrap());
+        a.send("hello").await.unwrap();
+        assert_eq!(b.receive::<String>().await.unwrap(), "hello");
+        assert_eq!(*seen.lock().unwrap(), [Transport::Mem]);
+
+        // rejected peers get their channel closed
+        let other = SnowConfig::generate_keypair().unwrap();
+        let connector = SnowConfig::new(other.private);
+        let (a, b) = handshake_pair(&connector, &acceptor).await;
+        let e = b.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(e.to_string().contains("unknown peer"));
+        assert!(a.unwrap().receive::<String>().await.is_err());
+
+        // verifiers see the ephemeral key of peers without a static one
+        let acceptor = SnowConfig::default().verifier(Only(client.public, Default::default()));
+        let (_, b) = handshake_pair(&SnowConfig::default(), &acceptor).await;
+        assert_eq!(b.unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+    }
 }
@@ -1,4 +1,13 @@
-use crate::serialization::formats::Format;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    serialization::{formats::Format, RxState},
+    Error,
+};
 
 use super::encrypted::{bidirectional, receive_channel, send_channel};
 
@@ -19,6 +28,193 @@ pub type ReceiveChannel<F = Format> = receive_channel::ReceiveChannel<F>;
 /// Reference receive channel, similar to &ReceiveChannel
 pub type RefReceiveChannel<'a, F = Format> = receive_channel::RefReceiveChannel<'a, F>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Transport backend a channel communicates through
+pub enum Transport {
+    /// Tcp backend
+    Tcp,
+    /// Unix backend
+    Unix,
+    /// WebSocket backend
+    Wss,
+    /// Quic backend
+    Quic,
+    /// In-memory backend
+    Mem,
+    /// Tls backend
+    Tls,
+}
+
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Credentials of the process at the other end of a unix channel,
+/// checked by the kernel when it connected (`SO_PEERCRED`)
+pub struct PeerCred {
+    /// User id of the process
+    pub uid: u32,
+    /// Group id of the process
+    pub gid: u32,
+    /// Process id, if the platform reports it
+    pub pid: Option<i32>,
+}
+
+#[cfg(unix)]
+impl PeerCred {
+    /// credentials of the peer of the stream
+    pub(crate) fn of(stream: &crate::io::UnixStream) -> crate::Result<Self> {
+        let cred = stream.peer_cred()?;
+        Ok(PeerCred {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+        })
+    }
+
+    /// error of channels over transports without credentials
+    pub(crate) fn unsupported(transport: Transport) -> crate::Result<Self> {
+        crate::err!((
+            unsupported,
+            format!("{:?} channels have no peer credentials", transport)
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Permit taken from the limits of the provider that accepted a channel.
+/// The halves of a split channel share it, so it's released once they are all dropped.
+pub(crate) struct Permit {
+    #[cfg(not(target_arch = "wasm32"))]
+    _permit: Option<Arc<tokio::sync::OwnedSemaphorePermit>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Permit {
+    pub(crate) fn new(permit: tokio::sync::OwnedSemaphorePermit) -> Self {
+        Permit {
+            _permit: Some(Arc::new(permit)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Counters of the messages that went through a channel.
+/// Byte counts are of the payloads as they are written to the stream,
+/// so encrypted payloads are counted after encryption. Framing and
+/// heartbeats are not counted.
+pub struct ChannelStats {
+    /// Number of messages sent
+    pub messages_sent: u64,
+    /// Number of messages received
+    pub messages_received: u64,
+    /// Number of payload bytes sent
+    pub bytes_sent: u64,
+    /// Number of payload bytes received
+    pub bytes_received: u64,
+    /// Last time a message was sent, always `None` on wasm
+    pub last_send: Option<SystemTime>,
+    /// Last time a message was received, always `None` on wasm
+    pub last_receive: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Error sent by the peer with `send_result`.
+/// Errors returned by `receive_result` wrap it, so they can be told apart
+/// from errors of the channel itself.
+/// ```no_run
+/// # use canary::{channel::channels::RemoteError, Channel};
+/// # async fn run(mut chan: Channel) -> canary::Result<()> {
+/// match chan.receive_result::<String>().await {
+///     Err(e) if RemoteError::is_remote(&e) => println!("peer failed: {e}"),
+///     Err(e) => return Err(e),
+///     Ok(string) => println!("{string}"),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct RemoteError(pub String);
+
+impl RemoteError {
+    /// Returns `true` if the error was sent by the peer
+    pub fn is_remote(e: &Error) -> bool {
+        e.get_ref().is_some_and(|e| e.is::<RemoteError>())
+    }
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+impl ChannelStats {
+    pub(crate) fn record_send(&mut self, len: usize) {
+        self.messages_sent += 1;
+        self.bytes_sent += len as u64;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.last_send = Some(SystemTime::now());
+        }
+    }
+    pub(crate) fn record_receive(&mut self, len: usize) {
+        self.messages_received += 1;
+        self.bytes_received += len as u64;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.last_receive = Some(SystemTime::now());
+        }
+    }
+}
+
+/// Capacity the receive buffer of a channel keeps between messages by default
+pub const DEFAULT_RECEIVE_BUFFER_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug)]
+/// Buffer reused to read the payloads received through a channel, so
+/// receiving doesn't allocate for payloads that fit in its capacity.
+/// It keeps the frame in progress, so receives can be cancelled and
+/// resumed. Buffers grown past the limit are dropped after the message is
+/// received, so a single large message doesn't keep its memory around.
+/// Messages received to be borrowed are kept until the next receive.
+pub(crate) struct ReceiveBuffer {
+    state: RxState,
+    // plaintext of the last message received to be borrowed from an encrypted channel
+    plain: Vec<u8>,
+    pub(crate) limit: usize,
+}
+
+impl Default for ReceiveBuffer {
+    fn default() -> Self {
+        ReceiveBuffer {
+            state: RxState::default(),
+            plain: Vec::new(),
+            limit: DEFAULT_RECEIVE_BUFFER_LIMIT,
+        }
+    }
+}
+
+impl ReceiveBuffer {
+    /// state to receive the next payload with
+    pub(crate) fn get(&mut self) -> &mut RxState {
+        &mut self.state
+    }
+    /// state to receive the next payload with, along with the buffer its
+    /// plaintext is decrypted into
+    pub(crate) fn parts(&mut self) -> (&mut RxState, &mut Vec<u8>) {
+        (&mut self.state, &mut self.plain)
+    }
+    /// drop the buffers if a message grew them past the limit
+    pub(crate) fn trim(&mut self) {
+        if self.state.capacity() > self.limit {
+            self.state.release();
+        }
+        if self.plain.capacity() > self.limit {
+            self.plain = Vec::new();
+        }
+    }
+}
+
 // this will allow channels to abstract over any type that can receive or send bytes.
 
 // #[async_trait]
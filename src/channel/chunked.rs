@@ -0,0 +1,197 @@
+use crate::{
+    channel::channels::Channel,
+    err,
+    io::{Read, ReadExt, Write, WriteExt},
+    serialization::formats::{ReadFormat, SendFormat},
+    Result,
+};
+
+/// maximum payload of a chunk
+pub(crate) const CHUNK_LEN: usize = 64 * 1024;
+
+// total length, sequence number and final flag
+const HEADER_LEN: usize = 8 + 8 + 1;
+// total length of transfers started without one
+const UNKNOWN_LEN: u64 = u64::MAX;
+
+impl<R, W> Channel<R, W> {
+    /// Stream the contents of `reader` through the channel in chunks, without
+    /// holding the whole payload in memory. Each chunk is sent as its own
+    /// message, so encrypted channels encrypt every chunk independently.
+    /// If `len` is given, exactly `len` bytes are sent, and the peer checks
+    /// it received all of them. Returns the number of bytes sent.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// let file = tokio::fs::File::open("video.mp4").await?;
+    /// let len = file.metadata().await?.len();
+    /// chan.send_chunked(file, Some(len)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_chunked(&mut self, reader: impl Read + Unpin, len: Option<u64>) -> Result<u64>
+    where
+        W: SendFormat,
+    {
+        let mut reader = reader.take(len.unwrap_or(u64::MAX));
+        let mut buf = vec![0u8; HEADER_LEN + CHUNK_LEN];
+        let mut sent = 0u64;
+        for seq in 0u64.. {
+            // fill the chunk, since readers may return less than asked for
+            let mut filled = HEADER_LEN;
+            while filled < buf.len() {
+                match reader.read(&mut buf[filled..]).await? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+            sent += (filled - HEADER_LEN) as u64;
+            let last = filled < buf.len() || Some(sent) == len;
+
+            buf[..8].copy_from_slice(&len.unwrap_or(UNKNOWN_LEN).to_be_bytes());
+            buf[8..16].copy_from_slice(&seq.to_be_bytes());
+            buf[16] = last as u8;
+            self.send_raw(&buf[..filled]).await?;
+
+            if last {
+                break;
+            }
+        }
+        match len {
+            Some(len) if sent != len => err!((
+                unexpected_eof,
+                format!("reader ended after {} of {} bytes", sent, len)
+            )),
+            _ => Ok(sent),
+        }
+    }
+
+    /// Receive a payload sent with `send_chunked`, writing it to `writer`
+    /// chunk by chunk. Chunks over the chunk size are rejected from their
+    /// length prefix, so a peer can't make the channel allocate the whole
+    /// transfer at once.
+    /// Returns the number of bytes received.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// let file = tokio::fs::File::create("video.mp4").await?;
+    /// let len = chan.receive_chunked(file).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_chunked(&mut self, writer: impl Write + Unpin) -> Result<u64>
+    where
+        R: ReadFormat,
+    {
+        let mut writer = writer;
+        let mut total = None;
+        let mut received = 0u64;
+        for seq in 0u64.. {
+            let chunk = self
+                .receive_raw_limited(Some(HEADER_LEN + CHUNK_LEN))
+                .await?;
+            if chunk.len() < HEADER_LEN {
+                return err!((invalid_data, "chunk is too short to carry a header"));
+            }
+            let (header, data) = chunk.split_at(HEADER_LEN);
+            let len = u64::from_be_bytes(header[..8].try_into().unwrap());
+            let chunk_seq = u64::from_be_bytes(header[8..16].try_into().unwrap());
+            let last = header[16] != 0;
+
+            if chunk_seq != seq {
+                return err!((
+                    invalid_data,
+                    format!("expected chunk {}, found chunk {}", seq, chunk_seq)
+                ));
+            }
+            if *total.get_or_insert(len) != len {
+                return err!((invalid_data, "total length changed during the transfer"));
+            }
+            received += data.len() as u64;
+            if len != UNKNOWN_LEN && received > len {
+                return err!((
+                    invalid_data,
+                    format!("received more than the {} bytes announced", len)
+                ));
+            }
+            writer.write_all(data).await?;
+
+            if last {
+                break;
+            }
+        }
+        writer.flush().await?;
+        match total {
+            Some(len) if len != UNKNOWN_LEN && received != len => err!((
+                unexpected_eof,
+                format!("transfer ended after {} of {} bytes", received, len)
+            )),
+            _ => Ok(received),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::{async_snow::PACKET_LEN, channel::handshake::Handshake, providers::Tcp};
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    // several packets and chunks, ending partway through both
+    fn payload() -> Vec<u8> {
+        (0..5 * PACKET_LEN as usize + 123)
+            .map(|i| (i * 7 + i / 251) as u8)
+            .collect()
+    }
+
+    fn digest(bytes: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    async fn tcp_pair() -> (Handshake, Handshake) {
+        let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let (a, b) = tokio::join!(Tcp::connect(addr), tcp.next());
+        (a.unwrap(), b.unwrap())
+    }
+
+    async fn round_trip(mut a: Channel, mut b: Channel) {
+        let payload = payload();
+        let len = payload.len() as u64;
+        let mut received = Vec::new();
+        let (sent, got) = tokio::join!(
+            a.send_chunked(payload.as_slice(), Some(len)),
+            b.receive_chunked(&mut received),
+        );
+        assert_eq!(sent.unwrap(), len);
+        assert_eq!(got.unwrap(), len);
+        assert_eq!(digest(&received), digest(&payload));
+    }
+
+    #[tokio::test]
+    async fn payloads_round_trip_over_tcp() {
+        let (a, b) = tcp_pair().await;
+        round_trip(a.raw(), b.raw()).await;
+    }
+
+    #[tokio::test]
+    async fn payloads_round_trip_over_snow() {
+        let (a, b) = tcp_pair().await;
+        let (a, b) = tokio::try_join!(a.encrypted(), b.encrypted()).unwrap();
+        round_trip(a, b).await;
+    }
+
+    #[tokio::test]
+    async fn oversized_chunks_are_rejected() {
+        let (a, b) = tcp_pair().await;
+        let (mut a, mut b) = (a.raw(), b.raw());
+        let mut chunk = vec![0u8; HEADER_LEN + CHUNK_LEN + 1];
+        chunk[16] = 1;
+        a.send_raw(&chunk).await.unwrap();
+        let e = b.receive_chunked(tokio::io::sink()).await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("over the limit"), "{}", e);
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    err,
+    io::{Read, ReadExt, Write, WriteExt},
+    serialization::formats::{PostcardCobs, ReadFormat, SendFormat},
+    Result,
+};
+
+// largest encoded frame accepted, longer frames are skipped
+const MAX_FRAME_LEN: usize = 64 * 1024;
+// bytes read from the stream at once
+const READ_LEN: usize = 256;
+
+/// Unencrypted channel over any byte stream, such as a serial port, where
+/// messages are postcard encoded and delimited by COBS zero bytes instead
+/// of a length prefix, which is what embedded peers usually speak.
+///
+/// Frames that can't be decoded, like the ones corrupted by line noise,
+/// are skipped up to the next zero byte, so the channel resynchronizes
+/// on the next valid frame instead of failing.
+/// ```no_run
+/// # use canary::channel::CobsChannel;
+/// # mod tokio_serial {
+/// #     pub struct Builder;
+/// #     pub fn new(_: &str, _: u32) -> Builder {
+/// #         Builder
+/// #     }
+/// #     impl Builder {
+/// #         pub fn open_native_async(self) -> std::io::Result<tokio::io::DuplexStream> {
+/// #             Ok(tokio::io::duplex(64).0)
+/// #         }
+/// #     }
+/// # }
+/// # #[derive(serde::Serialize)]
+/// # enum Command {
+/// #     Blink,
+/// # }
+/// # type Reading = u16;
+/// # async fn run() -> canary::Result<()> {
+/// let serial = tokio_serial::new("/dev/ttyUSB0", 115_200).open_native_async()?;
+/// let mut chan = CobsChannel::new(serial);
+/// chan.send(Command::Blink).await?;
+/// let reading: Reading = chan.receive().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CobsChannel<S> {
+    /// underlying stream
+    stream: S,
+    /// bytes read past the last frame received
+    buf: Vec<u8>,
+    /// whether the frame being read is over the limit and dropped
+    discarding: bool,
+    /// frames skipped since the channel was created
+    skipped: u64,
+}
+
+impl<S: Read + Write + Unpin> CobsChannel<S> {
+    /// Construct a channel over the stream
+    pub fn new(stream: S) -> Self {
+        CobsChannel {
+            stream,
+            buf: Vec::new(),
+            discarding: false,
+            skipped: 0,
+        }
+    }
+    /// Send an object through the channel, returning the length of the frame
+    /// ```no_run
+    /// # use canary::channel::CobsChannel;
+    /// # #[derive(serde::Serialize)]
+    /// # enum Command {
+    /// #     Blink,
+    /// # }
+    /// # async fn run(mut chan: CobsChannel<tokio::io::DuplexStream>) -> canary::Result<()> {
+    /// chan.send(Command::Blink).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send<T: Serialize>(&mut self, obj: T) -> Result<usize> {
+        let frame = PostcardCobs.serialize(&obj)?;
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await?;
+        Ok(frame.len())
+    }
+    /// Receive the next valid frame sent through the channel,
+    /// skipping the ones that can't be decoded
+    /// ```no_run
+    /// # use canary::channel::CobsChannel;
+    /// # type Reading = u16;
+    /// # async fn run(mut chan: CobsChannel<tokio::io::DuplexStream>) -> canary::Result<()> {
+    /// let reading: Reading = chan.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let mut start = 0;
+        loop {
+            if let Some(end) = self.buf[start..].iter().position(|&b| b == 0) {
+                let frame: Vec<u8> = self.buf.drain(..=start + end).collect();
+                start = 0;
+                if std::mem::take(&mut self.discarding) {
+                    self.skipped += 1;
+                    continue;
+                }
+                // consecutive zero bytes are only delimiters
+                if frame.len() == 1 {
+                    continue;
+                }
+                match PostcardCobs.deserialize(&frame) {
+                    Ok(obj) => return Ok(obj),
+                    Err(_) => self.skipped += 1,
+                }
+                continue;
+            }
+            // there is no zero byte in the buffer
+            start = self.buf.len();
+            if start > MAX_FRAME_LEN {
+                self.buf.clear();
+                self.discarding = true;
+                start = 0;
+            }
+
+            let mut chunk = [0u8; READ_LEN];
+            let read = self.stream.read(&mut chunk).await?;
+            if read == 0 {
+                return err!((unexpected_eof, "stream closed"));
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+    }
+    /// Get the number of frames skipped because they couldn't be decoded
+    /// or were too long
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+    /// Get the underlying stream back. Bytes read past the last frame are lost
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn garbage_between_frames_is_skipped() {
+        let (mut serial, stream) = duplex(4 * MAX_FRAME_LEN);
+        let mut chan = CobsChannel::new(stream);
+        let frame = |n: u32| PostcardCobs.serialize(&n).unwrap();
+
+        serial.write_all(&frame(1)).await.unwrap();
+        // line noise that isn't a valid frame
+        serial.write_all(&[0xff, 0xff, 0xff, 0]).await.unwrap();
+        // delimiters alone aren't frames
+        serial.write_all(&[0, 0]).await.unwrap();
+        serial.write_all(&frame(2)).await.unwrap();
+        // a frame cut by noise runs into the next one, so both are lost
+        let cut = frame(3);
+        serial.write_all(&cut[..cut.len() - 1]).await.unwrap();
+        serial.write_all(&[0x42; 7]).await.unwrap();
+        serial.write_all(&frame(4)).await.unwrap();
+        // noise past the longest frame
+        serial
+            .write_all(&vec![0x42; 2 * MAX_FRAME_LEN])
+            .await
+            .unwrap();
+        serial.write_all(&[0]).await.unwrap();
+        serial.write_all(&frame(5)).await.unwrap();
+
+        assert_eq!(chan.receive::<u32>().await.unwrap(), 1);
+        assert_eq!(chan.receive::<u32>().await.unwrap(), 2);
+        assert_eq!(chan.skipped(), 1);
+        assert_eq!(chan.receive::<u32>().await.unwrap(), 5);
+        assert_eq!(chan.skipped(), 3);
+        drop(serial);
+        let e = chan.receive::<u32>().await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
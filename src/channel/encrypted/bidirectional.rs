@@ -1,18 +1,40 @@
-use std::sync::Arc;
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+use std::time::Instant;
 
 use derive_more::From;
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use snow::StatelessTransportState;
 
+#[cfg(feature = "compression")]
+use crate::serialization::compression::{Compressed, Compression};
+#[cfg(feature = "checksum")]
+use crate::serialization::formats::Checked;
 use crate::{
-    async_snow::RefDividedSnow,
+    async_snow::{Nonce, RefDividedSnow, SecurityContext, SharedTransport},
+    channel::channels::{ChannelStats, Permit, ReceiveBuffer, RemoteError, Transport},
+    channel::forward::is_closed,
     channel::raw::{
         joint::unformatted::RefUnformattedRawChannel,
         unified::unformatted::UnformattedRawUnifiedChannel,
     },
-    serialization::formats::{Format, ReadFormat, SendFormat},
+    err,
+    serialization::{
+        formats::{Format, Fragmented, Framed, ReadFormat, SendFormat},
+        Framing,
+    },
     Result,
 };
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    channel::{handshake::Handshake, keepalive::Keepalive},
+    io::duplex,
+    serialization::timed_out,
+};
 
 use super::{
     bipartite::{BipartiteChannel, UnformattedBipartiteChannel},
@@ -30,8 +52,8 @@ pub enum RefUnformattedBidirectionalChannel<'a> {
     /// Encrypted channel
     Encrypted(
         RefUnformattedRawChannel<'a>,
-        &'a StatelessTransportState,
-        &'a mut u32,
+        &'a RwLock<StatelessTransportState>,
+        &'a mut Nonce,
     ),
 }
 
@@ -97,20 +119,186 @@ impl<R, W> Channel<R, W> {
             channel: UnformattedUnifiedChannel::Raw(raw.into()),
             receive_format,
             send_format,
+            stats: ChannelStats::default(),
+            security: None,
+            peer_certificates: None,
+            buffer: ReceiveBuffer::default(),
+            permit: Permit::default(),
         })
     }
 
+    /// Attach the permit of the provider that accepted the channel
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn set_permit(&mut self, permit: Permit) {
+        match self {
+            Channel::Unified(chan) => chan.permit = permit,
+            Channel::Bipartite(chan) => {
+                chan.send_channel.permit = permit.clone();
+                chan.receive_channel.permit = permit;
+            }
+        }
+    }
+
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: StatelessTransportState,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` and `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: StatelessTransportState) -> Result<(), SharedTransport> {
+        match self {
+            Channel::Unified(unified) => unified
+                .encrypt(transport)
+                .map_err(|transport| Arc::new(RwLock::new(transport))),
+            Channel::Bipartite(bipartite) => bipartite.encrypt(Arc::new(RwLock::new(transport))),
+        }
+    }
+
+    /// Returns `true` if the channel is encrypted
+    /// ```no_run
+    /// # use canary::{err, Channel};
+    /// # fn run(chan: &Channel) -> canary::Result<()> {
+    /// if !chan.is_encrypted() {
+    ///     return err!((permission_denied, "refusing to send credentials in plaintext"));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        match self {
+            Channel::Unified(chan) => chan.is_encrypted(),
+            Channel::Bipartite(chan) => chan.is_encrypted(),
+        }
+    }
+
+    /// Get the transport backend of the channel
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # fn run(chan: &Channel) {
+    /// tracing::info!("new channel over {:?}", chan.transport());
+    /// # }
+    /// ```
+    pub fn transport(&self) -> Transport {
+        match self {
+            Channel::Unified(chan) => chan.transport(),
+            Channel::Bipartite(chan) => chan.transport(),
+        }
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel,
+    /// such as its uid to authorize it. Channels over other transports
+    /// fail with `Unsupported`.
+    /// ```no_run
+    /// # use canary::{err, Channel};
+    /// # fn run(chan: Channel) -> canary::Result<()> {
+    /// let cred = chan.peer_cred()?;
+    /// if cred.uid != 0 {
+    ///     return err!((permission_denied, "only root can connect"));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        match self {
+            Channel::Unified(chan) => chan.peer_cred(),
+            Channel::Bipartite(chan) => chan.peer_cred(),
+        }
+    }
+
+    /// Get the static public key the peer authenticated with during the
+    /// handshake. Returns `None` if the channel isn't encrypted or the
+    /// handshake used ephemeral keys only.
+    /// ```no_run
+    /// # use canary::{err, Channel};
+    /// # fn run(chan: Channel) -> canary::Result<()> {
+    /// let key = chan.peer_static_key().ok_or(err!(permission_denied, "unauthenticated peer"))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peer_static_key(&self) -> Option<Vec<u8>> {
+        let transport = match self {
+            Channel::Unified(chan) => match &chan.channel {
+                UnformattedUnifiedChannel::Encrypted { transport, .. } => transport,
+                UnformattedUnifiedChannel::Raw(_) => return None,
+            },
+            Channel::Bipartite(chan) => match &chan.send_channel.channel {
+                UnformattedSendChannel::Encrypted(_, transport, _) => &**transport,
+                UnformattedSendChannel::Raw(_) => return None,
+            },
+        };
+        let transport = transport.read().ok()?;
+        transport.get_remote_static().map(<[u8]>::to_vec)
+    }
+
+    /// Get the values bound to the encryption handshake, which are the same
+    /// on both peers. Returns `None` if the channel isn't encrypted, was
+    /// encrypted with `encrypt` directly, or was split and joined again.
+    /// ```no_run
+    /// # use canary::{err, Channel};
+    /// # fn sign(_: &[u8]) -> Vec<u8> {
+    /// #     Vec::new()
+    /// # }
+    /// # fn run(chan: Channel) -> canary::Result<()> {
+    /// let context = chan.security_context().ok_or(err!("channel isn't encrypted"))?;
+    /// let signature = sign(context.handshake_hash());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn security_context(&self) -> Option<SecurityContext> {
         match self {
-            Channel::Unified(unified) => unified.encrypt(transport).map_err(Arc::new),
-            Channel::Bipartite(bipartite) => bipartite.encrypt(Arc::new(transport)),
+            Channel::Unified(chan) => chan.security.clone(),
+            Channel::Bipartite(chan) => chan.security.clone(),
+        }
+    }
+
+    /// Set the number of packets sent with a key before it's replaced
+    pub(crate) fn set_rekey_interval(&mut self, packets: u32) {
+        match self {
+            Channel::Unified(chan) => chan.channel.set_rekey_interval(packets),
+            Channel::Bipartite(chan) => chan.send_channel.channel.set_rekey_interval(packets),
+        }
+    }
+
+    pub(crate) fn set_security_context(&mut self, context: SecurityContext) {
+        match self {
+            Channel::Unified(chan) => chan.security = Some(context),
+            Channel::Bipartite(chan) => chan.security = Some(context),
+        }
+    }
+
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// Get the certificate chain the peer presented in the tls handshake of
+    /// a tls or secure websocket channel, verified against the roots of the
+    /// configuration, leaf first. Returns `None` for other transports, peers
+    /// that presented no certificate, or channels that were split and joined again.
+    /// ```no_run
+    /// # use canary::{err, providers::rustls::Certificate, Channel};
+    /// # fn authorize(_: &Certificate) -> canary::Result<()> {
+    /// #     Ok(())
+    /// # }
+    /// # fn run(chan: Channel) -> canary::Result<()> {
+    /// let certs = chan.peer_certificates().ok_or(err!(permission_denied, "no client certificate"))?;
+    /// authorize(&certs[0])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peer_certificates(&self) -> Option<Vec<rustls::Certificate>> {
+        let certs = self.certificates()?;
+        Some(certs.iter().cloned().map(rustls::Certificate).collect())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn certificates(&self) -> Option<Arc<[Vec<u8>]>> {
+        match self {
+            Channel::Unified(chan) => chan.peer_certificates.clone(),
+            Channel::Bipartite(chan) => chan.peer_certificates.clone(),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn set_peer_certificates(&mut self, certs: Option<Arc<[Vec<u8>]>>) {
+        match self {
+            Channel::Unified(chan) => chan.peer_certificates = certs,
+            Channel::Bipartite(chan) => chan.peer_certificates = certs,
         }
     }
 
@@ -140,6 +328,259 @@ impl<R, W> Channel<R, W> {
             Channel::Bipartite(chan) => chan.receive().await,
         }
     }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Receive an object, failing with a `TimedOut` error if it doesn't
+    /// arrive within `timeout`. The channel can be used after the error,
+    /// unless [`TimedOut::is_poisoned`](crate::serialization::TimedOut::is_poisoned)
+    /// says the message was cut in the middle.
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::Channel;
+    /// # type Tick = u64;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// let tick: Tick = chan.receive_timeout(Duration::from_secs(1)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_timeout<T: DeserializeOwned>(&mut self, timeout: Duration) -> Result<T>
+    where
+        R: ReadFormat,
+    {
+        self.receive_until(Instant::now().checked_add(timeout))
+            .await
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// receive an object by `deadline`, timeouts too long to have one wait forever
+    async fn receive_until<T: DeserializeOwned>(&mut self, deadline: Option<Instant>) -> Result<T>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.receive_until(deadline).await,
+            Channel::Bipartite(chan) => chan.receive_until(deadline).await,
+        }
+    }
+    /// Send a result through the channel, so the peer can get the
+    /// error back with `receive_result`
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn handle(req: String) -> canary::Result<String> {
+    /// #     Ok(req)
+    /// # }
+    /// # async fn run(mut chan: Channel, req: String) -> canary::Result<()> {
+    /// let resp = handle(req).await;
+    /// chan.send_result(resp).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_result<T: Serialize>(&mut self, r: Result<T>) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        self.send(r).await
+    }
+    /// Receive a result sent with `send_result`, flattening it.
+    /// Errors sent by the peer keep their kind and wrap a [`RemoteError`],
+    /// so they can be told apart with [`RemoteError::is_remote`].
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// let string: String = chan.receive_result().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_result<T: DeserializeOwned>(&mut self) -> Result<T>
+    where
+        R: ReadFormat,
+    {
+        let r: Result<T> = self.receive().await?;
+        r.map_err(|e| {
+            let remote = RemoteError(e.to_string());
+            std::io::Error::new(e.kind(), remote).into()
+        })
+    }
+    /// Send a request and receive its response
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// let sum: u64 = chan.call((1u64, 2u64)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn call<Req, Resp>(&mut self, req: Req) -> Result<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+        R: ReadFormat,
+        W: SendFormat,
+    {
+        self.send(req).await?;
+        self.receive().await
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Send a request and receive its response, failing with a `TimedOut`
+    /// error if the whole call takes longer than `timeout`. If the call times
+    /// out, the response may still arrive later, so the channel shouldn't be
+    /// used for more calls.
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// let sum: u64 = chan.call_timeout((1u64, 2u64), Duration::from_secs(5)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn call_timeout<Req, Resp>(&mut self, req: Req, timeout: Duration) -> Result<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+        R: ReadFormat,
+        W: SendFormat,
+    {
+        let deadline = Instant::now().checked_add(timeout);
+        match crate::io::timeout(timeout, self.send(req)).await {
+            Ok(sent) => sent?,
+            // the request may have been cut in the middle
+            Err(_) => return Err(timed_out(true)),
+        };
+        self.receive_until(deadline).await
+    }
+    /// Receive requests and answer each one with the response of `f`,
+    /// until the peer closes the channel.
+    /// Returns the first error of `f` or of the channel.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// chan.serve_fn(|(a, b): (u64, u64)| async move { Ok(a + b) }).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn serve_fn<Req, Resp, F, Fut>(mut self, mut f: F) -> Result<()>
+    where
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        F: FnMut(Req) -> Fut,
+        Fut: Future<Output = Result<Resp>>,
+        R: ReadFormat,
+        W: SendFormat,
+    {
+        loop {
+            let req = match self.receive().await {
+                Ok(req) => req,
+                Err(e) if is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let resp = f(req).await?;
+            self.send(resp).await?;
+        }
+    }
+    /// Send bytes through the channel without serializing them.
+    /// The bytes are framed and encrypted like any other message,
+    /// so the peer can receive them with `receive` or `receive_raw`.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel, mut upstream: Channel) -> canary::Result<()> {
+    /// let bytes = chan.receive_raw().await?;
+    /// upstream.send_raw(&bytes).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.send_raw(bytes).await,
+            Channel::Bipartite(chan) => chan.send_raw(bytes).await,
+        }
+    }
+    /// Receive the bytes of the next message without deserializing them.
+    /// Encrypted messages are decrypted, so the bytes are exactly what
+    /// `receive` would have deserialized. Messages over the limit of the
+    /// format, such as the one of `SafeBincode`, are rejected like they are by `receive`.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel, mut upstream: Channel) -> canary::Result<()> {
+    /// let bytes = chan.receive_raw().await?;
+    /// upstream.send_raw(&bytes).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_raw(&mut self) -> Result<Vec<u8>>
+    where
+        R: ReadFormat,
+    {
+        self.receive_raw_limited(None).await
+    }
+    /// Receive the bytes of the next message, rejecting messages over `max`
+    /// bytes or over the limit of the format before reading them
+    pub(crate) async fn receive_raw_limited(&mut self, max: Option<usize>) -> Result<Vec<u8>>
+    where
+        R: ReadFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.receive_raw_limited(max).await,
+            Channel::Bipartite(chan) => chan.receive_raw_limited(max).await,
+        }
+    }
+    /// Replace the key used to send messages right away, instead of after
+    /// `SnowConfig::rekey_interval` packets. The peer replaces its receive
+    /// key too once it receives the message telling it to, so messages
+    /// sent afterwards can't be decrypted with the keys used before.
+    /// Fails with an `Unsupported` error if the channel isn't encrypted.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// chan.rekey_now().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn rekey_now(&mut self) -> Result<()>
+    where
+        W: SendFormat,
+    {
+        match self {
+            Channel::Unified(chan) => chan.rekey_now().await,
+            Channel::Bipartite(chan) => chan.rekey_now().await,
+        }
+    }
+    #[cfg(feature = "proto")]
+    /// Send a protobuf message through the channel. It's framed and
+    /// encrypted like any other message, without going through the format.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # type Response = String;
+    /// # async fn run(mut chan: Channel, request: String) -> canary::Result<()> {
+    /// chan.send_proto(&request).await?;
+    /// let response: Response = chan.receive_proto().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_proto<M: prost::Message>(&mut self, msg: &M) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        self.send_raw(&msg.encode_to_vec()).await
+    }
+    #[cfg(feature = "proto")]
+    /// Receive a protobuf message sent with `send_proto`, failing with an
+    /// `InvalidData` error if the message can't be decoded.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # type Response = String;
+    /// # async fn run(mut chan: Channel, request: String) -> canary::Result<()> {
+    /// chan.send_proto(&request).await?;
+    /// let response: Response = chan.receive_proto().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_proto<M: prost::Message + Default>(&mut self) -> Result<M>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.receive_raw().await?;
+        M::decode(bytes.as_slice()).map_err(err!(@invalid_data))
+    }
     #[must_use]
     /// Split channel into its send and receive components
     pub fn split(self) -> (SendChannel<W>, ReceiveChannel<R>) {
@@ -148,13 +589,413 @@ impl<R, W> Channel<R, W> {
             Channel::Bipartite(chan) => chan.split(),
         }
     }
+    /// Consume the channel, getting back the inner stream so it can be used
+    /// without canary framing. Split channels are reunited first.
+    /// Returns an error if the channel is encrypted, since the transport state
+    /// would be lost, and if a cancelled receive left part of a frame in the
+    /// receive buffer, since those bytes would be lost. Otherwise the channel
+    /// holds no bytes past the last message it received.
+    /// ```no_run
+    /// # use canary::channel::raw::unified::unformatted::UnformattedRawUnifiedChannel;
+    /// # use canary::Channel;
+    /// # fn run(chan: Channel) -> canary::Result<()> {
+    /// if let UnformattedRawUnifiedChannel::Tcp(stream) = chan.into_inner()? {
+    ///     // use the stream directly
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn into_inner(mut self) -> Result<UnformattedRawUnifiedChannel> {
+        if !self.buffer().get().is_idle() {
+            return err!((
+                invalid_input,
+                "cannot take the inner stream while a frame is partially received"
+            ));
+        }
+        let encrypted = || {
+            err!((
+                invalid_input,
+                "cannot take the inner stream of an encrypted channel"
+            ))
+        };
+        match self {
+            Channel::Unified(chan) => match chan.channel {
+                UnformattedUnifiedChannel::Raw(chan) => Ok(chan),
+                UnformattedUnifiedChannel::Encrypted { .. } => encrypted(),
+            },
+            Channel::Bipartite(chan) => {
+                match (chan.send_channel.channel, chan.receive_channel.channel) {
+                    (
+                        UnformattedSendChannel::Raw(send),
+                        UnformattedReceiveChannel::Raw(receive),
+                    ) => UnformattedRawUnifiedChannel::reunite(send, receive),
+                    _ => encrypted(),
+                }
+            }
+        }
+    }
     /// Join send and receive channels into a channel
     pub fn join(send: SendChannel<W>, receive: ReceiveChannel<R>) -> Self {
         Self::Bipartite(BipartiteChannel {
             receive_channel: receive,
             send_channel: send,
+            keepalive: None,
+            stats: ChannelStats::default(),
+            security: None,
+            peer_certificates: None,
+            buffer: ReceiveBuffer::default(),
         })
     }
+
+    #[cfg(feature = "compression")]
+    /// Compress every message sent through the channel, on top of its formats.
+    /// Compression happens before encryption, and both peers need to enable it.
+    /// Messages from a peer without compression fail to deserialize, unless
+    /// they happen to start with the bytes of the compression header.
+    /// Raw bytes sent with `send_raw` are not compressed.
+    /// ```no_run
+    /// # use canary::{serialization::compression::Compression, Channel};
+    /// # async fn run(chan: Channel, large_document: String) -> canary::Result<()> {
+    /// let mut chan = chan.with_compression(Compression::Zstd { level: 3 });
+    /// chan.send(large_document).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_compression(
+        self,
+        compression: Compression,
+    ) -> Channel<Compressed<R>, Compressed<W>> {
+        self.map_formats(
+            |format| Compressed::new(format, compression),
+            |format| Compressed::new(format, compression),
+        )
+    }
+
+    #[cfg(feature = "checksum")]
+    /// Append a checksum to every message sent through the channel, so
+    /// corrupted messages fail with an `InvalidData` error instead of
+    /// deserializing into wrong values. Both peers need to enable it.
+    /// Encrypted channels already detect corruption, so they don't need it.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(chan: Channel, reading: f64) -> canary::Result<()> {
+    /// let mut chan = chan.with_checksum();
+    /// chan.send(reading).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_checksum(self) -> Channel<Checked<R>, Checked<W>> {
+        self.map_formats(Checked::new, Checked::new)
+    }
+
+    /// Frame the messages of the channel with `framing`, such as a varint
+    /// length that takes a single byte for small messages instead of eight.
+    /// Both peers need to use the same framing, otherwise receiving fails
+    /// with an `InvalidData` error.
+    /// ```no_run
+    /// # use canary::{serialization::Framing, Channel};
+    /// # async fn run(chan: Channel) -> canary::Result<()> {
+    /// # let tick = 0u64;
+    /// let mut chan = chan.with_framing(Framing::VarInt);
+    /// chan.send(tick).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_framing(self, framing: Framing) -> Channel<Framed<R>, Framed<W>> {
+        let mut chan = self.map_formats(
+            |format| Framed::new(format, framing),
+            |format| Framed::new(format, framing),
+        );
+        chan.set_keepalive_framing(framing);
+        chan
+    }
+
+    /// Split websocket messages over `len` bytes into continuation frames,
+    /// for proxies that limit the size of single frames. Encrypted messages
+    /// are split after encryption. The peer reassembles the frames by
+    /// itself, and channels over other transports are unaffected.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(chan: Channel, large_document: String) -> canary::Result<()> {
+    /// let mut chan = chan.with_fragmentation(16 * 1024);
+    /// chan.send(large_document).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_fragmentation(self, len: usize) -> Channel<R, Fragmented<W>> {
+        self.map_formats(|format| format, |format| Fragmented::new(format, len))
+    }
+
+    /// Replace the formats of the channel, both peers need to use
+    /// compatible formats.
+    /// ```no_run
+    /// # use canary::serialization::formats::{Bincode, SafeBincode};
+    /// # use canary::Channel;
+    /// # fn run(chan: Channel) {
+    /// let mut chan: Channel<SafeBincode, SafeBincode> =
+    ///     chan.with_formats(SafeBincode::new(Bincode), SafeBincode::new(Bincode));
+    /// # }
+    /// ```
+    pub fn with_formats<R2, W2>(self, receive_format: R2, send_format: W2) -> Channel<R2, W2>
+    where
+        W2: SendFormat,
+    {
+        let framing = send_format.framing();
+        let mut chan = self.map_formats(|_| receive_format, |_| send_format);
+        chan.set_keepalive_framing(framing);
+        chan
+    }
+
+    // heartbeats are framed like the messages sent
+    fn set_keepalive_framing(&mut self, framing: Framing) {
+        if let Channel::Bipartite(BipartiteChannel {
+            keepalive: Some(keepalive),
+            ..
+        }) = self
+        {
+            keepalive.framing = framing;
+        }
+    }
+
+    fn map_formats<R2, W2>(
+        self,
+        receive: impl FnOnce(R) -> R2,
+        send: impl FnOnce(W) -> W2,
+    ) -> Channel<R2, W2> {
+        match self {
+            Channel::Unified(chan) => Channel::Unified(UnifiedChannel {
+                channel: chan.channel,
+                receive_format: receive(chan.receive_format),
+                send_format: send(chan.send_format),
+                stats: chan.stats,
+                security: chan.security,
+                peer_certificates: chan.peer_certificates,
+                buffer: chan.buffer,
+                permit: chan.permit,
+            }),
+            Channel::Bipartite(chan) => Channel::Bipartite(BipartiteChannel {
+                receive_channel: ReceiveChannel {
+                    channel: chan.receive_channel.channel,
+                    format: receive(chan.receive_channel.format),
+                    permit: chan.receive_channel.permit,
+                },
+                send_channel: SendChannel {
+                    channel: chan.send_channel.channel,
+                    format: send(chan.send_channel.format),
+                    permit: chan.send_channel.permit,
+                },
+                keepalive: chan.keepalive,
+                stats: chan.stats,
+                security: chan.security,
+                peer_certificates: chan.peer_certificates,
+                buffer: chan.buffer,
+            }),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Send a heartbeat whenever the channel stays idle for `interval` while
+    /// waiting in `receive`, so idle connections don't get dropped by NATs or
+    /// load balancers. Heartbeats sent by the peer are always skipped on receive.
+    /// Splitting the channel disables the keepalive.
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// chan.enable_keepalive(Duration::from_secs(30));
+    /// let string: String = chan.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn enable_keepalive(&mut self, interval: Duration)
+    where
+        W: SendFormat,
+    {
+        take_mut::take(self, |mut this| {
+            let stats = this.stats();
+            let security = this.security_context();
+            let peer_certificates = this.certificates();
+            let buffer = std::mem::take(this.buffer());
+            // the channel needs separate halves to send heartbeats while receiving
+            let (send, receive) = this.split();
+            let framing = send.format.framing();
+            Self::Bipartite(BipartiteChannel {
+                receive_channel: receive,
+                send_channel: send,
+                keepalive: Some(Keepalive::new(interval, framing)),
+                stats,
+                security,
+                peer_certificates,
+                buffer,
+            })
+        });
+    }
+
+    /// Get the message and byte counters of the channel.
+    /// Counters start at zero when the channel is created or joined, and
+    /// the halves returned by `split` don't keep them.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # fn run(chan: Channel) {
+    /// let stats = chan.stats();
+    /// println!("sent {} bytes in {} messages", stats.bytes_sent, stats.messages_sent);
+    /// # }
+    /// ```
+    pub fn stats(&self) -> ChannelStats {
+        match self {
+            Channel::Unified(chan) => chan.stats.clone(),
+            Channel::Bipartite(chan) => chan.stats.clone(),
+        }
+    }
+
+    /// Reset the message and byte counters of the channel
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # fn run(mut chan: Channel) {
+    /// let stats = chan.stats();
+    /// chan.reset_stats();
+    /// # }
+    /// ```
+    pub fn reset_stats(&mut self) {
+        match self {
+            Channel::Unified(chan) => chan.stats = ChannelStats::default(),
+            Channel::Bipartite(chan) => chan.stats = ChannelStats::default(),
+        }
+    }
+
+    /// Set the capacity the receive buffer of the channel keeps between
+    /// messages, `DEFAULT_RECEIVE_BUFFER_LIMIT` by default. Payloads that fit
+    /// are read without allocating, larger ones grow the buffer, which is
+    /// dropped once the message is received.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # fn run(mut chan: Channel) {
+    /// chan.set_receive_buffer_limit(64 * 1024);
+    /// # }
+    /// ```
+    pub fn set_receive_buffer_limit(&mut self, limit: usize) {
+        let buffer = self.buffer();
+        buffer.limit = limit;
+        buffer.trim();
+    }
+
+    fn buffer(&mut self) -> &mut ReceiveBuffer {
+        match self {
+            Channel::Unified(chan) => &mut chan.buffer,
+            Channel::Bipartite(chan) => &mut chan.buffer,
+        }
+    }
+
+    /// Get the last time an object was sent or received through the channel.
+    /// Returns `None` if keepalive is not enabled.
+    pub fn last_activity(&self) -> Option<Instant> {
+        match self {
+            Channel::Unified(_) => None,
+            Channel::Bipartite(chan) => chan.keepalive.map(|keepalive| keepalive.last_activity),
+        }
+    }
+}
+
+// size of the in-memory buffer of each direction of a channel pair
+#[cfg(not(target_arch = "wasm32"))]
+const PAIR_BUFFER_SIZE: usize = 1024 * 1024;
+
+impl<R: ReadFormat, W> Channel<R, W> {
+    /// Receive an object that borrows from the message, so large `&str` or
+    /// `&[u8]` fields aren't copied. The message is kept in the receive
+    /// buffer of the channel, and the borrow checker prevents receiving again
+    /// while the object is alive. Formats that can't borrow, such as CBOR,
+    /// fail with an `Unsupported` error.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// let (name, data): (&str, &[u8]) = chan.receive_borrowed().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_borrowed<'a, T: Deserialize<'a>>(&'a mut self) -> Result<T> {
+        let (format, payload) = match self {
+            Channel::Unified(chan) => chan.receive_payload().await?,
+            Channel::Bipartite(chan) => chan.receive_payload().await?,
+        };
+        format.deserialize_borrowed(payload)
+    }
+}
+
+#[cfg(feature = "flexbuffers_ser")]
+impl<R, W> Channel<R, W> {
+    /// Receive a message sent with the `Flexbuffers` format and get a reader
+    /// over it, so fields can be looked up lazily without deserializing or
+    /// copying the rest of the message. The message is kept in the receive
+    /// buffer of the channel, like with `receive_borrowed`.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel) -> canary::Result<()> {
+    /// let telemetry = chan.receive_flex().await?;
+    /// let temperature = telemetry.as_map().idx("temperature").as_f64();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_flex(&mut self) -> Result<flexbuffers::Reader<&[u8]>>
+    where
+        R: ReadFormat,
+    {
+        let (_, payload) = match self {
+            Channel::Unified(chan) => chan.receive_payload().await?,
+            Channel::Bipartite(chan) => chan.receive_payload().await?,
+        };
+        flexbuffers::Reader::get_root(payload).map_err(err!(@invalid_data))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Channel {
+    /// Create a pair of connected in-memory channels.
+    /// The channels use the same framing as sockets do, dropping
+    /// one end makes receiving from the other end fail, and sends wait for
+    /// the peer to receive once the in-memory buffer is full.
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run() -> canary::Result<()> {
+    /// let (mut a, mut b) = Channel::pair();
+    /// a.send("hello!").await?;
+    /// let string: String = b.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = duplex(PAIR_BUFFER_SIZE);
+        let a = Self::from_raw(a, Default::default(), Default::default());
+        let b = Self::from_raw(b, Default::default(), Default::default());
+        (a, b)
+    }
+
+    /// Create a pair of connected in-memory channels and run the
+    /// encryption handshake between them
+    /// ```no_run
+    /// # use canary::Channel;
+    /// # async fn run() -> canary::Result<()> {
+    /// let (mut a, mut b) = Channel::encrypted_pair().await?;
+    /// assert!(a.is_encrypted() && b.is_encrypted());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn encrypted_pair() -> Result<(Self, Self)> {
+        let (a, b) = Self::pair();
+        let a = Handshake::connector(a).encrypted();
+        let b = Handshake::acceptor(b).encrypted();
+        futures::try_join!(a, b)
+    }
+}
+
+impl<R, W> std::fmt::Debug for Channel<R, W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Channel")
+            .field("transport", &self.transport())
+            .field("encrypted", &self.is_encrypted())
+            .finish()
+    }
 }
 
 impl<'a> RefUnformattedBidirectionalChannel<'a> {
@@ -246,3 +1087,423 @@ impl UnformattedBidirectionalChannel {
         }
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    use super::*;
+    use crate::channel::handshake::Handshake;
+
+    #[tokio::test]
+    async fn into_inner_after_cancelled_receive() {
+        let (a, mut b) = Channel::pair();
+        let mut a = match a.into_inner().unwrap() {
+            UnformattedRawUnifiedChannel::Mem(stream) => stream,
+            _ => unreachable!(),
+        };
+        // a byte of a length prefix under both u64 and varint framing
+        a.write_all(&[0x85]).await.unwrap();
+        let receive = tokio::time::timeout(Duration::from_millis(50), b.receive::<String>());
+        assert!(receive.await.is_err());
+        assert!(b.into_inner().is_err());
+    }
+
+    fn stream(chan: Channel) -> tokio::io::DuplexStream {
+        match chan.into_inner().unwrap() {
+            UnformattedRawUnifiedChannel::Mem(stream) => stream,
+            _ => unreachable!(),
+        }
+    }
+
+    // forward bytes a few at a time, so receives see partial frames
+    async fn trickle(mut from: impl AsyncRead + Unpin, mut to: impl AsyncWrite + Unpin) {
+        let mut buf = [0; 3];
+        while let Ok(n @ 1..) = from.read(&mut buf).await {
+            if to.write_all(&buf[..n]).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    // channels whose bytes are relayed by `trickle`
+    fn trickling_pair() -> (Channel, Channel) {
+        let (a, x) = Channel::pair();
+        let (y, b) = Channel::pair();
+        let (x_read, x_write) = tokio::io::split(stream(x));
+        let (y_read, y_write) = tokio::io::split(stream(y));
+        tokio::spawn(trickle(x_read, y_write));
+        tokio::spawn(trickle(y_read, x_write));
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn receives_cancelled_by_select_resume_intact() {
+        for encrypted in [false, true] {
+            let (a, b) = trickling_pair();
+            let (mut a, mut b) = match encrypted {
+                false => (a, b),
+                true => {
+                    let (a, b) = tokio::join!(
+                        Handshake::connector(a).encrypted(),
+                        Handshake::acceptor(b).encrypted()
+                    );
+                    (a.unwrap(), b.unwrap())
+                }
+            };
+            let messages: Vec<String> = (0..10).map(|i| i.to_string().repeat(i * 20)).collect();
+            let sent = messages.clone();
+            tokio::spawn(async move {
+                for message in sent {
+                    a.send(message).await.unwrap();
+                }
+            });
+            let mut cancelled = 0;
+            for message in &messages {
+                loop {
+                    tokio::select! {
+                        received = b.receive::<String>() => {
+                            assert_eq!(&received.unwrap(), message);
+                            break;
+                        }
+                        _ = tokio::time::sleep(Duration::from_millis(2)) => cancelled += 1,
+                    }
+                }
+            }
+            assert!(cancelled > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn into_inner_after_receive() {
+        let (mut a, mut b) = Channel::pair();
+        a.send("hello").await.unwrap();
+        assert_eq!(b.receive::<String>().await.unwrap(), "hello");
+        assert!(b.into_inner().is_ok());
+    }
+
+    #[tokio::test]
+    async fn encrypted_heartbeats_are_skipped() {
+        let (a, mut b) = Channel::encrypted_pair().await.unwrap();
+        let (mut send, _receive) = a.split();
+        send.heartbeat().await.unwrap();
+        send.send("hello").await.unwrap();
+        assert_eq!(b.receive::<String>().await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn plaintext_heartbeats_are_rejected_when_encrypted() {
+        let (a, mut b) = Channel::encrypted_pair().await.unwrap();
+        let (mut send, _receive) = a.split();
+        let framing = SendFormat::framing(&send.format);
+        match &mut send.channel {
+            UnformattedSendChannel::Encrypted(chan, ..) => chan.heartbeat(framing).await.unwrap(),
+            UnformattedSendChannel::Raw(_) => unreachable!(),
+        }
+        let e = b.receive::<String>().await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn heartbeats_use_the_send_framing() {
+        let (a, b) = Channel::pair();
+        let mut a = a.with_formats(
+            Framed::new(Format::Bincode, Framing::VarInt),
+            Framed::new(Format::Bincode, Framing::U64),
+        );
+        let mut b = b.with_formats(
+            Framed::new(Format::Bincode, Framing::U64),
+            Framed::new(Format::Bincode, Framing::VarInt),
+        );
+        a.enable_keepalive(Duration::from_millis(10));
+        let peer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            b.send("ping").await.unwrap();
+            b.receive::<String>().await
+        });
+        assert_eq!(a.receive::<String>().await.unwrap(), "ping");
+        a.send("pong").await.unwrap();
+        assert_eq!(peer.await.unwrap().unwrap(), "pong");
+    }
+
+    #[tokio::test]
+    async fn heartbeats_follow_formats_replaced_after_enabling() {
+        let (mut a, b) = Channel::pair();
+        a.enable_keepalive(Duration::from_millis(10));
+        let mut a = a.with_framing(Framing::VarInt);
+        let mut b = b.with_framing(Framing::VarInt);
+        let peer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            b.send("ping").await.unwrap();
+            b.receive::<String>().await
+        });
+        assert_eq!(a.receive::<String>().await.unwrap(), "ping");
+        a.send("pong").await.unwrap();
+        assert_eq!(peer.await.unwrap().unwrap(), "pong");
+    }
+
+    #[tokio::test]
+    async fn receiving_needs_no_send_format() {
+        let (mut a, b) = Channel::pair();
+        let mut b = b.map_formats(|format| format, |_| ());
+        a.send("hello").await.unwrap();
+        assert_eq!(b.receive::<String>().await.unwrap(), "hello");
+        a.send_raw(b"bytes").await.unwrap();
+        assert_eq!(b.receive_raw().await.unwrap(), b"bytes");
+    }
+
+    #[tokio::test]
+    async fn calls_time_out_without_overflowing() {
+        use crate::serialization::TimedOut;
+
+        let (mut a, mut b) = Channel::encrypted_pair().await.unwrap();
+        let server = tokio::spawn(async move {
+            let (x, y): (u64, u64) = b.receive().await?;
+            b.send(x + y).await?;
+            // never answers the second call
+            b.receive::<(u64, u64)>().await?;
+            Ok::<_, crate::Error>(b)
+        });
+        let sum: u64 = a.call_timeout((1u64, 2u64), Duration::MAX).await.unwrap();
+        assert_eq!(sum, 3);
+        let e = a
+            .call_timeout::<_, u64>((3u64, 4u64), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        // the request went out whole, only the response is missing
+        assert!(!TimedOut::of(&e).unwrap().is_poisoned());
+        let mut b = server.await.unwrap().unwrap();
+        b.send("late").await.unwrap();
+        let late: String = a.receive_timeout(Duration::MAX).await.unwrap();
+        assert_eq!(late, "late");
+    }
+
+    #[tokio::test]
+    async fn handler_errors_stop_serving() {
+        let (mut a, b) = Channel::encrypted_pair().await.unwrap();
+        let server = tokio::spawn(b.serve_fn(|(x, y): (u64, u64)| async move {
+            match x.checked_add(y) {
+                Some(sum) => Ok(sum),
+                None => err!((invalid_input, "sum overflows")),
+            }
+        }));
+        let sum: u64 = a.call((1u64, 2u64)).await.unwrap();
+        assert_eq!(sum, 3);
+        // the handler fails, so the channel is dropped without a response
+        assert!(a.call::<_, u64>((u64::MAX, 1u64)).await.is_err());
+        let e = server.await.unwrap().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "sum overflows");
+    }
+
+    #[tokio::test]
+    async fn serving_ends_when_the_peer_closes() {
+        let (mut a, b) = Channel::pair();
+        let server = tokio::spawn(b.serve_fn(|x: u64| async move { Ok(x * 2) }));
+        assert_eq!(a.call::<_, u64>(21u64).await.unwrap(), 42);
+        drop(a);
+        assert!(server.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn results_keep_their_error_kind() {
+        use crate::channel::channels::RemoteError;
+        use std::io::ErrorKind;
+
+        let (mut a, mut b) = Channel::encrypted_pair().await.unwrap();
+        a.send_result(Ok("hello!")).await.unwrap();
+        assert_eq!(b.receive_result::<String>().await.unwrap(), "hello!");
+        let kinds = [
+            ErrorKind::NotFound,
+            ErrorKind::PermissionDenied,
+            ErrorKind::InvalidInput,
+            ErrorKind::TimedOut,
+            ErrorKind::Other,
+        ];
+        for kind in kinds {
+            let sent: Result<String> = Err(std::io::Error::new(kind, "failed").into());
+            a.send_result(sent).await.unwrap();
+            let e = b.receive_result::<String>().await.unwrap_err();
+            assert_eq!(e.kind(), kind);
+            assert_eq!(e.to_string(), "failed");
+            assert!(RemoteError::is_remote(&e));
+        }
+        // errors of the channel itself are local
+        drop(a);
+        let e = b.receive_result::<String>().await.unwrap_err();
+        assert!(!RemoteError::is_remote(&e));
+    }
+
+    // addresses of the bytes of `bytes`, to tell where a borrow points
+    fn span(bytes: &[u8]) -> std::ops::Range<usize> {
+        let start = bytes.as_ptr() as usize;
+        start..start + bytes.len()
+    }
+
+    #[tokio::test]
+    async fn borrowed_receives_point_into_the_receive_buffer() {
+        let data = vec![7u8; 4 * 1024 * 1024];
+        for encrypted in [false, true] {
+            let (mut a, mut b) = match encrypted {
+                false => Channel::pair(),
+                true => Channel::encrypted_pair().await.unwrap(),
+            };
+            let (sent, received) = tokio::join!(
+                a.send(("large", &data)),
+                b.receive_borrowed::<(&str, &[u8])>()
+            );
+            sent.unwrap();
+            let (name, bytes) = received.unwrap();
+            assert_eq!((name, bytes), ("large", &data[..]));
+            let borrowed = span(bytes);
+            let (state, plain) = b.buffer().parts();
+            let buffer = match encrypted {
+                false => span(state.payload()),
+                true => span(plain),
+            };
+            assert!(buffer.start <= borrowed.start && borrowed.end <= buffer.end);
+        }
+    }
+
+    #[cfg(feature = "flexbuffers_ser")]
+    #[tokio::test]
+    async fn flex_fields_are_read_from_the_receive_buffer() {
+        #[derive(Serialize)]
+        struct Telemetry {
+            temperature: f64,
+            log: String,
+        }
+
+        let (a, b) = Channel::encrypted_pair().await.unwrap();
+        let mut a = a.with_formats(Format::Flexbuffers, Format::Flexbuffers);
+        let mut b = b.with_formats(Format::Flexbuffers, Format::Flexbuffers);
+        let log = "x".repeat(100_000);
+        a.send(Telemetry {
+            temperature: 21.5,
+            log: log.clone(),
+        })
+        .await
+        .unwrap();
+        let telemetry = b.receive_flex().await.unwrap();
+        let fields = telemetry.as_map();
+        assert_eq!(fields.idx("temperature").as_f64(), 21.5);
+        let field = fields.idx("log").as_str();
+        assert_eq!(field, log);
+        let borrowed = span(field.as_bytes());
+        let (_, plain) = b.buffer().parts();
+        let buffer = span(plain);
+        assert!(buffer.start <= borrowed.start && borrowed.end <= buffer.end);
+    }
+
+    #[tokio::test]
+    async fn raw_receives_keep_the_limit_of_the_format() {
+        use crate::serialization::formats::Limited;
+
+        let (mut a, b) = Channel::pair();
+        let mut b = b.with_formats(Limited::<_, 16>::new(Format::Bincode), Format::Bincode);
+        a.send_raw(&[1; 16]).await.unwrap();
+        assert_eq!(b.receive_raw().await.unwrap(), [1; 16]);
+        a.send_raw(&[1; 17]).await.unwrap();
+        let e = b.receive_raw().await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+        // the receive half of a split channel too
+        let (mut a, b) = Channel::pair();
+        let b = b.with_formats(Limited::<_, 16>::new(Format::Bincode), Format::Bincode);
+        let (_send, mut receive) = b.split();
+        a.send_raw(&[1; 17]).await.unwrap();
+        let e = receive.receive_raw().await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn receive_buffers_keep_their_capacity() {
+        for encrypted in [false, true] {
+            let (mut a, mut b) = match encrypted {
+                false => Channel::pair(),
+                true => Channel::encrypted_pair().await.unwrap(),
+            };
+            let message = "a".repeat(64 * 1024);
+            a.send(&message).await.unwrap();
+            assert_eq!(b.receive::<String>().await.unwrap(), message);
+            let capacity = b.buffer().get().capacity();
+            let buffer = b.buffer().get().payload().as_ptr();
+            assert!(capacity >= message.len());
+            for len in [10, 1000, 64 * 1024] {
+                let message = "b".repeat(len);
+                a.send(&message).await.unwrap();
+                assert_eq!(b.receive::<String>().await.unwrap(), message);
+                assert_eq!(b.buffer().get().capacity(), capacity);
+                assert_eq!(b.buffer().get().payload().as_ptr(), buffer);
+            }
+            // buffers grown past the limit are dropped once received
+            b.set_receive_buffer_limit(capacity - 1);
+            a.send(&message).await.unwrap();
+            assert_eq!(b.receive::<String>().await.unwrap(), message);
+            assert_eq!(b.buffer().get().capacity(), 0);
+        }
+    }
+
+    #[cfg(feature = "proto")]
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Point {
+        #[prost(int32, tag = "1")]
+        x: i32,
+        #[prost(int32, tag = "2")]
+        y: i32,
+    }
+
+    #[cfg(feature = "proto")]
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Path {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(message, repeated, tag = "2")]
+        points: Vec<Point>,
+        #[prost(message, optional, tag = "3")]
+        origin: Option<Point>,
+    }
+
+    #[cfg(feature = "proto")]
+    #[tokio::test]
+    async fn nested_proto_messages_round_trip() {
+        let path = Path {
+            name: "hello!".into(),
+            points: vec![Point { x: 1, y: 2 }, Point { x: -3, y: 4 }],
+            origin: Some(Point { x: 0, y: -1 }),
+        };
+        let (mut a, mut b) = Channel::pair();
+        a.send_proto(&path).await.unwrap();
+        assert_eq!(b.receive_proto::<Path>().await.unwrap(), path);
+        let (mut a, mut b) = Channel::encrypted_pair().await.unwrap();
+        a.send_proto(&path).await.unwrap();
+        assert_eq!(b.receive_proto::<Path>().await.unwrap(), path);
+    }
+
+    #[cfg(feature = "proto")]
+    #[tokio::test]
+    async fn proto_messages_are_the_bytes_of_prost() {
+        use prost::Message;
+
+        let point = Point { x: 1, y: 2 };
+        let (mut a, mut b) = Channel::encrypted_pair().await.unwrap();
+        a.send_proto(&point).await.unwrap();
+        // field 1 and field 2 as varints
+        assert_eq!(b.receive_raw().await.unwrap(), [0x08, 1, 0x10, 2]);
+
+        let path = Path {
+            name: "a".into(),
+            points: vec![point.clone()],
+            origin: None,
+        };
+        a.send_raw(&path.encode_to_vec()).await.unwrap();
+        assert_eq!(b.receive_proto::<Path>().await.unwrap(), path);
+        // a field of wire type 7, which doesn't exist
+        a.send_raw(&[0x0f]).await.unwrap();
+        let e = b.receive_proto::<Path>().await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -1,11 +1,19 @@
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
 use std::sync::Arc;
+use std::time::Instant;
 
 use serde::{de::DeserializeOwned, Serialize};
-use snow::StatelessTransportState;
 
-use crate::channel::channels::{ReceiveChannel, SendChannel};
+use crate::channel::channels::{
+    ChannelStats, ReceiveBuffer, ReceiveChannel, SendChannel, Transport,
+};
+use crate::channel::keepalive::Keepalive;
 use crate::serialization::formats::{Format, ReadFormat, SendFormat};
-use crate::Result;
+use crate::{
+    async_snow::{SecurityContext, SharedTransport},
+    Result,
+};
 
 use super::{receive_channel::UnformattedReceiveChannel, send_channel::UnformattedSendChannel};
 
@@ -23,6 +31,16 @@ pub struct BipartiteChannel<R = Format, W = Format> {
     pub receive_channel: ReceiveChannel<R>,
     /// Inner receive channel
     pub send_channel: SendChannel<W>,
+    /// Keepalive state, if enabled
+    pub(crate) keepalive: Option<Keepalive>,
+    /// Message and byte counters
+    pub(crate) stats: ChannelStats,
+    /// Values bound to the encryption handshake, if known
+    pub(crate) security: Option<SecurityContext>,
+    /// Certificates the peer presented in the tls handshake of the transport, in der
+    pub(crate) peer_certificates: Option<Arc<[Vec<u8>]>>,
+    /// Buffer reused to read received payloads
+    pub(crate) buffer: ReceiveBuffer,
 }
 
 impl UnformattedBipartiteChannel {
@@ -57,12 +75,9 @@ impl UnformattedBipartiteChannel {
 impl<R, W> BipartiteChannel<R, W> {
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` and `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         let mut state = Ok(());
         take_mut::take(self, |mut this| {
             if let Err(_) = this.receive_channel.encrypt(transport.clone()) {
@@ -70,7 +85,7 @@ impl<R, W> BipartiteChannel<R, W> {
                 return this;
             }
 
-            if let Err(_) = this.receive_channel.encrypt(transport.clone()) {
+            if let Err(_) = this.send_channel.encrypt(transport.clone()) {
                 state = Err(transport);
                 return this;
             }
@@ -78,6 +93,20 @@ impl<R, W> BipartiteChannel<R, W> {
         });
         state
     }
+    /// Returns `true` if both halves of the channel are encrypted
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        self.send_channel.is_encrypted() && self.receive_channel.is_encrypted()
+    }
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        self.send_channel.transport()
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        self.send_channel.peer_cred()
+    }
     /// Receive an object sent through the channel
     /// ```no_run
     /// let string: String = chan.receive().await?;
@@ -86,7 +115,92 @@ impl<R, W> BipartiteChannel<R, W> {
     where
         R: ReadFormat,
     {
-        self.receive_channel.receive().await
+        self.receive_until(None).await
+    }
+    /// Receive an object by `deadline` if there is one
+    pub(crate) async fn receive_until<T: DeserializeOwned>(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Result<T>
+    where
+        R: ReadFormat,
+    {
+        let state = self.buffer.get();
+        let received = match &mut self.keepalive {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(keepalive) => {
+                let obj = self.receive_channel.receive_counted(state, deadline);
+                keepalive.receive(obj, &mut self.send_channel.channel).await
+            }
+            _ => self.receive_channel.receive_counted(state, deadline).await,
+        };
+        self.buffer.trim();
+        let (obj, len) = received?;
+        self.stats.record_receive(len);
+        Ok(obj)
+    }
+
+    /// Receive the bytes of the next message without deserializing them
+    /// ```no_run
+    /// # use canary::channel::encrypted::bipartite::BipartiteChannel;
+    /// # async fn run(mut chan: BipartiteChannel) -> canary::Result<()> {
+    /// let bytes = chan.receive_raw().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_raw(&mut self) -> Result<Vec<u8>>
+    where
+        R: ReadFormat,
+    {
+        self.receive_raw_limited(None).await
+    }
+
+    /// Receive the bytes of the next message, rejecting messages over `max`
+    /// bytes or over the limit of the format before reading them
+    pub(crate) async fn receive_raw_limited(&mut self, max: Option<usize>) -> Result<Vec<u8>>
+    where
+        R: ReadFormat,
+    {
+        let state = self.buffer.get();
+        let received = match &mut self.keepalive {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(keepalive) => {
+                let bytes = self.receive_channel.receive_raw_counted(state, max);
+                keepalive
+                    .receive(bytes, &mut self.send_channel.channel)
+                    .await
+            }
+            _ => self.receive_channel.receive_raw_counted(state, max).await,
+        };
+        self.buffer.trim();
+        let (bytes, len) = received?;
+        self.stats.record_receive(len);
+        Ok(bytes)
+    }
+
+    /// Receive the payload of the next message into the receive buffer of
+    /// the channel without deserializing it, decrypting it if the channel is
+    /// encrypted. The receive format is returned along with it to read it with
+    pub(crate) async fn receive_payload(&mut self) -> Result<(&mut R, &[u8])>
+    where
+        R: ReadFormat,
+    {
+        // the last payload can't be borrowed anymore, so its buffer can go
+        self.buffer.trim();
+        let (state, plain) = self.buffer.parts();
+        let received = match &mut self.keepalive {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(keepalive) => {
+                let payload = self.receive_channel.receive_payload(state, plain);
+                keepalive
+                    .receive(payload, &mut self.send_channel.channel)
+                    .await
+            }
+            _ => self.receive_channel.receive_payload(state, plain).await,
+        };
+        let (payload, len) = received?;
+        self.stats.record_receive(len);
+        Ok((&mut self.receive_channel.format, payload))
     }
 
     /// Send an object through the channel
@@ -97,7 +211,43 @@ impl<R, W> BipartiteChannel<R, W> {
     where
         W: SendFormat,
     {
-        self.send_channel.send(obj).await
+        let len = self.send_channel.send(obj).await?;
+        if let Some(keepalive) = &mut self.keepalive {
+            keepalive.touch();
+        }
+        self.stats.record_send(len);
+        Ok(len)
+    }
+
+    /// Send bytes through the channel without serializing them
+    /// ```no_run
+    /// # use canary::channel::encrypted::bipartite::BipartiteChannel;
+    /// # async fn run(mut chan: BipartiteChannel, bytes: Vec<u8>) -> canary::Result<()> {
+    /// chan.send_raw(&bytes).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        let len = self.send_channel.send_raw(bytes).await?;
+        if let Some(keepalive) = &mut self.keepalive {
+            keepalive.touch();
+        }
+        self.stats.record_send(len);
+        Ok(len)
+    }
+    /// Replace the key used to send messages, which the peer replaces too
+    pub async fn rekey_now(&mut self) -> Result<()>
+    where
+        W: SendFormat,
+    {
+        self.send_channel.rekey_now().await?;
+        if let Some(keepalive) = &mut self.keepalive {
+            keepalive.touch();
+        }
+        Ok(())
     }
     #[must_use]
     /// Split channel into its send and receive components
@@ -6,7 +6,7 @@ pub mod bipartite;
 pub mod receive_channel;
 /// Contains send channels which may be encrypted
 pub mod send_channel;
-/// Contains `SnowWith`, an encryption helper format
+/// Contains `WithCipher`, a format adapter that encrypts messages
 pub mod snowwith;
 /// Contains unified channels which may be encrypted
 pub mod unified;
@@ -1,21 +1,28 @@
-use std::sync::Arc;
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
+use std::time::Instant;
 
 use derive_more::From;
 use serde::de::DeserializeOwned;
-use snow::StatelessTransportState;
 
 use crate::{
-    async_snow::RefDividedSnow,
+    async_snow::{Nonce, RefDividedSnow, SharedTransport},
     channel::{
-        channels::SendChannel,
+        channels::{Permit, SendChannel, Transport},
         raw::bipartite::receive_channel::{
             RefUnformattedRawReceiveChannel, UnformattedRawReceiveChannel,
         },
     },
-    serialization::formats::{Format, ReadFormat},
+    serialization::{
+        formats::{Captured, Counted, Format, ReadFormat},
+        is_control, RxState,
+    },
     Channel, Result,
 };
 
+#[cfg(feature = "proto")]
+use crate::err;
+
 use super::snowwith::WithCipher;
 
 #[derive(From)]
@@ -26,8 +33,8 @@ pub enum RefUnformattedReceiveChannel<'a> {
     /// Encrypted channel
     Encrypted(
         RefUnformattedRawReceiveChannel<'a>,
-        &'a Arc<StatelessTransportState>,
-        &'a mut u32,
+        &'a SharedTransport,
+        &'a mut Nonce,
     ),
 }
 
@@ -37,11 +44,7 @@ pub enum UnformattedReceiveChannel {
     /// Unencrypted channel
     Raw(UnformattedRawReceiveChannel),
     /// Encrypted channel
-    Encrypted(
-        UnformattedRawReceiveChannel,
-        Arc<StatelessTransportState>,
-        u32,
-    ),
+    Encrypted(UnformattedRawReceiveChannel, SharedTransport, Nonce),
 }
 
 #[derive(From)]
@@ -53,13 +56,21 @@ pub struct RefReceiveChannel<'a, F = Format> {
     pub format: F,
 }
 
-#[derive(From)]
 /// Receive channel with format
 pub struct ReceiveChannel<F = Format> {
     /// Inner channel
     pub channel: UnformattedReceiveChannel,
     /// Inner format
     pub format: F,
+    /// Permit of the provider that accepted the channel, shared with the send half
+    pub(crate) permit: Permit,
+}
+
+impl<F> From<(UnformattedReceiveChannel, F)> for ReceiveChannel<F> {
+    #[inline]
+    fn from((channel, format): (UnformattedReceiveChannel, F)) -> Self {
+        channel.to_formatted(format)
+    }
 }
 
 impl<'a, F> RefReceiveChannel<'a, F> {
@@ -78,12 +89,9 @@ impl<'a, F> RefReceiveChannel<'a, F> {
 impl<R> ReceiveChannel<R> {
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` and `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         self.channel.encrypt(transport)
     }
     /// Receive an object sent through the channel
@@ -96,6 +104,109 @@ impl<R> ReceiveChannel<R> {
     {
         self.channel.receive(&mut self.format).await
     }
+    /// Receive the bytes of the next message without deserializing them.
+    /// Encrypted messages are decrypted, so the bytes are exactly what
+    /// `receive` would have deserialized. Messages over the limit of the
+    /// format, such as the one of `SafeBincode`, are rejected like they are by `receive`.
+    /// ```no_run
+    /// # use canary::channel::channels::ReceiveChannel;
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: ReceiveChannel, mut upstream: Channel) -> canary::Result<()> {
+    /// let bytes = chan.receive_raw().await?;
+    /// upstream.send_raw(&bytes).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_raw(&mut self) -> Result<Vec<u8>>
+    where
+        R: ReadFormat,
+    {
+        let mut captured = Captured::new(&self.format, None);
+        self.channel.receive::<(), _>(&mut captured).await?;
+        Ok(captured.0)
+    }
+    #[cfg(feature = "proto")]
+    /// Receive a protobuf message sent with `send_proto`, failing with an
+    /// `InvalidData` error if the message can't be decoded.
+    /// ```no_run
+    /// # use canary::channel::channels::ReceiveChannel;
+    /// # type Response = String;
+    /// # async fn run(mut chan: ReceiveChannel) -> canary::Result<()> {
+    /// let response: Response = chan.receive_proto().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_proto<M: prost::Message + Default>(&mut self) -> Result<M>
+    where
+        R: ReadFormat,
+    {
+        let bytes = self.receive_raw().await?;
+        M::decode(bytes.as_slice()).map_err(err!(@invalid_data))
+    }
+    /// Receive an object along with the length of its payload on the stream,
+    /// by `deadline` if there is one, keeping the progress of the frame in `state`
+    pub(crate) async fn receive_counted<T: DeserializeOwned>(
+        &mut self,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<(T, usize)>
+    where
+        R: ReadFormat,
+    {
+        self.channel
+            .receive_counted(&mut self.format, state, deadline)
+            .await
+    }
+    /// Receive the bytes of the next message along with the length of its
+    /// payload on the stream, keeping the progress of the frame in `state`.
+    /// Messages over `max` bytes or over the limit of the format are rejected
+    /// before they are read
+    pub(crate) async fn receive_raw_counted(
+        &mut self,
+        state: &mut RxState,
+        max: Option<usize>,
+    ) -> Result<(Vec<u8>, usize)>
+    where
+        R: ReadFormat,
+    {
+        let mut captured = Captured::new(&self.format, max);
+        let ((), len) = self
+            .channel
+            .receive_counted(&mut captured, state, None)
+            .await?;
+        Ok((captured.0, len))
+    }
+    /// Receive the payload of the next message into `state` without
+    /// deserializing it, decrypting it into `plain` if the channel is encrypted.
+    /// Returns the payload along with its length on the stream
+    pub(crate) async fn receive_payload<'a>(
+        &mut self,
+        state: &'a mut RxState,
+        plain: &'a mut Vec<u8>,
+    ) -> Result<(&'a [u8], usize)>
+    where
+        R: ReadFormat,
+    {
+        self.channel
+            .receive_payload(&mut self.format, state, plain, None)
+            .await
+    }
+    /// Returns `true` if the unformatted receive channel is [`Encrypted`].
+    ///
+    /// [`Encrypted`]: UnformattedReceiveChannel::Encrypted
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        self.channel.is_encrypted()
+    }
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        self.channel.transport()
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        self.channel.peer_cred()
+    }
     /// Join `Self` and a `SendChannel` into a bidirectional channel
     pub fn join<W>(self, send: SendChannel<W>) -> Channel<R, W> {
         Channel::join(send, self)
@@ -136,15 +247,12 @@ impl<'a> RefUnformattedReceiveChannel<'a> {
 impl UnformattedReceiveChannel {
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` and `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         let mut state = Ok(());
         take_mut::take(self, |this| match this {
-            Self::Raw(chan) => Self::Encrypted(chan, transport, 0),
+            Self::Raw(chan) => Self::Encrypted(chan, transport, Nonce::default()),
             Self::Encrypted(..) => {
                 state = Err(transport);
                 this
@@ -161,6 +269,7 @@ impl UnformattedReceiveChannel {
         ReceiveChannel {
             channel: self,
             format,
+            permit: Permit::default(),
         }
     }
     /// Receive an object sent through the channel with format
@@ -171,15 +280,71 @@ impl UnformattedReceiveChannel {
         &mut self,
         format: &mut F,
     ) -> Result<T> {
+        let (obj, _) = self
+            .receive_counted(format, &mut RxState::default(), None)
+            .await?;
+        Ok(obj)
+    }
+    /// Receive an object along with the length of its payload on the stream,
+    /// by `deadline` if there is one, keeping the progress of the frame in `state`
+    pub(crate) async fn receive_counted<T: DeserializeOwned, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<(T, usize)> {
         match self {
-            Self::Raw(chan) => chan.receive(format).await,
+            Self::Raw(chan) => {
+                let mut counted = Counted { format, len: 0 };
+                let obj = chan.receive_into(&mut counted, state, deadline).await?;
+                Ok((obj, counted.len))
+            }
             Self::Encrypted(chan, snow, nonce) => {
                 let ref mut snow = RefDividedSnow {
                     transport: snow,
                     nonce,
                 };
                 let mut with = WithCipher { snow, format };
-                chan.receive(&mut with).await
+                let mut counted = Counted {
+                    format: &mut with,
+                    len: 0,
+                };
+                let obj = chan.receive_into(&mut counted, state, deadline).await?;
+                Ok((obj, counted.len))
+            }
+        }
+    }
+    /// Receive the payload of the next message into `state` without
+    /// deserializing it, by `deadline` if there is one. Encrypted payloads are
+    /// decrypted into `plain`. Returns the payload along with its length on the stream
+    pub(crate) async fn receive_payload<'a, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+        state: &'a mut RxState,
+        plain: &'a mut Vec<u8>,
+        deadline: Option<Instant>,
+    ) -> Result<(&'a [u8], usize)> {
+        match self {
+            Self::Raw(chan) => {
+                chan.receive_payload(format, state, deadline).await?;
+                let payload = state.payload();
+                Ok((payload, payload.len()))
+            }
+            Self::Encrypted(chan, snow, nonce) => {
+                let snow = &mut RefDividedSnow {
+                    transport: snow,
+                    nonce,
+                };
+                let with = WithCipher { snow, format };
+                loop {
+                    chan.receive_payload(&with, state, deadline).await?;
+                    match with.snow.decrypt_flagged(state.payload(), plain) {
+                        // encrypted control messages are skipped like heartbeats
+                        Err(e) if is_control(&e) => continue,
+                        decrypted => decrypted?,
+                    }
+                    return Ok((&plain[1..], state.payload().len()));
+                }
             }
         }
     }
@@ -191,4 +356,19 @@ impl UnformattedReceiveChannel {
     pub fn is_encrypted(&self) -> bool {
         matches!(self, Self::Encrypted(..))
     }
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        match self {
+            Self::Raw(chan) => chan.transport(),
+            Self::Encrypted(chan, ..) => chan.transport(),
+        }
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        match self {
+            Self::Raw(chan) => chan.peer_cred(),
+            Self::Encrypted(chan, ..) => chan.peer_cred(),
+        }
+    }
 }
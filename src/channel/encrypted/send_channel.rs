@@ -1,16 +1,19 @@
-use std::sync::Arc;
-
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
 use derive_more::From;
 use serde::Serialize;
-use snow::StatelessTransportState;
 
 use crate::{
-    async_snow::RefDividedSnow,
+    async_snow::{Nonce, RefDividedSnow, SharedTransport},
     channel::{
-        channels::ReceiveChannel,
+        channels::{Permit, ReceiveChannel, Transport},
         raw::bipartite::send_channel::{RefUnformattedRawSendChannel, UnformattedRawSendChannel},
     },
-    serialization::formats::{Format, SendFormat},
+    err,
+    serialization::{
+        formats::{Format, Preformatted, SendFormat},
+        Framing,
+    },
     Channel, Result,
 };
 
@@ -24,8 +27,8 @@ pub enum RefUnformattedSendChannel<'a> {
     /// Encrypted channel
     Encrypted(
         RefUnformattedRawSendChannel<'a>,
-        &'a Arc<StatelessTransportState>,
-        &'a mut u32,
+        &'a SharedTransport,
+        &'a mut Nonce,
     ),
 }
 
@@ -35,7 +38,7 @@ pub enum UnformattedSendChannel {
     /// Unencrypted channel
     Raw(UnformattedRawSendChannel),
     /// Encrypted channel
-    Encrypted(UnformattedRawSendChannel, Arc<StatelessTransportState>, u32),
+    Encrypted(UnformattedRawSendChannel, SharedTransport, Nonce),
 }
 
 /// Reference send channel with format
@@ -65,6 +68,8 @@ pub struct SendChannel<W = Format> {
     pub channel: UnformattedSendChannel,
     /// Inner format used to serialize objects
     pub format: W,
+    /// Permit of the provider that accepted the channel, shared with the receive half
+    pub(crate) permit: Permit,
 }
 
 impl<W> SendChannel<W> {
@@ -75,6 +80,15 @@ impl<W> SendChannel<W> {
     pub fn is_encrypted(&self) -> bool {
         matches!(self.channel, UnformattedSendChannel::Encrypted(..))
     }
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        self.channel.transport()
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        self.channel.peer_cred()
+    }
     #[must_use]
     /// Join `Self` and a `SendChannel` into a bidirectional channel
     pub fn join<R>(self, receive: ReceiveChannel<R>) -> Channel<R, W> {
@@ -82,12 +96,9 @@ impl<W> SendChannel<W> {
     }
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` and `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         self.channel.encrypt(transport)
     }
     /// Send an object through the channel
@@ -100,6 +111,81 @@ impl<W> SendChannel<W> {
     {
         self.channel.send(obj, &mut self.format).await
     }
+    /// Send bytes through the channel without serializing them.
+    /// The bytes are framed and encrypted like any other message,
+    /// so the peer can receive them with `receive` or `receive_raw`.
+    /// ```no_run
+    /// # use canary::channel::channels::SendChannel;
+    /// # use canary::Channel;
+    /// # async fn run(mut chan: Channel, mut upstream: SendChannel) -> canary::Result<()> {
+    /// let bytes = chan.receive_raw().await?;
+    /// upstream.send_raw(&bytes).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        let mut preformatted = Preformatted::new(bytes, &self.format);
+        self.channel.send((), &mut preformatted).await
+    }
+    #[cfg(feature = "proto")]
+    /// Send a protobuf message through the channel. It's framed and
+    /// encrypted like any other message, without going through the format.
+    /// ```no_run
+    /// # use canary::channel::channels::SendChannel;
+    /// # async fn run(mut chan: SendChannel, request: String) -> canary::Result<()> {
+    /// chan.send_proto(&request).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_proto<M: prost::Message>(&mut self, msg: &M) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        self.send_raw(&msg.encode_to_vec()).await
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Send a heartbeat frame through the channel, which the peer skips when receiving.
+    /// Heartbeats of encrypted channels are encrypted like messages, and
+    /// peers reject plaintext heartbeats on them.
+    pub async fn heartbeat(&mut self) -> Result<()>
+    where
+        W: SendFormat,
+    {
+        self.channel.heartbeat(self.format.framing()).await
+    }
+    /// Replace the key used to send messages, which the peer replaces too
+    /// once it receives the message telling it to, before any later message.
+    /// Keys are also replaced every `SnowConfig::rekey_interval` packets.
+    /// Fails with an `Unsupported` error if the channel isn't encrypted.
+    /// ```no_run
+    /// # use canary::channel::channels::SendChannel;
+    /// # async fn run(mut chan: SendChannel) -> canary::Result<()> {
+    /// chan.rekey_now().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn rekey_now(&mut self) -> Result<()>
+    where
+        W: SendFormat,
+    {
+        self.channel.rekey(&self.format).await
+    }
+    /// Close the send half of the stream. The peer gets an end of stream
+    /// error once it has received every message sent before closing.
+    /// ```no_run
+    /// # use canary::channel::channels::SendChannel;
+    /// # async fn run(mut chan: SendChannel) -> canary::Result<()> {
+    /// chan.send("bye!").await?;
+    /// chan.close().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn close(&mut self) -> Result<()> {
+        self.channel.close().await
+    }
 }
 
 impl<'a> RefUnformattedSendChannel<'a> {
@@ -137,15 +223,12 @@ impl<'a> RefUnformattedSendChannel<'a> {
 impl UnformattedSendChannel {
     /// Try to encrypt channel using the provided transport.
     /// Will return an error if channel is already encrypted.
-    /// To turn `Arc<StatelessTransportState>` into the inner transport state
-    /// use `Arc::try_unwrap(transport)`.
-    pub fn encrypt(
-        &mut self,
-        transport: Arc<StatelessTransportState>,
-    ) -> Result<(), Arc<StatelessTransportState>> {
+    /// To turn `SharedTransport` into the inner transport state
+    /// use `Arc::try_unwrap(transport)` and `RwLock::into_inner`.
+    pub fn encrypt(&mut self, transport: SharedTransport) -> Result<(), SharedTransport> {
         let mut state = Ok(());
         take_mut::take(self, |this| match this {
-            Self::Raw(chan) => Self::Encrypted(chan, transport, 0),
+            Self::Raw(chan) => Self::Encrypted(chan, transport, Nonce::default()),
             Self::Encrypted(..) => {
                 state = Err(transport);
                 this
@@ -162,6 +245,7 @@ impl UnformattedSendChannel {
         SendChannel {
             channel: self,
             format,
+            permit: Permit::default(),
         }
     }
     /// Send an object through the channel serialized with format
@@ -186,6 +270,52 @@ impl UnformattedSendChannel {
         }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Send a heartbeat frame through the channel, which the peer skips when receiving.
+    /// Encrypted channels send an encrypted message without payload instead,
+    /// so heartbeats can't be forged.
+    pub async fn heartbeat(&mut self, framing: Framing) -> Result<()> {
+        match self {
+            Self::Raw(chan) => chan.heartbeat(framing).await,
+            Self::Encrypted(chan, transport, nonce) => {
+                let bytes = RefDividedSnow::new(transport, nonce).encrypt_control(false)?;
+                chan.send((), &mut Preformatted(&bytes, framing, None))
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Send a message telling the peer to replace its receive key,
+    /// replacing the send key once it's encrypted
+    pub async fn rekey<F: SendFormat>(&mut self, format: &F) -> Result<()> {
+        match self {
+            Self::Raw(_) => err!((unsupported, "channel isn't encrypted")),
+            Self::Encrypted(chan, transport, nonce) => {
+                let bytes = RefDividedSnow::new(transport, nonce).encrypt_control(true)?;
+                chan.send((), &mut Preformatted::new(&bytes, format))
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Close the send half of the stream. The peer gets an end of stream
+    /// error once it has received every message sent before closing.
+    pub async fn close(&mut self) -> Result<()> {
+        match self {
+            Self::Raw(chan) => chan.close().await,
+            Self::Encrypted(chan, ..) => chan.close().await,
+        }
+    }
+
+    /// Set the number of packets sent with a key before it's replaced
+    pub(crate) fn set_rekey_interval(&mut self, packets: u32) {
+        if let Self::Encrypted(_, _, nonce) = self {
+            nonce.rekey_interval = packets;
+        }
+    }
+
     /// Returns `true` if the unformatted send channel is [`Encrypted`].
     ///
     /// [`Encrypted`]: UnformattedSendChannel::Encrypted
@@ -193,4 +323,19 @@ impl UnformattedSendChannel {
     pub fn is_encrypted(&self) -> bool {
         matches!(self, Self::Encrypted(..))
     }
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        match self {
+            Self::Raw(chan) => chan.transport(),
+            Self::Encrypted(chan, ..) => chan.transport(),
+        }
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        match self {
+            Self::Raw(chan) => chan.peer_cred(),
+            Self::Encrypted(chan, ..) => chan.peer_cred(),
+        }
+    }
 }
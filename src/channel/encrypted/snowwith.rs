@@ -1,14 +1,42 @@
 use crate::{
-    async_snow::{Decrypt, Encrypt},
+    async_snow::{encrypted_len, Decrypt, Encrypt},
     Result,
 };
 use derive_more::From;
 use serde::{de::DeserializeOwned, Serialize};
 
-use crate::serialization::formats::{ReadFormat, SendFormat};
+use crate::serialization::{
+    formats::{ReadFormat, SendFormat},
+    Framing,
+};
 
 #[derive(From)]
-/// helper struct that facilitates encryption
+/// format adapter that encrypts the messages serialized by the inner format,
+/// and decrypts messages before the inner format deserializes them.
+/// Messages have the same layout as the ones of encrypted channels, so they
+/// can be carried by other transports, such as message queues.
+/// Each direction needs its own nonce, which both peers have to keep in step.
+/// ```no_run
+/// # use std::sync::RwLock;
+/// # use canary::async_snow::{self, Nonce, RefDividedSnow};
+/// # use canary::channel::encrypted::snowwith::WithCipher;
+/// # use canary::serialization::formats::{Format, SendFormat};
+/// # use canary::Channel;
+/// # struct Queue;
+/// # impl Queue {
+/// #     async fn publish(&self, _: Vec<u8>) -> canary::Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # async fn run(mut chan: Channel, queue: Queue) -> canary::Result<()> {
+/// let transport = RwLock::new(async_snow::new_initiator(&mut chan).await?);
+/// let (mut send_nonce, mut format) = (Nonce::default(), Format::Bincode);
+/// let mut snow = RefDividedSnow::new(&transport, &mut send_nonce);
+/// let bytes = WithCipher::new(&mut snow, &mut format).serialize(&"hello!")?;
+/// queue.publish(bytes).await?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct WithCipher<'a, C, F> {
     /// cipher
     pub snow: &'a mut C,
@@ -16,11 +44,24 @@ pub struct WithCipher<'a, C, F> {
     pub format: &'a mut F,
 }
 
+impl<'a, C, F> WithCipher<'a, C, F> {
+    /// encrypt the messages of `format` with `snow`
+    pub fn new(snow: &'a mut C, format: &'a mut F) -> Self {
+        WithCipher { snow, format }
+    }
+}
+
 impl<C: Encrypt, F: SendFormat> SendFormat for WithCipher<'_, C, F> {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> Result<Vec<u8>> {
         let obj = self.format.serialize(obj)?;
         self.snow.encrypt_packets(obj)
     }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn fragment_len(&self) -> Option<usize> {
+        self.format.fragment_len()
+    }
 }
 
 impl<C: Decrypt, F: ReadFormat> ReadFormat for WithCipher<'_, C, F> {
@@ -31,4 +72,13 @@ impl<C: Decrypt, F: ReadFormat> ReadFormat for WithCipher<'_, C, F> {
         let bytes = self.snow.decrypt(bytes)?;
         self.format.deserialize(&bytes)
     }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn max_len(&self) -> Option<usize> {
+        self.format.max_len().map(encrypted_len)
+    }
+    fn is_encrypted(&self) -> bool {
+        true
+    }
 }
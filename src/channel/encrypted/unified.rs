@@ -1,15 +1,22 @@
-use std::sync::Arc;
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use serde::{de::DeserializeOwned, Serialize};
 use snow::StatelessTransportState;
 
 use crate::{
-    async_snow::RefDividedSnow,
+    async_snow::{Nonce, RefDividedSnow, SecurityContext},
     channel::{
-        channels::{ReceiveChannel, SendChannel},
+        channels::{ChannelStats, Permit, ReceiveBuffer, ReceiveChannel, SendChannel, Transport},
         raw::unified::unformatted::UnformattedRawUnifiedChannel,
     },
-    serialization::formats::{Format, ReadFormat, SendFormat},
+    err,
+    serialization::{
+        formats::{Captured, Counted, Format, Preformatted, ReadFormat, SendFormat},
+        is_control, RxState,
+    },
     Result,
 };
 
@@ -28,11 +35,11 @@ pub enum UnformattedUnifiedChannel {
         /// Inner channel
         chan: UnformattedRawUnifiedChannel,
         /// Inner transport state
-        transport: StatelessTransportState,
+        transport: RwLock<StatelessTransportState>,
         /// Inner send nonce
-        send_nonce: u32,
+        send_nonce: Nonce,
         /// Inner receive nonce
-        receive_nonce: u32,
+        receive_nonce: Nonce,
     },
 }
 
@@ -44,6 +51,16 @@ pub struct UnifiedChannel<R = Format, W = Format> {
     pub receive_format: R,
     /// Inner send format
     pub send_format: W,
+    /// Message and byte counters
+    pub(crate) stats: ChannelStats,
+    /// Values bound to the encryption handshake, if known
+    pub(crate) security: Option<SecurityContext>,
+    /// Certificates the peer presented in the tls handshake of the transport, in der
+    pub(crate) peer_certificates: Option<Arc<[Vec<u8>]>>,
+    /// Buffer reused to read received payloads
+    pub(crate) buffer: ReceiveBuffer,
+    /// Permit of the provider that accepted the channel
+    pub(crate) permit: Permit,
 }
 
 impl<R, W> UnifiedChannel<R, W> {
@@ -55,6 +72,20 @@ impl<R, W> UnifiedChannel<R, W> {
     ) -> Result<(), StatelessTransportState> {
         self.channel.encrypt(transport)
     }
+    /// Returns `true` if the channel is encrypted
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        self.channel.is_encrypted()
+    }
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        self.channel.transport()
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        self.channel.peer_cred()
+    }
     /// Send an object through the channel
     /// ```no_run
     /// chan.send("Hello world!").await?;
@@ -63,7 +94,9 @@ impl<R, W> UnifiedChannel<R, W> {
     where
         W: SendFormat,
     {
-        self.channel.send(obj, &mut self.send_format).await
+        let len = self.channel.send(obj, &mut self.send_format).await?;
+        self.stats.record_send(len);
+        Ok(len)
     }
     /// Receive an object sent through the channel
     /// ```no_run
@@ -73,14 +106,104 @@ impl<R, W> UnifiedChannel<R, W> {
     where
         R: ReadFormat,
     {
-        self.channel.receive(&mut self.receive_format).await
+        self.receive_until(None).await
+    }
+    /// Receive an object by `deadline` if there is one
+    pub(crate) async fn receive_until<T: DeserializeOwned>(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Result<T>
+    where
+        R: ReadFormat,
+    {
+        let received = self
+            .channel
+            .receive_counted(&mut self.receive_format, self.buffer.get(), deadline)
+            .await;
+        self.buffer.trim();
+        let (obj, len) = received?;
+        self.stats.record_receive(len);
+        Ok(obj)
+    }
+    /// Send bytes through the channel without serializing them
+    /// ```no_run
+    /// # use canary::channel::encrypted::unified::UnifiedChannel;
+    /// # async fn run(mut chan: UnifiedChannel, bytes: Vec<u8>) -> canary::Result<()> {
+    /// chan.send_raw(&bytes).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<usize>
+    where
+        W: SendFormat,
+    {
+        let mut preformatted = Preformatted::new(bytes, &self.send_format);
+        let len = self.channel.send((), &mut preformatted).await?;
+        self.stats.record_send(len);
+        Ok(len)
+    }
+    /// Receive the bytes of the next message without deserializing them
+    /// ```no_run
+    /// # use canary::channel::encrypted::unified::UnifiedChannel;
+    /// # async fn run(mut chan: UnifiedChannel) -> canary::Result<()> {
+    /// let bytes = chan.receive_raw().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn receive_raw(&mut self) -> Result<Vec<u8>>
+    where
+        R: ReadFormat,
+    {
+        self.receive_raw_limited(None).await
+    }
+    /// Receive the bytes of the next message, rejecting messages over `max`
+    /// bytes or over the limit of the format before reading them
+    pub(crate) async fn receive_raw_limited(&mut self, max: Option<usize>) -> Result<Vec<u8>>
+    where
+        R: ReadFormat,
+    {
+        let mut captured = Captured::new(&self.receive_format, max);
+        let received = self
+            .channel
+            .receive_counted(&mut captured, self.buffer.get(), None)
+            .await;
+        self.buffer.trim();
+        let ((), len) = received?;
+        self.stats.record_receive(len);
+        Ok(captured.0)
+    }
+    /// Receive the payload of the next message into the receive buffer of
+    /// the channel without deserializing it, decrypting it if the channel is
+    /// encrypted. The receive format is returned along with it to read it with
+    pub(crate) async fn receive_payload(&mut self) -> Result<(&mut R, &[u8])>
+    where
+        R: ReadFormat,
+    {
+        // the last payload can't be borrowed anymore, so its buffer can go
+        self.buffer.trim();
+        let (state, plain) = self.buffer.parts();
+        let (payload, len) = self
+            .channel
+            .receive_payload(&mut self.receive_format, state, plain, None)
+            .await?;
+        self.stats.record_receive(len);
+        Ok((&mut self.receive_format, payload))
+    }
+    /// Replace the key used to send messages, which the peer replaces too
+    pub async fn rekey_now(&mut self) -> Result<()>
+    where
+        W: SendFormat,
+    {
+        self.channel.rekey(&self.send_format).await
     }
     #[must_use]
     /// Split channel into its send and receive components
     pub fn split(self) -> (SendChannel<W>, ReceiveChannel<R>) {
         let (send, receive) = self.channel.split();
-        let send = send.to_formatted(self.send_format);
-        let receive = receive.to_formatted(self.receive_format);
+        let mut send = send.to_formatted(self.send_format);
+        let mut receive = receive.to_formatted(self.receive_format);
+        send.permit = self.permit.clone();
+        receive.permit = self.permit;
         (send, receive)
     }
 }
@@ -96,9 +219,9 @@ impl UnformattedUnifiedChannel {
         take_mut::take(self, |this| match this {
             UnformattedUnifiedChannel::Raw(chan) => UnformattedUnifiedChannel::Encrypted {
                 chan,
-                transport,
-                send_nonce: 0,
-                receive_nonce: 0,
+                transport: RwLock::new(transport),
+                send_nonce: Nonce::default(),
+                receive_nonce: Nonce::default(),
             },
             UnformattedUnifiedChannel::Encrypted { .. } => {
                 state = Err(transport);
@@ -107,6 +230,28 @@ impl UnformattedUnifiedChannel {
         });
         state
     }
+    /// Returns `true` if the unformatted unified channel is [`Encrypted`].
+    ///
+    /// [`Encrypted`]: UnformattedUnifiedChannel::Encrypted
+    #[must_use]
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, Self::Encrypted { .. })
+    }
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        match self {
+            Self::Raw(chan) => chan.transport(),
+            Self::Encrypted { chan, .. } => chan.transport(),
+        }
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        match self {
+            Self::Raw(chan) => chan.peer_cred(),
+            Self::Encrypted { chan, .. } => chan.peer_cred(),
+        }
+    }
     /// Send an object through the channel serialized with format
     /// ```no_run
     /// chan.send("Hello world!", &mut Format::Bincode).await?;
@@ -141,8 +286,25 @@ impl UnformattedUnifiedChannel {
         &mut self,
         format: &mut F,
     ) -> Result<T> {
+        let (obj, _) = self
+            .receive_counted(format, &mut RxState::default(), None)
+            .await?;
+        Ok(obj)
+    }
+    /// Receive an object along with the length of its payload on the stream,
+    /// by `deadline` if there is one, keeping the progress of the frame in `state`
+    pub(crate) async fn receive_counted<T: DeserializeOwned, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<(T, usize)> {
         match self {
-            Self::Raw(chan) => chan.receive(format).await,
+            Self::Raw(chan) => {
+                let mut counted = Counted { format, len: 0 };
+                let obj = chan.receive_into(&mut counted, state, deadline).await?;
+                Ok((obj, counted.len))
+            }
             Self::Encrypted {
                 chan,
                 transport,
@@ -154,10 +316,78 @@ impl UnformattedUnifiedChannel {
                     nonce: receive_nonce,
                 };
                 let mut with = WithCipher { snow, format };
-                chan.receive(&mut with).await
+                let mut counted = Counted {
+                    format: &mut with,
+                    len: 0,
+                };
+                let obj = chan.receive_into(&mut counted, state, deadline).await?;
+                Ok((obj, counted.len))
             }
         }
     }
+    /// Receive the payload of the next message into `state` without
+    /// deserializing it, by `deadline` if there is one. Encrypted payloads are
+    /// decrypted into `plain`. Returns the payload along with its length on the stream
+    pub(crate) async fn receive_payload<'a, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+        state: &'a mut RxState,
+        plain: &'a mut Vec<u8>,
+        deadline: Option<Instant>,
+    ) -> Result<(&'a [u8], usize)> {
+        match self {
+            Self::Raw(chan) => {
+                chan.receive_payload(format, state, deadline).await?;
+                let payload = state.payload();
+                Ok((payload, payload.len()))
+            }
+            Self::Encrypted {
+                chan,
+                transport,
+                receive_nonce,
+                ..
+            } => {
+                let snow = &mut RefDividedSnow {
+                    transport,
+                    nonce: receive_nonce,
+                };
+                let with = WithCipher { snow, format };
+                loop {
+                    chan.receive_payload(&with, state, deadline).await?;
+                    match with.snow.decrypt_flagged(state.payload(), plain) {
+                        // encrypted control messages are skipped like heartbeats
+                        Err(e) if is_control(&e) => continue,
+                        decrypted => decrypted?,
+                    }
+                    return Ok((&plain[1..], state.payload().len()));
+                }
+            }
+        }
+    }
+    /// Send a message telling the peer to replace its receive key,
+    /// replacing the send key once it's encrypted
+    pub async fn rekey<F: SendFormat>(&mut self, format: &F) -> Result<()> {
+        match self {
+            Self::Raw(_) => err!((unsupported, "channel isn't encrypted")),
+            Self::Encrypted {
+                chan,
+                transport,
+                send_nonce,
+                ..
+            } => {
+                let bytes = RefDividedSnow::new(transport, send_nonce).encrypt_control(true)?;
+                chan.send((), &mut Preformatted::new(&bytes, format))
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+    /// Set the number of packets sent with a key before it's replaced
+    pub(crate) fn set_rekey_interval(&mut self, packets: u32) {
+        if let Self::Encrypted { send_nonce, .. } = self {
+            send_nonce.rekey_interval = packets;
+        }
+    }
     #[must_use]
     /// Split channel into its send and receive components
     pub fn split(self) -> (UnformattedSendChannel, UnformattedReceiveChannel) {
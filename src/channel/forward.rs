@@ -0,0 +1,149 @@
+use std::io::ErrorKind;
+
+#[cfg(feature = "transcode")]
+use crate::serialization::{
+    formats::{ReadFormat, SendFormat},
+    transcode,
+};
+use crate::{
+    channel::channels::{Channel, ReceiveChannel, SendChannel},
+    Error, Result,
+};
+
+/// Forward messages between two channels until both sides close, without
+/// deserializing them. The channels may use different transports and
+/// encryption. When one side closes, the send half of the other side is
+/// closed too. Returns the number of payload bytes forwarded from `a` to `b`
+/// and from `b` to `a`.
+/// ```no_run
+/// # async fn run() -> canary::Result<()> {
+/// # let listener = canary::providers::Tcp::bind("127.0.0.1:8081").await?;
+/// let client = listener.next().await?.encrypted().await?;
+/// let upstream = canary::connect("tcp@127.0.0.1:8080").await?;
+/// let (up, down) = canary::channel::forward(client, upstream).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn forward(a: Channel, b: Channel) -> Result<(u64, u64)> {
+    let (mut a_send, mut a_receive) = a.split();
+    let (mut b_send, mut b_receive) = b.split();
+    let a_to_b = copy(&mut a_receive, &mut b_send);
+    let b_to_a = copy(&mut b_receive, &mut a_send);
+    futures::try_join!(a_to_b, b_to_a)
+}
+
+#[cfg(feature = "transcode")]
+/// Forward messages from `from` to `to` until `from` closes, re-encoding
+/// them from the receive format of `from` into the send format of `to`
+/// without knowing their type, see `transcode`. The send half of `to` is
+/// closed once `from` closes. Returns the number of bytes sent to `to`.
+/// ```no_run
+/// # use canary::serialization::formats::{Cbor, Json};
+/// # async fn run() -> canary::Result<()> {
+/// # let listener = canary::providers::Tcp::bind("127.0.0.1:8081").await?;
+/// let legacy = listener.next().await?.encrypted().await?.with_formats(Json, Json);
+/// let upstream = canary::connect("tcp@127.0.0.1:8080").await?;
+/// canary::channel::relay_transcoding(legacy, upstream.with_formats(Cbor, Cbor)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn relay_transcoding<R: ReadFormat, W: SendFormat, R2, W2>(
+    from: Channel<R, W2>,
+    to: Channel<R2, W>,
+) -> Result<u64> {
+    // the unused halves are kept so the peers don't see them closed
+    let (_from_send, mut receive) = from.split();
+    let (mut send, _to_receive) = to.split();
+    let mut total = 0;
+    loop {
+        match receive.receive_raw().await {
+            Ok(bytes) => {
+                let bytes = transcode(&mut receive.format, &mut send.format, &bytes)?;
+                total += send.send_raw(&bytes).await? as u64;
+            }
+            Err(e) if is_closed(&e) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    send.close().await?;
+    Ok(total)
+}
+
+// copy messages until the receive side closes, then close the send side
+async fn copy(receive: &mut ReceiveChannel, send: &mut SendChannel) -> Result<u64> {
+    let mut total = 0;
+    loop {
+        match receive.receive_raw().await {
+            Ok(bytes) => {
+                send.send_raw(&bytes).await?;
+                total += bytes.len() as u64;
+            }
+            Err(e) if is_closed(&e) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    send.close().await?;
+    Ok(total)
+}
+
+// whether the error means the peer closed the channel
+pub(crate) fn is_closed(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::UnexpectedEof
+            | ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+    )
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::providers::{Tcp, WebSocket};
+    use crate::serialization::formats::{Format, SendFormat};
+
+    #[tokio::test]
+    async fn websockets_are_forwarded_to_tcp() {
+        // echoes messages until the proxy closes the channel
+        let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+        let tcp_addr = tcp.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut chan = tcp.next().await?.encrypted().await?;
+            loop {
+                match chan.receive_raw().await {
+                    Ok(bytes) => chan.send_raw(&bytes).await?,
+                    Err(e) if is_closed(&e) => return Ok(()),
+                    Err(e) => return Err(e),
+                };
+            }
+        });
+        let ws = WebSocket::bind("127.0.0.1:0").await.unwrap();
+        let ws_addr = ws.local_addr().unwrap();
+        let proxy = tokio::spawn(async move {
+            let client = ws.next().await?.encrypted().await?;
+            let upstream = crate::connect(&format!("tcp@{}", tcp_addr)).await?;
+            forward(client, upstream).await
+        });
+
+        let client = WebSocket::connect(ws_addr)
+            .await
+            .unwrap()
+            .encrypted()
+            .await
+            .unwrap();
+        let (mut send, mut receive) = client.split();
+        let messages = ["hello!".to_string(), "a".repeat(100_000)];
+        let mut len = 0;
+        for message in &messages {
+            send.send(message).await.unwrap();
+            assert_eq!(&receive.receive::<String>().await.unwrap(), message);
+            len += Format::Bincode.serialize(message).unwrap().len() as u64;
+        }
+        // closing the client closes the upstream channel, and then the client
+        send.close().await.unwrap();
+        let e = receive.receive::<String>().await.unwrap_err();
+        assert!(is_closed(&e), "{}", e);
+        assert_eq!(proxy.await.unwrap().unwrap(), (len, len));
+    }
+}
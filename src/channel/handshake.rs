@@ -1,25 +1,273 @@
-use derive_more::From;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::{err, Channel, Result};
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
+use crate::channel::channels::Permit;
+use crate::{
+    async_snow::SnowConfig, err, providers::Addr, serialization::formats::Format, Channel, Result,
+};
 
-#[derive(From)]
-#[repr(transparent)]
-/// Helper struct that represents a channel that may become encrypted
-pub struct Handshake(Channel);
+// starts format offers, so a peer that sends anything else is
+// known not to negotiate
+const FORMATS_PREAMBLE: &[u8] = b"\0canary/formats/1\0";
+
+/// Helper struct that represents a channel that may become encrypted.
+/// Channels from providers know whether they connected or accepted, so the
+/// connecting side initiates the encryption handshake. Channels created with
+/// `From` don't, and have the peers pick the roles with an extra round trip,
+/// so both peers must create their `Handshake` the same way.
+pub struct Handshake {
+    chan: Channel,
+    // whether this side initiates the handshake, `None` if it's unknown
+    initiator: Option<bool>,
+    // whether the transport already encrypts the channel
+    secure: bool,
+    // address of the peer, if the provider knows it
+    peer_addr: Option<Addr>,
+    // pending handshake permit of the provider, released once the channel is encrypted
+    _pending: Permit,
+}
+
+impl From<Channel> for Handshake {
+    fn from(chan: Channel) -> Self {
+        Handshake {
+            chan,
+            initiator: None,
+            secure: false,
+            peer_addr: None,
+            _pending: Permit::default(),
+        }
+    }
+}
 
 impl Handshake {
+    /// Handshake of a channel opened by connecting to the peer,
+    /// the peer must use `Handshake::acceptor`
+    pub fn connector(chan: Channel) -> Self {
+        Handshake {
+            chan,
+            initiator: Some(true),
+            secure: false,
+            peer_addr: None,
+            _pending: Permit::default(),
+        }
+    }
+
+    /// Handshake of a channel accepted from the peer,
+    /// the peer must use `Handshake::connector`
+    pub fn acceptor(chan: Channel) -> Self {
+        Handshake {
+            chan,
+            initiator: Some(false),
+            secure: false,
+            peer_addr: None,
+            _pending: Permit::default(),
+        }
+    }
+
+    #[must_use]
+    /// Attach the address of the peer, as providers do for the channels they accept
+    pub fn with_peer_addr(mut self, addr: Addr) -> Self {
+        self.peer_addr = Some(addr);
+        self
+    }
+
+    /// Get the address of the peer, such as the socket address of a tcp client,
+    /// to log it or check it against an allowlist before running the handshake.
+    /// `None` if the provider doesn't know it, such as for unnamed unix sockets.
+    /// The address has the scheme of the provider, without a handshake suffix.
+    /// ```no_run
+    /// # use canary::providers::Tcp;
+    /// # async fn run(tcp: Tcp) -> canary::Result<()> {
+    /// let hs = tcp.next().await?;
+    /// tracing::info!("connection from {:?}", hs.peer_addr());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peer_addr(&self) -> Option<&Addr> {
+        self.peer_addr.as_ref()
+    }
+
+    /// Attach the permits a provider with limits took for the connection:
+    /// the channel keeps the connection permit, and the handshake the pending one
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn with_permits(mut self, connection: Permit, pending: Permit) -> Self {
+        self.chan.set_permit(connection);
+        self._pending = pending;
+        self
+    }
+
+    /// Attach the certificates the peer presented in the tls handshake of the transport
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn with_peer_certificates(mut self, certs: Option<Arc<[Vec<u8>]>>) -> Self {
+        self.chan.set_peer_certificates(certs);
+        self
+    }
+
+    /// Mark the channel as encrypted by its transport, so the handshake is
+    /// skipped unless the configuration authenticates the peer or forces it
+    #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+    pub(crate) fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
     /// Get an encrypted channel
     pub async fn encrypted(self) -> Result<Channel> {
-        let mut stream = self.0;
-        let snow = crate::async_snow::new(&mut stream).await?;
+        self.encrypted_with(&SnowConfig::default()).await
+    }
+
+    /// Get an encrypted channel, failing with a `TimedOut` error if the
+    /// handshake doesn't complete in time. The channel is dropped on failure.
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::{channel::handshake::Handshake, Channel};
+    /// # async fn run(chan: Channel) -> canary::Result<()> {
+    /// let chan = Handshake::from(chan).encrypted_timeout(Duration::from_secs(3)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn encrypted_timeout(self, timeout: Duration) -> Result<Channel> {
+        self.encrypted_with(&SnowConfig::default().timeout(timeout))
+            .await
+    }
+
+    #[cfg(feature = "verifier")]
+    /// Get an encrypted channel, accepting the peer only if `verifier` does.
+    /// A rejected peer has its channel closed, and this fails with a
+    /// `PermissionDenied` error.
+    /// ```no_run
+    /// # use std::collections::HashSet;
+    /// # use canary::{async_snow::Verifier, channel::{channels::Transport, handshake::Handshake}, Channel};
+    /// # struct Allowlist(HashSet<Vec<u8>>);
+    /// # #[canary::async_trait]
+    /// # impl Verifier for Allowlist {
+    /// #     async fn verify(&self, _: &[u8], _: Transport) -> canary::Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn run(chan: Channel, keys: HashSet<Vec<u8>>) -> canary::Result<()> {
+    /// let chan = Handshake::from(chan).encrypted_with_verifier(Allowlist(keys)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn encrypted_with_verifier(
+        self,
+        verifier: impl crate::async_snow::Verifier + 'static,
+    ) -> Result<Channel> {
+        self.encrypted_with(&SnowConfig::default().verifier(verifier))
+            .await
+    }
+
+    /// Get an encrypted channel using the provided configuration,
+    /// which allows authenticating the peer with static keys.
+    /// Channels their transport already encrypts, such as quic channels,
+    /// are returned as is unless the configuration authenticates the peer
+    /// or forces the handshake with `SnowConfig::force_encryption`.
+    pub async fn encrypted_with(self, config: &SnowConfig) -> Result<Channel> {
+        if self.secure && !config.force_encryption && !config.authenticates() {
+            return Ok(self.chan);
+        }
+        let mut stream = self.chan;
+        // the key of the peer is only checked by verifiers
+        #[cfg_attr(not(feature = "verifier"), allow(unused_variables))]
+        let (snow, context, peer_key) =
+            crate::async_snow::establish(&mut stream, config, self.initiator).await?;
+        #[cfg(feature = "verifier")]
+        if let Some(verifier) = &config.verifier {
+            // dropping the channel closes the connection
+            if let Err(e) = verifier.verify(&peer_key, stream.transport()).await {
+                return err!((permission_denied, format!("peer rejected: {}", e)));
+            }
+        }
         stream
             .encrypt(snow)
             .map_err(|_| err!("channel already encrypted"))?;
+        stream.set_rekey_interval(config.rekey_interval);
+        stream.set_security_context(context);
         Ok(stream)
     }
 
+    /// Get an encrypted channel using the best format both peers support.
+    /// Both peers must negotiate, otherwise this fails with an `InvalidData`
+    /// error once the peer sends something other than its formats.
+    /// ```no_run
+    /// # use canary::{channel::handshake::Handshake, Channel};
+    /// # async fn run(chan: Channel) -> canary::Result<()> {
+    /// let mut chan = Handshake::connector(chan).negotiate().await?;
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn negotiate(self) -> Result<Channel> {
+        self.negotiate_with(&SnowConfig::default(), Format::supported())
+            .await
+    }
+
+    /// Get an encrypted channel using the provided configuration, with the
+    /// format picked from `formats`, which are in order of preference.
+    /// The preference of the acceptor wins, and if the roles are unknown,
+    /// the one of the peer whose offer sorts first.
+    /// Fails with an `Unsupported` error if the peers have no format in common.
+    pub async fn negotiate_with(self, config: &SnowConfig, formats: &[Format]) -> Result<Channel> {
+        let initiator = self.initiator;
+        let mut chan = self.encrypted_with(config).await?;
+
+        let mut offer = FORMATS_PREAMBLE.to_vec();
+        offer.extend(formats.iter().map(|&format| format as u8));
+        chan.send_raw(&offer).await?;
+        let peer_offer = chan.receive_raw().await?;
+        let peer_formats = match peer_offer.strip_prefix(FORMATS_PREAMBLE) {
+            // formats unknown to this build are ignored
+            Some(ids) => ids
+                .iter()
+                .filter_map(|&id| Format::supported().iter().find(|&&f| f as u8 == id))
+                .copied()
+                .collect::<Vec<_>>(),
+            None => return err!((invalid_data, "peer doesn't negotiate formats")),
+        };
+
+        let ours_win = match initiator {
+            Some(initiator) => !initiator,
+            None => offer <= peer_offer,
+        };
+        let (preferred, other) = match ours_win {
+            true => (formats, peer_formats.as_slice()),
+            false => (peer_formats.as_slice(), formats),
+        };
+        match preferred.iter().find(|format| other.contains(format)) {
+            Some(&format) => Ok(chan.with_formats(format, format)),
+            None => err!((unsupported, "no format in common with the peer")),
+        }
+    }
+
     /// Get the raw, unencrypted channel
     pub fn raw(self) -> Channel {
-        self.0
+        self.chan
+    }
+
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel,
+    /// to authorize it before running the handshake.
+    /// Channels over other transports fail with `Unsupported`.
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        self.chan.peer_cred()
+    }
+
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// Get the certificate chain the peer presented in the tls handshake of
+    /// the transport, to authorize it before running the encryption handshake
+    /// ```no_run
+    /// # use canary::{err, providers::Tls};
+    /// # async fn run(tls: Tls) -> canary::Result<()> {
+    /// let hs = tls.next().await?;
+    /// let certs = hs.peer_certificates().ok_or(err!(permission_denied, "no client certificate"))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peer_certificates(&self) -> Option<Vec<rustls::Certificate>> {
+        self.chan.peer_certificates()
     }
 }
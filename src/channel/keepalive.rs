@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+#[cfg(not(target_arch = "wasm32"))]
+use futures::{pin_mut, select, Future, FutureExt};
+
+use crate::serialization::Framing;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{channel::encrypted::send_channel::UnformattedSendChannel, Result};
+
+#[derive(Debug, Clone, Copy)]
+/// Keepalive state of a channel
+pub(crate) struct Keepalive {
+    /// Idle time after which a heartbeat is sent
+    pub interval: Duration,
+    /// Last time an object was sent or received
+    pub last_activity: Instant,
+    /// Framing of the messages sent, which heartbeats use too since
+    /// it's how the peer reads the frames of this side
+    pub framing: Framing,
+}
+
+impl Keepalive {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(interval: Duration, framing: Framing) -> Self {
+        Keepalive {
+            interval,
+            last_activity: Instant::now(),
+            framing,
+        }
+    }
+
+    #[inline]
+    pub fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// wait for a receive future, sending a heartbeat every time the channel
+    /// stays idle for the interval while waiting
+    pub async fn receive<T>(
+        &mut self,
+        obj: impl Future<Output = Result<T>>,
+        send: &mut UnformattedSendChannel,
+    ) -> Result<T> {
+        // the receive future is kept alive across heartbeats so no frame is lost
+        let obj = obj.fuse();
+        pin_mut!(obj);
+        loop {
+            let idle = self.interval.saturating_sub(self.last_activity.elapsed());
+            let obj = select! {
+                obj = obj => Some(obj),
+                _ = crate::io::sleep(idle).fuse() => None,
+            };
+            match obj {
+                Some(obj) => {
+                    self.touch();
+                    return obj;
+                }
+                None => {
+                    send.heartbeat(self.framing).await?;
+                    self.touch();
+                }
+            }
+        }
+    }
+}
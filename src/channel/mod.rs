@@ -1,8 +1,23 @@
 /// contains utility channels
 pub mod channels;
+mod chunked;
+#[cfg(feature = "postcard_ser")]
+mod cobs;
 /// contains encrypted channels
 pub mod encrypted;
+pub(crate) mod forward;
 /// contains the handshake struct
 pub mod handshake;
+mod keepalive;
+#[cfg(not(target_arch = "wasm32"))]
+mod mux;
 /// contains unencrypted channels
 pub mod raw;
+
+#[cfg(feature = "postcard_ser")]
+pub use cobs::CobsChannel;
+pub use forward::forward;
+#[cfg(feature = "transcode")]
+pub use forward::relay_transcoding;
+#[cfg(not(target_arch = "wasm32"))]
+pub use mux::Mux;
@@ -0,0 +1,496 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::runtime::Handle;
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender, WeakUnboundedSender},
+    Mutex as AsyncMutex, Semaphore,
+};
+
+use crate::{
+    channel::channels::{Channel, ReceiveChannel, SendChannel},
+    err,
+    io::{duplex, runtime, split, DuplexStream, ReadExt, ReadHalf, WriteExt, WriteHalf},
+    Result,
+};
+
+// size of the in-memory buffer between a sub-channel and the multiplexer
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+// bytes a stream can send before the peer has to grant more credit
+const WINDOW: usize = 256 * 1024;
+// largest payload of a data frame
+const MAX_DATA_LEN: usize = 16 * 1024;
+// set on stream ids of streams opened by the peer
+const REMOTE: u32 = 1 << 31;
+// streams the peer can have open at once, further ones are reset
+const MAX_STREAMS: usize = 1024;
+
+// frame kinds, every frame is `[kind][stream id (u32 be)][payload]`
+const OPEN: u8 = 0;
+const DATA: u8 = 1;
+const CREDIT: u8 = 2;
+const CLOSE: u8 = 3;
+// closes both directions of a stream, sent for streams past the limits
+const RESET: u8 = 4;
+
+/// state of a sub-channel shared between the tasks of the multiplexer
+struct Stream {
+    /// sends data received from the peer to the sub-channel,
+    /// `None` once the peer closed the stream
+    inbound: Option<Sender<Vec<u8>>>,
+    /// bytes received from the peer that weren't credited back yet,
+    /// which the peer keeps under `WINDOW`
+    pending: Arc<AtomicUsize>,
+    /// bytes the sub-channel can still send to the peer
+    credit: Arc<Semaphore>,
+    /// whether the close frame has already been sent to the peer
+    closed: bool,
+}
+
+type Streams = Arc<Mutex<HashMap<u32, Stream>>>;
+
+/// Multiplexer of virtual sub-channels over a single channel.
+/// Both peers can open and accept sub-channels, which support the usual
+/// `Channel` API and have independent backpressure, so a slow sub-channel
+/// doesn't stall the others. Dropping a sub-channel closes it on both ends
+/// without affecting the rest.
+/// The peer can have up to 1024 sub-channels open at once, the ones it opens
+/// past that are closed right away. Sub-channels the peer sends more data
+/// than the receiver granted are closed too, and opening a sub-channel
+/// with an id already in use or granting more credit than was used
+/// closes the whole multiplexer.
+///
+/// Sub-channels are in-memory channels, so they report the `Mem` transport
+/// and are not encrypted themselves, but everything they send goes through
+/// the underlying channel, encrypted if it is.
+/// ```no_run
+/// # use canary::{channel::Mux, Channel};
+/// # async fn run(chan: Channel) -> canary::Result<()> {
+/// let mux = Mux::new(chan)?;
+/// let mut control = mux.open()?;
+/// control.send("hello!").await?;
+/// let mut events = mux.accept().await?;
+/// let event: String = events.receive().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Mux {
+    /// frames queued to be sent through the underlying channel
+    frames: UnboundedSender<Vec<u8>>,
+    /// sub-channels of the multiplexer
+    streams: Streams,
+    /// id of the next sub-channel opened
+    next_id: AtomicU32,
+    /// sub-channels opened by the peer
+    incoming: AsyncMutex<UnboundedReceiver<Channel>>,
+    /// runtime the tasks of the multiplexer run on
+    runtime: Handle,
+}
+
+impl Mux {
+    /// Start multiplexing sub-channels over the channel.
+    /// Must be called within a tokio runtime, since the multiplexer runs in
+    /// background tasks, returns an error otherwise. The underlying channel is
+    /// closed once the `Mux` and all of its sub-channels are dropped.
+    pub fn new(chan: Channel) -> Result<Self> {
+        let runtime = runtime()?;
+        let (send, receive) = chan.split();
+        let (frames, queued) = mpsc::unbounded_channel();
+        let (opened, incoming) = mpsc::unbounded_channel();
+        let streams = Streams::default();
+        runtime.spawn(write_frames(send, queued));
+        runtime.spawn(read_frames(
+            receive,
+            frames.downgrade(),
+            streams.clone(),
+            opened,
+        ));
+        Ok(Mux {
+            frames,
+            streams,
+            next_id: AtomicU32::new(0),
+            incoming: AsyncMutex::new(incoming),
+            runtime,
+        })
+    }
+
+    /// Open a sub-channel, the peer gets it from `accept`
+    /// ```no_run
+    /// # use canary::channel::Mux;
+    /// # async fn run(mux: Mux) -> canary::Result<()> {
+    /// let mut chan = mux.open()?;
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn open(&self) -> Result<Channel> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if id >= REMOTE {
+            return err!((other, "ran out of sub-channel ids"));
+        }
+        if self.frames.is_closed() {
+            return err!((broken_pipe, "multiplexed channel closed"));
+        }
+        // the tasks of the sub-channel are spawned even if called outside the runtime
+        let _runtime = self.runtime.enter();
+        Ok(start_stream(id, &self.frames, &self.streams, true))
+    }
+
+    /// Wait for the peer to open a sub-channel.
+    /// Returns an error once the underlying channel is closed.
+    /// ```no_run
+    /// # use canary::channel::Mux;
+    /// # async fn run(mux: Mux) -> canary::Result<()> {
+    /// let mut chan = mux.accept().await?;
+    /// let string: String = chan.receive().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn accept(&self) -> Result<Channel> {
+        self.incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| err!(unexpected_eof, "multiplexed channel closed"))
+    }
+}
+
+fn frame(kind: u8, id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(kind);
+    // the peer sees the stream from the other side
+    frame.extend_from_slice(&(id ^ REMOTE).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// register a sub-channel and spawn the tasks that move its data
+fn start_stream(
+    id: u32,
+    frames: &UnboundedSender<Vec<u8>>,
+    streams: &Streams,
+    open: bool,
+) -> Channel {
+    let (chan, stream) = duplex(STREAM_BUFFER_SIZE);
+    let (read, write) = split(stream);
+    // data frames aren't empty, so the peer can't queue more than `WINDOW` of them
+    let (inbound, received) = mpsc::channel(WINDOW);
+    let pending = Arc::new(AtomicUsize::new(0));
+    let credit = Arc::new(Semaphore::new(WINDOW));
+    let state = Stream {
+        inbound: Some(inbound),
+        pending: pending.clone(),
+        credit: credit.clone(),
+        closed: false,
+    };
+    streams.lock().unwrap().insert(id, state);
+    // the open frame has to be queued before any data frame
+    if open {
+        let _ = frames.send(frame(OPEN, id, &[]));
+    }
+    tokio::spawn(receive_stream(id, write, received, pending, frames.clone()));
+    tokio::spawn(send_stream(
+        id,
+        read,
+        credit,
+        frames.clone(),
+        streams.clone(),
+    ));
+    Channel::from_raw(chan, Default::default(), Default::default())
+}
+
+// send every queued frame through the channel
+async fn write_frames(mut send: SendChannel, mut queued: UnboundedReceiver<Vec<u8>>) {
+    while let Some(frame) = queued.recv().await {
+        if send.send_raw(&frame).await.is_err() {
+            return;
+        }
+    }
+    let _ = send.close().await;
+}
+
+// dispatch frames received through the channel to their sub-channels
+async fn read_frames(
+    mut receive: ReceiveChannel,
+    frames: WeakUnboundedSender<Vec<u8>>,
+    streams: Streams,
+    opened: UnboundedSender<Channel>,
+) {
+    while let Ok(frame) = receive.receive_raw().await {
+        if !dispatch(&frame, &frames, &streams, &opened) {
+            break;
+        }
+    }
+    // the channel is gone, every sub-channel gets an end of stream
+    for (_, stream) in streams.lock().unwrap().drain() {
+        stream.credit.close();
+    }
+}
+
+// returns false if the frame is malformed or the peer breaks the protocol
+fn dispatch(
+    frame: &[u8],
+    frames: &WeakUnboundedSender<Vec<u8>>,
+    streams: &Streams,
+    opened: &UnboundedSender<Channel>,
+) -> bool {
+    if frame.len() < 5 {
+        return false;
+    }
+    let id = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]);
+    let payload = &frame[5..];
+    let mut streams_lock = streams.lock().unwrap();
+    match frame[0] {
+        // the peer can only open ids of its own, that aren't in use
+        OPEN if id & REMOTE == 0 || streams_lock.contains_key(&id) => return false,
+        OPEN => {
+            let remote = streams_lock.keys().filter(|&&id| id & REMOTE != 0).count();
+            if remote >= MAX_STREAMS {
+                reset(&mut streams_lock, id, frames);
+                return true;
+            }
+            drop(streams_lock);
+            if let Some(frames) = frames.upgrade() {
+                let chan = start_stream(id, &frames, streams, false);
+                let _ = opened.send(chan);
+            }
+        }
+        DATA => {
+            if let Some(Stream {
+                inbound: Some(inbound),
+                pending,
+                ..
+            }) = streams_lock.get(&id)
+            {
+                // data past the credit granted is dropped along with the stream,
+                // so a peer ignoring the window can't queue unbounded data
+                let queued = pending.fetch_add(payload.len(), Ordering::Relaxed) + payload.len();
+                if payload.is_empty()
+                    || queued > WINDOW
+                    || inbound.try_send(payload.to_vec()).is_err()
+                {
+                    reset(&mut streams_lock, id, frames);
+                }
+            }
+        }
+        CREDIT => {
+            let credit = match payload.try_into() {
+                Ok(credit) => u32::from_be_bytes(credit),
+                Err(_) => return false,
+            };
+            if let Some(stream) = streams_lock.get(&id) {
+                // the peer only grants back what it received, so the credit
+                // of a stream never exceeds the window
+                let granted = stream.credit.available_permits() as u64 + u64::from(credit);
+                if granted > WINDOW as u64 {
+                    return false;
+                }
+                stream.credit.add_permits(credit as usize);
+            }
+        }
+        CLOSE => {
+            if let Some(stream) = streams_lock.get_mut(&id) {
+                if stream.closed {
+                    streams_lock.remove(&id);
+                } else {
+                    stream.inbound = None;
+                }
+            }
+        }
+        RESET => {
+            if let Some(stream) = streams_lock.remove(&id) {
+                stream.credit.close();
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+// drop both directions of a stream, telling the peer to do the same
+fn reset(streams: &mut HashMap<u32, Stream>, id: u32, frames: &WeakUnboundedSender<Vec<u8>>) {
+    if let Some(stream) = streams.remove(&id) {
+        stream.credit.close();
+    }
+    if let Some(frames) = frames.upgrade() {
+        let _ = frames.send(frame(RESET, id, &[]));
+    }
+}
+
+// write data sent by the peer into the sub-channel, granting credit back
+async fn receive_stream(
+    id: u32,
+    mut write: WriteHalf<DuplexStream>,
+    mut received: Receiver<Vec<u8>>,
+    pending: Arc<AtomicUsize>,
+    frames: UnboundedSender<Vec<u8>>,
+) {
+    let mut open = true;
+    while let Some(bytes) = received.recv().await {
+        // data for a dropped sub-channel is discarded, but still credited
+        // so the peer doesn't wait forever
+        if open {
+            open = write.write_all(&bytes).await.is_ok();
+        }
+        pending.fetch_sub(bytes.len(), Ordering::Relaxed);
+        let credit = bytes.len() as u32;
+        let _ = frames.send(frame(CREDIT, id, &credit.to_be_bytes()));
+    }
+    let _ = write.shutdown().await;
+}
+
+// send data written into the sub-channel to the peer, as long as there's credit
+async fn send_stream(
+    id: u32,
+    mut read: ReadHalf<DuplexStream>,
+    credit: Arc<Semaphore>,
+    frames: UnboundedSender<Vec<u8>>,
+    streams: Streams,
+) {
+    let mut buf = vec![0; MAX_DATA_LEN];
+    loop {
+        let len = match read.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+        match credit.acquire_many(len as u32).await {
+            Ok(permits) => permits.forget(),
+            Err(_) => return,
+        }
+        if frames.send(frame(DATA, id, &buf[..len])).is_err() {
+            return;
+        }
+    }
+    let _ = frames.send(frame(CLOSE, id, &[]));
+    let mut streams = streams.lock().unwrap();
+    if let Some(stream) = streams.get_mut(&id) {
+        if stream.inbound.is_none() {
+            streams.remove(&id);
+        } else {
+            stream.closed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    // receive frames from the multiplexer until one of `kind` arrives,
+    // returning the id of its stream as the peer sees it
+    async fn next_frame(chan: &mut Channel, kind: u8) -> u32 {
+        loop {
+            let frame = chan.receive_raw().await.unwrap();
+            if frame[0] == kind {
+                return u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_receivers_hold_back_senders() {
+        let (a, b) = Channel::pair();
+        let (a, b) = (Mux::new(a).unwrap(), Mux::new(b).unwrap());
+        let mut sender = a.open().unwrap();
+        let mut receiver = b.accept().await.unwrap();
+        let payload = vec![7u8; 4 * WINDOW];
+        let send = tokio::spawn(async move {
+            sender.send_raw(&payload).await.unwrap();
+            sender
+        });
+        // nothing is read, so the sender runs out of credit
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!send.is_finished());
+        assert_eq!(receiver.receive_raw().await.unwrap().len(), 4 * WINDOW);
+        send.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplicate_opens_close_the_multiplexer() {
+        let (a, mut b) = Channel::pair();
+        let a = Mux::new(a).unwrap();
+        b.send_raw(&frame(OPEN, 0, &[])).await.unwrap();
+        assert!(a.accept().await.is_ok());
+        b.send_raw(&frame(OPEN, 0, &[])).await.unwrap();
+        assert!(a.accept().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn opens_of_local_ids_close_the_multiplexer() {
+        let (a, mut b) = Channel::pair();
+        let a = Mux::new(a).unwrap();
+        let _local = a.open().unwrap();
+        // the id of the stream opened by `a`, as `a` sees it
+        b.send_raw(&frame(OPEN, REMOTE, &[])).await.unwrap();
+        assert!(a.accept().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn opens_past_the_limit_are_reset() {
+        let (a, mut b) = Channel::pair();
+        let a = Mux::new(a).unwrap();
+        for id in 0..=MAX_STREAMS as u32 {
+            b.send_raw(&frame(OPEN, id, &[])).await.unwrap();
+        }
+        assert_eq!(next_frame(&mut b, RESET).await, MAX_STREAMS as u32);
+        let mut accepted = Vec::new();
+        for _ in 0..MAX_STREAMS {
+            accepted.push(a.accept().await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn data_past_the_window_resets_the_stream() {
+        let (a, mut b) = Channel::pair();
+        let a = Mux::new(a).unwrap();
+        b.send_raw(&frame(OPEN, 0, &[])).await.unwrap();
+        let _chan = a.accept().await.unwrap();
+        let data = frame(DATA, 0, &[1; MAX_DATA_LEN]);
+        // nothing is read from the sub-channel, so no credit is granted past its buffer
+        for _ in 0..=(WINDOW + STREAM_BUFFER_SIZE) / MAX_DATA_LEN {
+            b.send_raw(&data).await.unwrap();
+        }
+        assert_eq!(next_frame(&mut b, RESET).await, 0);
+        assert!(a.streams.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn credit_past_the_window_closes_the_multiplexer() {
+        let (a, mut b) = Channel::pair();
+        let a = Mux::new(a).unwrap();
+        let _chan = a.open().unwrap();
+        let id = next_frame(&mut b, OPEN).await;
+        // nothing was sent, so the stream already has the whole window
+        b.send_raw(&frame(CREDIT, id, &1u32.to_be_bytes()))
+            .await
+            .unwrap();
+        assert!(a.accept().await.is_err());
+    }
+
+    #[test]
+    fn multiplexers_need_a_runtime() {
+        let (a, _b) = Channel::pair();
+        assert!(Mux::new(a).is_err());
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (a, c) = Channel::pair();
+        let (a, c) = rt.block_on(async { (Mux::new(a).unwrap(), Mux::new(c).unwrap()) });
+        // sub-channels run on the runtime of their multiplexer
+        let mut sender = a.open().unwrap();
+        rt.block_on(async {
+            sender.send("hello!").await.unwrap();
+            let mut receiver = c.accept().await.unwrap();
+            assert_eq!(receiver.receive::<String>().await.unwrap(), "hello!");
+        });
+    }
+}
@@ -1,10 +1,22 @@
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
+use std::time::Instant;
+
 use derive_more::From;
 use futures::stream::SplitStream;
 use serde::de::DeserializeOwned;
 
+use crate::channel::channels::Transport;
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+use crate::io::Tls;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io::{DuplexStream, ReadHalf};
 use crate::serialization::formats::Format;
 use crate::Result;
-use crate::{io::Wss, serialization::formats::ReadFormat};
+use crate::{
+    io::Wss,
+    serialization::{formats::ReadFormat, RxState},
+};
 
 #[derive(From)]
 /// Reference unformatted raw receive channel
@@ -20,6 +32,12 @@ pub enum RefUnformattedRawReceiveChannel<'a> {
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// unencrypted quic backend
     Quic(&'a mut quinn::RecvStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// unencrypted in-memory backend
+    Mem(&'a mut ReadHalf<DuplexStream>),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// unencrypted tls backend
+    Tls(&'a mut ReadHalf<Tls>),
 }
 
 #[derive(From)]
@@ -38,6 +56,12 @@ pub enum UnformattedRawReceiveChannel {
     #[cfg(feature = "quic")]
     /// Unencrypted quic backend
     Quic(quinn::RecvStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Unencrypted in-memory backend
+    Mem(ReadHalf<DuplexStream>),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// Unencrypted tls backend
+    Tls(ReadHalf<Tls>),
 }
 
 #[derive(From)]
@@ -66,19 +90,81 @@ impl<'a> RefUnformattedRawReceiveChannel<'a> {
     pub async fn receive<T: DeserializeOwned, F: ReadFormat>(
         &mut self,
         format: &mut F,
+    ) -> Result<T> {
+        self.receive_into(format, &mut RxState::default(), None)
+            .await
+    }
+    /// Receive an object by `deadline` if there is one, keeping the
+    /// progress of the frame in `state`. Websocket messages arrive in their own buffers, so they don't use it.
+    pub(crate) async fn receive_into<T: DeserializeOwned, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+        #[allow(unused)] state: &mut RxState,
+        #[allow(unused)] deadline: Option<Instant>,
     ) -> Result<T> {
         #[allow(unused)]
-        use crate::serialization::{rx, wss_rx};
+        use crate::serialization::{rx_until, wss_rx, wss_rx_until};
         match self {
             #[cfg(not(target_arch = "wasm32"))]
-            RefUnformattedRawReceiveChannel::Tcp(st) => rx(st, format).await,
+            RefUnformattedRawReceiveChannel::Tcp(st) => rx_until(st, format, state, deadline).await,
             #[cfg(unix)]
-            RefUnformattedRawReceiveChannel::Unix(st) => rx(st, format).await,
+            RefUnformattedRawReceiveChannel::Unix(st) => {
+                rx_until(st, format, state, deadline).await
+            }
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
-            RefUnformattedRawReceiveChannel::Quic(st) => rx(st, format).await,
+            RefUnformattedRawReceiveChannel::Quic(st) => {
+                rx_until(st, format, state, deadline).await
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawReceiveChannel::Mem(st) => rx_until(st, format, state, deadline).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            RefUnformattedRawReceiveChannel::Tls(st) => rx_until(st, format, state, deadline)
+                .await
+                .map_err(crate::providers::certs::tls_read_err),
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawReceiveChannel::WSS(st) => wss_rx_until(st, format, deadline).await,
+            #[cfg(target_arch = "wasm32")]
             RefUnformattedRawReceiveChannel::WSS(st) => wss_rx(st, format).await,
         }
     }
+    /// Receive the payload of the next message into `state` without deserializing it,
+    /// by `deadline` if there is one
+    pub(crate) async fn receive_payload<F: ReadFormat>(
+        &mut self,
+        format: &F,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        #[allow(unused)]
+        use crate::serialization::{rx_payload_until, wss_rx_payload_until};
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawReceiveChannel::Tcp(st) => {
+                rx_payload_until(st, format, state, deadline).await
+            }
+            #[cfg(unix)]
+            RefUnformattedRawReceiveChannel::Unix(st) => {
+                rx_payload_until(st, format, state, deadline).await
+            }
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            RefUnformattedRawReceiveChannel::Quic(st) => {
+                rx_payload_until(st, format, state, deadline).await
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawReceiveChannel::Mem(st) => {
+                rx_payload_until(st, format, state, deadline).await
+            }
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            RefUnformattedRawReceiveChannel::Tls(st) => {
+                rx_payload_until(st, format, state, deadline)
+                    .await
+                    .map_err(crate::providers::certs::tls_read_err)
+            }
+            RefUnformattedRawReceiveChannel::WSS(st) => {
+                wss_rx_payload_until(st, state, deadline).await
+            }
+        }
+    }
     /// Get a formatted channel with the specified format
     /// ```no_run
     /// let string: String = unformatted.receive(&mut Format::Bincode).await?;
@@ -105,11 +191,40 @@ impl<'a> From<&'a mut UnformattedRawReceiveChannel> for RefUnformattedRawReceive
             #[cfg(not(target_arch = "wasm32"))]
             #[cfg(feature = "quic")]
             UnformattedRawReceiveChannel::Quic(ref mut chan) => chan.into(),
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawReceiveChannel::Mem(ref mut chan) => chan.into(),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawReceiveChannel::Tls(ref mut chan) => chan.into(),
         }
     }
 }
 
 impl UnformattedRawReceiveChannel {
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawReceiveChannel::Tcp(_) => Transport::Tcp,
+            #[cfg(unix)]
+            UnformattedRawReceiveChannel::Unix(_) => Transport::Unix,
+            UnformattedRawReceiveChannel::WSS(_) => Transport::Wss,
+            #[cfg(not(target_arch = "wasm32"))]
+            #[cfg(feature = "quic")]
+            UnformattedRawReceiveChannel::Quic(_) => Transport::Quic,
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawReceiveChannel::Mem(_) => Transport::Mem,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawReceiveChannel::Tls(_) => Transport::Tls,
+        }
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        match self {
+            UnformattedRawReceiveChannel::Unix(half) => PeerCred::of(half.as_ref()),
+            _ => PeerCred::unsupported(self.transport()),
+        }
+    }
     /// Receive an object sent through the channel with format
     /// ```no_run
     /// let string: String = chan.receive(&mut Format::Bincode).await?;
@@ -122,6 +237,28 @@ impl UnformattedRawReceiveChannel {
             .receive(format)
             .await
     }
+    /// Receive an object by `deadline` if there is one, keeping the progress of the frame in `state`
+    pub(crate) async fn receive_into<T: DeserializeOwned, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<T> {
+        RefUnformattedRawReceiveChannel::from(self)
+            .receive_into(format, state, deadline)
+            .await
+    }
+    /// Receive the payload of the next message into `state` without deserializing it
+    pub(crate) async fn receive_payload<F: ReadFormat>(
+        &mut self,
+        format: &F,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        RefUnformattedRawReceiveChannel::from(self)
+            .receive_payload(format, state, deadline)
+            .await
+    }
     #[inline]
     /// Format the channel
     /// ```no_run
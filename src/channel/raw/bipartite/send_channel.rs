@@ -1,8 +1,18 @@
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
 use crate::io::Message;
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+use crate::io::Tls;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io::{DuplexStream, WriteHalf};
 use crate::{
+    channel::channels::Transport,
     err,
     io::Wss,
-    serialization::formats::{Format, SendFormat},
+    serialization::{
+        formats::{Format, SendFormat},
+        Framing,
+    },
     Result,
 };
 use derive_more::From;
@@ -23,6 +33,12 @@ pub enum RefUnformattedRawSendChannel<'a> {
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// quic backend
     Quic(&'a mut quinn::SendStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// in-memory backend
+    Mem(&'a mut WriteHalf<DuplexStream>),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// tls backend
+    Tls(&'a mut WriteHalf<Tls>),
 }
 
 #[derive(From)]
@@ -39,6 +55,12 @@ pub enum UnformattedRawSendChannel {
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// quic backend
     Quic(quinn::SendStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// in-memory backend
+    Mem(WriteHalf<DuplexStream>),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// tls backend
+    Tls(WriteHalf<Tls>),
 }
 
 #[derive(From)]
@@ -68,6 +90,10 @@ impl<'a> From<&'a mut UnformattedRawSendChannel> for RefUnformattedRawSendChanne
             UnformattedRawSendChannel::WSS(ref mut chan) => chan.into(),
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
             UnformattedRawSendChannel::Quic(ref mut chan) => chan.into(),
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawSendChannel::Mem(ref mut chan) => chan.into(),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawSendChannel::Tls(ref mut chan) => chan.into(),
         }
     }
 }
@@ -79,7 +105,7 @@ impl<'a> RefUnformattedRawSendChannel<'a> {
     /// ```
     pub async fn send<T: Serialize, F: SendFormat>(&mut self, obj: T, f: &mut F) -> Result<usize> {
         #[allow(unused)]
-        use crate::serialization::tx;
+        use crate::serialization::{tx, wss_send};
         match self {
             #[cfg(not(target_arch = "wasm32"))]
             RefUnformattedRawSendChannel::Tcp(st) => tx(st, obj, f).await,
@@ -88,23 +114,52 @@ impl<'a> RefUnformattedRawSendChannel<'a> {
             RefUnformattedRawSendChannel::WSS(st) => {
                 let buf = f.serialize(&obj).map_err(err!(@invalid_data))?;
                 let len = buf.len();
-
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    let item = Message::Binary(buf);
-                    st.send(item).await.map_err(err!(@other))?;
-                }
-
-                #[cfg(target_arch = "wasm32")]
-                {
-                    let item = Message::Bytes(buf);
-                    st.send(item).await.map_err(|e| err!(e.to_string()))?;
-                }
-
+                wss_send(st, buf, f.is_text(), f.fragment_len()).await?;
                 Ok(len)
             }
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
             RefUnformattedRawSendChannel::Quic(st) => tx(st, obj, f).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawSendChannel::Mem(st) => tx(st, obj, f).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            RefUnformattedRawSendChannel::Tls(st) => tx(st, obj, f).await,
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Send a heartbeat frame through the channel, which the peer skips when receiving
+    pub async fn heartbeat(&mut self, framing: Framing) -> Result<()> {
+        use crate::serialization::{heartbeat, wss_heartbeat};
+        match self {
+            RefUnformattedRawSendChannel::Tcp(st) => heartbeat(st, framing).await,
+            #[cfg(unix)]
+            RefUnformattedRawSendChannel::Unix(st) => heartbeat(st, framing).await,
+            RefUnformattedRawSendChannel::WSS(st) => wss_heartbeat(st).await,
+            #[cfg(feature = "quic")]
+            RefUnformattedRawSendChannel::Quic(st) => heartbeat(st, framing).await,
+            RefUnformattedRawSendChannel::Mem(st) => heartbeat(st, framing).await,
+            #[cfg(feature = "tls")]
+            RefUnformattedRawSendChannel::Tls(st) => heartbeat(st, framing).await,
+        }
+    }
+    /// Close the send half of the stream. The peer gets an end of stream
+    /// error once it has received every message sent before closing.
+    pub async fn close(&mut self) -> Result<()> {
+        #[allow(unused)]
+        use crate::io::WriteExt;
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawSendChannel::Tcp(st) => Ok(st.shutdown().await?),
+            #[cfg(unix)]
+            RefUnformattedRawSendChannel::Unix(st) => Ok(st.shutdown().await?),
+            RefUnformattedRawSendChannel::WSS(st) => {
+                st.close().await.map_err(|e| err!(e.to_string()))
+            }
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            RefUnformattedRawSendChannel::Quic(st) => st.finish().await.map_err(err!(@other)),
+            #[cfg(not(target_arch = "wasm32"))]
+            RefUnformattedRawSendChannel::Mem(st) => Ok(st.shutdown().await?),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            RefUnformattedRawSendChannel::Tls(st) => Ok(st.shutdown().await?),
         }
     }
     /// Get a formatted channel with the specified format
@@ -122,6 +177,30 @@ impl<'a> RefUnformattedRawSendChannel<'a> {
 }
 
 impl UnformattedRawSendChannel {
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawSendChannel::Tcp(_) => Transport::Tcp,
+            #[cfg(unix)]
+            UnformattedRawSendChannel::Unix(_) => Transport::Unix,
+            UnformattedRawSendChannel::WSS(_) => Transport::Wss,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            UnformattedRawSendChannel::Quic(_) => Transport::Quic,
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawSendChannel::Mem(_) => Transport::Mem,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawSendChannel::Tls(_) => Transport::Tls,
+        }
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        match self {
+            UnformattedRawSendChannel::Unix(half) => PeerCred::of(half.as_ref()),
+            _ => PeerCred::unsupported(self.transport()),
+        }
+    }
     /// Send an object through the channel serialized with format
     /// ```no_run
     /// chan.send("Hello world!", &mut Format::Bincode).await?;
@@ -129,6 +208,18 @@ impl UnformattedRawSendChannel {
     pub async fn send<T: Serialize, F: SendFormat>(&mut self, obj: T, f: &mut F) -> Result<usize> {
         RefUnformattedRawSendChannel::from(self).send(obj, f).await
     }
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Send a heartbeat frame through the channel, which the peer skips when receiving
+    pub async fn heartbeat(&mut self, framing: Framing) -> Result<()> {
+        RefUnformattedRawSendChannel::from(self)
+            .heartbeat(framing)
+            .await
+    }
+    /// Close the send half of the stream. The peer gets an end of stream
+    /// error once it has received every message sent before closing.
+    pub async fn close(&mut self) -> Result<()> {
+        RefUnformattedRawSendChannel::from(self).close().await
+    }
     #[inline]
     /// Format the channel
     /// ```no_run
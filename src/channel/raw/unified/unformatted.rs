@@ -1,18 +1,27 @@
+#[cfg(unix)]
+use crate::channel::channels::PeerCred;
+use std::time::Instant;
+
 use derive_more::From;
-use futures::{SinkExt, StreamExt};
+use futures::StreamExt;
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::channel::channels::Transport;
 use crate::channel::raw::bipartite::receive_channel::UnformattedRawReceiveChannel;
 use crate::channel::raw::bipartite::send_channel::UnformattedRawSendChannel;
-use crate::io::Message;
-#[cfg(not(target_arch = "wasm32"))]
-use crate::io::TcpStream;
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+use crate::io::Tls;
 #[cfg(unix)]
 use crate::io::UnixStream;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io::{split, DuplexStream, TcpStream};
 use crate::{err, Result};
 use crate::{
     io::Wss,
-    serialization::formats::{ReadFormat, SendFormat},
+    serialization::{
+        formats::{ReadFormat, SendFormat},
+        RxState,
+    },
 };
 
 use super::formatted::RefRawUnifiedChannel;
@@ -32,6 +41,12 @@ pub enum RefUnformattedRawUnifiedChannel<'a> {
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// quic backend
     Quic(&'a mut quinn::SendStream, &'a mut quinn::RecvStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// in-memory backend
+    Mem(&'a mut DuplexStream),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// tls backend
+    Tls(&'a mut Tls),
 }
 
 #[derive(From)]
@@ -49,6 +64,12 @@ pub enum UnformattedRawUnifiedChannel {
     #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
     /// Quic backend
     Quic(quinn::SendStream, quinn::RecvStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// In-memory backend
+    Mem(DuplexStream),
+    #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+    /// Tls backend
+    Tls(Box<Tls>), // boxed since the tls session is heavy
 }
 
 impl UnformattedRawUnifiedChannel {
@@ -78,6 +99,81 @@ impl UnformattedRawUnifiedChannel {
             UnformattedRawUnifiedChannel::Quic(write, read) => {
                 (From::from(write), From::from(read))
             }
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawUnifiedChannel::Mem(stream) => {
+                let (read, write) = split(stream);
+                (From::from(write), From::from(read))
+            }
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawUnifiedChannel::Tls(stream) => {
+                let (read, write) = split(*stream);
+                (From::from(write), From::from(read))
+            }
+        }
+    }
+    /// Join the send and receive components of a split channel back together.
+    /// Returns an error if the components don't belong to the same stream.
+    pub fn reunite(
+        send: UnformattedRawSendChannel,
+        receive: UnformattedRawReceiveChannel,
+    ) -> Result<Self> {
+        match (send, receive) {
+            #[cfg(not(target_arch = "wasm32"))]
+            (UnformattedRawSendChannel::Tcp(write), UnformattedRawReceiveChannel::Tcp(read)) => {
+                let stream = read.reunite(write).map_err(err!(@invalid_input))?;
+                Ok(UnformattedRawUnifiedChannel::Tcp(stream))
+            }
+            #[cfg(unix)]
+            (UnformattedRawSendChannel::Unix(write), UnformattedRawReceiveChannel::Unix(read)) => {
+                let stream = read.reunite(write).map_err(err!(@invalid_input))?;
+                Ok(UnformattedRawUnifiedChannel::Unix(stream))
+            }
+            (UnformattedRawSendChannel::WSS(write), UnformattedRawReceiveChannel::WSS(read)) => {
+                let stream = read.reunite(write).map_err(err!(@invalid_input))?;
+                Ok(UnformattedRawUnifiedChannel::Wss(stream))
+            }
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            (UnformattedRawSendChannel::Quic(write), UnformattedRawReceiveChannel::Quic(read)) => {
+                Ok(UnformattedRawUnifiedChannel::Quic(write, read))
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            (UnformattedRawSendChannel::Mem(write), UnformattedRawReceiveChannel::Mem(read)) => {
+                Ok(UnformattedRawUnifiedChannel::Mem(read.unsplit(write)))
+            }
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            (UnformattedRawSendChannel::Tls(write), UnformattedRawReceiveChannel::Tls(read)) => {
+                let stream = Box::new(read.unsplit(write));
+                Ok(UnformattedRawUnifiedChannel::Tls(stream))
+            }
+            #[allow(unreachable_patterns)]
+            _ => err!((
+                invalid_input,
+                "cannot reunite send and receive channels with different transports"
+            )),
+        }
+    }
+    /// Get the transport backend of the channel
+    pub fn transport(&self) -> Transport {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawUnifiedChannel::Tcp(_) => Transport::Tcp,
+            #[cfg(unix)]
+            UnformattedRawUnifiedChannel::Unix(_) => Transport::Unix,
+            UnformattedRawUnifiedChannel::Wss(_) => Transport::Wss,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            UnformattedRawUnifiedChannel::Quic(..) => Transport::Quic,
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawUnifiedChannel::Mem(_) => Transport::Mem,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawUnifiedChannel::Tls(_) => Transport::Tls,
+        }
+    }
+    #[cfg(unix)]
+    /// Get the credentials of the process at the other end of a unix channel
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        match self {
+            UnformattedRawUnifiedChannel::Unix(stream) => PeerCred::of(stream),
+            _ => PeerCred::unsupported(self.transport()),
         }
     }
     /// Send an object through the channel serialized with format
@@ -105,6 +201,28 @@ impl UnformattedRawUnifiedChannel {
             .receive(format)
             .await
     }
+    /// Receive an object by `deadline` if there is one, keeping the progress of the frame in `state`
+    pub(crate) async fn receive_into<T: DeserializeOwned, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<T> {
+        RefUnformattedRawUnifiedChannel::from(self)
+            .receive_into(format, state, deadline)
+            .await
+    }
+    /// Receive the payload of the next message into `state` without deserializing it
+    pub(crate) async fn receive_payload<F: ReadFormat>(
+        &mut self,
+        format: &F,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        RefUnformattedRawUnifiedChannel::from(self)
+            .receive_payload(format, state, deadline)
+            .await
+    }
 }
 
 impl<'a> From<&'a mut UnformattedRawUnifiedChannel> for RefUnformattedRawUnifiedChannel<'a> {
@@ -120,6 +238,12 @@ impl<'a> From<&'a mut UnformattedRawUnifiedChannel> for RefUnformattedRawUnified
             }
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
             UnformattedRawUnifiedChannel::Quic(ref mut tx, ref mut rx) => From::from((tx, rx)),
+            #[cfg(not(target_arch = "wasm32"))]
+            UnformattedRawUnifiedChannel::Mem(ref mut chan) => chan.into(),
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            UnformattedRawUnifiedChannel::Tls(ref mut chan) => {
+                RefUnformattedRawUnifiedChannel::Tls(chan)
+            }
         }
     }
 }
@@ -135,7 +259,7 @@ impl<'a> RefUnformattedRawUnifiedChannel<'a> {
         format: &mut F,
     ) -> Result<usize> {
         #[allow(unused)]
-        use crate::serialization::tx;
+        use crate::serialization::{tx, wss_send};
         match self {
             #[cfg(not(target_arch = "wasm32"))]
             Self::Tcp(st) => tx(st, obj, format).await,
@@ -143,23 +267,14 @@ impl<'a> RefUnformattedRawUnifiedChannel<'a> {
             Self::Unix(st) => tx(st, obj, format).await,
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
             Self::Quic(st, _) => tx(st, obj, format).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Mem(st) => tx(st, obj, format).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            Self::Tls(st) => tx(st, obj, format).await,
             Self::Wss(st) => {
                 let buf = format.serialize(&obj).map_err(err!(@invalid_data))?;
                 let len = buf.len();
-
-                // use tungstenite for native
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    let item = Message::Binary(buf);
-                    st.send(item).await.map_err(err!(@other))?;
-                };
-
-                // use reqwasm for wasm
-                #[cfg(target_arch = "wasm32")]
-                {
-                    let item = Message::Bytes(buf);
-                    st.send(item).await.map_err(|e| err!(e.to_string()))?;
-                };
+                wss_send(st, buf, format.is_text(), format.fragment_len()).await?;
                 Ok(len)
             }
         }
@@ -171,17 +286,63 @@ impl<'a> RefUnformattedRawUnifiedChannel<'a> {
     pub async fn receive<T: DeserializeOwned, F: ReadFormat>(
         &mut self,
         format: &mut F,
+    ) -> Result<T> {
+        self.receive_into(format, &mut RxState::default(), None)
+            .await
+    }
+    /// Receive an object by `deadline` if there is one, keeping the
+    /// progress of the frame in `state`. Websocket messages arrive in their own buffers, so they don't use it.
+    pub(crate) async fn receive_into<T: DeserializeOwned, F: ReadFormat>(
+        &mut self,
+        format: &mut F,
+        #[allow(unused)] state: &mut RxState,
+        #[allow(unused)] deadline: Option<Instant>,
     ) -> Result<T> {
         #[allow(unused)]
-        use crate::serialization::{rx, wss_rx};
+        use crate::serialization::{rx_until, wss_rx, wss_rx_until};
         match self {
             #[cfg(not(target_arch = "wasm32"))]
-            Self::Tcp(st) => rx(st, format).await,
+            Self::Tcp(st) => rx_until(st, format, state, deadline).await,
             #[cfg(unix)]
-            Self::Unix(st) => rx(st, format).await,
+            Self::Unix(st) => rx_until(st, format, state, deadline).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Wss(st) => wss_rx_until(st, format, deadline).await,
+            #[cfg(target_arch = "wasm32")]
             Self::Wss(st) => wss_rx(st, format).await,
             #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
-            Self::Quic(_, st) => rx(st, format).await,
+            Self::Quic(_, st) => rx_until(st, format, state, deadline).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Mem(st) => rx_until(st, format, state, deadline).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            Self::Tls(st) => rx_until(st, format, state, deadline)
+                .await
+                .map_err(crate::providers::certs::tls_read_err),
+        }
+    }
+    /// Receive the payload of the next message into `state` without deserializing it,
+    /// by `deadline` if there is one
+    pub(crate) async fn receive_payload<F: ReadFormat>(
+        &mut self,
+        format: &F,
+        state: &mut RxState,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        #[allow(unused)]
+        use crate::serialization::{rx_payload_until, wss_rx_payload_until};
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Tcp(st) => rx_payload_until(st, format, state, deadline).await,
+            #[cfg(unix)]
+            Self::Unix(st) => rx_payload_until(st, format, state, deadline).await,
+            Self::Wss(st) => wss_rx_payload_until(st, state, deadline).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+            Self::Quic(_, st) => rx_payload_until(st, format, state, deadline).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Mem(st) => rx_payload_until(st, format, state, deadline).await,
+            #[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+            Self::Tls(st) => rx_payload_until(st, format, state, deadline)
+                .await
+                .map_err(crate::providers::certs::tls_read_err),
         }
     }
     /// Get a formatted channel with the specified format
@@ -0,0 +1,159 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use compact_str::CompactString;
+use serde::{Deserialize, Serialize};
+
+use crate::channel::forward::is_closed;
+use crate::providers::{Addr, AddrSet};
+use crate::{err, Channel, Result};
+
+#[cfg(feature = "mdns")]
+/// Contains discovery of endpoints on the local network through mdns
+pub mod mdns;
+mod registry;
+
+pub use registry::*;
+
+#[derive(Serialize, Deserialize)]
+/// queries of `query`. Variants are only ever appended,
+/// so peers of different versions can tell which ones they share
+enum Query {
+    V1 { name: CompactString },
+}
+
+#[derive(Serialize, Deserialize)]
+/// answers of `serve`, appended to like `Query`
+enum Answer {
+    /// addresses of the name, `None` if it's unknown
+    V1 { addrs: Option<Vec<Addr>> },
+    /// the query is of a version the server doesn't know
+    Unsupported,
+}
+
+/// Answer the queries of `query` with the addresses of the resolver,
+/// until the peer closes the channel. The resolver returns `None` for
+/// names it doesn't know.
+/// ```no_run
+/// # use canary::{discovery::{self, Registry}, providers::Tcp, Channel};
+/// # async fn run() -> canary::Result<()> {
+/// let registry = Registry::new();
+/// let gateway = Tcp::bind("0.0.0.0:7000").await?.serve(move |chan: Channel| {
+///     let registry = registry.clone();
+///     discovery::serve(chan, move |name| registry.lookup(name).ok())
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn serve(mut chan: Channel, resolver: impl Fn(&str) -> Option<Vec<Addr>>) -> Result<()> {
+    loop {
+        let answer = match chan.receive().await {
+            Ok(Query::V1 { name }) => Answer::V1 {
+                addrs: resolver(&name),
+            },
+            Err(e) if is_closed(&e) => return Ok(()),
+            // queries of newer versions don't deserialize
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                tracing::debug!("received an unsupported query: {}", e);
+                Answer::Unsupported
+            }
+            Err(e) => return Err(e),
+        };
+        chan.send(answer).await?;
+    }
+}
+
+/// Ask the peer, which runs `serve`, for the addresses of the name.
+/// Fails with `NotFound` if the peer doesn't know the name,
+/// names it knows may have no addresses.
+/// ```no_run
+/// # use canary::discovery;
+/// # async fn run() -> canary::Result<()> {
+/// let mut gateway = canary::connect("tcp@gateway.local:7000").await?;
+/// let addrs = discovery::query(&mut gateway, "users").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn query(chan: &mut Channel, name: &str) -> Result<Vec<Addr>> {
+    let answer = chan.call(Query::V1 { name: name.into() }).await?;
+    match answer {
+        Answer::V1 { addrs: Some(addrs) } => Ok(addrs),
+        Answer::V1 { addrs: None } => {
+            err!((not_found, format!("{:?} is not known to the peer", name)))
+        }
+        Answer::Unsupported => err!((unsupported, "the peer doesn't support the query")),
+    }
+}
+
+/// Ask the gateway, which runs `serve`, for the addresses of the name,
+/// and connect to the first of them that accepts like `AddrSet::connect`.
+/// Fails with `NotFound` if the gateway doesn't know the name or it has no addresses.
+/// ```no_run
+/// # use canary::discovery;
+/// # async fn run() -> canary::Result<()> {
+/// let mut chan = discovery::connect_via(&"tcp@gateway.local:7000".parse()?, "users").await?;
+/// chan.send("hello!").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn connect_via(gateway: &Addr, name: &str) -> Result<Channel> {
+    let addrs = query(&mut gateway.connect().await?, name).await?;
+    if addrs.is_empty() {
+        err!((not_found, format!("{:?} has no addresses", name)))?
+    }
+    AddrSet::new(addrs)?.connect().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+
+    fn resolve(name: &str) -> Option<Vec<Addr>> {
+        match name {
+            "users" => Some(vec!["tcp@127.0.0.1:8080".parse().unwrap()]),
+            "drained" => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_answer_known_names() {
+        let (mut a, b) = Channel::pair();
+        let server = tokio::spawn(serve(b, resolve));
+        let addrs = query(&mut a, "users").await.unwrap();
+        assert_eq!(addrs, resolve("users").unwrap());
+        // known names may have no addresses
+        assert!(query(&mut a, "drained").await.unwrap().is_empty());
+        let e = query(&mut a, "nobody").await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+
+        #[derive(Serialize)]
+        enum Newer {
+            #[allow(dead_code)]
+            V1 {
+                name: CompactString,
+            },
+            V2,
+        }
+        let answer: Answer = a.call(Newer::V2).await.unwrap();
+        assert!(matches!(answer, Answer::Unsupported));
+        drop(a);
+        assert!(server.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn gateways_without_addresses_are_not_found() {
+        let gateway: Addr = "mem@discovery-gateway".parse().unwrap();
+        gateway
+            .bind()
+            .await
+            .unwrap()
+            .serve(|chan: Channel| serve(chan, resolve))
+            .unwrap();
+        for name in ["drained", "nobody"] {
+            let e = connect_via(&gateway, name).await.err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::NotFound, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use compact_str::CompactString;
+use futures::future::ready;
+use futures::{Stream, StreamExt};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::providers::{Addr, HostAddr};
+use crate::{err, Channel, Result};
+
+/// Service type canary endpoints are announced under
+pub const SERVICE_TYPE: &str = "_canary._tcp.local.";
+
+/// Announcement of an endpoint on the local network.
+/// The endpoint is announced until this is dropped.
+pub struct Announcement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        // the daemon runs its commands in order, so peers are told the endpoint is gone
+        self.daemon.unregister(&self.fullname).ok();
+        self.daemon.shutdown().ok();
+    }
+}
+
+/// Announce the address as an endpoint of the service on the local network,
+/// as a `_canary._tcp.local` record that carries the address in its TXT data.
+/// Tcp addresses bound to an unspecified ip such as `0.0.0.0` are reached
+/// at the ips the record resolves to.
+/// ```no_run
+/// # use canary::{discovery::mdns, providers::Tcp};
+/// # async fn run() -> canary::Result<()> {
+/// let tcp = Tcp::bind("0.0.0.0:8080").await?;
+/// let _announcement = mdns::announce("chat", &"tcp@0.0.0.0:8080".parse()?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn announce(service: &str, addr: &Addr) -> Result<Announcement> {
+    let daemon = ServiceDaemon::new().map_err(err!(@other))?;
+    let instance = format!("{}-{:08x}", service, rand::random::<u32>());
+    // the address is in the TXT data, the port is only informative
+    let port = match addr {
+        Addr::Tcp(addr, _) | Addr::InsecureTcp(addr) => match **addr {
+            HostAddr::Socket(addr) => addr.port(),
+            HostAddr::Name(_, port) => port,
+        },
+        _ => 0,
+    };
+    let addr = addr.to_string();
+    let properties = [("service", service), ("addr", &addr)];
+    let host = format!("{}.local.", instance);
+    let info = ServiceInfo::new(SERVICE_TYPE, &instance, &host, "", port, &properties[..])
+        .map_err(err!(@invalid_input))?
+        .enable_addr_auto();
+    let fullname = info.get_fullname().to_owned();
+    if let Err(e) = daemon.register(info) {
+        daemon.shutdown().ok();
+        err!((other, e))?
+    }
+    Ok(Announcement { daemon, fullname })
+}
+
+#[derive(Debug, Clone)]
+/// Endpoint of a service found on the local network
+pub struct DiscoveredPeer {
+    /// Name of the mdns instance, unique to each announcement
+    pub instance: CompactString,
+    /// Address the endpoint was announced with
+    pub addr: Addr,
+    /// `false` once the endpoint stopped being announced
+    pub present: bool,
+}
+
+impl DiscoveredPeer {
+    #[inline]
+    /// Connect to the endpoint through `Addr::connect`
+    /// ```no_run
+    /// # use canary::discovery::mdns::DiscoveredPeer;
+    /// # async fn run(peer: DiscoveredPeer) -> canary::Result<()> {
+    /// let mut chan = peer.connect().await?;
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(&self) -> Result<Channel> {
+        self.addr.connect().await
+    }
+}
+
+/// endpoints found by a browse, shutting the daemon down once the stream is dropped
+struct Browser {
+    daemon: ServiceDaemon,
+    service: CompactString,
+    peers: HashMap<String, Addr>,
+}
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        self.daemon.shutdown().ok();
+    }
+}
+
+impl Browser {
+    /// peer the event is about, if it's an endpoint of the service
+    fn peer(&mut self, event: ServiceEvent) -> Option<DiscoveredPeer> {
+        match event {
+            ServiceEvent::ServiceResolved(info) => {
+                if info.get_property_val_str("service") != Some(self.service.as_str()) {
+                    return None;
+                }
+                let addr = info.get_property_val_str("addr")?.parse::<Addr>();
+                let addr = match addr {
+                    Ok(addr) => resolved(addr, &info)?,
+                    Err(e) => {
+                        tracing::debug!(
+                            "{} announced an invalid address: {}",
+                            info.get_fullname(),
+                            e
+                        );
+                        return None;
+                    }
+                };
+                let instance = info.get_fullname().to_owned();
+                // records are resolved again whenever they are announced
+                if self.peers.get(&instance) == Some(&addr) {
+                    return None;
+                }
+                self.peers.insert(instance.clone(), addr.clone());
+                Some(DiscoveredPeer {
+                    instance: instance.into(),
+                    addr,
+                    present: true,
+                })
+            }
+            ServiceEvent::ServiceRemoved(_, instance) => {
+                let addr = self.peers.remove(&instance)?;
+                Some(DiscoveredPeer {
+                    instance: instance.into(),
+                    addr,
+                    present: false,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// tcp addresses bound to an unspecified ip are reached at an ip of the record,
+/// of the same family unless it's `::`. `None` until the record has one
+fn resolved(addr: Addr, info: &ServiceInfo) -> Option<Addr> {
+    let unspecified = match &addr {
+        Addr::Tcp(host, _) | Addr::InsecureTcp(host) => match **host {
+            HostAddr::Socket(socket) if socket.ip().is_unspecified() => socket,
+            _ => return Some(addr),
+        },
+        _ => return Some(addr),
+    };
+    let ip = info
+        .get_addresses()
+        .iter()
+        .filter(|ip| unspecified.is_ipv6() || ip.is_ipv4())
+        .min_by_key(|ip| ip.is_ipv6())?;
+    let host = Arc::new(SocketAddr::new(*ip, unspecified.port()).into());
+    Some(match addr {
+        Addr::Tcp(_, suite) => Addr::Tcp(host, suite),
+        _ => Addr::InsecureTcp(host),
+    })
+}
+
+/// Find the endpoints of the service announced on the local network.
+/// Peers are yielded as they appear, and once more with `present` set
+/// to `false` when they stop being announced. The stream doesn't depend
+/// on any runtime and ends if the mdns daemon stops.
+/// ```no_run
+/// # use futures::StreamExt;
+/// # use canary::discovery::mdns;
+/// # async fn run() -> canary::Result<()> {
+/// let mut peers = mdns::browse("chat")?;
+/// while let Some(peer) = peers.next().await {
+///     if peer.present {
+///         let mut chan = peer.connect().await?;
+///         chan.send("hello!").await?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn browse(service: &str) -> Result<impl Stream<Item = DiscoveredPeer> + Send + Unpin> {
+    let daemon = ServiceDaemon::new().map_err(err!(@other))?;
+    let events = match daemon.browse(SERVICE_TYPE) {
+        Ok(events) => events,
+        Err(e) => {
+            daemon.shutdown().ok();
+            err!((other, e))?
+        }
+    };
+    let mut browser = Browser {
+        daemon,
+        service: service.into(),
+        peers: HashMap::new(),
+    };
+    Ok(events
+        .into_stream()
+        .filter_map(move |event| ready(browser.peer(event))))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn announcements_are_browsed_over_loopback() -> Result<()> {
+        let service = format!("test-{:08x}", rand::random::<u32>());
+        let addr = "tcp@127.0.0.1:8080".parse::<Addr>()?;
+        let mut peers = browse(&service)?;
+        let announcement = announce(&service, &addr)?;
+
+        let peer = tokio::time::timeout(Duration::from_secs(5), peers.next())
+            .await
+            .map_err(err!(@TimedOut))?
+            .unwrap();
+        assert!(peer.present);
+        assert_eq!(peer.addr, addr);
+
+        drop(announcement);
+        let peer = tokio::time::timeout(Duration::from_secs(5), peers.next())
+            .await
+            .map_err(err!(@TimedOut))?
+            .unwrap();
+        assert!(!peer.present);
+        assert_eq!(peer.addr, addr);
+        Ok(())
+    }
+}
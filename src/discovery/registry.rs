@@ -0,0 +1,660 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use compact_str::CompactString;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::channel::forward::is_closed;
+use crate::providers::{Addr, AddrSet};
+use crate::{err, Channel, Result};
+
+/// time an instance that failed to connect isn't picked for by default
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+/// longest ttl a registration can have, longer ones are rejected
+pub const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize)]
+/// requests of registry clients, each one is answered with a result.
+/// Variants are only ever appended, so peers of different versions
+/// can tell which ones they share
+enum Request {
+    Register {
+        name: CompactString,
+        addr: Addr,
+        ttl: Duration,
+        load: Load,
+    },
+    Lookup(CompactString),
+    Deregister {
+        name: CompactString,
+        addr: Addr,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Load an instance reports when it registers, refreshed every time it registers again
+/// ```no_run
+/// # use std::time::Duration;
+/// # use canary::discovery::{Load, RegistryClient};
+/// # use canary::providers::{Addr, Tcp};
+/// # async fn run(mut registry: RegistryClient, tcp: Tcp, addr: Addr) -> canary::Result<()> {
+/// let load = Load::default().connections(tcp.counts().connections as u64).weight(2);
+/// registry.register_with("users", addr, Duration::from_secs(30), load).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Load {
+    /// Number of connections the instance is handling
+    pub connections: Option<u64>,
+    /// Capacity of the instance relative to the others, 1 if unset.
+    /// Instances with a weight of 0 are only picked if every other one has it too.
+    pub weight: Option<u32>,
+}
+
+impl Load {
+    #[must_use]
+    /// Report the number of connections the instance is handling
+    pub fn connections(mut self, connections: u64) -> Self {
+        self.connections = Some(connections);
+        self
+    }
+
+    #[must_use]
+    /// Report the capacity of the instance relative to the others
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = Some(weight);
+        self
+    }
+
+    /// connections per weight once another connection is added
+    fn score(&self, picked: u64) -> f64 {
+        let connections = self.connections.unwrap_or(0) + picked + 1;
+        connections as f64 / f64::from(self.weight.unwrap_or(1))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Address registered under a name with the load it reported
+pub struct Instance {
+    /// Address of the instance
+    pub addr: Addr,
+    /// Load the instance reported when it last registered
+    pub load: Load,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How `RegistryClient::pick` chooses between the instances of a name
+pub enum Strategy {
+    /// Each instance in turn
+    RoundRobin,
+    /// An instance at random, in proportion to their weights
+    Random,
+    /// The instance with the fewest connections per weight, counting
+    /// the connections the client picked it for since its load was looked up
+    LeastLoaded,
+}
+
+/// instances registered under a name with the instant they expire at
+type Registrations = Vec<(Instance, Instant)>;
+
+#[derive(Debug, Clone, Default)]
+/// Registry of the addresses services can be reached at, by name.
+/// Registrations expire once their ttl elapses, so services have to register
+/// again before then to stay discoverable. The registry is exposed through
+/// any provider by handling its channels with `handle`.
+/// ```no_run
+/// # use canary::{discovery::Registry, providers::Tcp, Channel};
+/// # async fn run() -> canary::Result<()> {
+/// let registry = Registry::new();
+/// let server = Tcp::bind("0.0.0.0:7000").await?.serve(move |chan: Channel| {
+///     registry.clone().handle(chan)
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Registry {
+    services: Arc<Mutex<BTreeMap<CompactString, Registrations>>>,
+}
+
+impl Registry {
+    #[inline]
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    /// Register the address under the name until the ttl elapses.
+    /// Registering an address again refreshes its ttl.
+    /// Fails with `InvalidInput` if the ttl is longer than `MAX_TTL`.
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::discovery::Registry;
+    /// # fn run(registry: Registry) -> canary::Result<()> {
+    /// registry.register("users", "tcp@10.0.0.4:9000".parse()?, Duration::from_secs(30))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn register(&self, name: &str, addr: Addr, ttl: Duration) -> Result<()> {
+        self.register_with(name, addr, ttl, Load::default())
+    }
+
+    /// Register the address under the name with its load until the ttl elapses.
+    /// Registering an address again refreshes its ttl and load.
+    /// Fails with `InvalidInput` if the ttl is longer than `MAX_TTL`.
+    pub fn register_with(&self, name: &str, addr: Addr, ttl: Duration, load: Load) -> Result<()> {
+        if ttl > MAX_TTL {
+            err!((
+                invalid_input,
+                format!("ttl of {:?} is longer than the max of {:?}", ttl, MAX_TTL)
+            ))?
+        }
+        let expires = Instant::now() + ttl;
+        let mut services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        let instances = services.entry(name.into()).or_default();
+        match instances
+            .iter_mut()
+            .find(|(instance, _)| instance.addr == addr)
+        {
+            Some(registered) => *registered = (Instance { addr, load }, expires),
+            None => instances.push((Instance { addr, load }, expires)),
+        }
+        Ok(())
+    }
+
+    /// Remove the address from the name.
+    /// Returns `false` if it wasn't registered.
+    pub fn deregister(&self, name: &str, addr: &Addr) -> bool {
+        let mut services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        let instances = match services.get_mut(name) {
+            Some(instances) => instances,
+            None => return false,
+        };
+        let len = instances.len();
+        instances.retain(|(instance, _)| instance.addr != *addr);
+        let removed = instances.len() != len;
+        if instances.is_empty() {
+            services.remove(name);
+        }
+        removed
+    }
+
+    #[inline]
+    /// Get the addresses registered under the name, in the order they registered.
+    /// Fails with `NotFound` if there are none.
+    /// ```no_run
+    /// # use canary::discovery::Registry;
+    /// # fn run(registry: Registry) -> canary::Result<()> {
+    /// let addrs = registry.lookup("users")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn lookup(&self, name: &str) -> Result<Vec<Addr>> {
+        let instances = self.instances(name)?;
+        Ok(instances
+            .into_iter()
+            .map(|instance| instance.addr)
+            .collect())
+    }
+
+    #[inline]
+    /// Get the instances registered under the name with their load,
+    /// in the order they registered. Fails with `NotFound` if there are none.
+    pub fn instances(&self, name: &str) -> Result<Vec<Instance>> {
+        let entries = self.entries(name)?;
+        Ok(entries.into_iter().map(|(instance, _)| instance).collect())
+    }
+
+    /// instances of the name with the time they have left, dropping expired ones
+    fn entries(&self, name: &str) -> Result<Vec<(Instance, Duration)>> {
+        let now = Instant::now();
+        let mut services = self.services.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = vec![];
+        if let Some(instances) = services.get_mut(name) {
+            instances.retain(|(_, expires)| *expires > now);
+            entries.extend(
+                instances
+                    .iter()
+                    .map(|(instance, expires)| (instance.clone(), *expires - now)),
+            );
+            if instances.is_empty() {
+                services.remove(name);
+            }
+        }
+        if entries.is_empty() {
+            err!((not_found, format!("no service is registered as {:?}", name)))?
+        }
+        Ok(entries)
+    }
+
+    /// Answer the requests of a `RegistryClient` until it closes the channel.
+    /// Meant to be the handler of a provider, see `Registry`.
+    pub async fn handle(self, mut chan: Channel) -> Result<()> {
+        loop {
+            let request = match chan.receive().await {
+                Ok(request) => request,
+                Err(e) if is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match request {
+                Request::Register {
+                    name,
+                    addr,
+                    ttl,
+                    load,
+                } => {
+                    let registered = self.register_with(&name, addr, ttl, load);
+                    chan.send_result(registered).await?
+                }
+                Request::Lookup(name) => chan.send_result(self.entries(&name)).await?,
+                Request::Deregister { name, addr } => {
+                    chan.send_result(Ok(self.deregister(&name, &addr))).await?
+                }
+            };
+        }
+    }
+}
+
+/// instances of a name looked up by a client
+struct Cached {
+    instances: Vec<Instance>,
+    /// instant the first of the instances expires at
+    expires: Instant,
+    /// next instance of the round robin
+    next: usize,
+    /// connections picked for each instance since the lookup
+    picked: Vec<u64>,
+}
+
+/// Client of a `Registry` served through a channel.
+/// Lookups are cached until the first of their registrations expires.
+/// Calls that are cancelled leave their response in the channel,
+/// so the client shouldn't be used after a call is cancelled.
+/// ```no_run
+/// # use std::time::Duration;
+/// # use canary::discovery::RegistryClient;
+/// # async fn run() -> canary::Result<()> {
+/// let mut registry = RegistryClient::new(canary::connect("tcp@registry.local:7000").await?);
+/// registry.register("users", "tcp@10.0.0.4:9000".parse()?, Duration::from_secs(30)).await?;
+/// let mut chan = registry.connect("orders").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RegistryClient {
+    chan: Channel,
+    cache: BTreeMap<CompactString, Cached>,
+    /// addresses that failed to connect with the instant they can be picked again
+    failed: HashMap<Addr, Instant>,
+    cooldown: Duration,
+}
+
+impl RegistryClient {
+    #[inline]
+    /// Create a client that calls the registry at the other end of the channel
+    pub fn new(chan: Channel) -> Self {
+        RegistryClient {
+            chan,
+            cache: BTreeMap::new(),
+            failed: HashMap::new(),
+            cooldown: DEFAULT_COOLDOWN,
+        }
+    }
+
+    #[must_use]
+    /// Set the time instances that failed to connect aren't picked for,
+    /// `DEFAULT_COOLDOWN` by default
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    #[inline]
+    /// Register the address under the name until the ttl elapses,
+    /// refreshing its ttl if it was already registered
+    pub async fn register(&mut self, name: &str, addr: Addr, ttl: Duration) -> Result<()> {
+        self.register_with(name, addr, ttl, Load::default()).await
+    }
+
+    /// Register the address under the name with its load until the ttl elapses,
+    /// refreshing its ttl and load if it was already registered
+    pub async fn register_with(
+        &mut self,
+        name: &str,
+        addr: Addr,
+        ttl: Duration,
+        load: Load,
+    ) -> Result<()> {
+        self.cache.remove(name);
+        let name = name.into();
+        let request = Request::Register {
+            name,
+            addr,
+            ttl,
+            load,
+        };
+        self.call(request).await
+    }
+
+    /// Remove the address from the name.
+    /// Returns `false` if it wasn't registered.
+    pub async fn deregister(&mut self, name: &str, addr: Addr) -> Result<bool> {
+        self.cache.remove(name);
+        let name = name.into();
+        self.call(Request::Deregister { name, addr }).await
+    }
+
+    #[inline]
+    /// Get the addresses registered under the name, in the order they registered.
+    /// Fails with `NotFound` if there are none.
+    pub async fn lookup(&mut self, name: &str) -> Result<Vec<Addr>> {
+        let instances = self.instances(name).await?;
+        Ok(instances
+            .into_iter()
+            .map(|instance| instance.addr)
+            .collect())
+    }
+
+    /// Get the instances registered under the name with their load,
+    /// in the order they registered. Fails with `NotFound` if there are none.
+    pub async fn instances(&mut self, name: &str) -> Result<Vec<Instance>> {
+        let cached = self.cached(name).await?;
+        let instances = cached.instances.clone();
+        self.cache.insert(name.into(), cached);
+        Ok(instances)
+    }
+
+    /// Pick the address of an instance of the name with the strategy,
+    /// skipping the ones that failed to connect during the cooldown.
+    /// Fails with `NotFound` if there are no instances, and with
+    /// `ConnectionRefused` if all of them failed.
+    /// ```no_run
+    /// # use canary::discovery::Strategy;
+    /// # use canary::Channel;
+    /// # async fn handle(_: Channel) -> canary::Result<()> {
+    /// #     Ok(())
+    /// # }
+    /// # use canary::discovery::RegistryClient;
+    /// # async fn run(mut registry: RegistryClient) -> canary::Result<()> {
+    /// let addr = registry.pick("users", Strategy::LeastLoaded).await?;
+    /// match addr.connect().await {
+    ///     Ok(chan) => handle(chan).await?,
+    ///     Err(_) => registry.report_failure(&addr),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn pick(&mut self, name: &str, strategy: Strategy) -> Result<Addr> {
+        let now = Instant::now();
+        self.failed.retain(|_, until| *until > now);
+        let mut cached = self.cached(name).await?;
+        let available: Vec<usize> = (0..cached.instances.len())
+            .filter(|idx| !self.failed.contains_key(&cached.instances[*idx].addr))
+            .collect();
+        if available.is_empty() {
+            self.cache.insert(name.into(), cached);
+            return err!((
+                conn_refused,
+                format!("every instance of {:?} failed to connect recently", name)
+            ));
+        }
+        let load = |idx: usize| cached.instances[idx].load;
+        let idx = match strategy {
+            Strategy::RoundRobin => available[cached.next % available.len()],
+            Strategy::Random => {
+                let weights: Vec<u64> = available
+                    .iter()
+                    .map(|idx| u64::from(load(*idx).weight.unwrap_or(1)))
+                    .collect();
+                let total: u64 = weights.iter().sum();
+                let mut rng = rand::thread_rng();
+                if total == 0 {
+                    available[rng.gen_range(0..available.len())]
+                } else {
+                    let mut roll = rng.gen_range(0..total);
+                    let pos = weights
+                        .iter()
+                        .position(|weight| match roll.checked_sub(*weight) {
+                            Some(rest) => {
+                                roll = rest;
+                                false
+                            }
+                            None => true,
+                        })
+                        .unwrap_or(0);
+                    available[pos]
+                }
+            }
+            Strategy::LeastLoaded => available
+                .iter()
+                .copied()
+                .min_by(|a, b| {
+                    let a = load(*a).score(cached.picked[*a]);
+                    a.total_cmp(&load(*b).score(cached.picked[*b]))
+                })
+                .unwrap_or(available[0]),
+        };
+        cached.next += 1;
+        cached.picked[idx] += 1;
+        let addr = cached.instances[idx].addr.clone();
+        self.cache.insert(name.into(), cached);
+        Ok(addr)
+    }
+
+    #[inline]
+    /// Stop picking the address for the cooldown, because connecting to it failed
+    pub fn report_failure(&mut self, addr: &Addr) {
+        self.failed
+            .insert(addr.clone(), Instant::now() + self.cooldown);
+    }
+
+    /// Look up the name and connect to the first of its addresses that accepts,
+    /// like `AddrSet::connect`. Failed connections drop the cached addresses,
+    /// so the next attempt looks them up again.
+    /// ```no_run
+    /// # use canary::discovery::RegistryClient;
+    /// # async fn run(mut registry: RegistryClient) -> canary::Result<()> {
+    /// let mut chan = registry.connect("orders").await?;
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(&mut self, name: &str) -> Result<Channel> {
+        let addrs = AddrSet::new(self.lookup(name).await?)?;
+        let chan = addrs.connect().await;
+        if chan.is_err() {
+            self.cache.remove(name);
+        }
+        chan
+    }
+
+    /// Connect to instances of the name picked with the strategy until one accepts,
+    /// reporting the ones that fail. Fails with the error of the last instance
+    /// once every instance failed.
+    /// ```no_run
+    /// # use canary::discovery::Strategy;
+    /// # use canary::discovery::RegistryClient;
+    /// # async fn run(mut registry: RegistryClient) -> canary::Result<()> {
+    /// let mut chan = registry.connect_picked("orders", Strategy::LeastLoaded).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_picked(&mut self, name: &str, strategy: Strategy) -> Result<Channel> {
+        let mut last = None;
+        loop {
+            let addr = match self.pick(name, strategy).await {
+                Ok(addr) => addr,
+                // once every instance failed, the last failure says more
+                Err(e) => return Err(last.unwrap_or(e)),
+            };
+            match addr.connect().await {
+                Ok(chan) => return Ok(chan),
+                Err(e) => {
+                    tracing::debug!("connecting to {} failed: {}", addr, e);
+                    self.report_failure(&addr);
+                    last = Some(e);
+                }
+            }
+        }
+    }
+
+    /// instances of the name, looked up if they aren't cached or expired.
+    /// they are taken out of the cache, to be put back by the caller
+    async fn cached(&mut self, name: &str) -> Result<Cached> {
+        let now = Instant::now();
+        match self.cache.remove(name) {
+            Some(cached) if cached.expires > now => return Ok(cached),
+            _ => {}
+        }
+        let entries: Vec<(Instance, Duration)> = self.call(Request::Lookup(name.into())).await?;
+        let ttl = entries
+            .iter()
+            .map(|(_, ttl)| *ttl)
+            .min()
+            .unwrap_or_default();
+        Ok(Cached {
+            picked: vec![0; entries.len()],
+            instances: entries.into_iter().map(|(instance, _)| instance).collect(),
+            // the ttl comes from the peer, which may send any duration
+            expires: now.checked_add(ttl).unwrap_or(now),
+            next: 0,
+        })
+    }
+
+    async fn call<T: serde::de::DeserializeOwned>(&mut self, request: Request) -> Result<T> {
+        self.chan.send(request).await?;
+        self.chan.receive_result().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::Mem;
+
+    // registry served at mem@name, with a client for each service
+    async fn clients(name: &str, count: usize) -> Vec<RegistryClient> {
+        let addr: Addr = format!("mem@{}", name).parse().unwrap();
+        let registry = Registry::new();
+        addr.bind()
+            .await
+            .unwrap()
+            .serve(move |chan: Channel| registry.clone().handle(chan))
+            .unwrap();
+        let mut clients = vec![];
+        for _ in 0..count {
+            clients.push(RegistryClient::new(addr.connect().await.unwrap()));
+        }
+        clients
+    }
+
+    #[tokio::test]
+    async fn services_discover_each_other() {
+        let mut clients = clients("registry-discover", 2).await;
+        let (users_addr, orders_addr): (Addr, Addr) = (
+            "mem@registry-users".parse().unwrap(),
+            "mem@registry-orders".parse().unwrap(),
+        );
+        let users = Mem::bind("registry-users").await.unwrap();
+        let ttl = Duration::from_secs(30);
+        clients[0]
+            .register("users", users_addr.clone(), ttl)
+            .await
+            .unwrap();
+        clients[1]
+            .register("orders", orders_addr.clone(), ttl)
+            .await
+            .unwrap();
+
+        assert_eq!(clients[0].lookup("orders").await.unwrap(), [orders_addr]);
+        assert_eq!(clients[1].lookup("users").await.unwrap(), [users_addr]);
+
+        let (chan, accepted) = tokio::join!(clients[1].connect("users"), async {
+            users.next().await?.encrypted().await
+        });
+        let (mut chan, mut accepted) = (chan.unwrap(), accepted.unwrap());
+        chan.send("hello!").await.unwrap();
+        assert_eq!(accepted.receive::<String>().await.unwrap(), "hello!");
+    }
+
+    #[tokio::test]
+    async fn unknown_names_are_not_found() {
+        let mut clients = clients("registry-unknown", 1).await;
+        let e = clients[0].lookup("nobody").await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn overlong_ttls_are_rejected() {
+        let mut clients = clients("registry-ttl", 1).await;
+        let addr: Addr = "mem@registry-ttl-service".parse().unwrap();
+        let e = clients[0]
+            .register("service", addr.clone(), Duration::MAX)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+
+        // the registry keeps answering the client
+        let e = clients[0].lookup("service").await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+        clients[0].register("service", addr, MAX_TTL).await.unwrap();
+    }
+
+    // number of times each address is picked out of `count` picks
+    async fn picks(
+        client: &mut RegistryClient,
+        addrs: &[Addr],
+        strategy: Strategy,
+        count: usize,
+    ) -> [u32; 3] {
+        let mut picked = [0; 3];
+        for _ in 0..count {
+            let addr = client.pick("service", strategy).await.unwrap();
+            picked[addrs.iter().position(|a| *a == addr).unwrap()] += 1;
+        }
+        picked
+    }
+
+    #[tokio::test]
+    async fn picks_spread_over_loaded_instances() {
+        let mut clients = clients("registry-pick", 1).await;
+        let client = &mut clients[0];
+        let ttl = Duration::from_secs(30);
+        // connections and weight of each instance
+        let loads = [(0, 1), (10, 2), (4, 1)];
+        let mut addrs = vec![];
+        for (idx, (connections, weight)) in loads.into_iter().enumerate() {
+            let addr: Addr = format!("mem@registry-pick-{}", idx).parse().unwrap();
+            let load = Load::default().connections(connections).weight(weight);
+            client
+                .register_with("service", addr.clone(), ttl, load)
+                .await
+                .unwrap();
+            addrs.push(addr);
+        }
+        assert_eq!(
+            picks(client, &addrs, Strategy::RoundRobin, 30).await,
+            [10, 10, 10]
+        );
+        // weights of 1, 2 and 1
+        let random = picks(client, &addrs, Strategy::Random, 4000).await;
+        for (picked, expected) in random.into_iter().zip([1000, 2000, 1000]) {
+            assert!(picked.abs_diff(expected) < 200, "{:?}", random);
+        }
+        // a fresh lookup starts counting picks over. the 44 connections over a
+        // total weight of 4 are 11 per weight, which the picks even out to
+        client.cache.clear();
+        assert_eq!(
+            picks(client, &addrs, Strategy::LeastLoaded, 30).await,
+            [11, 12, 7]
+        );
+
+        client.report_failure(&addrs[1]);
+        assert_eq!(picks(client, &addrs, Strategy::RoundRobin, 10).await[1], 0);
+        client.report_failure(&addrs[0]);
+        client.report_failure(&addrs[2]);
+        let e = client.pick("service", Strategy::Random).await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -14,16 +14,20 @@ cfg_if! {
         pub(crate) use tokio::io::WriteHalf;
         pub(crate) use tokio::io::ReadHalf;
         pub(crate) use tokio::io::split;
+        pub(crate) use tokio::io::{duplex, DuplexStream};
 
         pub(crate) use tokio::net::ToSocketAddrs;
 
-        pub(crate) use tokio::time::sleep;
+        pub(crate) use tokio::time::{sleep, timeout, timeout_at, Instant};
         pub(crate) use async_tungstenite as wss;
 
         pub(crate) type Wss = crate::io::wss::WebSocketStream<
-            async_tungstenite::tokio::TokioAdapter<TcpStream>
+            async_tungstenite::tokio::TokioAdapter<crate::providers::WssStream>
         >;
         pub(crate) type Message = tungstenite::Message;
+
+        #[cfg(feature = "tls")]
+        pub(crate) type Tls = tokio_rustls::TlsStream<TcpStream>;
     } else if #[cfg(target_arch = "wasm32")] {
         pub(crate) use futures::io::AsyncRead as Read;
         pub(crate) use futures::io::AsyncReadExt as ReadExt;
@@ -33,3 +37,15 @@ cfg_if! {
         pub(crate) type Message = reqwasm::websocket::Message;
     }
 }
+
+/// handle of the tokio runtime canary spawns its background tasks on,
+/// an error instead of a panic if none is running
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn runtime() -> crate::Result<tokio::runtime::Handle> {
+    tokio::runtime::Handle::try_current().map_err(|_| {
+        crate::err!(
+            other,
+            "no tokio runtime is running, canary runs background tasks on it"
+        )
+    })
+}
@@ -14,6 +14,23 @@
 //! Channels help communicate through the network,
 //! and providers help create endpoints through which you can get Channels.
 //!
+//! The quickest way to get a channel is [`connect`], which parses the address,
+//! connects and encrypts the channel according to the address scheme:
+//! ```no_run
+//! # async fn run() -> canary::Result<()> {
+//! let mut chan = canary::connect("tcp@127.0.0.1:8080").await?;
+//! chan.send("hello!").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Channels serialize with Bincode by default, which is always available.
+//! JSON, BSON, postcard and MessagePack are enabled by default through the
+//! `json_ser`, `bson_ser`, `postcard_ser` and `messagepack_ser` features,
+//! which can be turned off to trim dependencies, the first three also under
+//! the names `format-json`, `format-bson` and `format-postcard`.
+//! `cbor_ser`, `ron_ser` and `flexbuffers_ser` enable the other formats.
+//!
 //! The crate is well-documented, but if you need any examples
 //! you should use [the book](https://znx3p0.github.io/canary-book/),
 //! and additional questions should be asked in [the discord](https://discord.gg/QaWxMzAZs8)
@@ -22,6 +39,8 @@
 pub mod async_snow;
 /// Contains channels and constructs associated with them
 pub mod channel;
+/// Contains a registry to find services by name
+pub mod discovery;
 mod io;
 /// Contains common imports
 pub mod prelude;
@@ -37,5 +56,8 @@ pub mod serialization;
 pub mod type_iter;
 
 pub use channel::channels::Channel;
+pub use providers::connect;
 
+#[cfg(feature = "verifier")]
+pub use async_trait::async_trait;
 pub use io_err::{err, Error, Result};
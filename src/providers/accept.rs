@@ -0,0 +1,179 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{select, Future, FutureExt, StreamExt};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::err;
+use crate::io::{sleep, timeout};
+use crate::Result;
+
+/// time to wait before accepting again after running out of resources
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// time peers have to complete the handshake of their transport,
+/// such as the websocket upgrade or the tls handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// accept a connection, retrying on errors that only affect a single connection
+/// or that go away as connections close, such as running out of file descriptors.
+/// Other errors, such as the listener being closed, are returned.
+pub(crate) async fn retry<T, Fut>(mut accept: impl FnMut() -> Fut) -> Result<T>
+where
+    Fut: Future<Output = std::io::Result<T>>,
+{
+    loop {
+        let e = match accept().await {
+            Ok(conn) => return Ok(conn),
+            Err(e) => e,
+        };
+        match backoff(&e) {
+            Some(backoff) => {
+                tracing::warn!("accepting a connection failed, retrying: {}", e);
+                if !backoff.is_zero() {
+                    sleep(backoff).await;
+                }
+            }
+            None => return Err(e.into()),
+        }
+    }
+}
+
+/// time to wait before accepting again after the error, `None` if it's fatal
+fn backoff(e: &std::io::Error) -> Option<Duration> {
+    match e.kind() {
+        ErrorKind::ConnectionAborted
+        | ErrorKind::ConnectionReset
+        | ErrorKind::Interrupted
+        | ErrorKind::WouldBlock
+        | ErrorKind::TimedOut => return Some(Duration::ZERO),
+        ErrorKind::OutOfMemory => return Some(ACCEPT_BACKOFF),
+        _ => (),
+    }
+    // file descriptors and buffers free up as connections close
+    #[cfg(unix)]
+    let exhausted = [libc::EMFILE, libc::ENFILE, libc::ENOBUFS, libc::ENOMEM];
+    // WSAEMFILE and WSAENOBUFS
+    #[cfg(windows)]
+    let exhausted = [10024, 10055];
+    #[cfg(not(any(unix, windows)))]
+    let exhausted: [i32; 0] = [];
+    match e.raw_os_error() {
+        #[cfg(unix)]
+        Some(libc::EPROTO) => Some(Duration::ZERO),
+        Some(code) if exhausted.contains(&code) => Some(ACCEPT_BACKOFF),
+        _ => None,
+    }
+}
+
+/// connections accepted by a listener that are still running the handshake
+/// of their transport. They are driven by `next`, so a peer that is slow or
+/// sends garbage only fails its own connection instead of holding up the others.
+pub(crate) struct Accepting<T> {
+    pending: AsyncMutex<FuturesUnordered<BoxFuture<'static, Result<T>>>>,
+}
+
+impl<T> Default for Accepting<T> {
+    fn default() -> Self {
+        Accepting {
+            pending: Default::default(),
+        }
+    }
+}
+
+impl<T> Accepting<T> {
+    /// accept connections and run their handshakes until one completes.
+    /// failed handshakes are logged and skipped.
+    ///
+    /// CANCEL SAFETY: connections accepted by a cancelled call are
+    /// kept and completed by the next call.
+    pub(crate) async fn next<S, AcceptFut, HandshakeFut>(
+        &self,
+        mut accept: impl FnMut() -> AcceptFut,
+        handshake: impl Fn(S) -> HandshakeFut,
+    ) -> Result<T>
+    where
+        AcceptFut: Future<Output = std::io::Result<S>>,
+        HandshakeFut: Future<Output = Result<T>> + Send + 'static,
+    {
+        let mut pending = self.pending.lock().await;
+        loop {
+            select! {
+                // skipped while no handshake is running
+                conn = pending.select_next_some() => match conn {
+                    Ok(conn) => return Ok(conn),
+                    Err(e) => tracing::debug!("handshake of an accepted connection failed: {}", e),
+                },
+                stream = retry(&mut accept).fuse() => {
+                    let handshake = timeout(HANDSHAKE_TIMEOUT, handshake(stream?));
+                    pending.push(Box::pin(async move {
+                        match handshake.await {
+                            Ok(conn) => conn,
+                            Err(_) => err!((timeout, "handshake timed out")),
+                        }
+                    }));
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    // accept that fails with `errors` before accepting
+    async fn accept_after(errors: Vec<std::io::Error>) -> (Result<()>, usize) {
+        let errors = Mutex::new(VecDeque::from(errors));
+        let attempts = Mutex::new(0);
+        let accepted = retry(|| {
+            *attempts.lock().unwrap() += 1;
+            let next = errors.lock().unwrap().pop_front();
+            async move { next.map_or(Ok(()), Err) }
+        })
+        .await;
+        (accepted, attempts.into_inner().unwrap())
+    }
+
+    #[tokio::test]
+    async fn aborted_connections_are_skipped() {
+        let aborted = || std::io::Error::from(ErrorKind::ConnectionAborted);
+        let (accepted, attempts) = accept_after(vec![aborted(), aborted()]).await;
+        assert!(accepted.is_ok());
+        assert_eq!(attempts, 3);
+        #[cfg(unix)]
+        {
+            let errors = [libc::ECONNABORTED, libc::EPROTO].map(std::io::Error::from_raw_os_error);
+            let (accepted, attempts) = accept_after(errors.into()).await;
+            assert!(accepted.is_ok());
+            assert_eq!(attempts, 3);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn exhausted_resources_back_off() {
+        let start = std::time::Instant::now();
+        let emfile = std::io::Error::from_raw_os_error(libc::EMFILE);
+        let (accepted, attempts) = accept_after(vec![emfile]).await;
+        assert!(accepted.is_ok());
+        assert_eq!(attempts, 2);
+        assert!(start.elapsed() >= ACCEPT_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn fatal_errors_are_returned() {
+        let closed = std::io::Error::new(ErrorKind::InvalidInput, "listener closed");
+        let (accepted, attempts) = accept_after(vec![closed]).await;
+        let e = accepted.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        assert_eq!(attempts, 1);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::async_snow::{HandshakePattern, SnowConfig};
 use crate::{err, Error};
 use crate::{Channel, Result};
 use cfg_if::cfg_if;
@@ -18,38 +19,92 @@ use super::WebSocket;
 
 cfg_if! {
     if #[cfg(not(target_arch = "wasm32"))] {
+        use crate::channel::handshake::Handshake;
+        use crate::providers::ConnectOptions;
         use crate::providers::Tcp;
+        use crate::providers::Mem;
         #[cfg(unix)]
         use crate::providers::Unix;
+        #[cfg(feature = "tls")]
+        use crate::providers::Tls;
+        #[cfg(feature = "quic")]
+        use crate::providers::Quic;
+        #[cfg(feature = "srv")]
+        use crate::providers::SrvResolver;
     }
 }
 
 #[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
 /// Represents the address of a provider.
 /// ```no_run
+/// # use canary::providers::Addr;
+/// # async fn run() -> canary::Result<()> {
 /// let tcp = "tcp@127.0.0.1:8080".parse::<Addr>()?;
 /// let unix = "unix@mysocket.sock".parse::<Addr>()?;
 /// let insecure_tcp = "itcp@127.0.0.1:8080".parse::<Addr>()?;
 /// let insecure_unix = "iunix@mysocket.sock".parse::<Addr>()?;
+/// let authenticated_tcp = "tcp+xx@127.0.0.1:8080".parse::<Addr>()?;
+/// let hostname = "tcp@db.internal:5432".parse::<Addr>()?;
+/// let tls = "itls@example.com:443".parse::<Addr>()?;
+/// let quic = "quic@example.com:4433".parse::<Addr>()?;
+/// let mem = "mem@service".parse::<Addr>()?;
+/// let ws = "ws@gateway.example.com:80/canary".parse::<Addr>()?;
+/// let srv = "srv@_canary._tcp.myservice.internal".parse::<Addr>()?;
 ///
 /// tcp.bind().await?; // bind all addresses to the global route
 /// unix.bind().await?;
 /// insecure_tcp.bind().await?;
 /// insecure_unix.bind().await?;
+/// # Ok(())
+/// # }
 /// ```
 pub enum Addr {
-    /// Tcp provider
-    Tcp(Arc<SocketAddr>),
-    /// Unix provider
-    Unix(Arc<PathBuf>),
+    /// Tcp provider, with the handshake pattern of its suffix if any
+    Tcp(Arc<HostAddr>, Option<Suite>),
+    /// Unix provider, with the handshake pattern of its suffix if any
+    Unix(Arc<PathBuf>, Option<Suite>),
     /// Unencrypted tcp provider
-    InsecureTcp(Arc<SocketAddr>),
+    InsecureTcp(Arc<HostAddr>),
     /// Unencrypted unix provider
     InsecureUnix(Arc<PathBuf>),
-    /// Websocket provider
-    Wss(Arc<CompactString>),
-    /// Unencrypted websocket provider
+    /// Websocket provider over tls (`wss://`) at `host:port` and an optional path,
+    /// encrypted with noise inside the tls session,
+    /// with the handshake pattern of its suffix if any
+    Wss(Arc<CompactString>, Option<Suite>),
+    /// Unencrypted websocket provider without tls (`ws://`) at `host:port`
+    /// and an optional path
     InsecureWss(Arc<CompactString>),
+    /// Tls provider at `host:port`, encrypted with noise inside the tls session,
+    /// with the handshake pattern of its suffix if any
+    Tls(Arc<CompactString>, Option<Suite>),
+    /// Tls provider at `host:port`, relying on tls alone
+    InsecureTls(Arc<CompactString>),
+    /// Quic provider at `host:port`, relying on the tls session of quic
+    Quic(Arc<CompactString>),
+    /// In-memory provider bound to a name, with the handshake pattern of its suffix if any
+    Mem(Arc<CompactString>, Option<Suite>),
+    /// Unencrypted in-memory provider
+    InsecureMem(Arc<CompactString>),
+    /// Tcp providers at the targets of the srv records of a name,
+    /// with the handshake pattern of its suffix if any
+    Srv(Arc<CompactString>, Option<Suite>),
+    /// Unencrypted tcp providers at the targets of the srv records of a name
+    InsecureSrv(Arc<CompactString>),
+}
+
+#[inline]
+/// Connect to the provider at the given address, parsing it as an [`Addr`]
+/// and encrypting the channel according to its scheme.
+/// This is a thin wrapper over `Addr::connect`.
+/// ```no_run
+/// # async fn run() -> canary::Result<()> {
+/// let mut chan = canary::connect("tcp@127.0.0.1:8080").await?;
+/// chan.send("hello!").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn connect(addr: &str) -> Result<Channel> {
+    addr.parse::<Addr>()?.connect().await
 }
 
 impl From<&Addr> for String {
@@ -63,11 +118,11 @@ impl Display for Addr {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Addr::Tcp(addr) => {
-                write!(f, "tcp@{}", addr)
+            Addr::Tcp(addr, suite) => {
+                write!(f, "tcp{}@{}", SuiteSuffix(suite), addr)
             }
-            Addr::Unix(addr) => {
-                write!(f, "unix@{}", addr.to_string_lossy())
+            Addr::Unix(addr, suite) => {
+                write!(f, "unix{}@{}", SuiteSuffix(suite), addr.to_string_lossy())
             }
             Addr::InsecureTcp(addr) => {
                 write!(f, "itcp@{}", addr)
@@ -75,12 +130,33 @@ impl Display for Addr {
             Addr::InsecureUnix(addr) => {
                 write!(f, "iunix@{}", addr.to_string_lossy())
             }
-            Addr::Wss(addr) => {
-                write!(f, "wss@{}", addr)
+            Addr::Wss(addr, suite) => {
+                write!(f, "wss{}@{}", SuiteSuffix(suite), addr)
             }
             Addr::InsecureWss(addr) => {
                 write!(f, "ws@{}", addr)
             }
+            Addr::Tls(addr, suite) => {
+                write!(f, "tls{}@{}", SuiteSuffix(suite), addr)
+            }
+            Addr::InsecureTls(addr) => {
+                write!(f, "itls@{}", addr)
+            }
+            Addr::Quic(addr) => {
+                write!(f, "quic@{}", addr)
+            }
+            Addr::Mem(addr, suite) => {
+                write!(f, "mem{}@{}", SuiteSuffix(suite), addr)
+            }
+            Addr::InsecureMem(addr) => {
+                write!(f, "imem@{}", addr)
+            }
+            Addr::Srv(addr, suite) => {
+                write!(f, "srv{}@{}", SuiteSuffix(suite), addr)
+            }
+            Addr::InsecureSrv(addr) => {
+                write!(f, "isrv@{}", addr)
+            }
         }
     }
 }
@@ -102,28 +178,59 @@ impl Serialize for Addr {
             self.to_string().serialize(serializer)
         } else {
             let addr_ty = match &self {
-                Addr::Tcp(_) => AddressType::Tcp,
-                Addr::Unix(_) => AddressType::Unix,
+                Addr::Tcp(addr, _) if addr.is_name() => AddressType::TcpHost,
+                Addr::Tcp(..) => AddressType::Tcp,
+                Addr::Unix(..) => AddressType::Unix,
+                Addr::InsecureTcp(addr) if addr.is_name() => AddressType::InsecureTcpHost,
                 Addr::InsecureTcp(_) => AddressType::InsecureTcp,
                 Addr::InsecureUnix(_) => AddressType::InsecureUnix,
-                Addr::Wss(_) => AddressType::Wss,
+                Addr::Wss(..) => AddressType::Wss,
                 Addr::InsecureWss(_) => AddressType::InsecureWss,
+                Addr::Tls(..) => AddressType::Tls,
+                Addr::InsecureTls(_) => AddressType::InsecureTls,
+                Addr::Quic(_) => AddressType::Quic,
+                Addr::Mem(..) => AddressType::Mem,
+                Addr::InsecureMem(_) => AddressType::InsecureMem,
+                Addr::Srv(..) => AddressType::Srv,
+                Addr::InsecureSrv(_) => AddressType::InsecureSrv,
             };
-            let mut ser = serializer.serialize_seq(Some(2))?;
+            let suite = self.suite();
+            // the suite is only sent if there's one, so older peers can read the address
+            let len = if suite.is_some() { 3 } else { 2 };
+            let mut ser = serializer.serialize_seq(Some(len))?;
             ser.serialize_element(&addr_ty)?;
             match self {
-                Addr::Tcp(addr) => ser.serialize_element(addr)?,
-                Addr::Unix(addr) => ser.serialize_element(addr)?,
-                Addr::InsecureTcp(addr) => ser.serialize_element(addr)?,
+                Addr::Tcp(addr, _) => serialize_host_addr(&mut ser, addr)?,
+                Addr::Unix(addr, _) => ser.serialize_element(addr)?,
+                Addr::InsecureTcp(addr) => serialize_host_addr(&mut ser, addr)?,
                 Addr::InsecureUnix(addr) => ser.serialize_element(addr)?,
-                Addr::Wss(addr) => ser.serialize_element(addr)?,
+                Addr::Wss(addr, _) => ser.serialize_element(addr)?,
                 Addr::InsecureWss(addr) => ser.serialize_element(addr)?,
+                Addr::Tls(addr, _) => ser.serialize_element(addr)?,
+                Addr::InsecureTls(addr) => ser.serialize_element(addr)?,
+                Addr::Quic(addr) => ser.serialize_element(addr)?,
+                Addr::Mem(addr, _) => ser.serialize_element(addr)?,
+                Addr::InsecureMem(addr) => ser.serialize_element(addr)?,
+                Addr::Srv(addr, _) => ser.serialize_element(addr)?,
+                Addr::InsecureSrv(addr) => ser.serialize_element(addr)?,
             };
+            if let Some(suite) = suite {
+                ser.serialize_element(&suite)?;
+            }
             ser.end()
         }
     }
 }
 
+/// socket addresses are serialized as they were before hostnames were
+/// supported, hostnames as `(host, port)`
+fn serialize_host_addr<S: SerializeSeq>(ser: &mut S, addr: &HostAddr) -> Result<(), S::Error> {
+    match addr {
+        HostAddr::Socket(addr) => ser.serialize_element(addr),
+        HostAddr::Name(host, port) => ser.serialize_element(&(host, port)),
+    }
+}
+
 impl<'de> Deserialize<'de> for Addr {
     #[inline]
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -152,22 +259,38 @@ impl<'de> Deserialize<'de> for Addr {
                                 "expected AddressType, found nothing",
                             ))?;
                     use AddressType::*;
-                    Ok(match addr_ty {
+                    let addr = match addr_ty {
                         Tcp => seq
                             .next_element()?
-                            .and_then(|addr| Some(Addr::Tcp(addr)))
+                            .and_then(|addr: SocketAddr| {
+                                Some(Addr::Tcp(Arc::new(addr.into()), None))
+                            })
                             .ok_or(serde::de::Error::custom(
                                 "expected SocketAddr, found nothing",
                             ))?,
                         InsecureTcp => seq
                             .next_element()?
-                            .and_then(|addr| Some(Addr::InsecureTcp(addr)))
+                            .and_then(|addr: SocketAddr| {
+                                Some(Addr::InsecureTcp(Arc::new(addr.into())))
+                            })
                             .ok_or(serde::de::Error::custom(
                                 "expected SocketAddr, found nothing",
                             ))?,
+                        TcpHost => seq
+                            .next_element()?
+                            .map(|(host, port)| {
+                                Addr::Tcp(Arc::new(HostAddr::Name(host, port)), None)
+                            })
+                            .ok_or(serde::de::Error::custom("expected host, found nothing"))?,
+                        InsecureTcpHost => seq
+                            .next_element()?
+                            .map(|(host, port)| {
+                                Addr::InsecureTcp(Arc::new(HostAddr::Name(host, port)))
+                            })
+                            .ok_or(serde::de::Error::custom("expected host, found nothing"))?,
                         Unix => seq
                             .next_element()?
-                            .and_then(|addr| Some(Addr::Unix(addr)))
+                            .and_then(|addr| Some(Addr::Unix(addr, None)))
                             .ok_or(serde::de::Error::custom("expected Path, found nothing"))?,
                         InsecureUnix => seq
                             .next_element()?
@@ -175,13 +298,47 @@ impl<'de> Deserialize<'de> for Addr {
                             .ok_or(serde::de::Error::custom("expected Path, found nothing"))?,
                         Wss => seq
                             .next_element()?
-                            .and_then(|addr| Some(Addr::Wss(addr)))
+                            .and_then(|addr| Some(Addr::Wss(addr, None)))
                             .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
                         InsecureWss => seq
                             .next_element()?
                             .and_then(|addr| Some(Addr::InsecureWss(addr)))
                             .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
-                    })
+                        Tls => seq
+                            .next_element()?
+                            .map(|addr| Addr::Tls(addr, None))
+                            .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
+                        InsecureTls => seq
+                            .next_element()?
+                            .map(Addr::InsecureTls)
+                            .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
+                        Quic => seq
+                            .next_element()?
+                            .map(Addr::Quic)
+                            .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
+                        Mem => seq
+                            .next_element()?
+                            .map(|addr| Addr::Mem(addr, None))
+                            .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
+                        InsecureMem => seq
+                            .next_element()?
+                            .map(Addr::InsecureMem)
+                            .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
+                        Srv => seq
+                            .next_element()?
+                            .map(|addr| Addr::Srv(addr, None))
+                            .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
+                        InsecureSrv => seq
+                            .next_element()?
+                            .map(Addr::InsecureSrv)
+                            .ok_or(serde::de::Error::custom("expected String, found nothing"))?,
+                    };
+                    match seq.next_element::<Suite>()? {
+                        Some(suite) => addr
+                            .with_suite(suite)
+                            .map_err(|e| serde::de::Error::custom(e.to_string())),
+                        None => Ok(addr),
+                    }
                 }
             }
             deserializer.deserialize_seq(visitor)
@@ -196,40 +353,185 @@ impl Addr {
         addr.parse()
     }
 
+    /// Get the handshake pattern of the suffix of the address, if any
+    pub fn suite(&self) -> Option<Suite> {
+        match self {
+            Addr::Tcp(_, suite)
+            | Addr::Unix(_, suite)
+            | Addr::Wss(_, suite)
+            | Addr::Tls(_, suite)
+            | Addr::Mem(_, suite)
+            | Addr::Srv(_, suite) => *suite,
+            _ => None,
+        }
+    }
+
+    /// Set the handshake pattern of the address.
+    /// Returns an error if the address is insecure.
+    pub fn with_suite(self, suite: Suite) -> Result<Self> {
+        Ok(match self {
+            Addr::Tcp(addr, _) => Addr::Tcp(addr, Some(suite)),
+            Addr::Unix(addr, _) => Addr::Unix(addr, Some(suite)),
+            Addr::Wss(addr, _) => Addr::Wss(addr, Some(suite)),
+            Addr::Tls(addr, _) => Addr::Tls(addr, Some(suite)),
+            Addr::Mem(addr, _) => Addr::Mem(addr, Some(suite)),
+            Addr::Srv(addr, _) => Addr::Srv(addr, Some(suite)),
+            Addr::Quic(_) => err!((
+                invalid_input,
+                "quic addresses can't have a handshake pattern"
+            ))?,
+            _ => err!((
+                invalid_input,
+                "insecure addresses can't have a handshake pattern"
+            ))?,
+        })
+    }
+
     #[inline]
     /// connect to the address
     pub async fn connect(&self) -> Result<Channel> {
+        self.connect_with(&SnowConfig::default()).await
+    }
+
+    /// connect to the address, using the configuration to encrypt the channel.
+    /// the handshake pattern of the address suffix overrides the one of the
+    /// configuration, and insecure addresses ignore the configuration.
+    pub async fn connect_with(&self, config: &SnowConfig) -> Result<Channel> {
         cfg_if! {
             if #[cfg(target_arch = "wasm32")] {
+                let config = &match self.suite() {
+                    Some(suite) => config.clone().pattern(suite.pattern()),
+                    None => config.clone(),
+                };
                 match self {
-                    Addr::Wss(addrs) => WebSocket::connect(addrs.as_str()).await?.encrypted().await,
+                    Addr::Wss(addrs, _) => WebSocket::connect_tls(addrs.as_str()).await?.encrypted_with(config).await,
                     Addr::InsecureWss(addrs) => Ok(WebSocket::connect(addrs.as_str()).await?.raw()),
-                    Addr::Tcp(_) | Addr::InsecureTcp(_) => err!((
+                    Addr::Tcp(..) | Addr::InsecureTcp(_) => err!((
                         unsupported,
                         "connecting to tcp providers is not supported on wasm"
                     )),
-                    Addr::Unix(_) | Addr::InsecureUnix(_) => err!((
+                    Addr::Unix(..) | Addr::InsecureUnix(_) => err!((
                         unsupported,
                         "connecting to unix providers is not supported on wasm"
                     )),
+                    Addr::Tls(..) | Addr::InsecureTls(_) => err!((
+                        unsupported,
+                        "connecting to tls providers is not supported on wasm"
+                    )),
+                    Addr::Quic(_) => err!((
+                        unsupported,
+                        "connecting to quic providers is not supported on wasm"
+                    )),
+                    Addr::Mem(..) | Addr::InsecureMem(_) => err!((
+                        unsupported,
+                        "connecting to mem providers is not supported on wasm"
+                    )),
+                    Addr::Srv(..) | Addr::InsecureSrv(_) => err!((
+                        unsupported,
+                        "connecting to srv addresses is not supported on wasm"
+                    )),
                 }
-            } else if #[cfg(unix)] {
+            } else {
+                self.connect_with_options(config, ConnectOptions::default()).await
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// connect to the address like `connect_with`, within the time limits of the options.
+    /// They bound connecting, the encryption handshake has the timeout of the configuration.
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::{async_snow::SnowConfig, providers::{Addr, ConnectOptions}};
+    /// # async fn run(addr: Addr) -> canary::Result<()> {
+    /// let options = ConnectOptions::default().timeout(Duration::from_millis(100));
+    /// let mut chan = addr.connect_with_options(&SnowConfig::default(), options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with_options(
+        &self,
+        config: &SnowConfig,
+        options: ConnectOptions,
+    ) -> Result<Channel> {
+        let config = &match self.suite() {
+            Some(suite) => config.clone().pattern(suite.pattern()),
+            None => config.clone(),
+        };
+        cfg_if! {
+            if #[cfg(unix)] {
                 match self {
-                    Addr::Tcp(addrs) => Tcp::connect(addrs.as_ref()).await?.encrypted().await,
-                    Addr::InsecureTcp(addrs) => Ok(Tcp::connect(addrs.as_ref()).await?.raw()),
-                    Addr::Unix(addrs) => Unix::connect(addrs.as_ref()).await?.encrypted().await,
-                    Addr::InsecureUnix(addrs) => Ok(Unix::connect(addrs.as_ref()).await?.raw()),
-                    Addr::Wss(addrs) => WebSocket::connect(addrs.as_str()).await?.encrypted().await,
-                    Addr::InsecureWss(addrs) => Ok(WebSocket::connect(addrs.as_str()).await?.raw()),
+                    Addr::Tcp(addrs, _) => addrs.connect(options).await?.encrypted_with(config).await,
+                    Addr::InsecureTcp(addrs) => Ok(addrs.connect(options).await?.raw()),
+                    Addr::Mem(addrs, _) => Mem::connect(addrs.as_str()).await?.encrypted_with(config).await,
+                    Addr::InsecureMem(addrs) => Ok(Mem::connect(addrs.as_str()).await?.raw()),
+                    Addr::Unix(addrs, _) => Unix::connect_options(addrs.as_ref(), options).await?.encrypted_with(config).await,
+                    Addr::InsecureUnix(addrs) => Ok(Unix::connect_options(addrs.as_ref(), options).await?.raw()),
+                    #[cfg(feature = "tls")]
+                    Addr::Wss(addrs, _) => WebSocket::connect_request(&format!("wss://{}", addrs), &[], options, None).await?.encrypted_with(config).await,
+                    Addr::InsecureWss(addrs) => Ok(WebSocket::connect_options(addrs.as_str(), options).await?.raw()),
+                    #[cfg(feature = "tls")]
+                    Addr::Tls(addrs, _) => Tls::connect_options(addrs.as_str(), None, options).await?.encrypted_with(config).await,
+                    #[cfg(feature = "tls")]
+                    Addr::InsecureTls(addrs) => Ok(Tls::connect_options(addrs.as_str(), None, options).await?.raw()),
+                    #[cfg(not(feature = "tls"))]
+                    Addr::Wss(..) | Addr::Tls(..) | Addr::InsecureTls(_) => err!((
+                        unsupported,
+                        "connecting to tls providers requires the `tls` feature"
+                    )),
+                    #[cfg(feature = "quic")]
+                    Addr::Quic(addrs) => options.attempt(Quic::connect(addrs.as_str(), None)).await?.encrypted_with(config).await,
+                    #[cfg(not(feature = "quic"))]
+                    Addr::Quic(_) => err!((
+                        unsupported,
+                        "connecting to quic providers requires the `quic` feature"
+                    )),
+                    #[cfg(feature = "srv")]
+                    Addr::Srv(name, _) => SrvResolver::shared().connect(name, options).await?.encrypted_with(config).await,
+                    #[cfg(feature = "srv")]
+                    Addr::InsecureSrv(name) => Ok(SrvResolver::shared().connect(name, options).await?.raw()),
+                    #[cfg(not(feature = "srv"))]
+                    Addr::Srv(..) | Addr::InsecureSrv(_) => err!((
+                        unsupported,
+                        "connecting to srv addresses requires the `srv` feature"
+                    )),
                 }
             } else {
                 match self {
-                    Addr::Tcp(addrs) => Tcp::connect(addrs.as_ref()).await?.encrypted().await,
-                    Addr::InsecureTcp(addrs) => Ok(Tcp::connect(addrs.as_ref()).await?.raw()),
-                    Addr::Wss(addrs) => WebSocket::connect(addrs.as_str()).await?.encrypted().await,
-                    Addr::InsecureWss(addrs) => Ok(WebSocket::connect(addrs.as_str()).await?.raw()),
+                    Addr::Tcp(addrs, _) => addrs.connect(options).await?.encrypted_with(config).await,
+                    Addr::InsecureTcp(addrs) => Ok(addrs.connect(options).await?.raw()),
+                    Addr::Mem(addrs, _) => Mem::connect(addrs.as_str()).await?.encrypted_with(config).await,
+                    Addr::InsecureMem(addrs) => Ok(Mem::connect(addrs.as_str()).await?.raw()),
+                    #[cfg(feature = "tls")]
+                    Addr::Wss(addrs, _) => WebSocket::connect_request(&format!("wss://{}", addrs), &[], options, None).await?.encrypted_with(config).await,
+                    Addr::InsecureWss(addrs) => Ok(WebSocket::connect_options(addrs.as_str(), options).await?.raw()),
+                    #[cfg(feature = "tls")]
+                    Addr::Tls(addrs, _) => Tls::connect_options(addrs.as_str(), None, options).await?.encrypted_with(config).await,
+                    #[cfg(feature = "tls")]
+                    Addr::InsecureTls(addrs) => Ok(Tls::connect_options(addrs.as_str(), None, options).await?.raw()),
+                    #[cfg(not(feature = "tls"))]
+                    Addr::Wss(..) | Addr::Tls(..) | Addr::InsecureTls(_) => err!((
+                        unsupported,
+                        "connecting to tls providers requires the `tls` feature"
+                    )),
+                    #[cfg(feature = "quic")]
+                    Addr::Quic(addrs) => options.attempt(Quic::connect(addrs.as_str(), None)).await?.encrypted_with(config).await,
+                    #[cfg(not(feature = "quic"))]
+                    Addr::Quic(_) => err!((
+                        unsupported,
+                        "connecting to quic providers requires the `quic` feature"
+                    )),
+                    #[cfg(feature = "srv")]
+                    Addr::Srv(name, _) => SrvResolver::shared().connect(name, options).await?.encrypted_with(config).await,
+                    #[cfg(feature = "srv")]
+                    Addr::InsecureSrv(name) => Ok(SrvResolver::shared().connect(name, options).await?.raw()),
+                    #[cfg(not(feature = "srv"))]
+                    Addr::Srv(..) | Addr::InsecureSrv(_) => err!((
+                        unsupported,
+                        "connecting to srv addresses requires the `srv` feature"
+                    )),
 
-                    Addr::Unix(_) | Addr::InsecureUnix(_) => err!((
+                    Addr::Unix(..) | Addr::InsecureUnix(_) => err!((
                         unsupported,
                         "connecting to unix providers is not supported on non-unix platforms"
                     )),
@@ -243,19 +545,42 @@ impl Addr {
     /// connect to the address
     pub async fn bind(&self) -> Result<AnyProvider> {
         Ok(match self {
-            Addr::Tcp(addrs) => AnyProvider::Tcp(Tcp::bind(**addrs).await?),
-            Addr::InsecureTcp(addrs) => AnyProvider::InsecureTcp(Tcp::bind(**addrs).await?),
+            Addr::Tcp(addrs, _) => AnyProvider::Tcp(addrs.bind().await?),
+            Addr::InsecureTcp(addrs) => AnyProvider::InsecureTcp(addrs.bind().await?),
             #[cfg(unix)]
-            Addr::Unix(addrs) => AnyProvider::Unix(Unix::bind(&**addrs).await?),
+            Addr::Unix(addrs, _) => AnyProvider::Unix(Unix::bind(&**addrs).await?),
             #[cfg(unix)]
             Addr::InsecureUnix(addrs) => AnyProvider::InsecureUnix(Unix::bind(&**addrs).await?),
-            Addr::Wss(addrs) => AnyProvider::Wss(WebSocket::bind(addrs.as_str()).await?),
             Addr::InsecureWss(addrs) => {
-                AnyProvider::InsecureWss(WebSocket::bind(addrs.as_str()).await?)
+                // the path is part of the address, to match the urls of clients
+                let provider = match addrs.split_once('/') {
+                    Some((addrs, path)) => WebSocket::bind(addrs).await?.path(path),
+                    None => WebSocket::bind(addrs.as_str()).await?,
+                };
+                AnyProvider::InsecureWss(provider)
             }
+            Addr::Mem(addrs, _) => AnyProvider::Mem(Mem::bind(addrs.as_str()).await?),
+            Addr::InsecureMem(addrs) => AnyProvider::InsecureMem(Mem::bind(addrs.as_str()).await?),
+            // the address doesn't carry the certificate to present
+            Addr::Wss(..) => err!((
+                unsupported,
+                "binding to secure websocket providers needs a server config, use `WebSocket::bind_tls`"
+            ))?,
+            Addr::Tls(..) | Addr::InsecureTls(_) => err!((
+                unsupported,
+                "binding to tls providers needs a server config, use `Tls::bind`"
+            ))?,
+            Addr::Quic(_) => err!((
+                unsupported,
+                "binding to quic providers needs a server config, use `Quic::bind`"
+            ))?,
+            Addr::Srv(..) | Addr::InsecureSrv(_) => err!((
+                unsupported,
+                "srv addresses can only be connected to, bind to the address of a target"
+            ))?,
 
             #[cfg(not(unix))]
-            Addr::Unix(_) => err!((
+            Addr::Unix(..) => err!((
                 unsupported,
                 "binding to unix providers is not supported on non-unix platforms"
             ))?,
@@ -268,6 +593,9 @@ impl Addr {
     }
 }
 
+/// maximum length in bytes of an address string
+const MAX_ADDR_LEN: usize = 4096;
+
 impl FromStr for Addr {
     type Err = Error;
 
@@ -276,52 +604,389 @@ impl FromStr for Addr {
     /// tcp@127.0.0.1:8092
     /// tcp@127.0.0.1:8092
     /// unix@folder/address.sock
+    /// tcp+xx@127.0.0.1:8092
+    /// tcp@localhost:8092
+    /// tcp@[::1]:8092
+    /// tls@example.com:443
+    /// quic@example.com:4433
+    /// mem@service
+    /// srv@_canary._tcp.myservice.internal
+    ///
+    /// errors point to the byte range of the input that failed to parse,
+    /// such as `unexpected protocol "tpc" at 0..3`
     fn from_str(addr: &str) -> Result<Self> {
-        let (protocol, addr) = addr
-            .rsplit_once('@')
-            .ok_or(err!(invalid_input, "malformed address"))?;
-        let address_ty = protocol.parse::<AddressType>()?;
+        if addr.len() > MAX_ADDR_LEN {
+            err!((
+                invalid_input,
+                format!(
+                    "address is {} bytes long, the maximum is {}",
+                    addr.len(),
+                    MAX_ADDR_LEN
+                )
+            ))?
+        }
+        if let Some((idx, c)) = addr.char_indices().find(|(_, c)| c.is_control()) {
+            err!((
+                invalid_input,
+                format!(
+                    "invalid character {:?} at {}..{}",
+                    c,
+                    idx,
+                    idx + c.len_utf8()
+                )
+            ))?
+        }
+        let (protocol, address) = addr.split_once('@').ok_or_else(|| {
+            err!(
+                invalid_input,
+                format!(
+                    "malformed address, expected `protocol@address` at 0..{}",
+                    addr.len()
+                )
+            )
+        })?;
+        if protocol.is_empty() {
+            err!((invalid_input, "missing protocol at 0..0"))?
+        }
+        // offset of the address in the input, used for error ranges
+        let offset = protocol.len() + 1;
+        let (protocol, suite) = match protocol.split_once('+') {
+            Some((protocol, suite)) => (protocol, Some(suite)),
+            None => (protocol, None),
+        };
+        let address_ty = protocol.parse::<AddressType>().map_err(|_| {
+            err!(
+                invalid_input,
+                format!(
+                    "unexpected protocol {:?} at 0..{}",
+                    protocol,
+                    protocol.len()
+                )
+            )
+        })?;
+        let suite = suite
+            .map(|suite| {
+                let start = protocol.len() + 1;
+                let invalid = || {
+                    err!(
+                        invalid_input,
+                        format!(
+                            "unexpected protocol {:?} at {}..{}",
+                            suite,
+                            start,
+                            start + suite.len()
+                        )
+                    )
+                };
+                match address_ty {
+                    AddressType::Tcp
+                    | AddressType::Unix
+                    | AddressType::Wss
+                    | AddressType::Tls
+                    | AddressType::Mem
+                    | AddressType::Srv => suite.parse::<Suite>().map_err(|_| invalid()),
+                    // insecure addresses don't run a handshake
+                    _ => Err(invalid()),
+                }
+            })
+            .transpose()?;
+        if address.is_empty() {
+            err!((
+                invalid_input,
+                format!("missing address at {}..{}", offset, offset)
+            ))?
+        }
         Ok(match address_ty {
-            AddressType::Tcp => {
-                let addr = addr
-                    .parse::<SocketAddr>()
-                    .map_err(|e| err!(invalid_input, e))?;
-                Addr::Tcp(Arc::new(addr))
+            AddressType::Tcp | AddressType::TcpHost => {
+                Addr::Tcp(Arc::new(parse_host_addr(address, offset)?), suite)
             }
-            AddressType::Unix => {
-                let addr = addr
-                    .parse::<PathBuf>()
-                    .map_err(|e| err!(invalid_input, e))?;
-                Addr::Unix(Arc::new(addr))
+            AddressType::Unix => Addr::Unix(Arc::new(PathBuf::from(address)), suite),
+            AddressType::InsecureTcp | AddressType::InsecureTcpHost => {
+                Addr::InsecureTcp(Arc::new(parse_host_addr(address, offset)?))
             }
-            AddressType::InsecureTcp => {
-                let addr = addr
-                    .parse::<SocketAddr>()
-                    .map_err(|e| err!(invalid_input, e))?;
-                Addr::InsecureTcp(Arc::new(addr))
+            AddressType::InsecureUnix => Addr::InsecureUnix(Arc::new(PathBuf::from(address))),
+            AddressType::Wss => Addr::Wss(Arc::new(CompactString::from(address)), suite),
+            AddressType::InsecureWss => Addr::InsecureWss(Arc::new(CompactString::from(address))),
+            AddressType::Tls => Addr::Tls(Arc::new(parse_host_port(address, offset)?), suite),
+            AddressType::InsecureTls => {
+                Addr::InsecureTls(Arc::new(parse_host_port(address, offset)?))
             }
-            AddressType::InsecureUnix => {
-                let addr = addr
-                    .parse::<PathBuf>()
-                    .map_err(|e| err!(invalid_input, e))?;
-                Addr::InsecureUnix(Arc::new(addr))
-            }
-            AddressType::Wss => {
-                let addr = addr
-                    .parse::<CompactString>()
-                    .map_err(|e| err!(invalid_input, e))?;
-                Addr::Wss(Arc::new(addr))
-            }
-            AddressType::InsecureWss => {
-                let addr = addr
-                    .parse::<CompactString>()
-                    .map_err(|e| err!(invalid_input, e))?;
-                Addr::InsecureWss(Arc::new(addr))
+            AddressType::Quic => Addr::Quic(Arc::new(parse_host_port(address, offset)?)),
+            AddressType::Mem => Addr::Mem(Arc::new(CompactString::from(address)), suite),
+            AddressType::InsecureMem => Addr::InsecureMem(Arc::new(CompactString::from(address))),
+            AddressType::Srv => Addr::Srv(Arc::new(parse_srv_name(address, offset)?), suite),
+            AddressType::InsecureSrv => {
+                Addr::InsecureSrv(Arc::new(parse_srv_name(address, offset)?))
             }
         })
     }
 }
 
+/// parse a socket address or `hostname:port`, `offset` is the position of the address in the whole input
+fn parse_host_addr(address: &str, offset: usize) -> Result<HostAddr> {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok(HostAddr::Socket(addr));
+    }
+    let end = offset + address.len();
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| err!(invalid_input, format!("missing port at {}..{}", end, end)))?;
+    let port_start = offset + host.len() + 1;
+    let port = port.parse::<u16>().map_err(|_| {
+        err!(
+            invalid_input,
+            format!("invalid port at {}..{}", port_start, end)
+        )
+    })?;
+    if host.is_empty() {
+        err!((
+            invalid_input,
+            format!("missing host at {}..{}", offset, offset)
+        ))?
+    }
+    // brackets, colons and dotted digits can only be meant as an ip address
+    let ip_like = host.starts_with('[')
+        || host.contains(':')
+        || host.chars().all(|c| c.is_ascii_digit() || c == '.');
+    if ip_like {
+        err!((
+            invalid_input,
+            format!("invalid ip address at {}..{}", offset, offset + host.len())
+        ))?
+    }
+    if !is_hostname(host) {
+        err!((
+            invalid_input,
+            format!("invalid hostname at {}..{}", offset, offset + host.len())
+        ))?
+    }
+    Ok(HostAddr::Name(CompactString::from(host), port))
+}
+
+/// check the labels of a hostname, allowing underscores as srv names have them
+fn is_hostname(host: &str) -> bool {
+    host.split('.').all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+/// check the address is a domain name without a port, such as `_canary._tcp.example.com`,
+/// `offset` is the position of the address in the whole input
+fn parse_srv_name(address: &str, offset: usize) -> Result<CompactString> {
+    // fully qualified names may end in a dot
+    let name = address.strip_suffix('.').unwrap_or(address);
+    if !is_hostname(name) {
+        err!((
+            invalid_input,
+            format!("invalid srv name at {}..{}", offset, offset + address.len())
+        ))?
+    }
+    Ok(CompactString::from(address))
+}
+
+/// check the address is `host:port`, `offset` is the position of the address in the whole input.
+/// the host is kept as is, since tls checks the certificate against it
+fn parse_host_port(address: &str, offset: usize) -> Result<CompactString> {
+    let end = offset + address.len();
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| err!(invalid_input, format!("missing port at {}..{}", end, end)))?;
+    if host.is_empty() {
+        err!((
+            invalid_input,
+            format!("missing host at {}..{}", offset, offset)
+        ))?
+    }
+    if port.parse::<u16>().is_err() {
+        let port_start = offset + host.len() + 1;
+        err!((
+            invalid_input,
+            format!("invalid port at {}..{}", port_start, end)
+        ))?
+    }
+    Ok(CompactString::from(address))
+}
+
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+/// Address of a tcp provider, either a socket address or a hostname
+/// that is resolved every time the address is connected to or bound.
+/// ```no_run
+/// # use canary::providers::HostAddr;
+/// let addr = HostAddr::Name("db.internal".into(), 5432);
+/// assert_eq!(addr.to_string(), "db.internal:5432");
+/// ```
+pub enum HostAddr {
+    /// ip address and port
+    Socket(SocketAddr),
+    /// hostname and port
+    Name(CompactString, u16),
+}
+
+impl HostAddr {
+    #[inline]
+    fn is_name(&self) -> bool {
+        matches!(self, HostAddr::Name(..))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// connect to the address, trying every address the hostname resolves to in order
+    async fn connect(&self, options: ConnectOptions) -> Result<Handshake> {
+        match self {
+            HostAddr::Socket(addr) => Tcp::connect_options(addr, options).await,
+            HostAddr::Name(host, port) => {
+                Tcp::connect_options((host.as_str(), *port), options).await
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// bind to the first address the hostname resolves to that can be bound
+    async fn bind(&self) -> Result<Tcp> {
+        match self {
+            HostAddr::Socket(addr) => Tcp::bind(addr).await,
+            HostAddr::Name(host, port) => Tcp::bind((host.as_str(), *port)).await,
+        }
+    }
+}
+
+impl From<SocketAddr> for HostAddr {
+    #[inline]
+    fn from(addr: SocketAddr) -> Self {
+        HostAddr::Socket(addr)
+    }
+}
+
+impl Display for HostAddr {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostAddr::Socket(addr) => write!(f, "{}", addr),
+            HostAddr::Name(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl Debug for HostAddr {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Noise handshake pattern selected by the suffix of an address,
+/// such as `xx` in `tcp+xx@127.0.0.1:8080`.
+/// Suffixes are case insensitive and may end in `25519`, the only
+/// key exchange supported, so `tcp+xx25519@` is the same as `tcp+xx@`.
+/// Both peers must use the same pattern.
+pub struct Suite(HandshakePattern);
+
+impl Suite {
+    /// Get the handshake pattern of the suite
+    pub fn pattern(self) -> HandshakePattern {
+        self.0
+    }
+}
+
+impl From<HandshakePattern> for Suite {
+    #[inline]
+    fn from(pattern: HandshakePattern) -> Self {
+        Suite(pattern)
+    }
+}
+
+impl Eq for Suite {}
+
+// patterns are compared by name, which identifies them
+impl std::hash::Hash for Suite {
+    #[inline]
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state)
+    }
+}
+
+impl PartialOrd for Suite {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Suite {
+    #[inline]
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.as_str().cmp(other.0.as_str())
+    }
+}
+
+impl FromStr for Suite {
+    type Err = Error;
+
+    #[inline]
+    fn from_str(suite: &str) -> Result<Self> {
+        let name = suite.to_ascii_uppercase();
+        let name = name.strip_suffix("25519").unwrap_or(&name);
+        match name.parse::<HandshakePattern>() {
+            // one-way patterns can't be used by channels
+            Ok(pattern) if !pattern.is_oneway() => Ok(Suite(pattern)),
+            _ => err!((invalid_input, format!("unexpected suite {:?}", suite))),
+        }
+    }
+}
+
+impl Display for Suite {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.as_str().to_ascii_lowercase())
+    }
+}
+
+impl Debug for Suite {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+impl Serialize for Suite {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Suite {
+    #[inline]
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let string: CompactString = CompactString::deserialize(deserializer)?;
+        Suite::from_str(&string).map_err(serde::de::Error::custom)
+    }
+}
+
+/// displays the `+suite` suffix of an address, if any
+struct SuiteSuffix<'a>(&'a Option<Suite>);
+
+impl Display for SuiteSuffix<'_> {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(suite) => write!(f, "+{}", suite),
+            None => Ok(()),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[repr(u8)]
 enum AddressType {
@@ -337,6 +1002,26 @@ enum AddressType {
     Wss = 4,
     #[serde(rename = "ws")]
     InsecureWss = 5,
+    #[serde(rename = "tls")]
+    Tls = 6,
+    #[serde(rename = "itls")]
+    InsecureTls = 7,
+    #[serde(rename = "quic")]
+    Quic = 8,
+    #[serde(rename = "mem")]
+    Mem = 9,
+    #[serde(rename = "imem")]
+    InsecureMem = 10,
+    // tcp addresses with a hostname, only used by binary formats
+    // so older peers still read socket addresses
+    #[serde(rename = "tcp-host")]
+    TcpHost = 11,
+    #[serde(rename = "itcp-host")]
+    InsecureTcpHost = 12,
+    #[serde(rename = "srv")]
+    Srv = 13,
+    #[serde(rename = "isrv")]
+    InsecureSrv = 14,
 }
 
 impl FromStr for AddressType {
@@ -351,6 +1036,13 @@ impl FromStr for AddressType {
             "ws" => AddressType::InsecureWss,
             "unix" => AddressType::Unix,
             "iunix" => AddressType::InsecureUnix,
+            "tls" => AddressType::Tls,
+            "itls" => AddressType::InsecureTls,
+            "quic" => AddressType::Quic,
+            "mem" => AddressType::Mem,
+            "imem" => AddressType::InsecureMem,
+            "srv" => AddressType::Srv,
+            "isrv" => AddressType::InsecureSrv,
             protocol => err!((invalid_input, format!("unexpected protocol {:?}", protocol)))?,
         };
         Ok(protocol)
@@ -361,12 +1053,184 @@ impl AsRef<str> for AddressType {
     #[inline]
     fn as_ref(&self) -> &str {
         match self {
-            AddressType::Tcp => "tcp",
-            AddressType::InsecureTcp => "itcp",
+            AddressType::Tcp | AddressType::TcpHost => "tcp",
+            AddressType::InsecureTcp | AddressType::InsecureTcpHost => "itcp",
             AddressType::Unix => "unix",
             AddressType::InsecureUnix => "iunix",
             AddressType::Wss => "wss",
             AddressType::InsecureWss => "ws",
+            AddressType::Tls => "tls",
+            AddressType::InsecureTls => "itls",
+            AddressType::Quic => "quic",
+            AddressType::Mem => "mem",
+            AddressType::InsecureMem => "imem",
+            AddressType::Srv => "srv",
+            AddressType::InsecureSrv => "isrv",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // addresses that mostly get past the protocol, so the parsers of each kind run
+    fn addresses() -> impl Strategy<Value = String> {
+        let protocol = prop::sample::select(vec![
+            "tcp", "itcp", "unix", "iunix", "wss", "ws", "tls", "itls", "quic", "mem", "imem",
+            "srv", "isrv", "tpc", "",
+        ]);
+        let suite = prop::option::of("(xx|nn|ik|Nk25519|zz)");
+        let address = "([a-z0-9_.:\\[\\]/-]{0,24}|[0-9]{1,3}(\\.[0-9]{1,3}){3}:[0-9]{1,6}|\\[[0-9a-f:]{2,16}\\]:[0-9]{1,6}|\\PC{0,16})";
+        (protocol, suite, address).prop_map(|(protocol, suite, address)| match suite {
+            Some(suite) => format!("{}+{}@{}", protocol, suite, address),
+            None => format!("{}@{}", protocol, address),
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_never_panics(addr in "\\PC*") {
+            let _ = addr.parse::<Addr>();
+        }
+
+        #[test]
+        fn display_round_trips(addr in addresses()) {
+            if let Ok(parsed) = addr.parse::<Addr>() {
+                prop_assert_eq!(parsed.to_string().parse::<Addr>().unwrap(), parsed);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_addresses_point_at_the_error() {
+        let long = format!("mem@{}", "a".repeat(MAX_ADDR_LEN));
+        let cases = [
+            ("@127.0.0.1:8080", "missing protocol at 0..0"),
+            ("tcp@", "missing address at 4..4"),
+            ("tcp@127.0.0.1\0:8080", "invalid character '\\0' at 13..14"),
+            (
+                long.as_str(),
+                "address is 4100 bytes long, the maximum is 4096",
+            ),
+            ("tpc@127.0.0.1:8080", "unexpected protocol \"tpc\" at 0..3"),
+            (
+                "tcp+zz@127.0.0.1:8080",
+                "unexpected protocol \"zz\" at 4..6",
+            ),
+            (
+                "itcp+xx@127.0.0.1:8080",
+                "unexpected protocol \"xx\" at 5..7",
+            ),
+            ("tcp@127.0.0.1:99999", "invalid port at 14..19"),
+            ("tls@example.com:https", "invalid port at 16..21"),
+            (
+                "127.0.0.1:8080",
+                "malformed address, expected `protocol@address` at 0..14",
+            ),
+            ("tcp@localhost", "missing port at 13..13"),
+            ("tcp@:8080", "missing host at 4..4"),
+            ("tcp@-db:8080", "invalid hostname at 4..7"),
+            ("tcp@[::1:8080", "invalid ip address at 4..8"),
+            ("srv@not a name", "invalid srv name at 4..14"),
+        ];
+        for (addr, expected) in cases {
+            let e = addr.parse::<Addr>().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput, "{}", addr);
+            assert_eq!(e.to_string(), expected, "{}", addr);
+        }
+    }
+
+    #[test]
+    fn host_addresses_round_trip() {
+        let cases = [
+            (
+                "tcp@localhost:8080",
+                HostAddr::Name("localhost".into(), 8080),
+            ),
+            (
+                "tcp@127.0.0.1:8080",
+                HostAddr::Socket("127.0.0.1:8080".parse().unwrap()),
+            ),
+            (
+                "itcp@[::1]:8080",
+                HostAddr::Socket("[::1]:8080".parse().unwrap()),
+            ),
+        ];
+        for (string, host) in cases {
+            let addr = string.parse::<Addr>().unwrap();
+            match &addr {
+                Addr::Tcp(addr, None) | Addr::InsecureTcp(addr) => assert_eq!(**addr, host),
+                addr => panic!("{} parsed as {}", string, addr),
+            }
+            assert_eq!(addr.to_string(), string);
+            let bytes = bincode::serialize(&addr).unwrap();
+            assert_eq!(bincode::deserialize::<Addr>(&bytes).unwrap(), addr);
+            #[cfg(feature = "json_ser")]
+            assert_eq!(
+                serde_json::to_string(&addr).unwrap(),
+                format!("{:?}", string)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_echoes_over_each_scheme() {
+        for addr in ["tcp@127.0.0.1:0", "itcp@127.0.0.1:0", "ws@127.0.0.1:0"] {
+            let provider = addr.parse::<Addr>().unwrap().bind().await.unwrap();
+            let addr = provider.local_addr().unwrap().to_string();
+            provider
+                .serve(|mut chan: Channel| async move {
+                    let message: String = chan.receive().await?;
+                    chan.send(message).await?;
+                    Ok(())
+                })
+                .unwrap();
+            let mut chan = connect(&addr).await.unwrap();
+            chan.send("hello!").await.unwrap();
+            assert_eq!(
+                chan.receive::<String>().await.unwrap(),
+                "hello!",
+                "{}",
+                addr
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_returns_parse_errors_as_they_are() {
+        for addr in ["tcp@", "tpc@127.0.0.1:8080", "tcp@localhost"] {
+            let expected = addr.parse::<Addr>().unwrap_err();
+            let e = connect(addr).await.err().unwrap();
+            assert_eq!(e.kind(), expected.kind(), "{}", addr);
+            assert_eq!(e.to_string(), expected.to_string(), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn srv_addresses_round_trip() {
+        let name = "_canary._tcp.myservice.internal";
+        for string in [
+            format!("srv@{}", name),
+            format!("srv@{}.", name),
+            format!("isrv@{}", name),
+            format!("srv+xx@{}", name),
+        ] {
+            let addr = string.parse::<Addr>().unwrap();
+            match &addr {
+                Addr::Srv(parsed, suite) => {
+                    assert_eq!(parsed.trim_end_matches('.'), name);
+                    assert_eq!(suite.is_some(), string.starts_with("srv+"));
+                }
+                Addr::InsecureSrv(parsed) => assert_eq!(**parsed, name),
+                addr => panic!("{} parsed as {}", string, addr),
+            }
+            assert_eq!(addr.to_string(), string);
         }
+        let e = "isrv+xx@_canary._tcp.myservice.internal"
+            .parse::<Addr>()
+            .unwrap_err();
+        assert_eq!(e.to_string(), "unexpected protocol \"xx\" at 5..7");
     }
 }
@@ -0,0 +1,332 @@
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+use cfg_if::cfg_if;
+use compact_str::CompactString;
+use serde::{Deserialize, Serialize};
+
+use super::Addr;
+use crate::async_snow::SnowConfig;
+use crate::{err, Error};
+use crate::{Channel, Result};
+
+cfg_if! {
+    if #[cfg(not(target_arch = "wasm32"))] {
+        use std::time::Duration;
+
+        use futures::future::pending;
+        use futures::stream::FuturesUnordered;
+        use futures::{select, FutureExt, StreamExt};
+
+        use crate::io::sleep;
+        use crate::providers::{ConnectOptions, RetryPolicy};
+
+        /// time an attempt has before the next address is tried alongside it
+        const STAGGER: Duration = Duration::from_millis(250);
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+/// Addresses of the same service, such as an ipv6 and an ipv4 endpoint
+/// and a websocket fallback, in order of preference.
+/// Written as addresses separated by commas, so the addresses of a set can't contain commas.
+/// ```no_run
+/// # use canary::providers::AddrSet;
+/// # async fn run() -> canary::Result<()> {
+/// let addrs = "tcp@[::1]:9000,tcp@127.0.0.1:9000,wss@fallback.example.com:443".parse::<AddrSet>()?;
+/// let mut chan = addrs.connect().await?;
+/// chan.send("hello!").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AddrSet {
+    addrs: Vec<Addr>,
+}
+
+impl AddrSet {
+    /// Create a set from addresses in order of preference.
+    /// Returns an error if there are none.
+    pub fn new(addrs: Vec<Addr>) -> Result<Self> {
+        if addrs.is_empty() {
+            err!((invalid_input, "address sets can't be empty"))?
+        }
+        Ok(AddrSet { addrs })
+    }
+
+    #[inline]
+    /// Get the addresses of the set, in order of preference
+    pub fn addrs(&self) -> &[Addr] {
+        &self.addrs
+    }
+
+    #[inline]
+    /// connect to the first address of the set that accepts
+    pub async fn connect(&self) -> Result<Channel> {
+        self.connect_with(&SnowConfig::default()).await
+    }
+
+    /// connect to the first address of the set that accepts,
+    /// using the configuration to encrypt the channel like `Addr::connect_with`.
+    /// Fails with the outcome of every attempt if none of them succeeds.
+    pub async fn connect_with(&self, config: &SnowConfig) -> Result<Channel> {
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                // browsers have no timers to stagger attempts with
+                let mut errors = Vec::new();
+                for (idx, addr) in self.addrs.iter().enumerate() {
+                    match addr.connect_with(config).await {
+                        Ok(chan) => return Ok(chan),
+                        Err(e) => errors.push((idx, e)),
+                    }
+                }
+                Err(self.failed(errors))
+            } else {
+                self.connect_with_options(config, ConnectOptions::default()).await
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// connect to the addresses of the set in a staggered race: each address is tried
+    /// 250 milliseconds after the previous one, or as soon as it fails, and the first
+    /// channel to connect and encrypt is returned while the other attempts are dropped.
+    /// Each address is attempted once unless the options have a retry policy.
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::{async_snow::SnowConfig, providers::{AddrSet, ConnectOptions}};
+    /// # async fn run(addrs: AddrSet) -> canary::Result<()> {
+    /// let options = ConnectOptions::default().timeout(Duration::from_secs(2));
+    /// let mut chan = addrs.connect_with_options(&SnowConfig::default(), options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with_options(
+        &self,
+        config: &SnowConfig,
+        mut options: ConnectOptions,
+    ) -> Result<Channel> {
+        // retrying an address would hold back the error of the set
+        options
+            .policy
+            .get_or_insert(RetryPolicy::fixed(Duration::ZERO, 1));
+        let mut addrs = self.addrs.iter().enumerate();
+        let mut attempts = FuturesUnordered::new();
+        let mut errors = Vec::new();
+        loop {
+            if let Some((idx, addr)) = addrs.next() {
+                let attempt = addr.connect_with_options(config, options);
+                attempts.push(async move { (idx, attempt.await) });
+            }
+            if attempts.is_empty() {
+                return Err(self.failed(errors));
+            }
+            let stagger = async {
+                match addrs.len() {
+                    0 => pending().await,
+                    _ => sleep(STAGGER).await,
+                }
+            };
+            select! {
+                (idx, chan) = attempts.select_next_some() => match chan {
+                    Ok(chan) => return Ok(chan),
+                    Err(e) => {
+                        tracing::debug!("connecting to {} failed: {}", self.addrs[idx], e);
+                        errors.push((idx, e));
+                    }
+                },
+                _ = stagger.fuse() => {},
+            }
+        }
+    }
+
+    /// error listing the outcome of every attempt in the order of the set,
+    /// with their kind if they all have the same one
+    fn failed(&self, mut errors: Vec<(usize, Error)>) -> Error {
+        errors.sort_by_key(|(idx, _)| *idx);
+        let kind = match errors.split_first() {
+            Some(((_, first), rest)) if rest.iter().all(|(_, e)| e.kind() == first.kind()) => {
+                first.kind()
+            }
+            _ => std::io::ErrorKind::Other,
+        };
+        let outcomes = errors
+            .iter()
+            .map(|(idx, e)| format!("{}: {}", self.addrs[*idx], e))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let e = format!("connecting to every address failed ({})", outcomes);
+        Error::new(std::io::Error::new(kind, e))
+    }
+}
+
+impl From<Addr> for AddrSet {
+    #[inline]
+    fn from(addr: Addr) -> Self {
+        AddrSet { addrs: vec![addr] }
+    }
+}
+
+impl Display for AddrSet {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, addr) in self.addrs.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(",")?;
+            }
+            Display::fmt(addr, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for AddrSet {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self, f)
+    }
+}
+
+impl FromStr for AddrSet {
+    type Err = Error;
+
+    #[inline]
+    /// tcp@[::1]:9000,tcp@127.0.0.1:9000
+    /// tcp@127.0.0.1:9000,wss@fallback.example.com:443
+    ///
+    /// errors point to the byte range of the address that failed to parse,
+    /// such as `unexpected protocol "tpc" at 0..3 in "tpc@[::1]:9000" at 19..33`
+    fn from_str(addrs: &str) -> Result<Self> {
+        let mut start = 0;
+        let addrs = addrs
+            .split(',')
+            .map(|addr| {
+                let range = start..start + addr.len();
+                start = range.end + 1;
+                addr.parse::<Addr>().map_err(|e| {
+                    let msg = format!("{} in {:?} at {}..{}", e, addr, range.start, range.end);
+                    Error::new(std::io::Error::new(e.kind(), msg))
+                })
+            })
+            .collect::<Result<_>>()?;
+        AddrSet::new(addrs)
+    }
+}
+
+impl Serialize for AddrSet {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if serializer.is_human_readable() {
+            self.to_string().serialize(serializer)
+        } else {
+            self.addrs.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AddrSet {
+    #[inline]
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let string = CompactString::deserialize(deserializer)?;
+            AddrSet::from_str(&string).map_err(serde::de::Error::custom)
+        } else {
+            let addrs = Vec::<Addr>::deserialize(deserializer)?;
+            AddrSet::new(addrs).map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::providers::tcp::tests::full_backlog;
+    use crate::providers::Tcp;
+
+    #[test]
+    fn sets_parse_and_display_round_trip() {
+        let string = "tcp@[::1]:9000,tcp@127.0.0.1:9000,ws@example.com:80";
+        let addrs = string.parse::<AddrSet>().unwrap();
+        assert_eq!(addrs.addrs().len(), 3);
+        assert_eq!(addrs.addrs()[1], "tcp@127.0.0.1:9000".parse().unwrap());
+        assert_eq!(addrs.to_string(), string);
+        assert_eq!(addrs.to_string().parse::<AddrSet>().unwrap(), addrs);
+        let bytes = bincode::serialize(&addrs).unwrap();
+        assert_eq!(bincode::deserialize::<AddrSet>(&bytes).unwrap(), addrs);
+
+        let e = "".parse::<AddrSet>().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        // the error points to the address that failed
+        let e = "tcp@127.0.0.1:9000,tpc@[::1]:9000"
+            .parse::<AddrSet>()
+            .unwrap_err();
+        assert!(
+            e.to_string().ends_with(r#"in "tpc@[::1]:9000" at 19..33"#),
+            "{}",
+            e
+        );
+    }
+
+    // serve channels that send the index of the server, returning its address
+    async fn server(idx: usize) -> String {
+        let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("tcp@{}", tcp.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok(hs) = tcp.next().await {
+                tokio::spawn(async move { hs.encrypted().await?.send(idx).await });
+            }
+        });
+        addr
+    }
+
+    // address nothing listens on
+    async fn refused() -> String {
+        let listener = crate::io::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("tcp@{}", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn hanging_addresses_are_raced_after_the_stagger() {
+        let (hanging, _backlog) = full_backlog().await;
+        let addrs = format!("tcp@{},{}", hanging, server(1).await);
+        let start = Instant::now();
+        let mut chan = addrs.parse::<AddrSet>().unwrap().connect().await.unwrap();
+        assert_eq!(chan.receive::<usize>().await.unwrap(), 1);
+        assert!(start.elapsed() >= STAGGER);
+        assert!(start.elapsed() < 4 * STAGGER);
+    }
+
+    #[tokio::test]
+    async fn failed_addresses_dont_wait_for_the_stagger() {
+        let addrs = format!(
+            "{},{},{}",
+            refused().await,
+            server(1).await,
+            server(2).await
+        );
+        let start = Instant::now();
+        let mut chan = addrs.parse::<AddrSet>().unwrap().connect().await.unwrap();
+        // the first address that accepts wins
+        assert_eq!(chan.receive::<usize>().await.unwrap(), 1);
+        assert!(start.elapsed() < STAGGER);
+
+        let addrs = format!("{},{}", refused().await, refused().await);
+        let e = addrs
+            .parse::<AddrSet>()
+            .unwrap()
+            .connect()
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused);
+        for addr in addrs.split(',') {
+            assert!(e.to_string().contains(addr), "{}", e);
+        }
+    }
+}
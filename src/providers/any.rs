@@ -1,14 +1,20 @@
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 
 use futures::StreamExt;
 use futures::{pin_mut, select, stream::FuturesUnordered, FutureExt};
 
+#[cfg(not(target_arch = "wasm32"))]
+use super::Mem;
 #[cfg(not(target_arch = "wasm32"))]
 use super::Tcp;
 #[cfg(unix)]
 use super::Unix;
+use crate::async_snow::SnowConfig;
 use crate::channel::handshake::Handshake;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::providers::{Addr, HostAddr};
 use crate::Channel;
 use crate::Result;
 
@@ -32,9 +38,65 @@ pub enum AnyProvider {
     Wss(WebSocket),
     /// encapsulates the websocket provider without any encryption
     InsecureWss(WebSocket),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// encapsulates the in-memory provider
+    Mem(Mem),
+    #[cfg(not(target_arch = "wasm32"))]
+    /// encapsulates the in-memory provider without any encryption
+    InsecureMem(Mem),
 }
 
 impl AnyProvider {
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Bind the provider of the address, like `Addr::bind`.
+    /// Whether channels are encrypted follows the address, see `next_channel`.
+    /// ```no_run
+    /// # use canary::providers::AnyProvider;
+    /// # async fn run() -> canary::Result<()> {
+    /// let provider = AnyProvider::bind(&"tcp@127.0.0.1:8080".parse()?).await?;
+    /// while let Ok(mut chan) = provider.next_channel().await {
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind(addr: &Addr) -> Result<Self> {
+        addr.bind().await
+    }
+
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    /// get the next channel, encrypted unless the provider was bound
+    /// to an insecure address.
+    /// the handshake runs before this returns, so a slow peer holds up the
+    /// next channel, see `channels` or `serve` to accept concurrently.
+    /// ```no_run
+    /// # use canary::providers::AnyProvider;
+    /// # async fn run(provider: AnyProvider) -> canary::Result<()> {
+    /// while let Ok(mut chan) = provider.next_channel().await {
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn next_channel(&self) -> Result<Channel> {
+        self.next_channel_with(&SnowConfig::default()).await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// get the next channel, encrypted with the configuration unless the
+    /// provider was bound to an insecure address.
+    /// The handshake pattern of the address the provider was bound with isn't
+    /// kept, so it must be set in the configuration.
+    pub async fn next_channel_with(&self, config: &SnowConfig) -> Result<Channel> {
+        let hs = self.next_handshake().await?;
+        match self.encrypted() {
+            true => hs.encrypted_with(config).await,
+            false => Ok(hs.raw()),
+        }
+    }
+
     #[inline]
     #[cfg(not(target_arch = "wasm32"))]
     /// get the next handshake
@@ -49,13 +111,15 @@ impl AnyProvider {
     pub async fn next_handshake(&self) -> Result<Handshake> {
         match self {
             AnyProvider::Tcp(provider) => provider.next().await,
-            AnyProvider::InsecureTcp(provider) => provider.next().await,
+            AnyProvider::InsecureTcp(provider) => Ok(insecure(provider.next().await?)),
             #[cfg(unix)]
             AnyProvider::Unix(provider) => provider.next().await,
             #[cfg(unix)]
-            AnyProvider::InsecureUnix(provider) => provider.next().await,
+            AnyProvider::InsecureUnix(provider) => Ok(insecure(provider.next().await?)),
             AnyProvider::Wss(provider) => provider.next().await,
             AnyProvider::InsecureWss(provider) => provider.next().await,
+            AnyProvider::Mem(provider) => provider.next().await,
+            AnyProvider::InsecureMem(provider) => provider.next().await,
         }
     }
 
@@ -72,9 +136,52 @@ impl AnyProvider {
             AnyProvider::InsecureUnix(_) => false,
             AnyProvider::Wss(_) => true,
             AnyProvider::InsecureWss(_) => false,
+            AnyProvider::Mem(_) => true,
+            AnyProvider::InsecureMem(_) => false,
         }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    /// get the address the provider is bound to, such as the port picked
+    /// when binding to port 0, to hand it to other processes.
+    /// The handshake pattern of the address the provider was bound with isn't kept.
+    /// ```no_run
+    /// # use canary::providers::Addr;
+    /// # async fn run() -> canary::Result<()> {
+    /// let provider = "tcp@127.0.0.1:0".parse::<Addr>()?.bind().await?;
+    /// let addr = provider.local_addr()?; // tcp@127.0.0.1:41823
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn local_addr(&self) -> Result<Addr> {
+        let tcp = |addr| Arc::new(HostAddr::Socket(addr));
+        let wss = |provider: &WebSocket| -> Result<_> {
+            let addr = provider.local_addr()?;
+            Ok(Arc::new(match provider.url_path() {
+                Some(path) => format!("{}{}", addr, path).into(),
+                None => addr.to_string().into(),
+            }))
+        };
+        Ok(match self {
+            AnyProvider::Tcp(provider) => Addr::Tcp(tcp(provider.local_addr()?), None),
+            AnyProvider::InsecureTcp(provider) => Addr::InsecureTcp(tcp(provider.local_addr()?)),
+            #[cfg(unix)]
+            AnyProvider::Unix(provider) => {
+                Addr::Unix(Arc::new(provider.local_path().to_path_buf()), None)
+            }
+            #[cfg(unix)]
+            AnyProvider::InsecureUnix(provider) => {
+                Addr::InsecureUnix(Arc::new(provider.local_path().to_path_buf()))
+            }
+            AnyProvider::Wss(provider) => Addr::Wss(wss(provider)?, None),
+            AnyProvider::InsecureWss(provider) => Addr::InsecureWss(wss(provider)?),
+            AnyProvider::Mem(provider) => Addr::Mem(Arc::new(provider.name().into()), None),
+            AnyProvider::InsecureMem(provider) => {
+                Addr::InsecureMem(Arc::new(provider.name().into()))
+            }
+        })
+    }
+
     #[inline]
     #[cfg(not(target_arch = "wasm32"))]
     /// get the next channel
@@ -87,16 +194,49 @@ impl AnyProvider {
     /// }
     /// ```
     pub fn channels(self) -> ChannelIter {
+        self.channels_with(SnowConfig::default())
+    }
+
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    /// get the channels of the provider, encrypting them with the
+    /// provided configuration, such as a static key or a verifier of every peer
+    /// ```no_run
+    /// # use canary::{async_snow::SnowConfig, providers::AnyProvider};
+    /// # async fn run(provider: AnyProvider) -> canary::Result<()> {
+    /// # let keypair = SnowConfig::generate_keypair()?;
+    /// let config = SnowConfig::new(keypair.private);
+    /// let mut channels = provider.channels_with(config);
+    /// while let Ok(mut chan) = channels.next().await {
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn channels_with(self, config: SnowConfig) -> ChannelIter {
         ChannelIter {
             listener: self,
+            config: Arc::new(config),
             futures: FuturesUnordered::new(),
         }
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+/// give the peer address of a handshake the scheme of the insecure provider
+fn insecure(hs: Handshake) -> Handshake {
+    let addr = match hs.peer_addr() {
+        Some(Addr::Tcp(addr, _)) => Addr::InsecureTcp(addr.clone()),
+        Some(Addr::Unix(path, _)) => Addr::InsecureUnix(path.clone()),
+        _ => return hs,
+    };
+    hs.with_peer_addr(addr)
+}
+
 /// iterator over channels. NOTE: not completely zero-cost
 pub struct ChannelIter {
     listener: AnyProvider,
+    config: Arc<SnowConfig>,
     futures: FuturesUnordered<Pin<Box<dyn Future<Output = Result<Channel>> + Send + 'static>>>, // not Sync or UnwindSafe
 }
 
@@ -108,16 +248,13 @@ impl ChannelIter {
 
         loop {
             let chan = select! {
-                chan = self.futures.next().fuse() => {
-                    match chan {
-                        Some(chan) => chan,
-                        None => continue,
-                    }
-                },
+                // skipped while no handshake is running
+                chan = self.futures.select_next_some() => chan,
                 res = hs => {
                     let hs: Handshake = res?;
                     if self.listener.encrypted() {
-                        let fut = hs.encrypted();
+                        let config = self.config.clone();
+                        let fut = async move { hs.encrypted_with(&config).await };
                         self.futures.push(Box::pin(fut));
                         continue;
                     } else {
@@ -129,3 +266,70 @@ impl ChannelIter {
         }
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn providers_of_every_address_accept_their_clients() {
+        // addresses and whether their channels are encrypted
+        #[allow(unused_mut)]
+        let mut addrs = vec![
+            ("tcp@127.0.0.1:0".to_string(), true),
+            ("itcp@127.0.0.1:0".into(), false),
+            ("ws@127.0.0.1:0".into(), false),
+            ("mem@any-provider".into(), true),
+            ("imem@any-insecure-provider".into(), false),
+        ];
+        #[cfg(unix)]
+        {
+            let path =
+                |name| std::env::temp_dir().join(format!("{}-{}.sock", name, std::process::id()));
+            addrs.push((format!("unix@{}", path("canary-any").display()), true));
+            addrs.push((format!("iunix@{}", path("canary-iany").display()), false));
+        }
+        for (addr, encrypted) in addrs {
+            let provider = AnyProvider::bind(&addr.parse().unwrap()).await.unwrap();
+            assert_eq!(provider.encrypted(), encrypted, "{}", addr);
+            let local = provider.local_addr().unwrap();
+            let server = tokio::spawn(async move {
+                let mut chan = provider.next_channel().await?;
+                let message: String = chan.receive().await?;
+                chan.send(message).await?;
+                Ok::<_, crate::Error>(chan.is_encrypted())
+            });
+            let mut chan = local.connect().await.unwrap();
+            chan.send("hello!").await.unwrap();
+            assert_eq!(
+                chan.receive::<String>().await.unwrap(),
+                "hello!",
+                "{}",
+                addr
+            );
+            assert_eq!(chan.is_encrypted(), encrypted, "{}", addr);
+            assert_eq!(server.await.unwrap().unwrap(), encrypted, "{}", addr);
+        }
+    }
+
+    #[tokio::test]
+    async fn peer_addresses_are_the_local_addresses_of_clients() {
+        for (addr, insecure) in [("tcp@127.0.0.1:0", false), ("itcp@127.0.0.1:0", true)] {
+            let provider = AnyProvider::bind(&addr.parse().unwrap()).await.unwrap();
+            let local = match provider.local_addr().unwrap() {
+                Addr::Tcp(addr, _) | Addr::InsecureTcp(addr) => addr.to_string(),
+                addr => panic!("bound to {}", addr),
+            };
+            let (client, hs) = tokio::join!(
+                crate::io::TcpStream::connect(local),
+                provider.next_handshake()
+            );
+            let client = Arc::new(HostAddr::Socket(client.unwrap().local_addr().unwrap()));
+            let expected = match insecure {
+                true => Addr::InsecureTcp(client),
+                false => Addr::Tcp(client, None),
+            };
+            assert_eq!(hs.unwrap().peer_addr(), Some(&expected));
+        }
+    }
+}
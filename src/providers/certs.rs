@@ -0,0 +1,171 @@
+#![cfg(not(target_arch = "wasm32"))]
+#![cfg(any(feature = "tls", feature = "quic"))]
+
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+use crate::err;
+use crate::Result;
+
+use rustls::{AlertDescription, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+
+/// rustls version used by the tls and quic providers, so configurations
+/// can be built without depending on it
+pub use ::rustls;
+
+/// client configuration trusting the webpki roots
+pub(super) fn webpki_config() -> Arc<ClientConfig> {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(webpki_roots())
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// the webpki roots
+pub(super) fn webpki_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    roots
+}
+
+/// get the name the server certificate is checked against from `host:port`
+pub(super) fn server_name(addr: &str) -> Result<ServerName> {
+    let (host, _) = addr
+        .rsplit_once(':')
+        .ok_or_else(|| err!(invalid_input, format!("missing port in {:?}", addr)))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(host)
+        .map_err(|_| err!(invalid_input, format!("invalid server name {:?}", host)))
+}
+
+/// rustls errors are returned as `InvalidData`, certificates that failed
+/// verification on either side are turned into `PermissionDenied`.
+/// the rustls error is kept as the source.
+#[cfg(feature = "tls")]
+pub(super) fn tls_err(e: std::io::Error) -> std::io::Error {
+    match e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+        Some(tls) => std::io::Error::new(tls_err_kind(tls), tls.clone()),
+        None => e,
+    }
+}
+
+/// errors of reads from tls channels. Tls 1.3 clients complete their handshake
+/// before the server checks their certificate, so its rejection arrives as an
+/// alert on the first read, which is turned into `PermissionDenied` too.
+/// rustls servers reject certificates of unknown issuers with a handshake failure,
+/// which can't arrive on a read for any other reason
+#[cfg(feature = "tls")]
+pub(crate) fn tls_read_err(e: crate::Error) -> crate::Error {
+    let e = tls_err(e.into());
+    match e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+        Some(tls @ rustls::Error::AlertReceived(AlertDescription::HandshakeFailure)) => {
+            std::io::Error::new(ErrorKind::PermissionDenied, tls.clone()).into()
+        }
+        _ => e.into(),
+    }
+}
+
+#[cfg(feature = "tls")]
+fn tls_err_kind(e: &rustls::Error) -> ErrorKind {
+    use rustls::Error::*;
+    match e {
+        NoCertificatesPresented
+        | UnsupportedNameType
+        | InvalidCertificateSignatureType
+        | InvalidCertificateSignature
+        | InvalidCertificateData(_)
+        | InvalidSct(_) => ErrorKind::PermissionDenied,
+        AlertReceived(alert) => alert_kind(*alert),
+        _ => ErrorKind::InvalidData,
+    }
+}
+
+/// alerts about certificates are `PermissionDenied`, the rest `InvalidData`
+pub(super) fn alert_kind(alert: AlertDescription) -> ErrorKind {
+    match alert {
+        AlertDescription::BadCertificate
+        | AlertDescription::UnsupportedCertificate
+        | AlertDescription::CertificateRevoked
+        | AlertDescription::CertificateExpired
+        | AlertDescription::CertificateUnknown
+        | AlertDescription::UnknownCA
+        | AlertDescription::AccessDenied
+        | AlertDescription::CertificateRequired => ErrorKind::PermissionDenied,
+        _ => ErrorKind::InvalidData,
+    }
+}
+
+#[cfg(all(test, any(feature = "tls", feature = "quic")))]
+pub(crate) mod tests {
+    #[cfg(feature = "tls")]
+    use std::sync::{Arc, Mutex};
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    #[cfg(feature = "tls")]
+    use rustls::server::{ClientHello, ResolvesServerCert};
+    #[cfg(feature = "tls")]
+    use rustls::sign::{any_supported_type, CertifiedKey};
+    use rustls::{Certificate, PrivateKey, RootCertStore};
+
+    /// certificate authority generated at test time
+    pub(crate) struct Ca(rcgen::Certificate);
+
+    impl Ca {
+        pub(crate) fn new() -> Self {
+            let mut params = CertificateParams::new(vec![]);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Ca(rcgen::Certificate::from_params(params).unwrap())
+        }
+
+        /// roots trusting only this authority
+        pub(crate) fn roots(&self) -> RootCertStore {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add(&Certificate(self.0.serialize_der().unwrap()))
+                .unwrap();
+            roots
+        }
+
+        /// certificate for the name issued by this authority, with its key
+        pub(crate) fn issue(&self, name: &str) -> (Vec<Certificate>, PrivateKey) {
+            let cert = rcgen::generate_simple_self_signed(vec![name.into()]).unwrap();
+            let der = cert.serialize_der_with_signer(&self.0).unwrap();
+            let key = PrivateKey(cert.serialize_private_key_der());
+            (vec![Certificate(der)], key)
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    /// presents a certificate and records the server names clients sent
+    pub(crate) struct SniRecorder {
+        key: Arc<CertifiedKey>,
+        pub(crate) names: Mutex<Vec<Option<String>>>,
+    }
+
+    #[cfg(feature = "tls")]
+    impl SniRecorder {
+        pub(crate) fn new((chain, key): (Vec<Certificate>, PrivateKey)) -> Self {
+            let key = any_supported_type(&key).unwrap();
+            SniRecorder {
+                key: Arc::new(CertifiedKey::new(chain, key)),
+                names: Default::default(),
+            }
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    impl ResolvesServerCert for SniRecorder {
+        fn resolve(&self, hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+            let name = hello.server_name().map(str::to_owned);
+            self.names.lock().unwrap().push(name);
+            Some(self.key.clone())
+        }
+    }
+}
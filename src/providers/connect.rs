@@ -0,0 +1,312 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use rand::Rng;
+
+use crate::err;
+use crate::io::{sleep, timeout};
+use crate::Result;
+
+#[derive(Debug, Clone, Copy)]
+/// Delays between connection attempts, which grow exponentially up to `max_delay`.
+/// Jitter spreads the attempts of clients that lost their server at the same
+/// time, so they don't all reconnect at once when it comes back.
+/// The default retries for up to 15 minutes, starting at 500 milliseconds.
+/// ```no_run
+/// # use std::time::Duration;
+/// # use canary::providers::{RetryPolicy, Tcp};
+/// # async fn run() -> canary::Result<()> {
+/// let policy = RetryPolicy::default()
+///     .initial_delay(Duration::from_millis(100))
+///     .max_delay(Duration::from_secs(10))
+///     .max_attempts(20);
+/// let chan = Tcp::connect_with_policy("127.0.0.1:8080", policy).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RetryPolicy {
+    /// Delay after the first failed attempt
+    pub initial_delay: Duration,
+    /// Factor the delay grows by after every attempt
+    pub multiplier: f64,
+    /// Longest delay between attempts
+    pub max_delay: Duration,
+    /// Number of attempts after which connecting fails, unbounded if `None`
+    pub max_attempts: Option<u32>,
+    /// Time after which connecting stops retrying, unbounded if `None`
+    pub max_elapsed: Option<Duration>,
+    /// Randomize every delay between half and one and a half times its value
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_delay: Duration::from_millis(500),
+            multiplier: 1.5,
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+            max_elapsed: Some(Duration::from_secs(15 * 60)),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Retry `attempts` times in total, waiting `delay` after each failure
+    pub fn fixed(delay: Duration, attempts: u32) -> Self {
+        RetryPolicy {
+            initial_delay: delay,
+            multiplier: 1.0,
+            max_delay: delay,
+            max_attempts: Some(attempts),
+            max_elapsed: None,
+            jitter: false,
+        }
+    }
+
+    #[must_use]
+    /// Set the delay after the first failed attempt
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    #[must_use]
+    /// Set the factor the delay grows by
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    #[must_use]
+    /// Set the longest delay between attempts
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    #[must_use]
+    /// Fail after this many attempts
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    #[must_use]
+    /// Stop retrying after this long, or never if `None`
+    pub fn max_elapsed(mut self, elapsed: impl Into<Option<Duration>>) -> Self {
+        self.max_elapsed = elapsed.into();
+        self
+    }
+
+    #[must_use]
+    /// Randomize the delays or not
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Delay after the given failed attempt, counting from 0, before jitter
+    /// ```
+    /// # use std::time::Duration;
+    /// # use canary::providers::RetryPolicy;
+    /// let policy = RetryPolicy::default().jitter(false);
+    /// assert_eq!(policy.delay(0), Duration::from_millis(500));
+    /// assert_eq!(policy.delay(1), Duration::from_millis(750));
+    /// ```
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(attempt.min(i32::MAX as u32) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        match delay < self.max_delay.as_secs_f64() {
+            true => Duration::from_secs_f64(delay),
+            false => self.max_delay,
+        }
+    }
+
+    /// Delay after the given failed attempt, with jitter if it's enabled.
+    /// Delays that jitter would take past `Duration::MAX` saturate.
+    pub fn jittered_delay(&self, attempt: u32) -> Duration {
+        let delay = self.delay(attempt);
+        match self.jitter {
+            true => {
+                let jittered = delay.as_secs_f64() * rand::thread_rng().gen_range(0.5..=1.5);
+                Duration::try_from_secs_f64(jittered).unwrap_or(Duration::MAX)
+            }
+            false => delay,
+        }
+    }
+
+    /// Whether to attempt again after `attempts` failed ones,
+    /// the next one starting after `elapsed`
+    fn allows(&self, attempts: u32, elapsed: Duration) -> bool {
+        let attempts_left = self.max_attempts.is_none_or(|max| attempts < max);
+        let time_left = self.max_elapsed.is_none_or(|max| elapsed <= max);
+        attempts_left && time_left
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Limits on the time spent connecting, unbounded if unset.
+/// Failed attempts are retried with the delays of the policy until the deadline.
+/// ```no_run
+/// # use std::time::{Duration, Instant};
+/// # use canary::providers::{ConnectOptions, Tcp};
+/// # async fn run() -> canary::Result<()> {
+/// let options = ConnectOptions::default()
+///     .timeout(Duration::from_secs(1))
+///     .deadline(Instant::now() + Duration::from_secs(10));
+/// let chan = Tcp::connect_options("10.0.0.1:8080", options).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ConnectOptions {
+    /// Time each attempt has to connect, since connecting to an address that
+    /// drops packets can hang for minutes
+    pub timeout: Option<Duration>,
+    /// Instant after which attempts are cut off and connecting fails with `TimedOut`
+    pub deadline: Option<Instant>,
+    /// Delays between attempts and when to stop retrying,
+    /// the default policy of the provider if `None`
+    pub policy: Option<RetryPolicy>,
+}
+
+impl ConnectOptions {
+    #[must_use]
+    /// Give up each attempt after this long
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    #[must_use]
+    /// Stop retrying at this instant
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    #[must_use]
+    /// Retry with this policy
+    pub fn policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// time the next attempt has, `None` if it's unbounded
+    fn attempt_timeout(&self) -> Option<Duration> {
+        let left = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (self.timeout, left) {
+            (Some(timeout), Some(left)) => Some(timeout.min(left)),
+            (timeout, left) => timeout.or(left),
+        }
+    }
+
+    /// run `connect` once within the limits of the options
+    pub(crate) async fn attempt<T>(&self, connect: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.attempt_timeout() {
+            Some(limit) => match timeout(limit, connect).await {
+                Ok(conn) => conn,
+                Err(_) => err!((timeout, "connecting timed out")),
+            },
+            None => connect.await,
+        }
+    }
+
+    /// run `connect` until it succeeds, waiting between attempts as the policy says,
+    /// returning the last error once the next attempt would start after the deadline
+    pub(crate) async fn retry<T, Fut>(&self, mut connect: impl FnMut() -> Fut) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let policy = self.policy.unwrap_or_default();
+        let start = Instant::now();
+        let mut attempts = 0;
+        loop {
+            let e = match self.attempt(connect()).await {
+                Ok(conn) => return Ok(conn),
+                Err(e) => e,
+            };
+            let delay = policy.jittered_delay(attempts);
+            attempts += 1;
+            let next = Instant::now() + delay;
+            let before_deadline = self.deadline.is_none_or(|deadline| next <= deadline);
+            if !before_deadline || !policy.allows(attempts, next - start) {
+                return Err(e);
+            }
+            tracing::debug!(
+                "connecting failed, attempt {} in {:?}: {}",
+                attempts,
+                delay,
+                e
+            );
+            sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn delays_grow_up_to_the_max() {
+        let policy = RetryPolicy::default()
+            .initial_delay(Duration::from_millis(100))
+            .multiplier(2.0)
+            .max_delay(Duration::from_millis(500))
+            .jitter(false);
+        let delays: Vec<_> = (0..5)
+            .map(|attempt| policy.jittered_delay(attempt))
+            .collect();
+        let millis = [100, 200, 400, 500, 500].map(Duration::from_millis);
+        assert_eq!(delays, millis);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jitter_saturates() {
+        let policy = RetryPolicy::default()
+            .initial_delay(Duration::MAX)
+            .max_delay(Duration::MAX);
+        for attempt in 0..100 {
+            assert!(policy.jittered_delay(attempt) >= Duration::MAX / 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_attempts_wait_for_the_delays() {
+        let policy = RetryPolicy::default()
+            .initial_delay(Duration::from_millis(10))
+            .multiplier(2.0)
+            .max_delay(Duration::from_millis(30))
+            .max_attempts(4)
+            .jitter(false);
+        let options = ConnectOptions::default().policy(policy);
+        let attempts = Mutex::new(Vec::new());
+        let e = options
+            .retry::<(), _>(|| async {
+                attempts.lock().unwrap().push(Instant::now());
+                err!((conn_refused, "refused"))
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused);
+
+        let attempts = attempts.into_inner().unwrap();
+        assert_eq!(attempts.len(), 4);
+        let waits = attempts.windows(2).map(|pair| pair[1] - pair[0]);
+        for (wait, millis) in waits.zip([10, 20, 30]) {
+            assert!(wait >= Duration::from_millis(millis), "{:?}", wait);
+        }
+    }
+}
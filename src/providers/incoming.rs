@@ -0,0 +1,136 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use futures::future::BoxFuture;
+use futures::stream::{unfold, Stream};
+
+use crate::channel::handshake::Handshake;
+use crate::Result;
+
+use super::{AnyProvider, Mem, Tcp, WebSocket};
+
+/// yields the handshakes of `next` until it fails, which ends the stream
+/// after the error, since providers retry errors that only affect a connection.
+/// the stream is boxed so it's `Unpin`, like most streams are
+fn stream<P>(
+    provider: P,
+    next: impl for<'a> Fn(&'a P) -> BoxFuture<'a, Result<Handshake>> + Send + Copy,
+) -> impl Stream<Item = Result<Handshake>> + Send + Unpin
+where
+    P: Send,
+{
+    Box::pin(unfold(Some(provider), move |provider| async move {
+        let provider = provider?;
+        let hs = next(&provider).await;
+        let provider = hs.is_ok().then_some(provider);
+        Some((hs, provider))
+    }))
+}
+
+macro_rules! incoming {
+    ($provider: ty, $name: literal, $path: literal) => {
+        impl $provider {
+            /// Stream of the channels of the provider, which ends after an error
+            /// ```no_run
+            /// # use futures::StreamExt;
+            #[doc = concat!("# async fn run(", $name, ": ", $path, ") -> canary::Result<()> {")]
+            #[doc = concat!("let mut channels = ", $name, ".incoming();")]
+            /// while let Some(chan) = channels.next().await {
+            ///     let mut chan = chan?.encrypted().await?;
+            ///     chan.send("hello!").await?;
+            /// }
+            /// # Ok(())
+            /// # }
+            /// ```
+            pub fn incoming(self) -> impl Stream<Item = Result<Handshake>> + Send + Unpin {
+                stream(self, |provider| Box::pin(provider.next()))
+            }
+
+            /// Stream of the channels of the provider that borrows it,
+            /// which ends after an error
+            /// ```no_run
+            /// # use futures::StreamExt;
+            /// # use canary::{channel::handshake::Handshake, Result};
+            /// # async fn handle(chan: Result<Handshake>) -> Result<()> {
+            /// #     Ok(())
+            /// # }
+            #[doc = concat!("# async fn run(", $name, ": ", $path, ") {")]
+            #[doc = concat!("let channels = ", $name, ".incoming_ref();")]
+            /// channels
+            ///     .for_each_concurrent(None, |chan| async move {
+            ///         let _ = handle(chan).await;
+            ///     })
+            ///     .await;
+            /// # }
+            /// ```
+            pub fn incoming_ref(
+                &self,
+            ) -> impl Stream<Item = Result<Handshake>> + Send + Unpin + '_ {
+                stream(self, |provider| Box::pin(provider.next()))
+            }
+        }
+    };
+}
+
+incoming!(Tcp, "tcp", "canary::providers::Tcp");
+incoming!(WebSocket, "wss", "canary::providers::WebSocket");
+incoming!(Mem, "mem", "canary::providers::Mem");
+#[cfg(unix)]
+incoming!(super::Unix, "unix", "canary::providers::Unix");
+#[cfg(feature = "tls")]
+incoming!(super::Tls, "tls", "canary::providers::Tls");
+#[cfg(feature = "quic")]
+incoming!(super::Quic, "quic", "canary::providers::Quic");
+
+impl AnyProvider {
+    /// Stream of the channels of the provider, which ends after an error
+    /// ```no_run
+    /// # use futures::StreamExt;
+    /// # use canary::providers::Addr;
+    /// # async fn run() -> canary::Result<()> {
+    /// let tcp = "tcp@127.0.0.1:8080".parse::<Addr>()?.bind().await?;
+    /// let unix = "unix@service.sock".parse::<Addr>()?.bind().await?;
+    /// let mut channels = futures::stream::select(tcp.incoming(), unix.incoming());
+    /// while let Some(chan) = channels.next().await {
+    ///     let mut chan = chan?.encrypted().await?;
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn incoming(self) -> impl Stream<Item = Result<Handshake>> + Send + Unpin {
+        stream(self, |provider| Box::pin(provider.next_handshake()))
+    }
+
+    /// Stream of the channels of the provider that borrows it,
+    /// which ends after an error
+    pub fn incoming_ref(&self) -> impl Stream<Item = Result<Handshake>> + Send + Unpin + '_ {
+        stream(self, |provider| Box::pin(provider.next_handshake()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn merged_providers_yield_the_channels_of_both() {
+        let tcp = Tcp::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let mem = Mem::bind("merged-providers").await.unwrap();
+        let mut channels = futures::stream::select(tcp.incoming(), mem.incoming());
+
+        let mut tcp = Tcp::connect_no_backoff(addr).await.unwrap().raw();
+        let mut mem = Mem::connect("merged-providers").await.unwrap().raw();
+        tcp.send("tcp").await.unwrap();
+        mem.send("mem").await.unwrap();
+        let mut received = vec![];
+        for _ in 0..2 {
+            let mut chan = channels.next().await.unwrap().unwrap().raw();
+            received.push(chan.receive::<String>().await.unwrap());
+        }
+        received.sort();
+        assert_eq!(received, ["mem", "tcp"]);
+    }
+}
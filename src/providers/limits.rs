@@ -0,0 +1,161 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+use crate::channel::channels::Permit;
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Limits on the connections a provider holds, unbounded if unset.
+/// Once a limit is reached, `next` waits for a connection to be dropped
+/// instead of accepting, so a client opening connections in a loop
+/// can't exhaust the file descriptors of the process.
+/// Limits apply to the `Tcp`, `Unix` and `Wss` providers; `Mem` providers
+/// hold no file descriptors and don't take limits.
+/// ```no_run
+/// # use canary::providers::{Limits, Tcp};
+/// # async fn run() -> canary::Result<()> {
+/// let limits = Limits::default().max_connections(1024).max_pending_handshakes(64);
+/// let tcp = Tcp::bind("0.0.0.0:8080").await?.limits(limits);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Limits {
+    /// Number of accepted channels that can be open at once,
+    /// counting a split channel until both of its halves are dropped
+    pub max_connections: Option<usize>,
+    /// Number of accepted connections that can be running a handshake at once,
+    /// from being accepted until their `Handshake` becomes a channel or is dropped
+    pub max_pending_handshakes: Option<usize>,
+}
+
+impl Limits {
+    #[must_use]
+    /// Limit the number of open channels
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    #[must_use]
+    /// Limit the number of connections running a handshake
+    pub fn max_pending_handshakes(mut self, max: usize) -> Self {
+        self.max_pending_handshakes = Some(max);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Connections a provider holds at some point, for monitoring
+/// ```no_run
+/// # use canary::providers::Tcp;
+/// # fn run(tcp: Tcp) {
+/// let counts = tcp.counts();
+/// tracing::info!("{} open, {} handshaking", counts.connections, counts.pending_handshakes);
+/// # }
+/// ```
+pub struct ConnectionCounts {
+    /// Number of accepted channels that are open
+    pub connections: usize,
+    /// Number of accepted connections running a handshake
+    pub pending_handshakes: usize,
+}
+
+/// permits of a provider, unlimited ones count connections all the same
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    connections: Arc<Semaphore>,
+    pending: Arc<Semaphore>,
+    limits: Limits,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter::new(Limits::default())
+    }
+}
+
+impl Limiter {
+    pub(crate) fn new(limits: Limits) -> Self {
+        let max = |limit: Option<usize>| limit.unwrap_or(Semaphore::MAX_PERMITS);
+        Limiter {
+            connections: Arc::new(Semaphore::new(max(limits.max_connections))),
+            pending: Arc::new(Semaphore::new(max(limits.max_pending_handshakes))),
+            limits,
+        }
+    }
+
+    /// wait until there's room for another connection,
+    /// returning its connection and pending handshake permits
+    pub(crate) async fn acquire(&self) -> (Permit, Permit) {
+        // the semaphores are never closed. pending handshakes are connections too,
+        // so their permit is taken second
+        let connection = self.connections.clone().acquire_owned().await.unwrap();
+        let pending = self.pending.clone().acquire_owned().await.unwrap();
+        (Permit::new(connection), Permit::new(pending))
+    }
+
+    pub(crate) fn counts(&self) -> ConnectionCounts {
+        let used = |semaphore: &Semaphore, limit: Option<usize>| {
+            limit.unwrap_or(Semaphore::MAX_PERMITS) - semaphore.available_permits()
+        };
+        ConnectionCounts {
+            connections: used(&self.connections, self.limits.max_connections),
+            pending_handshakes: used(&self.pending, self.limits.max_pending_handshakes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::providers::Tcp;
+
+    #[tokio::test]
+    async fn connections_past_the_limit_wait() {
+        let limiter = Limiter::new(Limits::default().max_connections(2));
+        let first = limiter.acquire().await;
+        let _second = limiter.acquire().await;
+        let counts = limiter.counts();
+        assert_eq!((counts.connections, counts.pending_handshakes), (2, 2));
+        let third = tokio::time::timeout(Duration::from_millis(50), limiter.acquire());
+        assert!(third.await.is_err());
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_millis(50), limiter.acquire());
+        assert!(third.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn channels_hold_their_permit_until_dropped() {
+        let tcp = Tcp::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .limits(Limits::default().max_connections(2));
+        let addr = tcp.local_addr().unwrap();
+        let mut clients = vec![];
+        for _ in 0..3 {
+            clients.push(Tcp::connect_no_backoff(addr).await.unwrap());
+        }
+        let first = tcp.next().await.unwrap().raw();
+        let second = tcp.next().await.unwrap();
+        assert_eq!(tcp.counts().connections, 2);
+        // the third client waits in the backlog
+        let third = tokio::time::timeout(Duration::from_millis(50), tcp.next());
+        assert!(third.await.is_err());
+        // split channels release the permit once both halves are dropped
+        let (send, receive) = first.split();
+        drop(send);
+        let third = tokio::time::timeout(Duration::from_millis(50), tcp.next());
+        assert!(third.await.is_err());
+        drop(receive);
+        let third = tokio::time::timeout(Duration::from_millis(50), tcp.next());
+        let _third = third.await.unwrap().unwrap();
+        // handshakes hold it too
+        assert_eq!(tcp.counts().connections, 2);
+        drop(second);
+        assert_eq!(tcp.counts().connections, 1);
+    }
+}
@@ -0,0 +1,135 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use compact_str::CompactString;
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Mutex as AsyncMutex,
+};
+
+use crate::channel::handshake::Handshake;
+use crate::err;
+use crate::io::{duplex, DuplexStream};
+use crate::Channel;
+use crate::Result;
+
+// size of the in-memory buffer of each direction of a connection
+const MEM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// providers bound in this process, by name
+static REGISTRY: Mutex<BTreeMap<CompactString, UnboundedSender<DuplexStream>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Exposes routes in memory, under a name unique to the process.
+/// Connections are in-memory pipes with the same framing as sockets,
+/// so tests and single-process demos don't need real sockets.
+/// The name is released once the provider is dropped.
+pub struct Mem {
+    name: CompactString,
+    sender: UnboundedSender<DuplexStream>,
+    incoming: AsyncMutex<UnboundedReceiver<DuplexStream>>,
+}
+
+impl Mem {
+    #[inline]
+    /// Bind to this name, failing with `AddrInUse` if a provider is already bound to it
+    /// ```no_run
+    /// # use canary::providers::Mem;
+    /// # async fn run() -> canary::Result<()> {
+    /// let mem = Mem::bind("service").await?;
+    /// while let Ok(chan) = mem.next().await {
+    ///     let mut chan = chan.encrypted().await?;
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind(name: &str) -> Result<Self> {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        if registry.contains_key(name) {
+            err!((in_use, format!("mem@{} is already bound", name)))?
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        registry.insert(name.into(), sender.clone());
+        Ok(Mem {
+            name: name.into(),
+            sender,
+            incoming: AsyncMutex::new(receiver),
+        })
+    }
+
+    #[inline]
+    /// get the next channel
+    ///
+    /// CANCEL SAFETY: this method is cancel-safe, feel free to use it in select statements.
+    /// ```no_run
+    /// # use canary::providers::Mem;
+    /// # async fn run(mem: Mem) -> canary::Result<()> {
+    /// while let Ok(chan) = mem.next().await {
+    ///     let mut chan = chan.encrypted().await?;
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn next(&self) -> Result<Handshake> {
+        // the provider keeps a sender, so this only ends if the provider is gone
+        let stream = self
+            .incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| err!(unexpected_eof, "mem provider closed"))?;
+        Ok(Handshake::acceptor(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+
+    /// Get the name the provider is bound to
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Connect to the provider bound to this name,
+    /// failing with `ConnectionRefused` if there is none
+    /// ```no_run
+    /// # use canary::providers::Mem;
+    /// # async fn run() -> canary::Result<()> {
+    /// let mut chan = Mem::connect("service").await?.encrypted().await?;
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(name: &str) -> Result<Handshake> {
+        let (stream, remote) = duplex(MEM_BUFFER_SIZE);
+        let sent = match REGISTRY.lock().unwrap_or_else(|e| e.into_inner()).get(name) {
+            Some(sender) => sender.send(remote).is_ok(),
+            None => false,
+        };
+        if !sent {
+            err!((conn_refused, format!("nothing is bound at mem@{}", name)))?
+        }
+        Ok(Handshake::connector(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+}
+
+impl Drop for Mem {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        // the name may only be released by the provider that holds it
+        if let Some(sender) = registry.get(&self.name) {
+            if sender.same_channel(&self.sender) {
+                registry.remove(&self.name);
+            }
+        }
+    }
+}
@@ -1,18 +1,56 @@
+mod accept;
 pub(crate) mod addr;
+mod addr_set;
 #[cfg(not(target_arch = "wasm32"))]
 mod any;
+pub(crate) mod certs;
+mod connect;
+#[cfg(not(target_arch = "wasm32"))]
+mod incoming;
+mod limits;
+mod mem;
+mod quic;
+#[cfg(not(target_arch = "wasm32"))]
+mod serve;
+mod srv;
 mod tcp;
+mod tls;
 mod unix;
 mod wss;
 
 pub use addr::*;
+pub use addr_set::*;
 pub use wss::*;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub use any::*;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use serve::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use connect::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use limits::*;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub use tcp::*;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use mem::*;
+
+#[cfg(all(not(target_arch = "wasm32"), any(feature = "tls", feature = "quic")))]
+pub use certs::rustls;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "tls"))]
+pub use tls::*;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "quic"))]
+pub use quic::*;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "srv"))]
+pub use srv::*;
+
 #[cfg(unix)]
 pub use unix::*;
@@ -0,0 +1,284 @@
+#![cfg(not(target_arch = "wasm32"))]
+#![cfg(feature = "quic")]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::{select, FutureExt, StreamExt};
+use quinn::{ConnectionError, Endpoint, Incoming};
+use rustls::{AlertDescription, ClientConfig, ServerConfig};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Mutex as AsyncMutex,
+};
+
+use crate::channel::handshake::Handshake;
+use crate::err;
+use crate::io::{runtime, ToSocketAddrs};
+use crate::Channel;
+use crate::Result;
+
+use super::certs::{alert_kind, server_name, webpki_config};
+use super::Addr;
+
+/// Exposes routes over QUIC, presenting the certificate of the server configuration.
+/// Every bidirectional stream the peers open becomes its own channel.
+///
+/// QUIC encrypts its streams with TLS 1.3, so `Handshake::encrypted` returns
+/// the channels as they are instead of running a noise handshake on top,
+/// unless the configuration authenticates the peer or forces it with
+/// `SnowConfig::force_encryption`.
+pub struct Quic {
+    endpoint: Endpoint,
+    /// streams opened by the peers of the endpoint
+    incoming: AsyncMutex<UnboundedReceiver<Handshake>>,
+}
+
+impl Quic {
+    /// Bind to this address.
+    /// Must be called within a tokio runtime, since connections are
+    /// accepted in background tasks, returns an error otherwise.
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use canary::providers::{rustls::{Certificate, PrivateKey, ServerConfig}, Quic};
+    /// # async fn run(certs: Vec<Certificate>, key: PrivateKey) -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = ServerConfig::builder()
+    ///     .with_safe_defaults()
+    ///     .with_no_client_auth()
+    ///     .with_single_cert(certs, key)?;
+    /// let quic = Quic::bind("0.0.0.0:4433", Arc::new(config)).await?;
+    /// while let Ok(chan) = quic.next().await {
+    ///     let mut chan = chan.encrypted().await?;
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind(addrs: impl ToSocketAddrs, config: Arc<ServerConfig>) -> Result<Self> {
+        let runtime = runtime()?;
+        let addr = lookup(addrs).await?;
+        let (endpoint, incoming) =
+            Endpoint::server(quinn::ServerConfig::with_crypto(config), addr)?;
+        let (accepted, streams) = mpsc::unbounded_channel();
+        runtime.spawn(accept_connections(incoming, accepted));
+        Ok(Quic {
+            endpoint,
+            incoming: AsyncMutex::new(streams),
+        })
+    }
+
+    /// get the next channel, from any connection of the endpoint
+    ///
+    /// CANCEL SAFETY: this method is cancel-safe, feel free to use it in select statements.
+    /// ```no_run
+    /// # use canary::providers::Quic;
+    /// # async fn run(quic: Quic) -> canary::Result<()> {
+    /// while let Ok(chan) = quic.next().await {
+    ///     let mut chan = chan.encrypted().await?;
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn next(&self) -> Result<Handshake> {
+        self.incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| err!(unexpected_eof, "quic endpoint closed"))
+    }
+
+    /// Get the local address of the endpoint
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Connect to `host:port` and open a stream.
+    /// The peer only sees the stream once something is sent through it.
+    /// The server certificate is checked against the roots of `config`,
+    /// or the webpki roots if there is none.
+    /// ```no_run
+    /// # use canary::providers::Quic;
+    /// # async fn run() -> canary::Result<()> {
+    /// let mut chan = Quic::connect("example.com:4433", None).await?.encrypted().await?;
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(addr: &str, config: Option<Arc<ClientConfig>>) -> Result<Handshake> {
+        QuicConnection::connect(addr, config).await?.open().await
+    }
+}
+
+/// Connection to a quic endpoint, which can open any number of channels
+/// ```no_run
+/// # use canary::providers::QuicConnection;
+/// # async fn run() -> canary::Result<()> {
+/// let conn = QuicConnection::connect("example.com:4433", None).await?;
+/// let mut control = conn.open().await?.encrypted().await?;
+/// let mut events = conn.open().await?.encrypted().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct QuicConnection {
+    connection: quinn::Connection,
+}
+
+impl QuicConnection {
+    /// Connect to `host:port`.
+    /// The server certificate is checked against the roots of `config`,
+    /// or the webpki roots if there is none, and the host is sent as the server name.
+    pub async fn connect(addr: &str, config: Option<Arc<ClientConfig>>) -> Result<Self> {
+        let name = server_name(addr)?;
+        let host = match &name {
+            rustls::ServerName::DnsName(name) => name.as_ref().to_owned(),
+            rustls::ServerName::IpAddress(ip) => ip.to_string(),
+            _ => return err!((invalid_input, format!("invalid server name in {:?}", addr))),
+        };
+        let remote = lookup(addr).await?;
+        let local: SocketAddr = match remote {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let config = quinn::ClientConfig::new(config.unwrap_or_else(webpki_config));
+        let connection = Endpoint::client(local)?
+            .connect_with(config, remote, &host)
+            .map_err(err!(@invalid_input))?
+            .await
+            .map_err(quic_err)?
+            .connection;
+        Ok(QuicConnection { connection })
+    }
+
+    /// Open a stream of the connection.
+    /// The peer only sees the stream once something is sent through it.
+    pub async fn open(&self) -> Result<Handshake> {
+        let stream = self.connection.open_bi().await.map_err(quic_err)?;
+        Ok(Handshake::connector(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        ))
+        .secure())
+    }
+
+    /// Get the address of the peer
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+}
+
+/// resolve the first address
+async fn lookup(addrs: impl ToSocketAddrs) -> Result<SocketAddr> {
+    tokio::net::lookup_host(addrs)
+        .await?
+        .next()
+        .ok_or_else(|| err!(not_found, "no endpoint found"))
+}
+
+// accept connections until the endpoint or the provider is dropped
+async fn accept_connections(mut incoming: Incoming, accepted: UnboundedSender<Handshake>) {
+    loop {
+        let connecting = select! {
+            connecting = incoming.next().fuse() => connecting,
+            _ = accepted.closed().fuse() => None,
+        };
+        match connecting {
+            Some(connecting) => tokio::spawn(accept_streams(connecting, accepted.clone())),
+            None => break,
+        };
+    }
+}
+
+// hand the streams the peer opens to the provider until the connection closes
+async fn accept_streams(connecting: quinn::Connecting, accepted: UnboundedSender<Handshake>) {
+    let remote = Addr::Quic(Arc::new(connecting.remote_address().to_string().into()));
+    let mut streams = match connecting.await {
+        Ok(conn) => conn.bi_streams,
+        Err(e) => {
+            tracing::debug!("quic connection failed: {}", e);
+            return;
+        }
+    };
+    loop {
+        let stream = select! {
+            stream = streams.next().fuse() => stream,
+            _ = accepted.closed().fuse() => None,
+        };
+        let stream = match stream {
+            Some(Ok(stream)) => stream,
+            _ => break,
+        };
+        let chan = Channel::from_raw(stream, Default::default(), Default::default());
+        let hs = Handshake::acceptor(chan).secure();
+        if accepted.send(hs.with_peer_addr(remote.clone())).is_err() {
+            break;
+        }
+    }
+}
+
+/// tls alerts about certificates are `PermissionDenied` and other tls
+/// alerts `InvalidData`, the quinn error is kept as the source
+fn quic_err(e: ConnectionError) -> std::io::Error {
+    let code = match &e {
+        ConnectionError::TransportError(e) => Some(u64::from(e.code)),
+        ConnectionError::ConnectionClosed(close) => Some(u64::from(close.error_code)),
+        _ => None,
+    };
+    match code {
+        // crypto errors carry the tls alert in the low byte
+        Some(code @ 0x100..=0x1ff) => {
+            std::io::Error::new(alert_kind(AlertDescription::from(code as u8)), e)
+        }
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::certs::tests::Ca;
+
+    #[tokio::test]
+    async fn streams_echo_over_loopback() {
+        let ca = Ca::new();
+        let (chain, key) = ca.issue("localhost");
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        // the endpoint and the client resolve localhost the same way
+        let quic = Quic::bind("localhost:0", Arc::new(config)).await.unwrap();
+        let port = quic.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok(hs) = quic.next().await {
+                tokio::spawn(async move {
+                    let mut chan = hs.encrypted().await?;
+                    let message: String = chan.receive().await?;
+                    chan.send(message).await
+                });
+            }
+        });
+
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(ca.roots())
+            .with_no_client_auth();
+        let addr = format!("localhost:{}", port);
+        let conn = QuicConnection::connect(&addr, Some(Arc::new(config)))
+            .await
+            .unwrap();
+        // each stream of the connection is its own channel
+        let mut chans = Vec::new();
+        for message in ["hello!", "world!"] {
+            let mut chan = conn.open().await.unwrap().encrypted().await.unwrap();
+            chan.send(message).await.unwrap();
+            chans.push((chan, message));
+        }
+        for (mut chan, message) in chans {
+            assert_eq!(chan.receive::<String>().await.unwrap(), message);
+        }
+    }
+}
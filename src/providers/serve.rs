@@ -0,0 +1,185 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::sync::Arc;
+
+use futures::Future;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use super::{AnyProvider, Tcp};
+use crate::async_snow::SnowConfig;
+use crate::io::runtime;
+use crate::Channel;
+use crate::Result;
+
+#[derive(Debug, Clone)]
+/// Configuration of the accept loop of `serve_with`
+/// ```no_run
+/// # use canary::{async_snow::SnowConfig, providers::{AnyProvider, ServeConfig}, Channel};
+/// # async fn handle(_: Channel) -> canary::Result<()> {
+/// #     Ok(())
+/// # }
+/// # fn run(provider: AnyProvider, private_key: Vec<u8>) -> canary::Result<()> {
+/// let config = ServeConfig::default()
+///     .snow(SnowConfig::new(private_key))
+///     .max_connections(1024);
+/// let server = provider.serve_with(handle, config)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ServeConfig {
+    /// Run the encryption handshake before handing channels to the handler
+    pub encrypted: bool,
+    /// Configuration of the encryption handshake
+    pub snow: SnowConfig,
+    /// Maximum number of connections handled at once, unbounded if `None`
+    pub max_connections: Option<usize>,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            encrypted: true,
+            snow: SnowConfig::default(),
+            max_connections: None,
+        }
+    }
+}
+
+impl ServeConfig {
+    #[must_use]
+    /// Hand channels to the handler without encrypting them
+    pub fn raw(mut self) -> Self {
+        self.encrypted = false;
+        self
+    }
+
+    #[must_use]
+    /// Encrypt channels with this configuration
+    pub fn snow(mut self, snow: SnowConfig) -> Self {
+        self.snow = snow;
+        self
+    }
+
+    #[must_use]
+    /// Stop accepting while this many connections are being handled,
+    /// so further clients wait in the backlog of the provider
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+}
+
+impl AnyProvider {
+    /// Accept channels in a background task and run the handler on each of them
+    /// in its own task. See `serve_with`.
+    /// ```no_run
+    /// # use canary::{err, providers::AnyProvider, Channel};
+    /// # async fn run(provider: AnyProvider) -> canary::Result<()> {
+    /// let server = provider.serve(|mut chan: Channel| async move {
+    ///     let name: String = chan.receive().await?;
+    ///     chan.send(format!("hello {name}!")).await?;
+    ///     Ok(())
+    /// })?;
+    /// server.await.map_err(err!(@other))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn serve<F, Fut>(self, handler: F) -> Result<JoinHandle<()>>
+    where
+        F: Fn(Channel) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.serve_with(handler, ServeConfig::default())
+    }
+
+    /// Accept channels in a background task and run the handler on each of them
+    /// in its own task.
+    /// Handshakes run in the task of their connection, so a slow client doesn't
+    /// hold up accepting the others. Failed handshakes and handlers are logged
+    /// and the loop keeps going, until the provider fails or the returned
+    /// handle is aborted.
+    /// Insecure providers never encrypt their channels.
+    /// Must be called within a tokio runtime, returns an error otherwise.
+    pub fn serve_with<F, Fut>(self, handler: F, config: ServeConfig) -> Result<JoinHandle<()>>
+    where
+        F: Fn(Channel) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        Ok(runtime()?.spawn(async move {
+            let encrypted = config.encrypted && self.encrypted();
+            let limit = config
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max)));
+            let snow = Arc::new(config.snow);
+            loop {
+                let permit = match &limit {
+                    // the semaphore is never closed
+                    Some(limit) => limit.clone().acquire_owned().await.ok(),
+                    None => None,
+                };
+                // providers retry on errors that only affect a connection
+                let hs = match self.next_handshake().await {
+                    Ok(hs) => hs,
+                    Err(e) => {
+                        tracing::error!("accepting connections failed: {}", e);
+                        return;
+                    }
+                };
+                let handler = handler.clone();
+                let snow = snow.clone();
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    let chan = if encrypted {
+                        match hs.encrypted_with(&snow).await {
+                            Ok(chan) => chan,
+                            Err(e) => {
+                                tracing::debug!("handshake failed: {}", e);
+                                return;
+                            }
+                        }
+                    } else {
+                        hs.raw()
+                    };
+                    if let Err(e) = handler(chan).await {
+                        tracing::error!("handler failed: {}", e);
+                    }
+                });
+            }
+        }))
+    }
+}
+
+impl Tcp {
+    #[inline]
+    /// Accept channels in a background task and run the handler on each of them
+    /// in its own task. See `AnyProvider::serve_with`.
+    /// ```no_run
+    /// # use canary::{providers::Tcp, Channel};
+    /// # async fn run() -> canary::Result<()> {
+    /// let server = Tcp::bind("127.0.0.1:8080").await?.serve(|mut chan: Channel| async move {
+    ///     chan.send("hello!").await?;
+    ///     Ok(())
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn serve<F, Fut>(self, handler: F) -> Result<JoinHandle<()>>
+    where
+        F: Fn(Channel) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        AnyProvider::Tcp(self).serve(handler)
+    }
+
+    #[inline]
+    /// Accept channels in a background task and run the handler on each of them
+    /// in its own task. See `AnyProvider::serve_with`.
+    pub fn serve_with<F, Fut>(self, handler: F, config: ServeConfig) -> Result<JoinHandle<()>>
+    where
+        F: Fn(Channel) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        AnyProvider::Tcp(self).serve_with(handler, config)
+    }
+}
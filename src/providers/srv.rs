@@ -0,0 +1,232 @@
+#![cfg(not(target_arch = "wasm32"))]
+#![cfg(feature = "srv")]
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use compact_str::CompactString;
+use rand::Rng;
+use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::channel::handshake::Handshake;
+use crate::providers::{ConnectOptions, RetryPolicy, Tcp};
+use crate::{err, Error, Result};
+
+pub use trust_dns_resolver;
+
+/// resolver of `srv@` addresses, shared so its cache is too
+static RESOLVER: OnceLock<SrvResolver> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Target of a srv record
+pub struct SrvTarget {
+    /// Hostname of the target
+    pub host: CompactString,
+    /// Port of the target
+    pub port: u16,
+    /// Targets with lower priorities are tried first
+    pub priority: u16,
+    /// Targets with the same priority are picked in proportion to their weight
+    pub weight: u16,
+}
+
+#[derive(Clone)]
+/// Resolves srv records into the tcp targets of a service, caching
+/// them for their ttl. `srv@` addresses are resolved with one configured
+/// like the system.
+/// ```no_run
+/// # use canary::providers::{ConnectOptions, SrvResolver};
+/// # async fn run() -> canary::Result<()> {
+/// let resolver = SrvResolver::from_system_conf()?;
+/// let targets = resolver.resolve("_canary._tcp.myservice.internal").await?;
+/// let chan = resolver.connect("_canary._tcp.myservice.internal", ConnectOptions::default()).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SrvResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl SrvResolver {
+    #[inline]
+    /// Create a resolver that queries the name servers of the configuration
+    pub fn new(config: ResolverConfig, options: ResolverOpts) -> Self {
+        SrvResolver {
+            resolver: TokioAsyncResolver::tokio(config, options),
+        }
+    }
+
+    /// Create a resolver configured like the system, such as from `/etc/resolv.conf`
+    pub fn from_system_conf() -> Result<Self> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().map_err(resolve_err)?;
+        Ok(SrvResolver { resolver })
+    }
+
+    /// resolver of `srv@` addresses, falling back to the default
+    /// configuration if the system one can't be read
+    pub(crate) fn shared() -> &'static SrvResolver {
+        RESOLVER.get_or_init(|| {
+            Self::from_system_conf().unwrap_or_else(|e| {
+                tracing::debug!("reading the system dns configuration failed: {}", e);
+                Self::new(ResolverConfig::default(), ResolverOpts::default())
+            })
+        })
+    }
+
+    /// Get the targets of the name in the order they should be tried:
+    /// by priority, and picked at random in proportion to their weight
+    /// within a priority (RFC 2782).
+    /// Fails with `NotFound` if the name has no srv records or the service
+    /// is marked as unavailable, and with the error of the lookup otherwise.
+    pub async fn resolve(&self, name: &str) -> Result<Vec<SrvTarget>> {
+        let records = self
+            .resolver
+            .srv_lookup(name)
+            .await
+            .map_err(|e| match e.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => not_found(name),
+                _ => resolve_err(e),
+            })?;
+        let targets: Vec<_> = records
+            .iter()
+            // a target of "." means the service is not available
+            .filter(|srv| !srv.target().is_root())
+            .map(|srv| SrvTarget {
+                host: srv.target().to_utf8().trim_end_matches('.').into(),
+                port: srv.port(),
+                priority: srv.priority(),
+                weight: srv.weight(),
+            })
+            .collect();
+        if targets.is_empty() {
+            return Err(not_found(name));
+        }
+        Ok(ordered(targets))
+    }
+
+    /// Resolve the name and connect to its targets in order until one accepts.
+    /// Each target is attempted once unless the options have a retry policy.
+    /// Fails with the error of the last target if none of them accepts.
+    pub async fn connect(&self, name: &str, mut options: ConnectOptions) -> Result<Handshake> {
+        // retrying a target would hold back the next one
+        options
+            .policy
+            .get_or_insert(RetryPolicy::fixed(Duration::ZERO, 1));
+        let mut last = None;
+        for target in self.resolve(name).await? {
+            let addr = (target.host.as_str(), target.port);
+            match Tcp::connect_options(addr, options).await {
+                Ok(hs) => return Ok(hs),
+                Err(e) => {
+                    tracing::debug!(
+                        "connecting to {}:{} failed: {}",
+                        target.host,
+                        target.port,
+                        e
+                    );
+                    last = Some(e);
+                }
+            }
+        }
+        // resolve never returns an empty list
+        Err(last.unwrap_or_else(|| not_found(name)))
+    }
+}
+
+/// order targets by priority, and by weighted random picks within a priority
+fn ordered(mut targets: Vec<SrvTarget>) -> Vec<SrvTarget> {
+    // zero weights go first, so they are only picked by a roll of 0
+    targets.sort_by_key(|target| (target.priority, target.weight != 0));
+    let mut rng = rand::thread_rng();
+    let mut ordered = Vec::with_capacity(targets.len());
+    for group in targets.chunk_by(|a, b| a.priority == b.priority) {
+        let mut group = group.to_vec();
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|target| u32::from(target.weight)).sum();
+            let roll = rng.gen_range(0..=total);
+            let mut sum = 0;
+            let idx = group
+                .iter()
+                .position(|target| {
+                    sum += u32::from(target.weight);
+                    sum >= roll
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(idx));
+        }
+    }
+    ordered
+}
+
+fn not_found(name: &str) -> Error {
+    err!(not_found, format!("no srv records found for {:?}", name))
+}
+
+/// timeouts are `TimedOut` and other failures `Other`, the resolver error is kept as the source
+fn resolve_err(e: ResolveError) -> Error {
+    let kind = match e.kind() {
+        ResolveErrorKind::Timeout => std::io::ErrorKind::TimedOut,
+        _ => std::io::ErrorKind::Other,
+    };
+    Error::new(std::io::Error::new(kind, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(host: &str, priority: u16, weight: u16) -> SrvTarget {
+        SrvTarget {
+            host: host.into(),
+            port: 8080,
+            priority,
+            weight,
+        }
+    }
+
+    // hosts of the targets in the order they would be tried
+    fn hosts(targets: &[SrvTarget]) -> Vec<String> {
+        let ordered = ordered(targets.to_vec()).into_iter();
+        ordered.map(|target| target.host.to_string()).collect()
+    }
+
+    #[test]
+    fn lower_priorities_come_first() {
+        let targets = [
+            target("backup", 20, 100),
+            target("primary", 10, 0),
+            target("secondary", 15, 5),
+        ];
+        for _ in 0..100 {
+            assert_eq!(hosts(&targets), ["primary", "secondary", "backup"]);
+        }
+    }
+
+    #[test]
+    fn targets_are_picked_in_proportion_to_their_weight() {
+        let targets = [
+            target("light", 10, 1),
+            target("heavy", 10, 3),
+            target("idle", 10, 0),
+        ];
+        let runs = 10_000;
+        let mut heavy = 0;
+        let mut idle = 0;
+        for _ in 0..runs {
+            let hosts = hosts(&targets);
+            assert_eq!(hosts.len(), 3);
+            match hosts[0].as_str() {
+                "heavy" => heavy += 1,
+                "idle" => idle += 1,
+                _ => (),
+            }
+        }
+        // heavy goes first 3/5 of the time, zero weights only on a roll of 0, 1/5
+        let heavy = heavy as f64 / runs as f64;
+        let idle = idle as f64 / runs as f64;
+        assert!((0.55..0.65).contains(&heavy), "{}", heavy);
+        assert!((0.15..0.25).contains(&idle), "{}", idle);
+    }
+}
@@ -1,20 +1,63 @@
 #![cfg(not(target_arch = "wasm32"))]
 
+use super::accept::retry;
+use super::limits::Limiter;
+use super::{Addr, ConnectOptions, ConnectionCounts, HostAddr, Limits, RetryPolicy};
 use crate::channel::handshake::Handshake;
+use crate::err;
 use crate::io::TcpListener;
 use crate::io::TcpStream;
 use crate::io::ToSocketAddrs;
 use crate::Channel;
 use crate::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+#[cfg(feature = "socket_options")]
+use std::time::Duration;
+use std::time::Instant;
 
-use backoff::ExponentialBackoff;
-use derive_more::{From, Into};
+#[cfg(feature = "socket_options")]
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpSocket;
 
-#[derive(From, Into)]
-#[into(owned, ref, ref_mut)]
-#[repr(transparent)]
 /// Exposes routes over TCP
-pub struct Tcp(TcpListener);
+pub struct Tcp {
+    listener: TcpListener,
+    options: TcpOptions,
+    limiter: Limiter,
+}
+
+impl From<TcpListener> for Tcp {
+    #[inline]
+    fn from(listener: TcpListener) -> Self {
+        Tcp {
+            listener,
+            options: TcpOptions::default(),
+            limiter: Limiter::default(),
+        }
+    }
+}
+
+impl From<Tcp> for TcpListener {
+    #[inline]
+    fn from(tcp: Tcp) -> Self {
+        tcp.listener
+    }
+}
+
+impl<'a> From<&'a Tcp> for &'a TcpListener {
+    #[inline]
+    fn from(tcp: &'a Tcp) -> Self {
+        &tcp.listener
+    }
+}
+
+impl<'a> From<&'a mut Tcp> for &'a mut TcpListener {
+    #[inline]
+    fn from(tcp: &'a mut Tcp) -> Self {
+        &mut tcp.listener
+    }
+}
 
 impl Tcp {
     #[inline]
@@ -28,31 +71,101 @@ impl Tcp {
     /// ```
     pub async fn bind(addrs: impl ToSocketAddrs) -> Result<Self> {
         let listener = TcpListener::bind(addrs).await?;
-        Ok(Tcp(listener))
+        Ok(Tcp::from(listener))
     }
 
     #[inline]
-    /// get the next channel
+    /// Bind to this address with the socket options,
+    /// which also apply to the channels the provider accepts
     /// ```no_run
+    /// # use canary::providers::{Tcp, TcpOptions};
+    /// # async fn run() -> canary::Result<()> {
+    /// let options = TcpOptions::default().nodelay().reuse_port();
+    /// let tcp = Tcp::bind_with("0.0.0.0:8080", options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_with(addrs: impl ToSocketAddrs, options: TcpOptions) -> Result<Self> {
+        let listener = options.bind(addrs).await?;
+        Ok(Tcp {
+            listener,
+            options,
+            limiter: Limiter::default(),
+        })
+    }
+
+    #[inline]
+    /// get the next channel, with the address of the peer
+    /// ```no_run
+    /// # use canary::providers::Tcp;
+    /// # async fn run(tcp: Tcp) -> canary::Result<()> {
     /// while let Ok(chan) = tcp.next().await {
+    ///     tracing::info!("connection from {:?}", chan.peer_addr());
     ///     let mut chan = chan.encrypted().await?;
     ///     chan.send("hello!").await?;
     /// }
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn next(&self) -> Result<Handshake> {
-        let (stream, _) = self.0.accept().await?;
-        Ok(Handshake::from(Channel::from_raw(
+        let (connection, pending) = self.limiter.acquire().await;
+        let (stream, addr) = loop {
+            let (stream, addr) = retry(|| self.listener.accept()).await?;
+            // options can fail on sockets the peer already reset,
+            // which shouldn't stop the provider
+            match self.options.apply(&stream) {
+                Ok(()) => break (stream, addr),
+                Err(e) => tracing::warn!("setting the options of {} failed: {}", addr, e),
+            }
+        };
+        let hs = Handshake::acceptor(Channel::from_raw(
             stream,
             Default::default(),
             Default::default(),
-        )))
+        ));
+        let hs = hs.with_permits(connection, pending);
+        Ok(hs.with_peer_addr(Addr::Tcp(Arc::new(HostAddr::Socket(addr)), None)))
+    }
+    #[must_use]
+    /// Limit the connections the provider holds, `next` waits for
+    /// connections to be dropped once a limit is reached
+    /// ```no_run
+    /// # use canary::providers::{Limits, Tcp};
+    /// # async fn run() -> canary::Result<()> {
+    /// let limits = Limits::default().max_connections(1024);
+    /// let tcp = Tcp::bind("0.0.0.0:8080").await?.limits(limits);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limiter = Limiter::new(limits);
+        self
+    }
+    #[inline]
+    /// Get the number of open channels and pending handshakes the provider accepted
+    pub fn counts(&self) -> ConnectionCounts {
+        self.limiter.counts()
+    }
+    #[inline]
+    /// Get the address the provider is bound to,
+    /// such as the port picked when binding to port 0
+    /// ```no_run
+    /// # use canary::providers::Tcp;
+    /// # async fn run() -> canary::Result<()> {
+    /// let tcp = Tcp::bind("127.0.0.1:0").await?;
+    /// let port = tcp.local_addr()?.port();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
     }
     /// connect to address without any backoff strategy
     pub async fn connect_no_backoff(
         addrs: impl ToSocketAddrs + std::fmt::Debug,
     ) -> Result<Handshake> {
         let stream = TcpStream::connect(&addrs).await?;
-        Ok(Handshake::from(Channel::from_raw(
+        Ok(Handshake::connector(Channel::from_raw(
             stream,
             Default::default(),
             Default::default(),
@@ -61,15 +174,343 @@ impl Tcp {
     #[inline]
     /// Connect to the following address with the given id and retry in case of failure
     pub async fn connect(addrs: impl ToSocketAddrs + std::fmt::Debug) -> Result<Handshake> {
-        let hs = backoff::future::retry(ExponentialBackoff::default(), || async {
-            let stream = TcpStream::connect(&addrs).await?;
-            Ok(Handshake::from(Channel::from_raw(
-                stream,
-                Default::default(),
-                Default::default(),
-            )))
-        })
-        .await?;
-        Ok(hs)
+        Self::connect_options(addrs, ConnectOptions::default()).await
+    }
+    #[inline]
+    /// Connect to the following address and retry in case of failure until the deadline,
+    /// failing with `TimedOut` if it passes while connecting
+    /// ```no_run
+    /// # use std::time::{Duration, Instant};
+    /// # use canary::providers::Tcp;
+    /// # async fn run() -> canary::Result<()> {
+    /// let deadline = Instant::now() + Duration::from_secs(5);
+    /// let mut chan = Tcp::connect_deadline("127.0.0.1:8080", deadline).await?.encrypted().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_deadline(
+        addrs: impl ToSocketAddrs + std::fmt::Debug,
+        deadline: Instant,
+    ) -> Result<Handshake> {
+        Self::connect_options(addrs, ConnectOptions::default().deadline(deadline)).await
+    }
+    /// Connect to the following address and retry in case of failure
+    /// with the delays of the policy
+    /// ```no_run
+    /// # use canary::providers::{RetryPolicy, Tcp};
+    /// # async fn run() -> canary::Result<()> {
+    /// let policy = RetryPolicy::default().max_attempts(5);
+    /// let mut chan = Tcp::connect_with_policy("127.0.0.1:8080", policy).await?.encrypted().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with_policy(
+        addrs: impl ToSocketAddrs + std::fmt::Debug,
+        policy: RetryPolicy,
+    ) -> Result<Handshake> {
+        Self::connect_options(addrs, ConnectOptions::default().policy(policy)).await
+    }
+    /// Connect to the following address and retry in case of failure,
+    /// within the time limits of the options
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::providers::{ConnectOptions, Tcp};
+    /// # async fn run() -> canary::Result<()> {
+    /// let options = ConnectOptions::default().timeout(Duration::from_millis(500));
+    /// let mut chan = Tcp::connect_options("127.0.0.1:8080", options).await?.encrypted().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_options(
+        addrs: impl ToSocketAddrs + std::fmt::Debug,
+        options: ConnectOptions,
+    ) -> Result<Handshake> {
+        let stream = options
+            .retry(|| async { Ok(TcpStream::connect(&addrs).await?) })
+            .await?;
+        Ok(Handshake::connector(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    /// Connect to the following address with the socket options and retry in case of failure
+    /// ```no_run
+    /// # use canary::providers::{Tcp, TcpOptions};
+    /// # async fn run() -> canary::Result<()> {
+    /// let options = TcpOptions::default().nodelay();
+    /// let mut chan = Tcp::connect_with("127.0.0.1:8080", options).await?.encrypted().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with(
+        addrs: impl ToSocketAddrs + std::fmt::Debug,
+        options: TcpOptions,
+    ) -> Result<Handshake> {
+        let stream = ConnectOptions::default()
+            .retry(|| options.connect(&addrs))
+            .await?;
+        Ok(Handshake::connector(Channel::from_raw(
+            stream,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Options of tcp sockets, left to the system defaults if unset.
+/// Keepalive probes, and buffer sizes of accepted sockets on systems where
+/// they aren't inherited from the listener, need the `socket_options` feature.
+/// ```no_run
+/// # use canary::providers::TcpOptions;
+/// let options = TcpOptions::default()
+///     .nodelay()
+///     .send_buffer_size(256 * 1024)
+///     .recv_buffer_size(256 * 1024);
+/// ```
+pub struct TcpOptions {
+    /// Disable Nagle's algorithm, sending small messages right away (`TCP_NODELAY`)
+    pub nodelay: bool,
+    #[cfg(feature = "socket_options")]
+    /// Idle time after which keepalive probes are sent (`SO_KEEPALIVE`)
+    pub keepalive: Option<Duration>,
+    #[cfg(feature = "socket_options")]
+    /// Time between keepalive probes, ignored on platforms that can't set it
+    pub keepalive_interval: Option<Duration>,
+    /// Allow binding to an address in `TIME_WAIT` (`SO_REUSEADDR`),
+    /// listeners set it on unix unless this is `Some(false)`
+    pub reuse_address: Option<bool>,
+    /// Allow several listeners to bind to the same address (`SO_REUSEPORT`), unix only
+    pub reuse_port: bool,
+    /// Size of the send buffer of the socket (`SO_SNDBUF`)
+    pub send_buffer_size: Option<u32>,
+    /// Size of the receive buffer of the socket (`SO_RCVBUF`)
+    pub recv_buffer_size: Option<u32>,
+}
+
+impl TcpOptions {
+    #[must_use]
+    /// Disable Nagle's algorithm
+    pub fn nodelay(mut self) -> Self {
+        self.nodelay = true;
+        self
+    }
+
+    #[must_use]
+    #[cfg(feature = "socket_options")]
+    /// Send keepalive probes once the connection stays idle for this long
+    pub fn keepalive(mut self, time: Duration) -> Self {
+        self.keepalive = Some(time);
+        self
+    }
+
+    #[must_use]
+    #[cfg(feature = "socket_options")]
+    /// Send keepalive probes this often, keepalive must be enabled for it to apply
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    #[must_use]
+    /// Set `SO_REUSEADDR` on listeners
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.reuse_address = Some(reuse);
+        self
+    }
+
+    #[must_use]
+    /// Set `SO_REUSEPORT` on listeners
+    pub fn reuse_port(mut self) -> Self {
+        self.reuse_port = true;
+        self
+    }
+
+    #[must_use]
+    /// Set the size of the send buffer
+    pub fn send_buffer_size(mut self, size: u32) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    #[must_use]
+    /// Set the size of the receive buffer
+    pub fn recv_buffer_size(mut self, size: u32) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// create a socket for the address with the options that must be set before binding or connecting
+    fn socket(&self, addr: SocketAddr) -> Result<TcpSocket> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(socket)
+    }
+
+    /// bind a listener to the first address that can be bound
+    pub(crate) async fn bind(&self, addrs: impl ToSocketAddrs) -> Result<TcpListener> {
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(addrs).await? {
+            match self.bind_addr(addr) {
+                Ok(listener) => return Ok(listener),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| err!(invalid_input, "could not resolve to any address")))
+    }
+
+    fn bind_addr(&self, addr: SocketAddr) -> Result<TcpListener> {
+        let socket = self.socket(addr)?;
+        // the same default as `TcpListener::bind`
+        socket.set_reuseaddr(self.reuse_address.unwrap_or(cfg!(unix)))?;
+        if self.reuse_port {
+            #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+            socket.set_reuseport(true)?;
+            #[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+            err!((
+                unsupported,
+                "SO_REUSEPORT is not supported on this platform"
+            ))?;
+        }
+        socket.bind(addr)?;
+        Ok(socket.listen(1024)?)
+    }
+
+    /// connect to the first address that accepts the connection
+    pub(crate) async fn connect(&self, addrs: impl ToSocketAddrs) -> Result<TcpStream> {
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(addrs).await? {
+            match self.socket(addr)?.connect(addr).await {
+                Ok(stream) => {
+                    self.apply(&stream)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = Some(e.into()),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| err!(invalid_input, "could not resolve to any address")))
+    }
+
+    /// set the options of connected streams
+    pub(crate) fn apply(&self, stream: &TcpStream) -> Result<()> {
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+        #[cfg(feature = "socket_options")]
+        self.apply_socket_options(stream)?;
+        Ok(())
+    }
+
+    /// set the options tokio can't set on connected streams
+    #[cfg(feature = "socket_options")]
+    fn apply_socket_options(&self, stream: &TcpStream) -> Result<()> {
+        let socket = SockRef::from(stream);
+        if let Some(time) = self.keepalive {
+            #[allow(unused_mut)]
+            let mut keepalive = TcpKeepalive::new().with_time(time);
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "windows"
+            ))]
+            if let Some(interval) = self.keepalive_interval {
+                keepalive = keepalive.with_interval(interval);
+            }
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        // accepted sockets may not inherit the buffer sizes of the listener
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size as usize)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size as usize)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::channel::raw::unified::unformatted::UnformattedRawUnifiedChannel;
+
+    #[tokio::test]
+    async fn accepted_streams_get_the_options() {
+        let options = TcpOptions::default().nodelay();
+        let tcp = Tcp::bind_with("127.0.0.1:0", options).await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let (client, hs) = tokio::join!(TcpStream::connect(addr), tcp.next());
+        let _client = client.unwrap();
+        match hs.unwrap().raw().into_inner().unwrap() {
+            UnformattedRawUnifiedChannel::Tcp(stream) => assert!(stream.nodelay().unwrap()),
+            _ => panic!("accepted a channel that isn't tcp"),
+        }
+    }
+
+    // options that give up after a single attempt of 100ms
+    fn attempt_of_100ms() -> ConnectOptions {
+        ConnectOptions::default()
+            .timeout(Duration::from_millis(100))
+            .policy(RetryPolicy::default().max_attempts(1))
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a network that drops the packets sent to 10.255.255.1"]
+    async fn unroutable_addresses_time_out() {
+        let start = Instant::now();
+        let e = Tcp::connect_options("10.255.255.1:8080", attempt_of_100ms())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// address of a listener whose backlog is full, along with the listener
+    /// and the connections filling it. Linux drops the connections it has no
+    /// room for, which hang like the ones to addresses that drop packets
+    pub(crate) async fn full_backlog() -> (SocketAddr, (TcpListener, Vec<TcpStream>)) {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut queued = Vec::new();
+        loop {
+            match timeout(Duration::from_millis(100), TcpStream::connect(addr)).await {
+                Ok(stream) => queued.push(stream.unwrap()),
+                Err(_) => break,
+            }
+            assert!(queued.len() < 64, "the backlog never filled");
+        }
+        (addr, (listener, queued))
+    }
+
+    #[tokio::test]
+    async fn listeners_with_a_full_backlog_time_out() {
+        let (addr, _backlog) = full_backlog().await;
+        let start = Instant::now();
+        let e = Tcp::connect_options(addr, attempt_of_100ms())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
     }
 }
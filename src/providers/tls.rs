@@ -0,0 +1,439 @@
+#![cfg(not(target_arch = "wasm32"))]
+#![cfg(feature = "tls")]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::channel::handshake::Handshake;
+use crate::err;
+use crate::io::TcpListener;
+use crate::io::TcpStream;
+use crate::io::ToSocketAddrs;
+use crate::Channel;
+use crate::Result;
+
+use super::accept::Accepting;
+use super::{Addr, ConnectOptions};
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig, ServerName};
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+
+pub(super) use super::certs::server_name;
+use super::certs::{tls_err, webpki_config, webpki_roots};
+
+#[derive(Clone)]
+/// Configuration of tls servers, for tls and secure websocket providers,
+/// presenting a certificate chain and optionally requiring one from clients
+/// ```no_run
+/// # use canary::providers::{rustls::{Certificate, PrivateKey, RootCertStore}, Tls, TlsServerConfig};
+/// # async fn run(client_roots: RootCertStore, chain: Vec<Certificate>, key: PrivateKey) -> canary::Result<()> {
+/// let config = TlsServerConfig::new(chain, key).require_client_auth(client_roots).build()?;
+/// let tls = Tls::bind("0.0.0.0:443", config).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TlsServerConfig {
+    /// Certificate chain presented to clients, leaf first
+    pub chain: Vec<Certificate>,
+    /// Private key of the leaf certificate
+    pub key: PrivateKey,
+    /// Roots client certificates are verified against,
+    /// clients aren't asked for a certificate if `None`
+    pub client_roots: Option<RootCertStore>,
+}
+
+impl TlsServerConfig {
+    /// Present this certificate chain, leaf first, signed by the key
+    pub fn new(chain: Vec<Certificate>, key: PrivateKey) -> Self {
+        TlsServerConfig {
+            chain,
+            key,
+            client_roots: None,
+        }
+    }
+
+    #[must_use]
+    /// Require clients to present a certificate issued by these roots,
+    /// failing their handshake otherwise
+    pub fn require_client_auth(mut self, roots: RootCertStore) -> Self {
+        self.client_roots = Some(roots);
+        self
+    }
+
+    /// Build the rustls configuration, failing with `InvalidInput`
+    /// if the key doesn't match the certificate
+    pub fn build(self) -> Result<Arc<ServerConfig>> {
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match self.client_roots {
+            Some(roots) => {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(self.chain, self.key)
+            .map_err(err!(@invalid_input))?;
+        Ok(Arc::new(config))
+    }
+}
+
+#[derive(Clone, Default)]
+/// Configuration of tls clients, for tls and secure websocket providers,
+/// trusting the webpki roots unless others are set
+/// ```no_run
+/// # use canary::providers::{rustls::{Certificate, PrivateKey, RootCertStore}, Tls, TlsClientConfig};
+/// # async fn run(roots: RootCertStore, chain: Vec<Certificate>, key: PrivateKey) -> canary::Result<()> {
+/// let config = TlsClientConfig::default().roots(roots).with_client_cert(chain, key).build()?;
+/// let chan = Tls::connect("gateway.internal:443", Some(config)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TlsClientConfig {
+    /// Roots the server certificate is verified against, the webpki roots if `None`
+    pub roots: Option<RootCertStore>,
+    /// Certificate chain presented to servers that ask for one, with the key of its leaf
+    pub client_cert: Option<(Vec<Certificate>, PrivateKey)>,
+}
+
+impl TlsClientConfig {
+    #[must_use]
+    /// Verify the server certificate against these roots
+    pub fn roots(mut self, roots: RootCertStore) -> Self {
+        self.roots = Some(roots);
+        self
+    }
+
+    #[must_use]
+    /// Present this certificate chain, leaf first, signed by the key
+    pub fn with_client_cert(mut self, chain: Vec<Certificate>, key: PrivateKey) -> Self {
+        self.client_cert = Some((chain, key));
+        self
+    }
+
+    /// Build the rustls configuration, failing with `InvalidInput`
+    /// if the key doesn't match the certificate
+    pub fn build(self) -> Result<Arc<ClientConfig>> {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.roots.unwrap_or_else(webpki_roots));
+        let config = match self.client_cert {
+            Some((chain, key)) => builder
+                .with_single_cert(chain, key)
+                .map_err(err!(@invalid_input))?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Arc::new(config))
+    }
+}
+
+/// Exposes routes over TLS, presenting the certificate of the server configuration
+pub struct Tls {
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    accepting: Accepting<Handshake>,
+}
+
+impl Tls {
+    #[inline]
+    /// Bind to this address
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use canary::providers::{rustls::{Certificate, PrivateKey, ServerConfig}, Tls};
+    /// # async fn run(certs: Vec<Certificate>, key: PrivateKey) -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = ServerConfig::builder()
+    ///     .with_safe_defaults()
+    ///     .with_no_client_auth()
+    ///     .with_single_cert(certs, key)?;
+    /// let tls = Tls::bind("127.0.0.1:443", Arc::new(config)).await?;
+    /// while let Ok(chan) = tls.next().await {
+    ///     let mut chan = chan.raw();
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind(addrs: impl ToSocketAddrs, config: Arc<ServerConfig>) -> Result<Self> {
+        let listener = TcpListener::bind(addrs).await?;
+        Ok(Tls {
+            listener,
+            acceptor: TlsAcceptor::from(config),
+            accepting: Default::default(),
+        })
+    }
+
+    /// get the next channel, once its tls handshake completes.
+    /// Connections run their handshakes concurrently, so peers that are
+    /// slow or fail them don't hold up the others.
+    ///
+    /// CANCEL SAFETY: this method is cancel-safe, feel free to use it in select statements.
+    /// ```no_run
+    /// # use canary::providers::Tls;
+    /// # async fn run(tls: Tls) -> canary::Result<()> {
+    /// while let Ok(chan) = tls.next().await {
+    ///     let mut chan = chan.raw();
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn next(&self) -> Result<Handshake> {
+        let accept = || self.listener.accept();
+        let handshake = |(stream, addr): (TcpStream, SocketAddr)| {
+            let acceptor = self.acceptor.clone();
+            async move {
+                let stream = accept_stream(&acceptor, stream).await?;
+                let certs = peer_certificates(&stream);
+                let hs = Handshake::acceptor(Channel::from_raw(
+                    Box::new(stream),
+                    Default::default(),
+                    Default::default(),
+                ));
+                let hs = hs.with_peer_certificates(certs);
+                Ok(hs.with_peer_addr(Addr::Tls(Arc::new(addr.to_string().into()), None)))
+            }
+        };
+        self.accepting.next(accept, handshake).await
+    }
+
+    #[inline]
+    /// Get the address the provider is bound to,
+    /// such as the port picked when binding to port 0
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// connect to `host:port` without any backoff strategy.
+    /// The server certificate is checked against the roots of `config`,
+    /// or the webpki roots if there is none.
+    pub async fn connect_no_backoff(
+        addr: &str,
+        config: Option<Arc<ClientConfig>>,
+    ) -> Result<Handshake> {
+        let name = server_name(addr)?;
+        let stream = TcpStream::connect(addr).await?;
+        handshake(stream, name, config).await
+    }
+
+    /// Connect to `host:port` and retry in case of failure.
+    /// The server certificate is checked against the roots of `config`,
+    /// or the webpki roots if there is none.
+    /// Only connecting is retried, tls errors are returned right away.
+    /// ```no_run
+    /// # use canary::providers::Tls;
+    /// # async fn run() -> canary::Result<()> {
+    /// let mut chan = Tls::connect("example.com:443", None).await?.raw();
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect(addr: &str, config: Option<Arc<ClientConfig>>) -> Result<Handshake> {
+        Self::connect_options(addr, config, ConnectOptions::default()).await
+    }
+
+    /// Connect to `host:port` like `connect`, retrying within the time limits of the options
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::providers::{ConnectOptions, Tls};
+    /// # async fn run() -> canary::Result<()> {
+    /// let options = ConnectOptions::default().timeout(Duration::from_secs(1));
+    /// let mut chan = Tls::connect_options("example.com:443", None, options).await?.raw();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_options(
+        addr: &str,
+        config: Option<Arc<ClientConfig>>,
+        options: ConnectOptions,
+    ) -> Result<Handshake> {
+        let name = server_name(addr)?;
+        let stream = options
+            .retry(|| async { Ok(TcpStream::connect(addr).await?) })
+            .await?;
+        handshake(stream, name, config).await
+    }
+}
+
+/// run the client side of the tls handshake and get a channel
+async fn handshake(
+    stream: TcpStream,
+    name: ServerName,
+    config: Option<Arc<ClientConfig>>,
+) -> Result<Handshake> {
+    let stream = connect_stream(stream, name, config).await?;
+    let certs = peer_certificates(&stream);
+    let hs = Handshake::connector(Channel::from_raw(
+        Box::new(stream),
+        Default::default(),
+        Default::default(),
+    ));
+    Ok(hs.with_peer_certificates(certs))
+}
+
+/// run the client side of the tls handshake, checking the server certificate
+/// against the roots of `config` or the webpki roots if there is none
+pub(super) async fn connect_stream(
+    stream: TcpStream,
+    name: ServerName,
+    config: Option<Arc<ClientConfig>>,
+) -> Result<TlsStream<TcpStream>> {
+    let config = config.unwrap_or_else(webpki_config);
+    let stream = TlsConnector::from(config)
+        .connect(name, stream)
+        .await
+        .map_err(tls_err)?;
+    Ok(stream.into())
+}
+
+/// certificate chain the peer presented in the tls handshake, in der
+pub(super) fn peer_certificates(stream: &TlsStream<TcpStream>) -> Option<Arc<[Vec<u8>]>> {
+    let certs = match stream {
+        TlsStream::Client(stream) => stream.get_ref().1.peer_certificates(),
+        TlsStream::Server(stream) => stream.get_ref().1.peer_certificates(),
+    }?;
+    Some(certs.iter().map(|cert| cert.0.clone()).collect())
+}
+
+/// run the server side of the tls handshake
+pub(super) async fn accept_stream(
+    acceptor: &TlsAcceptor,
+    stream: TcpStream,
+) -> Result<TlsStream<TcpStream>> {
+    let stream = acceptor.accept(stream).await.map_err(tls_err)?;
+    Ok(stream.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+    use crate::providers::certs::tests::{Ca, SniRecorder};
+
+    /// serve echoes with the configuration, returning the port
+    async fn echo(config: Arc<ServerConfig>) -> u16 {
+        let tls = Tls::bind("127.0.0.1:0", config).await.unwrap();
+        let port = tls.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok(hs) = tls.next().await {
+                tokio::spawn(async move {
+                    let mut chan = hs.raw();
+                    let msg: String = chan.receive().await?;
+                    chan.send(msg).await
+                });
+            }
+        });
+        port
+    }
+
+    fn client(ca: &Ca) -> Arc<ClientConfig> {
+        TlsClientConfig::default()
+            .roots(ca.roots())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn channels_run_over_tls() {
+        let ca = Ca::new();
+        let (chain, key) = ca.issue("localhost");
+        let port = echo(TlsServerConfig::new(chain.clone(), key).build().unwrap()).await;
+        let hs = Tls::connect(&format!("localhost:{}", port), Some(client(&ca)))
+            .await
+            .unwrap();
+        assert_eq!(hs.peer_certificates(), Some(chain));
+        let mut chan = hs.raw();
+        chan.send("hello").await.unwrap();
+        assert_eq!(chan.receive::<String>().await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn unknown_issuers_are_denied() {
+        let (chain, key) = Ca::new().issue("localhost");
+        let port = echo(TlsServerConfig::new(chain, key).build().unwrap()).await;
+        let addr = format!("localhost:{}", port);
+        let e = Tls::connect(&addr, Some(client(&Ca::new())))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        // the webpki roots don't trust it either
+        let e = Tls::connect(&addr, None).await.err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn server_names_come_from_the_host() {
+        let ca = Ca::new();
+        let recorder = Arc::new(SniRecorder::new(ca.issue("localhost")));
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(recorder.clone());
+        let port = echo(Arc::new(config)).await;
+        let mut chan = Tls::connect(&format!("localhost:{}", port), Some(client(&ca)))
+            .await
+            .unwrap()
+            .raw();
+        chan.send("hello").await.unwrap();
+        chan.receive::<String>().await.unwrap();
+        assert_eq!(*recorder.names.lock().unwrap(), [Some("localhost".into())]);
+
+        // certificates for other names are denied
+        let (chain, key) = ca.issue("example.com");
+        let port = echo(TlsServerConfig::new(chain, key).build().unwrap()).await;
+        let e = Tls::connect(&format!("localhost:{}", port), Some(client(&ca)))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    }
+
+    /// kind of the error of a connection, tls 1.3 servers reject
+    /// client certificates after the client finished its handshake
+    async fn rejection(hs: Result<Handshake>) -> ErrorKind {
+        let mut chan = match hs {
+            Ok(hs) => hs.raw(),
+            Err(e) => return e.kind(),
+        };
+        chan.send("hello").await.unwrap();
+        chan.receive::<String>().await.err().unwrap().kind()
+    }
+
+    #[tokio::test]
+    async fn client_certificates_are_verified() {
+        let ca = Ca::new();
+        let (chain, key) = ca.issue("localhost");
+        let config = TlsServerConfig::new(chain, key)
+            .require_client_auth(ca.roots())
+            .build()
+            .unwrap();
+        let tls = Tls::bind("127.0.0.1:0", config).await.unwrap();
+        let addr = format!("localhost:{}", tls.local_addr().unwrap().port());
+        let (client_chain, client_key) = ca.issue("client");
+        let config = TlsClientConfig::default()
+            .roots(ca.roots())
+            .with_client_cert(client_chain.clone(), client_key)
+            .build()
+            .unwrap();
+        let (hs, connected) = tokio::join!(tls.next(), Tls::connect(&addr, Some(config)));
+        let hs = hs.unwrap();
+        assert_eq!(hs.peer_certificates(), Some(client_chain));
+        let (mut server, mut connected) = (hs.raw(), connected.unwrap().raw());
+        connected.send("hello").await.unwrap();
+        assert_eq!(server.receive::<String>().await.unwrap(), "hello");
+
+        tokio::spawn(async move { while tls.next().await.is_ok() {} });
+        // issued by another authority
+        let (chain, key) = Ca::new().issue("client");
+        let config = TlsClientConfig::default()
+            .roots(ca.roots())
+            .with_client_cert(chain, key)
+            .build()
+            .unwrap();
+        let hs = Tls::connect(&addr, Some(config)).await;
+        assert_eq!(rejection(hs).await, ErrorKind::PermissionDenied);
+        // without any certificate
+        let hs = Tls::connect(&addr, Some(client(&ca))).await;
+        assert_eq!(rejection(hs).await, ErrorKind::PermissionDenied);
+    }
+}
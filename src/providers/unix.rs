@@ -1,8 +1,15 @@
 #![cfg(unix)]
 #![cfg(not(target_arch = "wasm32"))]
 
-use std::path::Path;
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use super::accept::retry;
+use super::limits::Limiter;
+use super::{Addr, ConnectOptions, ConnectionCounts, Limits, RetryPolicy};
 use crate::channel::handshake::Handshake;
 use crate::err;
 use crate::io::UnixListener;
@@ -10,11 +17,56 @@ use crate::io::UnixStream;
 use crate::Channel;
 use crate::Result;
 
-use derive_more::{From, Into};
-#[derive(From, Into)]
-#[into(owned, ref, ref_mut)]
-/// Exposes routes over TCP
-pub struct Unix(UnixListener);
+/// Exposes routes over unix sockets
+pub struct Unix {
+    listener: UnixListener,
+    path: PathBuf,
+    unlink: Option<Unlink>,
+    limiter: Limiter,
+}
+
+impl From<UnixListener> for Unix {
+    #[inline]
+    fn from(listener: UnixListener) -> Self {
+        // unnamed sockets have no path
+        let path = listener
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(Path::to_path_buf))
+            .unwrap_or_default();
+        Unix {
+            listener,
+            path,
+            unlink: None,
+            limiter: Limiter::default(),
+        }
+    }
+}
+
+impl From<Unix> for UnixListener {
+    #[inline]
+    fn from(unix: Unix) -> Self {
+        // the socket file stays while the listener is in use
+        if let Some(unlink) = unix.unlink {
+            unlink.disarm();
+        }
+        unix.listener
+    }
+}
+
+impl<'a> From<&'a Unix> for &'a UnixListener {
+    #[inline]
+    fn from(unix: &'a Unix) -> Self {
+        &unix.listener
+    }
+}
+
+impl<'a> From<&'a mut Unix> for &'a mut UnixListener {
+    #[inline]
+    fn from(unix: &'a mut Unix) -> Self {
+        &mut unix.listener
+    }
+}
 
 impl Unix {
     #[inline]
@@ -27,8 +79,47 @@ impl Unix {
     /// }
     /// ```
     pub async fn bind(addrs: impl AsRef<Path>) -> Result<Self> {
-        let listener = UnixListener::bind(addrs)?;
-        Ok(Unix(listener))
+        let listener = UnixListener::bind(&addrs)?;
+        Ok(Unix {
+            listener,
+            path: addrs.as_ref().to_path_buf(),
+            unlink: None,
+            limiter: Limiter::default(),
+        })
+    }
+    #[inline]
+    /// Bind to this path with the options
+    /// ```no_run
+    /// # use canary::providers::{Unix, UnixOptions};
+    /// # async fn run() -> canary::Result<()> {
+    /// let options = UnixOptions::default().remove_stale().unlink_on_drop().mode(0o660);
+    /// let unix = Unix::bind_with("/run/service.sock", options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_with(addrs: impl AsRef<Path>, options: UnixOptions) -> Result<Self> {
+        let path = addrs.as_ref();
+        let bind = || match options.mode {
+            Some(mode) => bind_private(path, mode),
+            None => UnixListener::bind(path),
+        };
+        let listener = match bind() {
+            Err(e) if e.kind() == ErrorKind::AddrInUse && options.remove_stale => {
+                remove_stale(path).await?;
+                bind()?
+            }
+            listener => listener?,
+        };
+        let unlink = match options.unlink_on_drop {
+            true => Some(Unlink::new(path)?),
+            false => None,
+        };
+        Ok(Unix {
+            listener,
+            path: path.to_path_buf(),
+            unlink,
+            limiter: Limiter::default(),
+        })
     }
     #[inline]
     /// get the next channel
@@ -39,12 +130,44 @@ impl Unix {
     /// }
     /// ```
     pub async fn next(&self) -> Result<Handshake> {
-        let (raw, _) = self.0.accept().await?;
-        Ok(Handshake::from(Channel::from_raw(
+        let (connection, pending) = self.limiter.acquire().await;
+        let (raw, addr) = retry(|| self.listener.accept()).await?;
+        let hs = Handshake::acceptor(Channel::from_raw(
             raw,
             Default::default(),
             Default::default(),
-        )))
+        ));
+        let hs = hs.with_permits(connection, pending);
+        // clients rarely bind their socket to a path
+        Ok(match addr.as_pathname() {
+            Some(path) => hs.with_peer_addr(Addr::Unix(Arc::new(path.to_path_buf()), None)),
+            None => hs,
+        })
+    }
+    #[must_use]
+    /// Limit the connections the provider holds, `next` waits for
+    /// connections to be dropped once a limit is reached
+    /// ```no_run
+    /// # use canary::providers::{Limits, Unix};
+    /// # async fn run() -> canary::Result<()> {
+    /// let limits = Limits::default().max_connections(1024);
+    /// let unix = Unix::bind("service.sock").await?.limits(limits);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limiter = Limiter::new(limits);
+        self
+    }
+    #[inline]
+    /// Get the number of open channels and pending handshakes the provider accepted
+    pub fn counts(&self) -> ConnectionCounts {
+        self.limiter.counts()
+    }
+    #[inline]
+    /// Get the path the provider is bound to
+    pub fn local_path(&self) -> &Path {
+        &self.path
     }
     #[inline]
     /// connect to the following address with the following id. Defaults to 3 retries.
@@ -52,36 +175,301 @@ impl Unix {
         Self::connect_retry(addrs, 3, 10).await
     }
     #[inline]
+    /// connect to the following address and retry in case of failure,
+    /// failing with `TimedOut` if the deadline passes while connecting
+    pub async fn connect_deadline(
+        addrs: impl AsRef<Path> + std::fmt::Debug,
+        deadline: Instant,
+    ) -> Result<Handshake> {
+        Self::connect_options(addrs, ConnectOptions::default().deadline(deadline)).await
+    }
+    /// connect to the following address and retry in case of failure
+    /// with the delays of the policy
+    pub async fn connect_with_policy(
+        addrs: impl AsRef<Path> + std::fmt::Debug,
+        policy: RetryPolicy,
+    ) -> Result<Handshake> {
+        Self::connect_options(addrs, ConnectOptions::default().policy(policy)).await
+    }
+    /// connect to the following address and retry in case of failure,
+    /// within the time limits of the options
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::providers::{ConnectOptions, Unix};
+    /// # async fn run() -> canary::Result<()> {
+    /// let options = ConnectOptions::default().timeout(Duration::from_millis(500));
+    /// let mut chan = Unix::connect_options("service.sock", options).await?.encrypted().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_options(
+        addrs: impl AsRef<Path> + std::fmt::Debug,
+        mut options: ConnectOptions,
+    ) -> Result<Handshake> {
+        // sockets that are missing rarely show up later
+        let policy = RetryPolicy::fixed(Duration::from_millis(10), 3);
+        options.policy.get_or_insert(policy);
+        let raw = options
+            .retry(|| async { Ok(UnixStream::connect(&addrs).await?) })
+            .await?;
+        Ok(Handshake::connector(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    #[inline]
     /// connect to the following address with the given id and retry in case of failure
     pub async fn connect_retry(
         addrs: impl AsRef<Path> + std::fmt::Debug,
         retries: u32,
         time_to_retry: u64,
     ) -> Result<Handshake> {
-        let addrs = &addrs;
-        let mut attempt = 0;
-        let raw = loop {
-            match UnixStream::connect(&addrs).await {
-                Ok(s) => break s,
-                Err(e) => {
-                    tracing::error!(
-                        "connecting to address `{:?}` failed, attempt {} starting",
-                        addrs,
-                        attempt
-                    );
-                    crate::io::sleep(std::time::Duration::from_millis(time_to_retry)).await;
-                    attempt += 1;
-                    if attempt == retries {
-                        err!((e))?
-                    }
-                    continue;
-                }
+        let policy = RetryPolicy::fixed(Duration::from_millis(time_to_retry), retries);
+        Self::connect_with_policy(addrs, policy).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Options of unix providers
+/// ```no_run
+/// # use canary::providers::UnixOptions;
+/// let options = UnixOptions::default().remove_stale().unlink_on_drop().mode(0o600);
+/// ```
+pub struct UnixOptions {
+    /// Remove the socket file left behind by a process that is gone
+    /// instead of failing with `AddrInUse`. Sockets are considered stale if
+    /// connecting to them is refused.
+    ///
+    /// Another process could bind the path between the check and the removal,
+    /// so only one process should be started at a path at a time.
+    pub remove_stale: bool,
+    /// Remove the socket file when the provider is dropped,
+    /// unless another socket was bound at the path since
+    pub unlink_on_drop: bool,
+    /// Permissions of the socket file, such as `0o660` to let only the owner
+    /// and its group connect. They apply before the socket appears at the path,
+    /// since it's bound in a private directory next to it first
+    pub mode: Option<u32>,
+}
+
+impl UnixOptions {
+    #[must_use]
+    /// Remove stale socket files before binding
+    pub fn remove_stale(mut self) -> Self {
+        self.remove_stale = true;
+        self
+    }
+
+    #[must_use]
+    /// Remove the socket file when the provider is dropped
+    pub fn unlink_on_drop(mut self) -> Self {
+        self.unlink_on_drop = true;
+        self
+    }
+
+    #[must_use]
+    /// Set the permissions of the socket file
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+}
+
+/// bind in a private directory next to `path` and set the permissions of the
+/// socket there, so no one can connect before they apply, then link it at `path`.
+/// Linking fails if `path` exists, like binding does
+fn bind_private(path: &Path, mode: u32) -> std::io::Result<UnixListener> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = parent.join(format!(".canary-{:08x}", rand::random::<u32>()));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let private = dir.join("s");
+    let bind = || {
+        let listener = UnixListener::bind(&private)?;
+        std::fs::set_permissions(&private, std::fs::Permissions::from_mode(mode))?;
+        match std::fs::hard_link(&private, path) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(std::io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("{:?} already exists", path),
+            )),
+            linked => linked.map(|_| listener),
+        }
+    };
+    let listener = bind();
+    // the listener keeps the socket, reachable through `path` only
+    let _ = std::fs::remove_file(&private);
+    let _ = std::fs::remove_dir(&dir);
+    listener
+}
+
+/// remove the socket file at `path` if nothing is listening on it
+async fn remove_stale(path: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.file_type().is_socket() {
+        err!((in_use, format!("{:?} exists and is not a socket", path)))?
+    }
+    match UnixStream::connect(path).await {
+        Ok(_) => err!((in_use, format!("a provider is listening at {:?}", path))),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            tracing::warn!("removing stale socket {:?}", path);
+            Ok(std::fs::remove_file(path)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// removes the socket file of a provider when dropped, if it's still the one
+/// the provider bound
+struct Unlink {
+    path: PathBuf,
+    /// device and inode of the socket file
+    id: Option<(u64, u64)>,
+}
+
+impl Unlink {
+    fn new(path: &Path) -> Result<Self> {
+        let metadata = std::fs::symlink_metadata(path)?;
+        Ok(Unlink {
+            path: path.to_path_buf(),
+            id: Some((metadata.dev(), metadata.ino())),
+        })
+    }
+
+    /// keep the socket file
+    fn disarm(mut self) {
+        self.id = None;
+    }
+}
+
+impl Drop for Unlink {
+    fn drop(&mut self) {
+        let id = std::fs::symlink_metadata(&self.path)
+            .ok()
+            .map(|metadata| (metadata.dev(), metadata.ino()));
+        if self.id.is_some() && id == self.id {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                tracing::warn!("removing socket {:?} failed: {}", self.path, e);
             }
-        };
-        Ok(Handshake::from(Channel::from_raw(
-            raw,
-            Default::default(),
-            Default::default(),
-        )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // empty directory of a test, removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("canary-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir(&dir).unwrap();
+            TestDir(dir)
+        }
+
+        fn entries(&self) -> Vec<PathBuf> {
+            let entries = std::fs::read_dir(&self.0).unwrap();
+            entries.map(|entry| entry.unwrap().path()).collect()
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn sockets_are_created_with_the_mode() {
+        let dir = TestDir::new("mode");
+        let path = dir.0.join("service.sock");
+        let unix = Unix::bind_with(&path, UnixOptions::default().mode(0o600))
+            .await
+            .unwrap();
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+        // the private directory is gone
+        assert_eq!(dir.entries(), std::slice::from_ref(&path));
+
+        let (client, server) = tokio::join!(Unix::connect(&path), unix.next());
+        let (mut client, mut server) = (client.unwrap().raw(), server.unwrap().raw());
+        client.send("hello").await.unwrap();
+        assert_eq!(server.receive::<String>().await.unwrap(), "hello");
+
+        // taken paths fail like binding does
+        let e = Unix::bind_with(&path, UnixOptions::default().mode(0o600))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::AddrInUse);
+        assert_eq!(dir.entries(), [path]);
+    }
+
+    #[tokio::test]
+    async fn sockets_are_bound_again_once_dropped() {
+        let dir = TestDir::new("rebind");
+        let path = dir.0.join("service.sock");
+        let options = UnixOptions::default().unlink_on_drop().mode(0o660);
+        for _ in 0..2 {
+            let unix = Unix::bind_with(&path, options).await.unwrap();
+            assert!(path.exists());
+            drop(unix);
+            assert!(!path.exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn orphaned_sockets_are_removed() {
+        let dir = TestDir::new("orphan");
+        let path = dir.0.join("service.sock");
+        // the socket file of a provider that didn't unlink it
+        drop(Unix::bind(&path).await.unwrap());
+        assert!(path.exists());
+        for options in [UnixOptions::default(), UnixOptions::default().mode(0o600)] {
+            let e = Unix::bind_with(&path, options).await.err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::AddrInUse);
+        }
+
+        let options = UnixOptions::default().remove_stale().mode(0o600);
+        let unix = Unix::bind_with(&path, options).await.unwrap();
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o600);
+        // live providers aren't removed
+        let e = Unix::bind_with(&path, options).await.err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::AddrInUse);
+        drop(unix);
+    }
+
+    #[tokio::test]
+    async fn peers_have_the_credentials_of_the_process() {
+        let dir = TestDir::new("cred");
+        let path = dir.0.join("service.sock");
+        let unix = Unix::bind(&path).await.unwrap();
+        let (client, server) = tokio::join!(Unix::connect(&path), unix.next());
+        let (client, server) = (client.unwrap(), server.unwrap());
+        // the crate forbids unsafe code, so getuid is read off the socket the process created
+        let uid = std::fs::symlink_metadata(&path).unwrap().uid();
+        for cred in [client.peer_cred().unwrap(), server.peer_cred().unwrap()] {
+            assert_eq!(cred.uid, uid);
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            assert_eq!(cred.pid, Some(std::process::id() as i32));
+        }
+        // encryption keeps them, and so do the halves of split channels
+        let (client, server) = tokio::join!(client.encrypted(), server.encrypted());
+        let (client, server) = (client.unwrap(), server.unwrap());
+        assert_eq!(client.peer_cred().unwrap().uid, uid);
+        let (send, receive) = server.split();
+        assert_eq!(send.peer_cred().unwrap().uid, uid);
+        assert_eq!(receive.peer_cred().unwrap().uid, uid);
+
+        let (mem, _) = crate::Channel::pair();
+        let e = mem.peer_cred().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
     }
 }
@@ -8,27 +8,106 @@ use cfg_if::cfg_if;
 
 cfg_if! {
     if #[cfg(not(target_arch = "wasm32"))] {
-        use crate::io::{TcpListener, ToSocketAddrs};
+        use std::net::SocketAddr;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+
+        use crate::io::{Read, TcpListener, TcpStream, ToSocketAddrs, Write};
         use crate::io::wss;
-        use backoff::ExponentialBackoff;
+        use crate::io::wss::tungstenite::client::IntoClientRequest;
+        use crate::io::wss::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+        use crate::io::wss::tungstenite::http::header::{HeaderName, HeaderValue};
+        use crate::io::wss::tungstenite::http::StatusCode;
+        use compact_str::CompactString;
+        use super::{Addr, ConnectOptions, ConnectionCounts, Limits, RetryPolicy, TcpOptions};
+        use super::limits::Limiter;
+        use crate::channel::channels::Permit;
+        use std::time::Instant;
+        use super::accept::Accepting;
+        use futures::Future;
+        use std::sync::Arc;
+        use tokio::io::ReadBuf;
+
+        #[cfg(feature = "tls")]
+        use super::rustls::{ClientConfig, ServerConfig};
+        #[cfg(feature = "tls")]
+        use super::tls::{accept_stream, connect_stream, peer_certificates, server_name};
+        #[cfg(feature = "tls")]
+        use tokio_rustls::{TlsAcceptor, TlsStream};
     } else {
         use crate::io::Wss;
     }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-#[derive(derive_more::From, derive_more::Into)]
-#[into(owned, ref, ref_mut)]
-/// Websocket Provider
-pub struct WebSocket(TcpListener);
+/// http types of the upgrade requests of websockets, such as `StatusCode`
+pub use crate::io::wss::tungstenite::http;
+
+#[cfg(not(target_arch = "wasm32"))]
+/// check of the upgrade requests of a websocket provider
+type RequestFilter = Arc<dyn Fn(&Request) -> std::result::Result<(), StatusCode> + Send + Sync>;
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Websocket Provider, which serves websockets over tls if it was bound with a server config
+pub struct WebSocket {
+    listener: TcpListener,
+    options: TcpOptions,
+    accepting: Accepting<Handshake>,
+    path: Option<CompactString>,
+    filter: Option<RequestFilter>,
+    #[cfg(feature = "tls")]
+    acceptor: Option<TlsAcceptor>,
+    limiter: Limiter,
+}
 
 #[cfg(target_arch = "wasm32")]
 pub struct WebSocket;
 
+#[cfg(not(target_arch = "wasm32"))]
+impl From<TcpListener> for WebSocket {
+    #[inline]
+    fn from(listener: TcpListener) -> Self {
+        WebSocket {
+            listener,
+            options: TcpOptions::default(),
+            accepting: Default::default(),
+            path: None,
+            filter: None,
+            #[cfg(feature = "tls")]
+            acceptor: None,
+            limiter: Limiter::default(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<WebSocket> for TcpListener {
+    #[inline]
+    fn from(wss: WebSocket) -> Self {
+        wss.listener
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> From<&'a WebSocket> for &'a TcpListener {
+    #[inline]
+    fn from(wss: &'a WebSocket) -> Self {
+        &wss.listener
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> From<&'a mut WebSocket> for &'a mut TcpListener {
+    #[inline]
+    fn from(wss: &'a mut WebSocket) -> Self {
+        &mut wss.listener
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 impl WebSocket {
     #[inline]
-    /// Bind to this address
+    /// Bind to this address, serving websockets without tls (`ws://`)
     /// ```no_run
     /// let wss = WebSocket::bind("127.0.0.1:8080").await?;
     /// while let Ok(chan) = wss.next().await {
@@ -38,10 +117,155 @@ impl WebSocket {
     /// ```
     pub async fn bind(addrs: impl ToSocketAddrs) -> Result<Self> {
         let listener = TcpListener::bind(addrs).await?;
-        Ok(WebSocket(listener))
+        Ok(WebSocket::from(listener))
+    }
+    #[inline]
+    /// Bind to this address with the socket options, serving websockets without tls (`ws://`).
+    /// The options also apply to the connections the provider accepts.
+    /// ```no_run
+    /// # use canary::providers::{TcpOptions, WebSocket};
+    /// # async fn run() -> canary::Result<()> {
+    /// let wss = WebSocket::bind_with("127.0.0.1:8080", TcpOptions::default().nodelay()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_with(addrs: impl ToSocketAddrs, options: TcpOptions) -> Result<Self> {
+        let listener = options.bind(addrs).await?;
+        Ok(WebSocket {
+            listener,
+            options,
+            accepting: Default::default(),
+            path: None,
+            filter: None,
+            #[cfg(feature = "tls")]
+            acceptor: None,
+            limiter: Limiter::default(),
+        })
+    }
+    #[inline]
+    #[cfg(feature = "tls")]
+    /// Bind to this address, serving websockets over tls (`wss://`)
+    /// with the certificate of the server configuration
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use canary::providers::{rustls::ServerConfig, WebSocket};
+    /// # async fn run(config: ServerConfig) -> canary::Result<()> {
+    /// let wss = WebSocket::bind_tls("127.0.0.1:443", Arc::new(config)).await?;
+    /// while let Ok(chan) = wss.next().await {
+    ///     let mut chan = chan.encrypted().await?;
+    ///     chan.send("hello!").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn bind_tls(addrs: impl ToSocketAddrs, config: Arc<ServerConfig>) -> Result<Self> {
+        let listener = TcpListener::bind(addrs).await?;
+        Ok(WebSocket {
+            listener,
+            options: TcpOptions::default(),
+            accepting: Default::default(),
+            path: None,
+            filter: None,
+            acceptor: Some(TlsAcceptor::from(config)),
+            limiter: Limiter::default(),
+        })
+    }
+    #[inline]
+    #[cfg(feature = "tls")]
+    /// Bind to this address with the socket options, serving websockets over tls (`wss://`)
+    /// with the certificate of the server configuration.
+    /// The options also apply to the connections the provider accepts.
+    pub async fn bind_tls_with(
+        addrs: impl ToSocketAddrs,
+        config: Arc<ServerConfig>,
+        options: TcpOptions,
+    ) -> Result<Self> {
+        let listener = options.bind(addrs).await?;
+        Ok(WebSocket {
+            listener,
+            options,
+            accepting: Default::default(),
+            path: None,
+            filter: None,
+            acceptor: Some(TlsAcceptor::from(config)),
+            limiter: Limiter::default(),
+        })
+    }
+    #[must_use]
+    /// Only accept websockets opened at this path, such as `/canary`,
+    /// rejecting the others with `404 Not Found`.
+    /// Useful behind a reverse proxy that routes on the path.
+    /// ```no_run
+    /// # use canary::providers::WebSocket;
+    /// # async fn run() -> canary::Result<()> {
+    /// let wss = WebSocket::bind("127.0.0.1:8080").await?.path("/canary");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn path(mut self, path: &str) -> Self {
+        let path = match path.starts_with('/') {
+            true => CompactString::from(path),
+            false => CompactString::from(format!("/{}", path)),
+        };
+        self.path = Some(path);
+        self
+    }
+    #[must_use]
+    /// Inspect the http request of every websocket before it's established,
+    /// such as its path or headers, rejecting it with the returned status.
+    /// Runs after the check of `path`.
+    /// ```no_run
+    /// # use canary::providers::{http, WebSocket};
+    /// # async fn run() -> canary::Result<()> {
+    /// let wss = WebSocket::bind("127.0.0.1:8080").await?.filter(|req| {
+    ///     match req.headers().get("authorization") {
+    ///         Some(token) if token == "Bearer secret" => Ok(()),
+    ///         _ => Err(http::StatusCode::UNAUTHORIZED),
+    ///     }
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn filter(
+        mut self,
+        filter: impl Fn(&Request) -> std::result::Result<(), StatusCode> + Send + Sync + 'static,
+    ) -> Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+    #[must_use]
+    /// Limit the connections the provider holds, `next` waits for
+    /// connections to be dropped once a limit is reached.
+    /// Connections count as pending handshakes during the tls handshake
+    /// and the websocket upgrade too.
+    /// ```no_run
+    /// # use canary::providers::{Limits, WebSocket};
+    /// # async fn run() -> canary::Result<()> {
+    /// let limits = Limits::default().max_connections(1024).max_pending_handshakes(64);
+    /// let wss = WebSocket::bind("127.0.0.1:8080").await?.limits(limits);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limiter = Limiter::new(limits);
+        self
     }
     #[inline]
-    /// get the next channel
+    /// Get the number of open channels and pending handshakes the provider accepted
+    pub fn counts(&self) -> ConnectionCounts {
+        self.limiter.counts()
+    }
+    #[inline]
+    /// Get the path websockets must be opened at, if any
+    pub fn url_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+    #[inline]
+    /// get the next channel.
+    /// Connections run their tls handshake and websocket upgrade concurrently,
+    /// so peers that are slow or fail them don't hold up the others.
+    ///
+    /// CANCEL SAFETY: this method is cancel-safe, feel free to use it in select statements.
     /// ```no_run
     /// while let Ok(chan) = wss.next().await {
     ///     let mut chan = chan.encrypted().await?;
@@ -49,19 +273,84 @@ impl WebSocket {
     /// }
     /// ```
     pub async fn next(&self) -> Result<Handshake> {
-        let (chan, _) = self.0.accept().await?;
-        let raw = wss::tokio::accept_async(chan)
-            .await // this future doesn't suspend, hence why this await point is not delegated upwards.
-            .map_err(|e| err!(e))?;
-        let raw = Box::new(raw);
-        Ok(Handshake::from(Channel::from_raw(
-            raw,
-            Default::default(),
-            Default::default(),
-        )))
+        let accept = || async {
+            let permits = self.limiter.acquire().await;
+            let (chan, addr) = self.listener.accept().await?;
+            Ok((chan, addr, permits))
+        };
+        self.accepting
+            .next(accept, |(chan, addr, permits)| {
+                self.upgrade(chan, addr, permits)
+            })
+            .await
+    }
+    /// run the tls handshake if the provider has a server config, then the websocket upgrade
+    fn upgrade(
+        &self,
+        chan: TcpStream,
+        addr: SocketAddr,
+        (connection, pending): (Permit, Permit),
+    ) -> impl Future<Output = Result<Handshake>> + Send + 'static {
+        let options = self.options;
+        let path = self.path.clone();
+        let filter = self.filter.clone();
+        #[cfg(feature = "tls")]
+        let acceptor = self.acceptor.clone();
+        let peer = Arc::new(addr.to_string().into());
+        #[cfg(feature = "tls")]
+        let peer = match acceptor {
+            Some(_) => Addr::Wss(peer, None),
+            None => Addr::InsecureWss(peer),
+        };
+        #[cfg(not(feature = "tls"))]
+        let peer = Addr::InsecureWss(peer);
+        async move {
+            options.apply(&chan)?;
+            #[cfg(feature = "tls")]
+            let chan = match &acceptor {
+                Some(acceptor) => WssStream::Tls(Box::new(accept_stream(acceptor, chan).await?)),
+                None => WssStream::Plain(chan),
+            };
+            #[cfg(not(feature = "tls"))]
+            let chan = WssStream::Plain(chan);
+            // the error response is the type tungstenite expects
+            #[allow(clippy::result_large_err)]
+            let check = move |req: &Request, res: Response| {
+                let status = match (&path, &filter) {
+                    (Some(path), _) if req.uri().path() != path.as_str() => StatusCode::NOT_FOUND,
+                    (_, Some(filter)) => match filter(req) {
+                        Ok(()) => return Ok(res),
+                        Err(status) => status,
+                    },
+                    _ => return Ok(res),
+                };
+                let mut res = ErrorResponse::new(None);
+                *res.status_mut() = status;
+                Err(res)
+            };
+            let certs = chan.peer_certificates();
+            let raw = wss::tokio::accept_hdr_async(chan, check)
+                .await
+                .map_err(|e| err!(e))?;
+            let raw = Box::new(raw);
+            let hs = Handshake::acceptor(Channel::from_raw(
+                raw,
+                Default::default(),
+                Default::default(),
+            ));
+            let hs = hs.with_permits(connection, pending);
+            let hs = hs.with_peer_certificates(certs);
+            Ok(hs.with_peer_addr(peer))
+        }
+    }
+    #[inline]
+    /// Get the address the provider is bound to,
+    /// such as the port picked when binding to port 0
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
     }
 
-    /// connect to address without any backoff strategy
+    /// connect to address without tls and without any backoff strategy
     pub async fn connect_no_backoff(
         addrs: impl ToSocketAddrs + std::fmt::Debug,
     ) -> Result<Handshake> {
@@ -70,52 +359,311 @@ impl WebSocket {
             .map_err(|e| err!(e))?
             .next()
             .ok_or(err!("no endpoint found"))?;
-        let (raw, _) = wss::tokio::connect_async(&format!("ws://{}", &addrs))
-            .await
-            .map_err(err!(@other))?;
+        let stream = TcpStream::connect(addrs).await?;
+        let (raw, _) =
+            wss::tokio::client_async(format!("ws://{}", &addrs), WssStream::Plain(stream))
+                .await
+                .map_err(err!(@other))?;
         let raw = Box::new(raw);
-        Ok(Handshake::from(Channel::from_raw(
+        Ok(Handshake::connector(Channel::from_raw(
             raw,
             Default::default(),
             Default::default(),
         )))
     }
     #[inline]
-    /// Connect to the following address with the given id and retry in case of failure
+    /// Connect to the following address without tls and retry in case of failure
     pub async fn connect(addrs: impl ToSocketAddrs + std::fmt::Debug) -> Result<Handshake> {
         let addrs = tokio::net::lookup_host(&addrs)
             .await
             .map_err(|e| err!(e))?
             .next()
             .ok_or(err!("no endpoint found"))?;
-        let hs = backoff::future::retry(ExponentialBackoff::default(), || async {
-            let (raw, _) = wss::tokio::connect_async(&format!("ws://{}", &addrs))
-                .await
-                .map_err(err!(@other))?;
-            let raw = Box::new(raw);
-            Ok(Handshake::from(Channel::from_raw(
-                raw,
-                Default::default(),
-                Default::default(),
-            )))
-        })
-        .await?;
-        Ok(hs)
+        let raw = ConnectOptions::default()
+            .retry(|| async {
+                let stream = TcpStream::connect(addrs).await?;
+                let (raw, _) =
+                    wss::tokio::client_async(format!("ws://{}", &addrs), WssStream::Plain(stream))
+                        .await
+                        .map_err(err!(@other))?;
+                Ok(raw)
+            })
+            .await?;
+        let raw = Box::new(raw);
+        Ok(Handshake::connector(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    #[cfg(feature = "tls")]
+    /// Connect to `host:port` over tls (`wss://`) and retry in case of failure.
+    /// The server certificate is checked against the roots of `config`,
+    /// or the webpki roots if there is none, and the host is sent as the server name.
+    /// Only connecting is retried, tls errors are returned right away.
+    /// ```no_run
+    /// # use canary::providers::WebSocket;
+    /// # async fn run() -> canary::Result<()> {
+    /// let mut chan = WebSocket::connect_tls("example.com:443", None).await?.encrypted().await?;
+    /// chan.send("hello!").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_tls(addr: &str, config: Option<Arc<ClientConfig>>) -> Result<Handshake> {
+        let name = server_name(addr)?;
+        let stream = ConnectOptions::default()
+            .retry(|| async { Ok(TcpStream::connect(addr).await?) })
+            .await?;
+        let stream = WssStream::Tls(Box::new(connect_stream(stream, name, config).await?));
+        let certs = stream.peer_certificates();
+        let (raw, _) = wss::tokio::client_async(format!("wss://{}", addr), stream)
+            .await
+            .map_err(upgrade_err)?;
+        let raw = Box::new(raw);
+        let hs = Handshake::connector(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        ));
+        Ok(hs.with_peer_certificates(certs))
+    }
+    #[inline]
+    /// Connect to the url, `ws://` or `wss://`, sending the headers with the
+    /// upgrade request, and retry in case of failure.
+    /// `wss://` urls check the server certificate against the webpki roots
+    /// and need the `tls` feature.
+    /// Only connecting is retried, a rejected upgrade is returned right away.
+    /// ```no_run
+    /// # use canary::providers::WebSocket;
+    /// # async fn run() -> canary::Result<()> {
+    /// let url = "wss://gateway.example.com/canary";
+    /// let hs = WebSocket::connect_url(url, &[("authorization", "Bearer secret")]).await?;
+    /// let mut chan = hs.encrypted().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_url(url: &str, headers: &[(&str, &str)]) -> Result<Handshake> {
+        Self::connect_request(
+            url,
+            headers,
+            ConnectOptions::default(),
+            #[cfg(feature = "tls")]
+            None,
+        )
+        .await
+    }
+    #[inline]
+    #[cfg(feature = "tls")]
+    /// Connect to the url like `connect_url`, checking the certificate of
+    /// `wss://` servers against the roots of the configuration
+    pub async fn connect_url_with(
+        url: &str,
+        headers: &[(&str, &str)],
+        config: Arc<ClientConfig>,
+    ) -> Result<Handshake> {
+        Self::connect_request(url, headers, ConnectOptions::default(), Some(config)).await
+    }
+    #[inline]
+    /// Connect to `host:port`, with an optional path, without tls and retry
+    /// in case of failure until the deadline, failing with `TimedOut` if it
+    /// passes while connecting
+    pub async fn connect_deadline(addr: &str, deadline: Instant) -> Result<Handshake> {
+        Self::connect_options(addr, ConnectOptions::default().deadline(deadline)).await
+    }
+    #[inline]
+    /// Connect to `host:port`, with an optional path, without tls and retry
+    /// in case of failure with the delays of the policy
+    pub async fn connect_with_policy(addr: &str, policy: RetryPolicy) -> Result<Handshake> {
+        Self::connect_options(addr, ConnectOptions::default().policy(policy)).await
+    }
+    #[inline]
+    /// Connect to `host:port`, with an optional path, without tls and retry
+    /// in case of failure, within the time limits of the options
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use canary::providers::{ConnectOptions, WebSocket};
+    /// # async fn run() -> canary::Result<()> {
+    /// let options = ConnectOptions::default().timeout(Duration::from_millis(500));
+    /// let mut chan = WebSocket::connect_options("127.0.0.1:8080/canary", options).await?.raw();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_options(addr: &str, options: ConnectOptions) -> Result<Handshake> {
+        Self::connect_request(
+            &format!("ws://{}", addr),
+            &[],
+            options,
+            #[cfg(feature = "tls")]
+            None,
+        )
+        .await
+    }
+    /// open a websocket to the url with the headers,
+    /// retrying to connect within the time limits of the options
+    pub(crate) async fn connect_request(
+        url: &str,
+        headers: &[(&str, &str)],
+        options: ConnectOptions,
+        #[cfg(feature = "tls")] config: Option<Arc<ClientConfig>>,
+    ) -> Result<Handshake> {
+        let mut request = url.into_client_request().map_err(err!(@invalid_input))?;
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| err!(invalid_input, format!("invalid header name {:?}", name)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| err!(invalid_input, format!("invalid value of header {:?}", name)))?;
+            request.headers_mut().append(name, value);
+        }
+        let uri = request.uri();
+        let secure = match uri.scheme_str() {
+            Some("ws") => false,
+            Some("wss") => true,
+            _ => err!((
+                invalid_input,
+                format!("expected a ws or wss url, found {:?}", url)
+            ))?,
+        };
+        let host = uri
+            .host()
+            .ok_or_else(|| err!(invalid_input, format!("missing host in {:?}", url)))?;
+        let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+        let addr = format!("{}:{}", host, port);
+        let stream = options
+            .retry(|| async {
+                // ipv6 hosts keep their brackets in urls
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                Ok(TcpStream::connect((host, port)).await?)
+            })
+            .await?;
+        let stream = match secure {
+            #[cfg(feature = "tls")]
+            true => {
+                let stream = connect_stream(stream, server_name(&addr)?, config).await?;
+                WssStream::Tls(Box::new(stream))
+            }
+            #[cfg(not(feature = "tls"))]
+            true => err!((
+                unsupported,
+                format!("connecting to {:?} requires the `tls` feature", addr)
+            ))?,
+            false => WssStream::Plain(stream),
+        };
+        let certs = stream.peer_certificates();
+        let (raw, _) = wss::tokio::client_async(request, stream)
+            .await
+            .map_err(upgrade_err)?;
+        let raw = Box::new(raw);
+        let hs = Handshake::connector(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        ));
+        Ok(hs.with_peer_certificates(certs))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// errors of upgrade requests. Rejected upgrades are `PermissionDenied`, like
+/// tls servers rejecting the client certificate while the request is sent
+fn upgrade_err(e: wss::tungstenite::Error) -> crate::Error {
+    match e {
+        wss::tungstenite::Error::Http(res) => err!(
+            permission_denied,
+            format!("websocket upgrade rejected with status {}", res.status())
+        ),
+        #[cfg(feature = "tls")]
+        wss::tungstenite::Error::Io(e) => crate::providers::certs::tls_read_err(e.into()),
+        e => err!(other, e),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Stream native websockets run over
+pub enum WssStream {
+    /// plain tcp stream, for `ws://`
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    /// tls stream, for `wss://`
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WssStream {
+    /// certificate chain the peer presented over tls, in der
+    fn peer_certificates(&self) -> Option<Arc<[Vec<u8>]>> {
+        match self {
+            WssStream::Plain(_) => None,
+            #[cfg(feature = "tls")]
+            WssStream::Tls(stream) => peer_certificates(stream),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Read for WssStream {
+    #[inline]
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            WssStream::Plain(st) => Pin::new(st).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            WssStream::Tls(st) => Pin::new(st).poll_read(cx, buf),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Write for WssStream {
+    #[inline]
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            WssStream::Plain(st) => Pin::new(st).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            WssStream::Tls(st) => Pin::new(st).poll_write(cx, buf),
+        }
+    }
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            WssStream::Plain(st) => Pin::new(st).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            WssStream::Tls(st) => Pin::new(st).poll_flush(cx),
+        }
+    }
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            WssStream::Plain(st) => Pin::new(st).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            WssStream::Tls(st) => Pin::new(st).poll_shutdown(cx),
+        }
     }
 }
+
 #[cfg(target_arch = "wasm32")]
 impl WebSocket {
     #[inline]
     /// connect to the following address without discovery
     pub async fn inner_connect(addrs: &str, retries: u32, time_to_retry: u64) -> Result<Wss> {
+        Self::open(&format!("ws://{}", addrs), retries, time_to_retry).await
+    }
+    /// open a websocket to the url, retrying in case of failure
+    async fn open(url: &str, retries: u32, time_to_retry: u64) -> Result<Wss> {
         let mut attempt = 0;
         let stream = loop {
-            match reqwasm::websocket::futures::WebSocket::open(&format!("ws://{}", addrs)) {
+            match reqwasm::websocket::futures::WebSocket::open(url) {
                 Ok(s) => break s,
                 Err(e) => {
                     tracing::error!(
-                        "connecting to address `{}` failed, attempt {} starting",
-                        addrs,
+                        "connecting to `{}` failed, attempt {} starting",
+                        url,
                         attempt
                     );
                     async_timer::timed(
@@ -137,6 +685,12 @@ impl WebSocket {
     }
     #[inline]
     /// connect to the following address with the following id. Defaults to 3 retries.
+    /// `encrypted` runs the handshake over the browser websocket through the
+    /// same channel methods native websockets use.
+    /// ```no_run
+    /// let mut chan = WebSocket::connect("example.com:8080").await?.encrypted().await?;
+    /// chan.send("hello!").await?;
+    /// ```
     pub async fn connect(addrs: &str) -> Result<Handshake> {
         Self::connect_retry(addrs, 3, 10).await
     }
@@ -145,10 +699,181 @@ impl WebSocket {
     pub async fn connect_retry(addrs: &str, retries: u32, time_to_retry: u64) -> Result<Handshake> {
         let raw = Self::inner_connect(addrs, retries, time_to_retry).await?;
         let raw = Box::new(raw);
-        Ok(Handshake::from(Channel::from_raw(
+        Ok(Handshake::connector(Channel::from_raw(
             raw,
             Default::default(),
             Default::default(),
         )))
     }
+    #[inline]
+    /// connect to the url, `ws://` or `wss://`. Defaults to 3 retries.
+    /// Browsers don't let websockets set headers, so only the url is sent.
+    /// ```no_run
+    /// let hs = WebSocket::connect_url("wss://gateway.example.com/canary").await?;
+    /// ```
+    pub async fn connect_url(url: &str) -> Result<Handshake> {
+        let raw = Self::open(url, 3, 10).await?;
+        let raw = Box::new(raw);
+        Ok(Handshake::connector(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+    #[inline]
+    /// connect to the following address over tls (`wss://`). Defaults to 3 retries.
+    /// The browser checks the certificate of the server.
+    pub async fn connect_tls(addrs: &str) -> Result<Handshake> {
+        let raw = Self::open(&format!("wss://{}", addrs), 3, 10).await?;
+        let raw = Box::new(raw);
+        Ok(Handshake::connector(Channel::from_raw(
+            raw,
+            Default::default(),
+            Default::default(),
+        )))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32"), feature = "tls"))]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+    use crate::providers::certs::tests::{Ca, SniRecorder};
+    use crate::providers::{TlsClientConfig, TlsServerConfig};
+
+    /// serve echoes over wss with the configuration, returning the port
+    async fn echo(config: Arc<ServerConfig>) -> u16 {
+        let wss = WebSocket::bind_tls("127.0.0.1:0", config).await.unwrap();
+        let port = wss.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok(hs) = wss.next().await {
+                tokio::spawn(async move {
+                    let mut chan = hs.raw();
+                    let msg: String = chan.receive().await?;
+                    chan.send(msg).await
+                });
+            }
+        });
+        port
+    }
+
+    fn client(ca: &Ca) -> Arc<ClientConfig> {
+        TlsClientConfig::default()
+            .roots(ca.roots())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn websockets_run_over_tls() {
+        let ca = Ca::new();
+        let (chain, key) = ca.issue("localhost");
+        let port = echo(TlsServerConfig::new(chain.clone(), key).build().unwrap()).await;
+        let hs = WebSocket::connect_tls(&format!("localhost:{}", port), Some(client(&ca)))
+            .await
+            .unwrap();
+        assert_eq!(hs.peer_certificates(), Some(chain));
+        let mut chan = hs.raw();
+        chan.send("hello").await.unwrap();
+        assert_eq!(chan.receive::<String>().await.unwrap(), "hello");
+
+        let url = format!("wss://localhost:{}/canary", port);
+        let mut chan = WebSocket::connect_url_with(&url, &[], client(&ca))
+            .await
+            .unwrap()
+            .raw();
+        chan.send("hello").await.unwrap();
+        assert_eq!(chan.receive::<String>().await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn unknown_issuers_are_denied() {
+        let (chain, key) = Ca::new().issue("localhost");
+        let port = echo(TlsServerConfig::new(chain, key).build().unwrap()).await;
+        let addr = format!("localhost:{}", port);
+        let e = WebSocket::connect_tls(&addr, Some(client(&Ca::new())))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        let url = format!("wss://{}", addr);
+        let e = WebSocket::connect_url(&url, &[]).await.err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn server_names_come_from_the_host() {
+        let ca = Ca::new();
+        let recorder = Arc::new(SniRecorder::new(ca.issue("localhost")));
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(recorder.clone());
+        let port = echo(Arc::new(config)).await;
+        WebSocket::connect_tls(&format!("localhost:{}", port), Some(client(&ca)))
+            .await
+            .unwrap();
+        let url = format!("wss://localhost:{}/canary", port);
+        WebSocket::connect_url_with(&url, &[], client(&ca))
+            .await
+            .unwrap();
+        let localhost = Some("localhost".to_owned());
+        assert_eq!(
+            *recorder.names.lock().unwrap(),
+            [localhost.clone(), localhost]
+        );
+
+        // certificates for other names are denied
+        let (chain, key) = ca.issue("example.com");
+        let port = echo(TlsServerConfig::new(chain, key).build().unwrap()).await;
+        let e = WebSocket::connect_tls(&format!("localhost:{}", port), Some(client(&ca)))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn client_certificates_are_verified() {
+        let ca = Ca::new();
+        let (chain, key) = ca.issue("localhost");
+        let config = TlsServerConfig::new(chain, key)
+            .require_client_auth(ca.roots())
+            .build()
+            .unwrap();
+        let port = echo(config).await;
+        let addr = format!("localhost:{}", port);
+        let (chain, key) = ca.issue("client");
+        let config = TlsClientConfig::default()
+            .roots(ca.roots())
+            .with_client_cert(chain, key)
+            .build()
+            .unwrap();
+        let mut chan = WebSocket::connect_tls(&addr, Some(config))
+            .await
+            .unwrap()
+            .raw();
+        chan.send("hello").await.unwrap();
+        assert_eq!(chan.receive::<String>().await.unwrap(), "hello");
+
+        // tls 1.3 servers reject certificates while the upgrade is sent
+        let (chain, key) = Ca::new().issue("client");
+        let config = TlsClientConfig::default()
+            .roots(ca.roots())
+            .with_client_cert(chain, key)
+            .build()
+            .unwrap();
+        let e = WebSocket::connect_tls(&addr, Some(config))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+        let url = format!("wss://{}", addr);
+        let e = WebSocket::connect_url_with(&url, &[], client(&ca))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    }
 }
@@ -14,18 +14,110 @@ use reqwasm::websocket::Message;
 use super::formats::{ReadFormat, SendFormat};
 use super::zc;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io::timeout_at;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+/// length prefix reserved for heartbeat frames, which carry no payload.
+/// no message can have this length, since it can't be allocated.
+pub(crate) const HEARTBEAT: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// length prefix of the messages sent through byte streams.
+/// Both peers must use the same framing, a mismatch is detected on the
+/// first message and fails with an `InvalidData` error.
+/// Websocket messages are framed by the websocket, so it doesn't apply to them.
+/// ```no_run
+/// # use canary::{serialization::Framing, Channel};
+/// # fn run(chan: Channel) {
+/// let mut chan = chan.with_framing(Framing::VarInt);
+/// # }
+/// ```
+pub enum Framing {
+    #[default]
+    /// 8 byte big-endian length
+    U64,
+    /// LEB128 length, a single byte for messages under 127 bytes
+    VarInt,
+}
+
+// frames with a u64 length this large can't be real, since their first
+// byte is nonzero, which is what varint frames start with
+const MAX_U64_LEN: u64 = 1 << 56;
+
 /// send an item through the stream
 pub async fn tx<T, O, F: SendFormat>(st: &mut T, obj: O, f: &mut F) -> Result<usize>
 where
     T: Write + Unpin,
     O: Serialize,
 {
-    let serialized = f.serialize(&obj)?;
-    zc::send_u64(st, serialized.len() as _).await?;
-    // return length of object sent
-    st.write_all(&serialized).await?;
+    // most messages fit in the initial capacity, so sending allocates once
+    tx_into(st, obj, f, &mut Vec::with_capacity(64)).await
+}
+
+/// send an item through the stream, framing it in `buf`.
+/// the buffer is cleared and reused, so sending only allocates when
+/// the frame doesn't fit in its capacity.
+pub async fn tx_into<T, O, F: SendFormat>(
+    st: &mut T,
+    obj: O,
+    f: &mut F,
+    buf: &mut Vec<u8>,
+) -> Result<usize>
+where
+    T: Write + Unpin,
+    O: Serialize,
+{
+    let (start, len) = frame_into(obj, f, buf)?;
+    st.write_all(&buf[start..]).await?;
     st.flush().await?;
-    Ok(serialized.len())
+    // return length of object sent
+    Ok(len)
+}
+
+// serialize `obj` after its length prefix, returning the buffer, where the
+// frame starts in it and the length of the object.
+// the length prefix and the object are written at once
+fn frame<O: Serialize, F: SendFormat>(obj: O, f: &mut F) -> Result<(Vec<u8>, usize, usize)> {
+    let mut buf = Vec::with_capacity(64);
+    let (start, len) = frame_into(obj, f, &mut buf)?;
+    Ok((buf, start, len))
+}
+
+// serialize `obj` after its length prefix into `buf`, returning where the
+// frame starts in it and the length of the object
+fn frame_into<O: Serialize, F: SendFormat>(
+    obj: O,
+    f: &mut F,
+    buf: &mut Vec<u8>,
+) -> Result<(usize, usize)> {
+    buf.clear();
+    let (start, len) = match f.framing() {
+        Framing::U64 => {
+            buf.extend_from_slice(&[0u8; 8]);
+            let len = f.serialize_into(&obj, buf)?;
+            buf[..8].copy_from_slice(&(len as u64).to_be_bytes());
+            (0, len)
+        }
+        Framing::VarInt => {
+            // the prefix is right-aligned in the reserved space once its length is known
+            buf.extend_from_slice(&[0u8; zc::MAX_VARINT_LEN]);
+            let len = f.serialize_into(&obj, buf)?;
+            let mut prefix = [0u8; zc::MAX_VARINT_LEN];
+            let prefix_len = zc::encode_varint(varint_len(len), &mut prefix);
+            let start = zc::MAX_VARINT_LEN - prefix_len;
+            buf[start..zc::MAX_VARINT_LEN].copy_from_slice(&prefix[..prefix_len]);
+            (start, len)
+        }
+    };
+    Ok((start, len))
+}
+
+// varint lengths are offset by one, so no varint frame starts with a zero
+// byte like u64 frames do
+fn varint_len(len: usize) -> u64 {
+    len as u64 + 1
 }
 
 /// receive an item from the stream
@@ -34,32 +126,491 @@ where
     T: Read + Unpin,
     O: DeserializeOwned,
 {
-    let size = zc::read_u64(st).await?;
-    // this is done for fallibility, we don't want people sending in usize::MAX
-    // as the len unexpectedly crashing the program
-    let mut buf = zc::try_vec(size as usize)?;
+    rx_into(st, f, &mut Vec::new()).await
+}
+
+/// receive an item from the stream, reading its payload into `buf`.
+/// the buffer is cleared and reused, so receiving only allocates when
+/// the payload doesn't fit in its capacity.
+pub async fn rx_into<T, O, F: ReadFormat>(st: &mut T, f: &mut F, buf: &mut Vec<u8>) -> Result<O>
+where
+    T: Read + Unpin,
+    O: DeserializeOwned,
+{
+    let mut state = RxState {
+        buf: std::mem::take(buf),
+        ..Default::default()
+    };
+    let obj = rx_resume(st, f, &mut state).await;
+    *buf = state.buf;
+    obj
+}
+
+#[derive(Debug, Default)]
+/// progress of the frame being received, kept between receives so a
+/// receive can be dropped and resumed later without losing bytes
+pub(crate) struct RxState {
+    /// payload of the frame
+    buf: Vec<u8>,
+    // bytes of the length prefix read so far
+    prefix: [u8; zc::MAX_VARINT_LEN],
+    prefix_len: usize,
+    // length of the payload, once the prefix is read
+    size: Option<usize>,
+    // bytes of the payload read so far
+    filled: usize,
+}
+
+impl RxState {
+    /// whether no frame is in progress
+    pub(crate) fn is_idle(&self) -> bool {
+        self.prefix_len == 0 && self.size.is_none()
+    }
+    /// payload of the last frame received with `rx_payload_until`
+    pub(crate) fn payload(&self) -> &[u8] {
+        &self.buf
+    }
+    /// capacity of the payload buffer
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+    /// drop the payload buffer, only when no frame is in progress
+    pub(crate) fn release(&mut self) {
+        if self.is_idle() {
+            self.buf = Vec::new();
+        }
+    }
+    fn reset(&mut self) {
+        self.prefix_len = 0;
+        self.size = None;
+        self.filled = 0;
+    }
+}
+
+/// receive an item like `rx_into`, keeping the progress of the frame in
+/// `state`. Only single reads are awaited, which lose no bytes when they
+/// are cancelled, so the future can be dropped at any point and the next
+/// call resumes the frame where it stopped.
+pub(crate) async fn rx_resume<T, O, F: ReadFormat>(
+    st: &mut T,
+    f: &mut F,
+    state: &mut RxState,
+) -> Result<O>
+where
+    T: Read + Unpin,
+    O: DeserializeOwned,
+{
+    loop {
+        if let Err(e) = read_frame(st, state, f).await {
+            // the stream is broken, a frame in progress can't be finished
+            state.reset();
+            return Err(e);
+        }
+        state.reset();
+        match f.deserialize(&state.buf) {
+            // encrypted control messages are skipped like heartbeats
+            Err(e) if is_control(&e) => continue,
+            obj => return obj,
+        }
+    }
+}
+
+// read the rest of the frame in progress into `state.buf`.
+// heartbeats of encrypted channels are encrypted, so plaintext ones are
+// rejected instead of letting anyone on the path inject them
+async fn read_frame<T: Read + Unpin, F: ReadFormat>(
+    st: &mut T,
+    state: &mut RxState,
+    f: &F,
+) -> Result<()> {
+    let framing = f.framing();
+    while state.size.is_none() {
+        // varints are read a byte at a time, so no byte of the payload is read
+        let end = match framing {
+            Framing::U64 => 8,
+            Framing::VarInt => state.prefix_len + 1,
+        };
+        let read = st.read(&mut state.prefix[state.prefix_len..end]).await?;
+        if read == 0 {
+            return err!((unexpected_eof, "stream closed"));
+        }
+        state.prefix_len += read;
+        let last = state.prefix[state.prefix_len - 1];
+        let len = match framing {
+            Framing::U64 if state.prefix_len == 8 => {
+                u64::from_be_bytes(state.prefix[..8].try_into().unwrap())
+            }
+            Framing::VarInt if last & 0x80 == 0 => {
+                zc::decode_varint(&state.prefix[..state.prefix_len])?
+            }
+            Framing::VarInt if state.prefix_len == zc::MAX_VARINT_LEN => {
+                return err!((invalid_data, "varint overflows a u64"))
+            }
+            _ => continue,
+        };
+        state.prefix_len = 0;
+        // heartbeats are skipped since they only keep the connection alive
+        let size = check_len(len, framing)?;
+        if size == HEARTBEAT && f.is_encrypted() {
+            return err!((invalid_data, "plaintext heartbeat on an encrypted channel"));
+        }
+        match f.max_len() {
+            Some(max) if size != HEARTBEAT && size > max as u64 => {
+                return err!((
+                    invalid_data,
+                    format!("frame of {} bytes is over the limit of {}", size, max)
+                ))
+            }
+            _ => {}
+        }
+        if size != HEARTBEAT {
+            // this is done for fallibility, we don't want people sending in usize::MAX
+            // as the len unexpectedly crashing the program
+            zc::try_resize(&mut state.buf, size as usize)?;
+            state.size = Some(size as usize);
+        }
+    }
     // read message into buffer
-    st.read_exact(&mut buf).await?;
-    f.deserialize(&buf)
+    while state.filled < state.buf.len() {
+        let read = st.read(&mut state.buf[state.filled..]).await?;
+        if read == 0 {
+            return err!((unexpected_eof, "stream closed in the middle of a frame"));
+        }
+        state.filled += read;
+    }
+    Ok(())
+}
+
+// check the length prefix of a frame, failing if the peer uses other framing
+fn check_len(len: u64, framing: Framing) -> Result<u64> {
+    match framing {
+        Framing::U64 => match len {
+            HEARTBEAT => Ok(HEARTBEAT),
+            size if size >= MAX_U64_LEN => err!((
+                invalid_data,
+                "invalid u64 frame length, the peer may use varint framing"
+            )),
+            size => Ok(size),
+        },
+        Framing::VarInt => match len {
+            0 => err!((
+                invalid_data,
+                "invalid varint frame length, the peer may use u64 framing"
+            )),
+            HEARTBEAT => Ok(HEARTBEAT),
+            size => Ok(size - 1),
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Error of sends and receives whose deadline passed, wrapped in errors
+/// of kind `TimedOut`. If the deadline passed in the middle of a frame, the
+/// rest of the frame is still on the stream, so it can't be used anymore.
+/// Channels keep a frame the deadline cut and finish it on the next receive,
+/// so their receives never poison them.
+/// ```no_run
+/// # use std::time::Duration;
+/// # use canary::{serialization::TimedOut, Channel};
+/// # fn retry() -> String {
+/// #     String::new()
+/// # }
+/// # async fn run(mut chan: Channel) -> canary::Result<()> {
+/// let string = match chan.receive_timeout::<String>(Duration::from_secs(1)).await {
+///     Err(e) if TimedOut::of(&e).is_some_and(|t| !t.is_poisoned()) => retry(),
+///     result => result?,
+/// };
+/// # Ok(())
+/// # }
+/// ```
+pub struct TimedOut {
+    poisoned: bool,
+}
+
+impl TimedOut {
+    /// Get the timeout that caused `e`, if any
+    pub fn of(e: &crate::Error) -> Option<&TimedOut> {
+        e.get_ref()?.downcast_ref()
+    }
+    /// Returns `true` if the deadline passed in the middle of a frame,
+    /// so the stream can't be resynchronized
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.poisoned {
+            false => f.write_str("deadline passed"),
+            true => f.write_str("deadline passed in the middle of a frame, the stream is unusable"),
+        }
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+pub(crate) fn timed_out(poisoned: bool) -> crate::Error {
+    std::io::Error::new(std::io::ErrorKind::TimedOut, TimedOut { poisoned }).into()
+}
+
+#[derive(Debug)]
+// error of encrypted messages that only carry control information,
+// which receives skip after the cipher has processed them
+struct Control;
+
+impl std::fmt::Display for Control {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("control message")
+    }
+}
+
+impl std::error::Error for Control {}
+
+pub(crate) fn control() -> crate::Error {
+    std::io::Error::other(Control).into()
+}
+
+pub(crate) fn is_control(e: &crate::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<Control>())
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-/// send a message from a websocket stream
-pub async fn wss_tx<T, O, F: SendFormat>(st: &mut T, obj: O, f: &mut F) -> Result<usize>
+/// send an item through the stream, failing with a `TimedOut` error if it
+/// isn't written by `deadline`. The error is poisoned if part of the frame
+/// was written, since the peer can't tell where the next frame starts.
+/// ```no_run
+/// # use std::time::{Duration, Instant};
+/// # use canary::serialization::{formats::Bincode, tx_deadline};
+/// # async fn run(mut stream: tokio::net::TcpStream) -> canary::Result<()> {
+/// let deadline = Instant::now() + Duration::from_secs(1);
+/// tx_deadline(&mut stream, "hello!", &mut Bincode, deadline).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn tx_deadline<T, O, F: SendFormat>(
+    st: &mut T,
+    obj: O,
+    f: &mut F,
+    deadline: Instant,
+) -> Result<usize>
 where
-    T: futures::prelude::Sink<Message> + Unpin,
+    T: Write + Unpin,
     O: Serialize,
-    <T as futures::prelude::Sink<Message>>::Error: ToString,
 {
-    let serialized = f.serialize(&obj)?;
-    let len = serialized.len();
-    let msg = Message::Binary(serialized.to_vec());
-    st.feed(msg).await.map_err(|e| err!(e.to_string()))?;
-    st.flush().await.map_err(|e| err!(e.to_string()))?;
+    let deadline = crate::io::Instant::from_std(deadline);
+    let (buf, start, len) = frame(obj, f)?;
+    // single writes can be cancelled, so we know how much of the frame went out
+    let mut written = start;
+    while written < buf.len() {
+        match timeout_at(deadline, st.write(&buf[written..])).await {
+            Ok(Ok(0)) => return err!((write_zero, "failed to write the whole frame")),
+            Ok(n) => written += n?,
+            Err(_) => return Err(timed_out(written != start)),
+        }
+    }
+    // the frame is written, so it still arrives whole if flushing times out
+    timeout_at(deadline, st.flush())
+        .await
+        .map_err(|_| timed_out(false))??;
     Ok(len)
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+/// receive an item from the stream, failing with a `TimedOut` error if it
+/// isn't received by `deadline`.
+/// Frames cut short with few bytes left are drained if the rest already
+/// arrived, otherwise the error is poisoned, since the rest of the frame
+/// is still on the stream. Channels keep the frame in progress instead,
+/// see `Channel::receive_timeout`.
+/// ```no_run
+/// # use std::time::{Duration, Instant};
+/// # use canary::serialization::{formats::Bincode, rx_deadline};
+/// # async fn run(mut stream: tokio::net::TcpStream) -> canary::Result<()> {
+/// let deadline = Instant::now() + Duration::from_secs(1);
+/// let string: String = rx_deadline(&mut stream, &mut Bincode, deadline).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn rx_deadline<T, O, F: ReadFormat>(st: &mut T, f: &mut F, deadline: Instant) -> Result<O>
+where
+    T: Read + Unpin,
+    O: DeserializeOwned,
+{
+    use futures::FutureExt;
+    let deadline = crate::io::Instant::from_std(deadline);
+    let mut state = RxState::default();
+    match timeout_at(deadline, rx_resume(st, f, &mut state)).await {
+        Ok(obj) => obj,
+        Err(_) => {
+            let left = state.size.map(|size| size - state.filled);
+            let drained = state.is_idle()
+                || left.is_some_and(|left| left <= DRAIN_LEN)
+                    && matches!(read_frame(st, &mut state, &*f).now_or_never(), Some(Ok(())));
+            Err(timed_out(!drained))
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+// frames that time out with at most this many bytes left are drained if
+// the bytes already arrived, so the stream can still be used
+const DRAIN_LEN: usize = 64 * 1024;
+
+#[cfg(not(target_arch = "wasm32"))]
+/// receive an item like `rx_resume`, by `deadline` if there is one.
+/// the frame is kept in `state` on timeout, so the stream is never poisoned
+pub(crate) async fn rx_until<T, O, F: ReadFormat>(
+    st: &mut T,
+    f: &mut F,
+    state: &mut RxState,
+    deadline: Option<Instant>,
+) -> Result<O>
+where
+    T: Read + Unpin,
+    O: DeserializeOwned,
+{
+    match deadline {
+        Some(deadline) => timeout_at(
+            crate::io::Instant::from_std(deadline),
+            rx_resume(st, f, state),
+        )
+        .await
+        .map_err(|_| timed_out(false))?,
+        None => rx_resume(st, f, state).await,
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// receive the payload of the next frame into `state` without deserializing
+/// it, by `deadline` if there is one. the payload is left in the buffer of
+/// `state` until the next receive, see `RxState::payload`
+pub(crate) async fn rx_payload_until<T, F: ReadFormat>(
+    st: &mut T,
+    f: &F,
+    state: &mut RxState,
+    deadline: Option<Instant>,
+) -> Result<()>
+where
+    T: Read + Unpin,
+{
+    // the frame is only reset once it's read, a cancelled read keeps it
+    let read = async {
+        let read = read_frame(st, state, f).await;
+        state.reset();
+        read
+    };
+    match deadline {
+        Some(deadline) => timeout_at(crate::io::Instant::from_std(deadline), read)
+            .await
+            .map_err(|_| timed_out(false))?,
+        None => read.await,
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// send a heartbeat frame through the stream.
+/// heartbeats are skipped by `rx` on the other end.
+pub async fn heartbeat<T>(st: &mut T, framing: Framing) -> Result<()>
+where
+    T: Write + Unpin,
+{
+    match framing {
+        Framing::U64 => zc::send_u64_be(st, HEARTBEAT).await?,
+        Framing::VarInt => zc::send_varint(st, HEARTBEAT).await?,
+    }
+    st.flush().await?;
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// send a ping through a websocket stream.
+/// pings and pongs are skipped by `wss_rx` on the other end.
+pub async fn wss_heartbeat<T>(st: &mut T) -> Result<()>
+where
+    T: futures::prelude::Sink<Message> + Unpin,
+    <T as futures::prelude::Sink<Message>>::Error: ToString,
+{
+    st.send(Message::Ping(vec![]))
+        .await
+        .map_err(|e| err!(e.to_string()))
+}
+
+/// websocket message carrying `bytes`, as text if the format says they are
+pub(crate) fn wss_message(bytes: Vec<u8>, text: bool) -> Result<Message> {
+    if !text {
+        #[cfg(not(target_arch = "wasm32"))]
+        return Ok(Message::Binary(bytes));
+        #[cfg(target_arch = "wasm32")]
+        return Ok(Message::Bytes(bytes));
+    }
+    let text = String::from_utf8(bytes).map_err(err!(@invalid_data))?;
+    Ok(Message::Text(text))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// send `bytes` through a websocket stream as a single message, split into
+/// continuation frames of at most `fragment_len` bytes if it is larger
+pub(crate) async fn wss_send<T>(
+    st: &mut T,
+    bytes: Vec<u8>,
+    text: bool,
+    fragment_len: Option<usize>,
+) -> Result<()>
+where
+    T: futures::prelude::Sink<Message> + Unpin,
+    <T as futures::prelude::Sink<Message>>::Error: ToString,
+{
+    use crate::io::wss::tungstenite::protocol::frame::{
+        coding::{Data, OpCode},
+        Frame,
+    };
+    match fragment_len {
+        Some(fragment_len) if bytes.len() > fragment_len => {
+            let mut opcode = match text {
+                true => {
+                    std::str::from_utf8(&bytes).map_err(err!(@invalid_data))?;
+                    Data::Text
+                }
+                false => Data::Binary,
+            };
+            let mut frames = bytes.chunks(fragment_len.max(1)).peekable();
+            while let Some(frame) = frames.next() {
+                let last = frames.peek().is_none();
+                let frame = Frame::message(frame.to_vec(), OpCode::Data(opcode), last);
+                st.feed(Message::Frame(frame))
+                    .await
+                    .map_err(|e| err!(e.to_string()))?;
+                opcode = Data::Continue;
+            }
+        }
+        _ => st
+            .feed(wss_message(bytes, text)?)
+            .await
+            .map_err(|e| err!(e.to_string()))?,
+    }
+    st.flush().await.map_err(|e| err!(e.to_string()))
+}
+
 #[cfg(target_arch = "wasm32")]
+/// send `bytes` through a websocket stream as a single message.
+/// browsers frame messages by themselves, so `fragment_len` is ignored
+pub(crate) async fn wss_send<T>(
+    st: &mut T,
+    bytes: Vec<u8>,
+    text: bool,
+    _fragment_len: Option<usize>,
+) -> Result<()>
+where
+    T: futures::prelude::Sink<Message> + Unpin,
+    <T as futures::prelude::Sink<Message>>::Error: ToString,
+{
+    st.feed(wss_message(bytes, text)?)
+        .await
+        .map_err(|e| err!(e.to_string()))?;
+    st.flush().await.map_err(|e| err!(e.to_string()))
+}
+
 /// send a message from a websocket stream
 pub async fn wss_tx<T, O, F: SendFormat>(st: &mut T, obj: O, f: &mut F) -> Result<usize>
 where
@@ -69,54 +620,357 @@ where
 {
     let serialized = f.serialize(&obj)?;
     let len = serialized.len();
-    let msg = Message::Bytes(serialized);
-    st.feed(msg).await.map_err(|e| err!(e.to_string()))?;
-    st.flush().await.map_err(|e| err!(e.to_string()))?;
+    wss_send(st, serialized, f.is_text(), f.fragment_len()).await?;
     Ok(len)
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-/// receive a message from a websocket stream
-pub async fn wss_rx<T, O, F: ReadFormat>(st: &mut T, f: &mut F) -> Result<O>
+/// item of the websocket streams messages are received from
+pub(crate) type WssItem = std::result::Result<Message, crate::io::wss::tungstenite::error::Error>;
+
+#[cfg(target_arch = "wasm32")]
+/// item of the websocket streams messages are received from
+pub(crate) type WssItem = std::result::Result<Message, reqwasm::websocket::WebSocketError>;
+
+#[cfg(not(target_arch = "wasm32"))]
+// receive the bytes of the next data message from a websocket stream
+async fn wss_next<T>(st: &mut T) -> Result<Vec<u8>>
 where
-    T: futures::prelude::Stream<
-            Item = std::result::Result<Message, crate::io::wss::tungstenite::error::Error>,
-        > + Unpin,
-    O: DeserializeOwned,
+    T: futures::prelude::Stream<Item = WssItem> + Unpin,
 {
-    let msg = st
-        .next()
-        .await
-        .ok_or(err!(broken_pipe, "websocket connection broke"))?
-        .map_err(|e| err!(broken_pipe, e))?;
+    use crate::io::wss::tungstenite::error::Error as WsError;
+    loop {
+        let msg = match st.next().await {
+            Some(Ok(msg)) => msg,
+            // the stream ends after the closing handshake, like tcp streams do
+            None | Some(Err(WsError::ConnectionClosed | WsError::AlreadyClosed)) => {
+                return err!((unexpected_eof, "websocket connection closed"))
+            }
+            Some(Err(e)) => return err!((broken_pipe, e)),
+        };
 
-    match msg {
-        Message::Binary(vec) => f.deserialize(&vec),
-        Message::Text(_) => err!((invalid_data, "expected binary message, found text message")),
-        Message::Ping(_) => err!((invalid_data, "expected binary message, found ping message")),
-        Message::Pong(_) => err!((invalid_data, "expected binary message, found pong message")),
-        Message::Close(_) => err!((invalid_data, "expected binary message, found close message")),
-        Message::Frame(_) => err!((invalid_data, "expected binary message, found frame")),
+        return match msg {
+            Message::Binary(vec) => Ok(vec),
+            // pings and pongs only keep the connection alive.
+            // tungstenite answers pings by itself while reading
+            Message::Ping(_) | Message::Pong(_) => continue,
+            // sent by text-safe formats
+            Message::Text(text) => Ok(text.into_bytes()),
+            Message::Close(Some(frame)) if !frame.reason.is_empty() => err!((
+                unexpected_eof,
+                format!("websocket connection closed: {}", frame.reason)
+            )),
+            Message::Close(_) => err!((unexpected_eof, "websocket connection closed")),
+            Message::Frame(_) => err!((invalid_data, "expected binary message, found frame")),
+        };
     }
 }
 
 #[cfg(target_arch = "wasm32")]
+// receive the bytes of the next data message from a websocket stream
+async fn wss_next<T>(st: &mut T) -> Result<Vec<u8>>
+where
+    T: futures::prelude::Stream<Item = WssItem> + Unpin,
+{
+    use reqwasm::websocket::WebSocketError;
+    let msg = match st.next().await {
+        Some(Ok(msg)) => msg,
+        // closed like native websockets, so both ends see the same errors
+        Some(Err(WebSocketError::ConnectionClose(event))) if !event.reason.is_empty() => {
+            return err!((
+                unexpected_eof,
+                format!("websocket connection closed: {}", event.reason)
+            ))
+        }
+        None | Some(Err(WebSocketError::ConnectionClose(_))) => {
+            return err!((unexpected_eof, "websocket connection closed"))
+        }
+        Some(Err(e)) => return err!((broken_pipe, e.to_string())),
+    };
+    match msg {
+        Message::Bytes(vec) => Ok(vec),
+        // sent by text-safe formats
+        Message::Text(text) => Ok(text.into_bytes()),
+    }
+}
+
 /// receive a message from a websocket stream
 pub async fn wss_rx<T, O, F: ReadFormat>(st: &mut T, f: &mut F) -> Result<O>
 where
-    T: futures::prelude::Stream<
-            Item = std::result::Result<Message, reqwasm::websocket::WebSocketError>,
-        > + Unpin,
+    T: futures::prelude::Stream<Item = WssItem> + Unpin,
     O: DeserializeOwned,
 {
-    let msg = st
-        .next()
-        .await
-        .ok_or(err!(broken_pipe, "websocket connection broke"))?
-        .map_err(|e| err!(broken_pipe, e.to_string()))?;
+    loop {
+        let bytes = wss_next(st).await?;
+        match f.deserialize(&bytes) {
+            // encrypted control messages are skipped like pings
+            Err(e) if is_control(&e) => continue,
+            obj => break obj,
+        }
+    }
+}
 
-    match msg {
-        Message::Bytes(vec) => f.deserialize(&vec),
-        Message::Text(_) => err!((invalid_data, "expected binary data, found text")),
+#[cfg(not(target_arch = "wasm32"))]
+/// receive a message from a websocket stream by `deadline` if there is one.
+/// websocket messages arrive whole, so the stream is never poisoned
+pub(crate) async fn wss_rx_until<T, O, F: ReadFormat>(
+    st: &mut T,
+    f: &mut F,
+    deadline: Option<Instant>,
+) -> Result<O>
+where
+    T: futures::prelude::Stream<Item = WssItem> + Unpin,
+    O: DeserializeOwned,
+{
+    match deadline {
+        Some(deadline) => timeout_at(crate::io::Instant::from_std(deadline), wss_rx(st, f))
+            .await
+            .map_err(|_| timed_out(false))?,
+        None => wss_rx(st, f).await,
+    }
+}
+
+/// receive the bytes of the next message from a websocket stream into
+/// `state`, by `deadline` if there is one. websocket messages arrive in
+/// their own buffers, which replace the one of `state`
+pub(crate) async fn wss_rx_payload_until<T>(
+    st: &mut T,
+    state: &mut RxState,
+    #[allow(unused)] deadline: Option<std::time::Instant>,
+) -> Result<()>
+where
+    T: futures::prelude::Stream<Item = WssItem> + Unpin,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    let bytes = match deadline {
+        Some(deadline) => timeout_at(crate::io::Instant::from_std(deadline), wss_next(st))
+            .await
+            .map_err(|_| timed_out(false))?,
+        None => wss_next(st).await,
+    };
+    #[cfg(target_arch = "wasm32")]
+    let bytes = wss_next(st).await;
+    state.buf = bytes?;
+    Ok(())
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use super::*;
+    use crate::serialization::formats::{Format, Framed};
+
+    #[derive(Default)]
+    /// writer that records every write it gets
+    struct Writes(Vec<Vec<u8>>);
+
+    impl Write for Writes {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.0.push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_sent_in_a_single_write() {
+        for framing in [Framing::U64, Framing::VarInt] {
+            let mut format = Framed::new(Format::Bincode, framing);
+            // past the initial capacity of the frame buffer
+            for message in ["hello!".to_string(), "a".repeat(100_000)] {
+                let mut writes = Writes::default();
+                tx(&mut writes, &message, &mut format).await.unwrap();
+                assert_eq!(writes.0.len(), 1);
+                let frame = writes.0.concat();
+                let received: String = rx(&mut frame.as_slice(), &mut format).await.unwrap();
+                assert_eq!(received, message);
+            }
+            let mut writes = Writes::default();
+            heartbeat(&mut writes, framing).await.unwrap();
+            assert_eq!(writes.0.len(), 1);
+        }
+    }
+
+    fn deadline(millis: u64) -> Instant {
+        Instant::now() + std::time::Duration::from_millis(millis)
+    }
+
+    #[tokio::test]
+    async fn sends_past_the_deadline_say_if_they_poisoned_the_stream() {
+        // nothing reads from the other end
+        let (mut a, _b) = tokio::io::duplex(16);
+        let mut format = Format::Bincode;
+        let message = "a".repeat(64);
+        // part of the frame fits in the buffer
+        let e = tx_deadline(&mut a, &message, &mut format, deadline(50))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        assert!(TimedOut::of(&e).unwrap().is_poisoned());
+        // the buffer is full, so nothing is written
+        let e = tx_deadline(&mut a, &message, &mut format, deadline(50))
+            .await
+            .unwrap_err();
+        assert!(!TimedOut::of(&e).unwrap().is_poisoned());
+
+        let (mut c, mut d) = tokio::io::duplex(1024);
+        tx_deadline(&mut c, &message, &mut format, deadline(50))
+            .await
+            .unwrap();
+        let received: String = rx(&mut d, &mut format).await.unwrap();
+        assert_eq!(received, message);
+    }
+
+    #[tokio::test]
+    async fn receives_past_the_deadline_say_if_they_poisoned_the_stream() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let mut format = Format::Bincode;
+        // nothing arrived, so the stream is still usable
+        let e = rx_deadline::<_, String, _>(&mut b, &mut format, deadline(50))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+        assert!(!TimedOut::of(&e).unwrap().is_poisoned());
+        tx(&mut a, "hello!", &mut format).await.unwrap();
+        let received: String = rx_deadline(&mut b, &mut format, deadline(50))
+            .await
+            .unwrap();
+        assert_eq!(received, "hello!");
+
+        // half a frame arrives before the deadline
+        let (frame, start, _) = super::frame("hello!", &mut format).unwrap();
+        let half = &frame[start..frame.len() / 2];
+        a.write_all(half).await.unwrap();
+        let e = rx_deadline::<_, String, _>(&mut b, &mut format, deadline(50))
+            .await
+            .unwrap_err();
+        assert!(TimedOut::of(&e).unwrap().is_poisoned());
+    }
+
+    #[tokio::test]
+    async fn websocket_receives_skip_pings_and_pongs() {
+        let mut format = Format::Bincode;
+        let mut sent = Vec::new();
+        wss_tx(&mut sent, "hello!", &mut format).await.unwrap();
+        wss_tx(&mut sent, "world!", &mut format).await.unwrap();
+        let mut sent = sent.into_iter();
+        let items = vec![
+            Message::Ping(vec![1, 2, 3]),
+            sent.next().unwrap(),
+            Message::Pong(vec![1, 2, 3]),
+            Message::Ping(vec![]),
+            sent.next().unwrap(),
+            Message::Close(None),
+        ];
+        let mut st = futures::stream::iter(items.into_iter().map(Ok));
+        let received: String = wss_rx(&mut st, &mut format).await.unwrap();
+        assert_eq!(received, "hello!");
+        let received: String = wss_rx(&mut st, &mut format).await.unwrap();
+        assert_eq!(received, "world!");
+        let e = wss_rx::<_, String, _>(&mut st, &mut format)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(all(feature = "text_safe", feature = "json_ser"))]
+    #[tokio::test]
+    async fn text_safe_messages_reach_text_peers() {
+        use crate::providers::WebSocket;
+        use crate::serialization::formats::{Json, TextSafe};
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+        let ws = WebSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = ws.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let chan = ws.next().await?.raw();
+            let mut chan = chan.with_formats(TextSafe::new(Json), TextSafe::new(Json));
+            let message: String = chan.receive().await?;
+            chan.send(message.to_uppercase()).await
+        });
+        // a peer that only reads and writes text, like browser scripts do
+        let stream = crate::io::TcpStream::connect(addr).await.unwrap();
+        let (mut peer, _) = crate::io::wss::tokio::client_async(format!("ws://{}", addr), stream)
+            .await
+            .unwrap();
+        let hello = BASE64.encode(br#""hello!""#);
+        peer.send(Message::Text(hello)).await.unwrap();
+        match peer.next().await.unwrap().unwrap() {
+            Message::Text(text) => assert_eq!(BASE64.decode(text).unwrap(), br#""HELLO!""#),
+            msg => panic!("expected a text message, found {:?}", msg),
+        }
+        server.await.unwrap().unwrap();
+    }
+
+    type InProcessWss = crate::io::wss::WebSocketStream<
+        crate::io::wss::tokio::TokioAdapter<crate::io::DuplexStream>,
+    >;
+
+    // websockets over an in-process stream, the server rejecting frames over `max_frame` bytes
+    async fn websocket_pair(max_frame: usize) -> (InProcessWss, InProcessWss) {
+        use crate::io::wss::tokio::TokioAdapter;
+        use crate::io::wss::tungstenite::protocol::{Role, WebSocketConfig};
+        use crate::io::wss::WebSocketStream;
+
+        let (a, b) = crate::io::duplex(64 * 1024);
+        let config = WebSocketConfig {
+            max_frame_size: Some(max_frame),
+            ..Default::default()
+        };
+        let client = WebSocketStream::from_raw_socket(TokioAdapter::new(a), Role::Client, None);
+        let server =
+            WebSocketStream::from_raw_socket(TokioAdapter::new(b), Role::Server, Some(config));
+        tokio::join!(client, server)
+    }
+
+    #[tokio::test]
+    async fn messages_four_times_the_frame_cap_arrive_whole() {
+        use std::sync::RwLock;
+
+        use crate::async_snow::{new_initiator, new_responder, Nonce, RefDividedSnow};
+        use crate::channel::encrypted::snowwith::WithCipher;
+        use crate::serialization::formats::Fragmented;
+        use crate::Channel;
+
+        const FRAME: usize = 1024;
+        let (mut client, mut server) = websocket_pair(FRAME).await;
+        let message = vec![7u8; 4 * FRAME];
+        let mut format = Fragmented::new(Format::Bincode, FRAME);
+        wss_tx(&mut client, &message, &mut format).await.unwrap();
+        let received: Vec<u8> = wss_rx(&mut server, &mut format).await.unwrap();
+        assert_eq!(received, message);
+
+        // encrypted messages are split once encrypted
+        let (mut a, mut b) = Channel::pair();
+        let (initiator, responder) = tokio::join!(new_initiator(&mut a), new_responder(&mut b));
+        let initiator = RwLock::new(initiator.unwrap());
+        let responder = RwLock::new(responder.unwrap());
+        let (mut send_nonce, mut receive_nonce) = (Nonce::default(), Nonce::default());
+        let mut send = RefDividedSnow::new(&initiator, &mut send_nonce);
+        let mut receive = RefDividedSnow::new(&responder, &mut receive_nonce);
+        let (mut send_format, mut receive_format) = (Format::Bincode, Format::Bincode);
+        let mut format = Fragmented::new(WithCipher::new(&mut send, &mut send_format), FRAME);
+        wss_tx(&mut client, &message, &mut format).await.unwrap();
+        let mut format = WithCipher::new(&mut receive, &mut receive_format);
+        let received: Vec<u8> = wss_rx(&mut server, &mut format).await.unwrap();
+        assert_eq!(received, message);
+
+        // in a single frame the message is over the cap
+        wss_tx(&mut client, &message, &mut Format::Bincode)
+            .await
+            .unwrap();
+        let received = wss_rx::<_, Vec<u8>, _>(&mut server, &mut Format::Bincode).await;
+        assert!(received.is_err());
     }
 }
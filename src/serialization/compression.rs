@@ -0,0 +1,271 @@
+use std::borrow::Cow;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    err,
+    serialization::{
+        formats::{Format, ReadFormat, SendFormat},
+        zc, Framing,
+    },
+    Result,
+};
+
+// first bytes of every compressed message, used to detect peers without
+// compression. Binary formats like bincode and messagepack can start a message
+// with any bytes, so a plain message that starts with these and a valid kind
+// byte is misread instead of rejected. That takes five specific leading bytes,
+// so it's unlikely but not impossible. Text formats never start with 0xFF.
+const MAGIC: [u8; 4] = [0xFF, b'c', b'z', b'1'];
+// byte after the magic of messages sent uncompressed
+const STORED: u8 = 0;
+// zstd messages follow it with their uncompressed length, as a big endian u32
+const ZSTD: u8 = 1;
+// messages smaller than this are not worth compressing
+const MIN_COMPRESSED_LEN: usize = 64;
+// largest message accepted after decompression
+const MAX_DECOMPRESSED_LEN: usize = 1 << 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// compression algorithms allowed for channels
+pub enum Compression {
+    /// the zstd compression algorithm with the given level,
+    /// 0 uses the default level
+    Zstd {
+        /// compression level, from 1 to 22
+        level: i32,
+    },
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Zstd { level: 0 }
+    }
+}
+
+/// format adapter that compresses messages serialized by the inner format.
+/// Every message carries a five byte header, messages that don't shrink are
+/// sent uncompressed. Compressed messages also carry their uncompressed
+/// length, so receiving allocates exactly what the message needs.
+/// Both peers need compression enabled. Messages from a peer without it are
+/// rejected with an `InvalidData` error, unless they happen to start with
+/// the bytes of the header.
+pub struct Compressed<F = Format> {
+    /// inner serialization format
+    pub format: F,
+    /// compression algorithm
+    pub compression: Compression,
+}
+
+impl<F> Compressed<F> {
+    /// wrap the format so messages get compressed
+    pub fn new(format: F, compression: Compression) -> Self {
+        Compressed {
+            format,
+            compression,
+        }
+    }
+}
+
+impl<F: SendFormat> SendFormat for Compressed<F> {
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> Result<Vec<u8>> {
+        let bytes = self.format.serialize(obj)?;
+        if bytes.len() >= MIN_COMPRESSED_LEN {
+            let Compression::Zstd { level } = self.compression;
+            let compressed = zstd::bulk::compress(&bytes, level)?;
+            if compressed.len() + 4 < bytes.len() {
+                let mut message = with_header(ZSTD, &(bytes.len() as u32).to_be_bytes());
+                message.extend_from_slice(&compressed);
+                return Ok(message);
+            }
+        }
+        Ok(with_header(STORED, &bytes))
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn fragment_len(&self) -> Option<usize> {
+        self.format.fragment_len()
+    }
+}
+
+impl<F: ReadFormat> ReadFormat for Compressed<F> {
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.format
+            .deserialize(&decompress(bytes, MAX_DECOMPRESSED_LEN)?)
+    }
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let max = usize::try_from(limit).map_or(MAX_DECOMPRESSED_LEN, |limit| {
+            limit.min(MAX_DECOMPRESSED_LEN)
+        });
+        self.format
+            .deserialize_limited(&decompress(bytes, max)?, limit)
+    }
+    fn is_self_describing(&self) -> bool {
+        self.format.is_self_describing()
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> Result<Vec<u8>> {
+        self.format
+            .transcode(&decompress(bytes, MAX_DECOMPRESSED_LEN)?, to)
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+}
+
+// strip the header of the message, decompressing it if needed.
+// the length in the header comes from the peer, so it's checked against
+// `max` and the zstd frame before anything is allocated for it
+fn decompress(bytes: &[u8], max: usize) -> Result<Cow<'_, [u8]>> {
+    let Some(bytes) = bytes.strip_prefix(&MAGIC[..]) else {
+        return err!((
+            invalid_data,
+            "received an uncompressed message, the peer may not have compression enabled"
+        ));
+    };
+    match bytes {
+        [STORED, bytes @ ..] => Ok(Cow::Borrowed(bytes)),
+        [ZSTD, a, b, c, d, bytes @ ..] => {
+            let len = u32::from_be_bytes([*a, *b, *c, *d]) as usize;
+            if len > max {
+                return err!((invalid_data, "compressed message is too large"));
+            }
+            if zstd::zstd_safe::get_frame_content_size(bytes).ok() != Some(Some(len as u64)) {
+                return err!((invalid_data, "compressed message has the wrong length"));
+            }
+            let mut buf = zc::try_vec(len)?;
+            let read = zstd::bulk::decompress_to_buffer(bytes, &mut buf[..])
+                .map_err(err!(@invalid_data))?;
+            if read != len {
+                return err!((invalid_data, "compressed message has the wrong length"));
+            }
+            Ok(Cow::Owned(buf))
+        }
+        [ZSTD, ..] => err!((invalid_data, "truncated compressed message")),
+        _ => err!((invalid_data, "unknown compression of the message")),
+    }
+}
+
+fn with_header(kind: u8, bytes: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(MAGIC.len() + 1 + bytes.len());
+    message.extend_from_slice(&MAGIC);
+    message.push(kind);
+    message.extend_from_slice(bytes);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::*;
+    use crate::serialization::formats::Bincode;
+    use crate::Channel;
+
+    fn compressed<F>(format: F) -> Compressed<F> {
+        Compressed::new(format, Compression::default())
+    }
+
+    #[test]
+    fn empty_payload() {
+        let mut format = compressed(Bincode);
+        let bytes = format.serialize(&()).unwrap();
+        format.deserialize::<()>(&bytes).unwrap();
+        let bytes = format.serialize(&Vec::<u8>::new()).unwrap();
+        assert!(format.deserialize::<Vec<u8>>(&bytes).unwrap().is_empty());
+    }
+
+    #[test]
+    fn already_compressed_data() {
+        let data = zstd::bulk::compress(&rand::random::<[u8; 32]>().repeat(64), 19).unwrap();
+        let mut format = compressed(Bincode);
+        let bytes = format.serialize(&data).unwrap();
+        // compressing again doesn't shrink it, so it's stored
+        assert_eq!(bytes[MAGIC.len()], STORED);
+        assert_eq!(format.deserialize::<Vec<u8>>(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn corrupted_stream() {
+        let mut format = compressed(Bincode);
+        let mut bytes = format.serialize(&"canary ".repeat(100)).unwrap();
+        assert_eq!(bytes[MAGIC.len()], ZSTD);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        bytes[last - 1] ^= 0xFF;
+        let e = format.deserialize::<String>(&bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        bytes.truncate(MAGIC.len() + 3);
+        let e = format.deserialize::<String>(&bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn length_is_checked_before_allocating() {
+        // a header claiming a gigabyte with no frame behind it
+        let header = [&MAGIC[..], &[ZSTD, 0x40, 0, 0, 0]].concat();
+        let e = compressed(Bincode)
+            .deserialize::<String>(&header)
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        let mut format = compressed(Bincode);
+        let bytes = format.serialize(&"canary ".repeat(100)).unwrap();
+        let e = format
+            .deserialize_limited::<String>(&bytes, 100)
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert!(format.deserialize_limited::<String>(&bytes, 1000).is_ok());
+    }
+
+    #[cfg(feature = "json_ser")]
+    #[test]
+    fn json_shrinks() {
+        use crate::serialization::formats::Json;
+
+        let document: Vec<_> = (0..1000)
+            .map(|i| serde_json::json!({ "id": i, "name": "canary", "tags": ["a", "b"] }))
+            .collect();
+        let plain = Json.serialize(&document).unwrap();
+        let mut format = compressed(Json);
+        let bytes = format.serialize(&document).unwrap();
+        assert!(bytes.len() * 10 < plain.len());
+        let received: Vec<serde_json::Value> = format.deserialize(&bytes).unwrap();
+        assert_eq!(received, document);
+    }
+
+    #[test]
+    fn peers_without_compression_are_rejected() {
+        let plain = Bincode.serialize(&"canary ".repeat(100)).unwrap();
+        let e = compressed(Bincode)
+            .deserialize::<String>(&plain)
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        let bytes = compressed(Bincode).serialize(&"canary").unwrap();
+        assert!(Bincode.deserialize::<String>(&bytes).is_err());
+    }
+
+    #[tokio::test]
+    async fn channels_round_trip() {
+        let (a, b) = Channel::pair();
+        let mut a = a.with_compression(Compression::Zstd { level: 3 });
+        let mut b = b.with_compression(Compression::Zstd { level: 3 });
+        for message in ["small".to_owned(), "canary ".repeat(1000)] {
+            a.send(&message).await.unwrap();
+            assert_eq!(b.receive::<String>().await.unwrap(), message);
+        }
+
+        let (a, b) = Channel::encrypted_pair().await.unwrap();
+        let mut a = a.with_compression(Compression::default());
+        let mut b = b.with_compression(Compression::default());
+        a.send("canary ".repeat(1000)).await.unwrap();
+        assert_eq!(b.receive::<String>().await.unwrap(), "canary ".repeat(1000));
+    }
+}
@@ -1,12 +1,29 @@
+#[cfg(feature = "text_safe")]
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use bincode::Options;
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::sync::Arc;
 
+use super::Framing;
 use crate::err;
 
-#[derive(Serialize_repr, Deserialize_repr, Clone, Copy)]
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[repr(u8)]
-/// formats allowed for channels
+/// formats allowed for channels.
+/// The format is picked at runtime, so it can come from a configuration
+/// file and be changed without recompiling. Both peers must use the same one.
+/// ```no_run
+/// # use canary::{serialization::formats::Format, Channel};
+/// # struct Config {
+/// #     format: String,
+/// # }
+/// # fn run(chan: Channel, config: Config) -> canary::Result<()> {
+/// let format: Format = config.format.parse()?;
+/// let mut chan = chan.with_formats(format, format);
+/// # Ok(())
+/// # }
+/// ```
 pub enum Format {
     /// the Bincode serialization format
     Bincode = 1,
@@ -22,6 +39,15 @@ pub enum Format {
     #[cfg(feature = "messagepack_ser")]
     /// the MessagePack serialization format
     MessagePack = 5,
+    #[cfg(feature = "cbor_ser")]
+    /// the CBOR serialization format
+    Cbor = 6,
+    #[cfg(feature = "ron_ser")]
+    /// the RON serialization format
+    Ron = 7,
+    #[cfg(feature = "flexbuffers_ser")]
+    /// the Flexbuffers serialization format
+    Flexbuffers = 8,
 }
 
 impl Default for Format {
@@ -30,6 +56,111 @@ impl Default for Format {
     }
 }
 
+impl Format {
+    /// formats enabled in this build, fastest and most compact first
+    pub fn supported() -> &'static [Format] {
+        &[
+            Format::Bincode,
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard,
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack,
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor,
+            #[cfg(feature = "ron_ser")]
+            Format::Ron,
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers,
+            #[cfg(feature = "json_ser")]
+            Format::Json,
+            #[cfg(feature = "bson_ser")]
+            Format::Bson,
+        ]
+    }
+    /// name of the format, which `from_str` parses back
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Bincode => "bincode",
+            #[cfg(feature = "json_ser")]
+            Format::Json => "json",
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => "bson",
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => "postcard",
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => "messagepack",
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => "cbor",
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => "ron",
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => "flexbuffers",
+        }
+    }
+    /// deserialize an object that borrows from `bytes`, so fields such as
+    /// `&str` or `&[u8]` don't allocate.
+    /// CBOR can't borrow, and fails with an `Unsupported` error.
+    pub fn deserialize_borrowed<'a, T: Deserialize<'a>>(
+        &self,
+        bytes: &'a [u8],
+    ) -> crate::Result<T> {
+        match self {
+            Format::Bincode => Bincode.deserialize_borrowed(bytes),
+            #[cfg(feature = "json_ser")]
+            Format::Json => Json.deserialize_borrowed(bytes),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.deserialize_borrowed(bytes),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack.deserialize_borrowed(bytes),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.deserialize_borrowed(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize_borrowed(bytes),
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => Ron.deserialize_borrowed(bytes),
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => Flexbuffers.deserialize_borrowed(bytes),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = crate::Error;
+
+    /// parse the name of a format, ignoring case.
+    /// formats whose feature is disabled fail with an `Unsupported` error
+    fn from_str(s: &str) -> crate::Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "bincode" => Format::Bincode,
+            #[cfg(feature = "json_ser")]
+            "json" => Format::Json,
+            #[cfg(feature = "bson_ser")]
+            "bson" => Format::Bson,
+            #[cfg(feature = "postcard_ser")]
+            "postcard" => Format::Postcard,
+            #[cfg(feature = "messagepack_ser")]
+            "messagepack" | "msgpack" => Format::MessagePack,
+            #[cfg(feature = "cbor_ser")]
+            "cbor" => Format::Cbor,
+            #[cfg(feature = "ron_ser")]
+            "ron" => Format::Ron,
+            #[cfg(feature = "flexbuffers_ser")]
+            "flexbuffers" => Format::Flexbuffers,
+            // names of the formats whose feature is disabled
+            #[allow(unreachable_patterns)]
+            "json" | "bson" | "postcard" | "messagepack" | "msgpack" | "cbor" | "ron"
+            | "flexbuffers" => err!((unsupported, format!("the {} format is not enabled", s)))?,
+            _ => err!((invalid_input, format!("unknown format \"{}\"", s)))?,
+        })
+    }
+}
+
 impl SendFormat for Format {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
         match self {
@@ -42,6 +173,31 @@ impl SendFormat for Format {
             Format::MessagePack => MessagePack.serialize(obj),
             #[cfg(feature = "bson_ser")]
             Format::Bson => Bson.serialize(obj),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.serialize(obj),
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => Ron.serialize(obj),
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => Flexbuffers.serialize(obj),
+        }
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        match self {
+            Format::Bincode => Bincode.serialize_into(obj, buf),
+            #[cfg(feature = "json_ser")]
+            Format::Json => Json.serialize_into(obj, buf),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.serialize_into(obj, buf),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack.serialize_into(obj, buf),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.serialize_into(obj, buf),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.serialize_into(obj, buf),
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => Ron.serialize_into(obj, buf),
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => Flexbuffers.serialize_into(obj, buf),
         }
     }
 }
@@ -61,6 +217,62 @@ impl ReadFormat for Format {
             Format::MessagePack => MessagePack.deserialize(bytes),
             #[cfg(feature = "bson_ser")]
             Format::Bson => Bson.deserialize(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize(bytes),
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => Ron.deserialize(bytes),
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => Flexbuffers.deserialize(bytes),
+        }
+    }
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> crate::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Format::Bincode => Bincode.deserialize_limited(bytes, limit),
+            // only reachable with other formats enabled
+            #[allow(unreachable_patterns)]
+            format => {
+                check_limit(bytes, limit)?;
+                format.deserialize(bytes)
+            }
+        }
+    }
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        Format::deserialize_borrowed(self, bytes)
+    }
+    fn is_self_describing(&self) -> bool {
+        match self {
+            Format::Bincode => false,
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => false,
+            // only reachable with other formats enabled
+            #[allow(unreachable_patterns)]
+            _ => true,
+        }
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        match self {
+            Format::Bincode => Bincode.transcode(bytes, to),
+            #[cfg(feature = "json_ser")]
+            Format::Json => Json.transcode(bytes, to),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.transcode(bytes, to),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack.transcode(bytes, to),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.transcode(bytes, to),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.transcode(bytes, to),
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => Ron.transcode(bytes, to),
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => Flexbuffers.transcode(bytes, to),
         }
     }
 }
@@ -77,6 +289,31 @@ impl SendFormat for &mut Format {
             Format::MessagePack => MessagePack.serialize(obj),
             #[cfg(feature = "bson_ser")]
             Format::Bson => Bson.serialize(obj),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.serialize(obj),
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => Ron.serialize(obj),
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => Flexbuffers.serialize(obj),
+        }
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        match self {
+            Format::Bincode => Bincode.serialize_into(obj, buf),
+            #[cfg(feature = "json_ser")]
+            Format::Json => Json.serialize_into(obj, buf),
+            #[cfg(feature = "postcard_ser")]
+            Format::Postcard => Postcard.serialize_into(obj, buf),
+            #[cfg(feature = "messagepack_ser")]
+            Format::MessagePack => MessagePack.serialize_into(obj, buf),
+            #[cfg(feature = "bson_ser")]
+            Format::Bson => Bson.serialize_into(obj, buf),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.serialize_into(obj, buf),
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => Ron.serialize_into(obj, buf),
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => Flexbuffers.serialize_into(obj, buf),
         }
     }
 }
@@ -96,8 +333,33 @@ impl ReadFormat for &mut Format {
             Format::MessagePack => MessagePack.deserialize(bytes),
             #[cfg(feature = "bson_ser")]
             Format::Bson => Bson.deserialize(bytes),
+            #[cfg(feature = "cbor_ser")]
+            Format::Cbor => Cbor.deserialize(bytes),
+            #[cfg(feature = "ron_ser")]
+            Format::Ron => Ron.deserialize(bytes),
+            #[cfg(feature = "flexbuffers_ser")]
+            Format::Flexbuffers => Flexbuffers.deserialize(bytes),
         }
     }
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> crate::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        (**self).deserialize_limited(bytes, limit)
+    }
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        Format::deserialize_borrowed(self, bytes)
+    }
+    fn is_self_describing(&self) -> bool {
+        (**self).is_self_describing()
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        (**self).transcode(bytes, to)
+    }
 }
 
 /// bincode serialization format
@@ -107,126 +369,1517 @@ pub struct Bincode;
 /// JSON serialization format
 pub struct Json;
 #[cfg(feature = "bson_ser")]
-/// Postcard serialization format
+/// BSON serialization format
 pub struct Bson;
 
 #[cfg(feature = "postcard_ser")]
 /// Postcard serialization format
 pub struct Postcard;
 
+#[cfg(feature = "postcard_ser")]
+/// Postcard serialization format with COBS encoding, as used by embedded
+/// peers. Messages contain no zero bytes but the trailing one, so they can
+/// be delimited by it, see `CobsChannel`
+pub struct PostcardCobs;
+
 #[cfg(feature = "messagepack_ser")]
-/// Postcard serialization format
+/// MessagePack serialization format
 pub struct MessagePack;
 
-/// trait that represents the serialize side of a format
-pub trait SendFormat {
-    /// serialize object in this format
-    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>>;
+#[cfg(feature = "cbor_ser")]
+/// CBOR serialization format.
+/// Byte strings are only used for fields marked with `serde_bytes`,
+/// while arrays of any length, definite or not, are accepted when receiving
+pub struct Cbor;
+
+#[cfg(feature = "ron_ser")]
+/// RON serialization format, readable and easy to write by hand.
+/// Errors report the line and column they were found at
+pub struct Ron;
+
+#[cfg(feature = "flexbuffers_ser")]
+/// Flexbuffers serialization format. Messages can be read lazily without
+/// deserializing them, see `Channel::receive_flex`
+pub struct Flexbuffers;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// kind of failure of a format
+pub enum SerializationErrorKind {
+    /// the message ended before the object did
+    Eof,
+    /// the object ended before the message did
+    TrailingBytes,
+    /// the message names an enum variant the object doesn't have
+    UnknownVariant,
+    /// the object is over the size limit of the format
+    SizeLimit,
+    /// the message doesn't describe the object
+    Invalid,
 }
 
-/// trait that represents the deserialize side of a format
-pub trait ReadFormat {
-    /// deserialize object in this format
-    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
-    where
-        T: serde::de::DeserializeOwned;
+#[derive(Debug, Clone)]
+/// Error of a format, carried by the `InvalidData` errors formats return,
+/// so the cause of the failure can be told apart
+/// ```no_run
+/// # use canary::serialization::formats::{SerializationError, SerializationErrorKind};
+/// # use canary::{err, Channel};
+/// # type Request = String;
+/// # async fn handle(_: Request) -> canary::Result<()> {
+/// #     Ok(())
+/// # }
+/// # async fn run(mut chan: Channel) -> canary::Result<()> {
+/// match chan.receive::<Request>().await {
+///     Err(e) if SerializationError::of(&e).is_some_and(|e| e.kind() == SerializationErrorKind::UnknownVariant) => {
+///         chan.send_result::<()>(err!((unsupported, "unknown request"))).await?;
+///     }
+///     req => handle(req?).await?,
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct SerializationError {
+    format: &'static str,
+    kind: SerializationErrorKind,
+    source: Arc<dyn std::error::Error + Send + Sync>,
 }
 
-/// trait that represents a format that can serialize and deserialize
-pub trait CompleteFormat: SendFormat + ReadFormat {}
+impl SerializationError {
+    /// Get the error of the format that caused `e`, if any
+    pub fn of(e: &crate::Error) -> Option<&SerializationError> {
+        e.get_ref()?.downcast_ref()
+    }
+    /// Get the name of the format that failed
+    pub fn format(&self) -> &'static str {
+        self.format
+    }
+    /// Get the kind of failure
+    pub fn kind(&self) -> SerializationErrorKind {
+        self.kind
+    }
+}
 
-impl SendFormat for Bincode {
-    #[inline]
-    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        let obj = bincode::DefaultOptions::new()
-            .allow_trailing_bytes()
-            .serialize(obj)
-            .map_err(err!(@invalid_data))?;
-        Ok(obj.into())
+impl std::fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.source.fmt(f)
     }
 }
-impl ReadFormat for Bincode {
-    #[inline]
-    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
-    where
-        T: serde::de::DeserializeOwned,
-    {
-        bincode::DefaultOptions::new()
-            .allow_trailing_bytes()
-            .deserialize(bytes)
-            .map_err(err!(@invalid_data))
+
+/// the error of the format library is the source, so it can be downcast
+impl std::error::Error for SerializationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+// wrap an error of a format into an `InvalidData` error
+fn serialization_error<E>(
+    format: &'static str,
+    kind: SerializationErrorKind,
+    source: E,
+) -> crate::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let e = SerializationError {
+        format,
+        kind,
+        source: Arc::new(source),
+    };
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e).into()
+}
+
+// kind of the errors formats only describe in their messages, such as the
+// custom errors of serde, last resort of formats without typed errors
+fn kind_of(message: &str) -> SerializationErrorKind {
+    let message = message.to_ascii_lowercase();
+    if message.contains("unknown variant") || message.contains("variant index") {
+        SerializationErrorKind::UnknownVariant
+    } else if message.contains("trailing") {
+        SerializationErrorKind::TrailingBytes
+    } else if message.contains("eof") || message.contains("end of") {
+        SerializationErrorKind::Eof
+    } else if message.contains("limit") {
+        SerializationErrorKind::SizeLimit
+    } else {
+        SerializationErrorKind::Invalid
+    }
+}
+
+#[cfg(any(
+    feature = "bson_ser",
+    feature = "messagepack_ser",
+    feature = "cbor_ser",
+    feature = "ron_ser",
+    feature = "flexbuffers_ser"
+))]
+fn format_error<E>(format: &'static str) -> impl FnOnce(E) -> crate::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    move |e| serialization_error(format, kind_of(&e.to_string()), e)
+}
+
+// kind of the io errors formats read with
+fn io_kind(e: &std::io::Error) -> SerializationErrorKind {
+    match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => SerializationErrorKind::Eof,
+        _ => SerializationErrorKind::Invalid,
     }
 }
 
+// unboxed, so the source downcasts to `bincode::ErrorKind`
+#[allow(clippy::boxed_local)]
+fn bincode_error(e: bincode::Error) -> crate::Error {
+    use bincode::ErrorKind::*;
+    let kind = match &*e {
+        SizeLimit => SerializationErrorKind::SizeLimit,
+        Io(e) => io_kind(e),
+        Custom(message) => kind_of(message),
+        _ => SerializationErrorKind::Invalid,
+    };
+    serialization_error("bincode", kind, *e)
+}
+
 #[cfg(feature = "json_ser")]
-impl SendFormat for Json {
-    #[inline]
+fn json_error(e: serde_json::Error) -> crate::Error {
+    use serde_json::error::Category;
+    let kind = match e.classify() {
+        Category::Eof => SerializationErrorKind::Eof,
+        Category::Io => SerializationErrorKind::Invalid,
+        // unknown variants are custom errors of serde, trailing characters syntax errors
+        Category::Data | Category::Syntax => kind_of(&e.to_string()),
+    };
+    serialization_error("json", kind, e)
+}
+
+#[cfg(feature = "postcard_ser")]
+fn postcard_error(e: postcard::Error) -> crate::Error {
+    use postcard::Error::*;
+    let kind = match e {
+        DeserializeUnexpectedEnd => SerializationErrorKind::Eof,
+        SerializeBufferFull => SerializationErrorKind::SizeLimit,
+        // postcard drops the message of custom errors
+        _ => SerializationErrorKind::Invalid,
+    };
+    serialization_error("postcard", kind, e)
+}
+
+#[cfg(feature = "messagepack_ser")]
+fn messagepack_error(e: rmp_serde::decode::Error) -> crate::Error {
+    use rmp_serde::decode::Error::*;
+    let kind = match &e {
+        InvalidMarkerRead(e) | InvalidDataRead(e) => io_kind(e),
+        DepthLimitExceeded => SerializationErrorKind::SizeLimit,
+        Syntax(message) | Uncategorized(message) => kind_of(message),
+        _ => SerializationErrorKind::Invalid,
+    };
+    serialization_error("messagepack", kind, e)
+}
+
+#[cfg(feature = "checksum")]
+/// format adapter that appends a CRC32 checksum to the messages serialized by
+/// the inner format, and verifies it before deserializing, so corruption on
+/// unreliable links is detected. Encrypted channels don't need it, since
+/// every encrypted packet is already authenticated.
+/// Messages with a checksum mismatch fail with an `InvalidData` error.
+pub struct Checked<F = Format> {
+    /// inner serialization format
+    pub format: F,
+}
+
+#[cfg(feature = "checksum")]
+impl<F> Checked<F> {
+    /// wrap the format so messages carry a checksum
+    pub fn new(format: F) -> Self {
+        Checked { format }
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl<F: SendFormat> SendFormat for Checked<F> {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        serde_json::to_vec(obj).map_err(err!(@invalid_data))
+        let mut bytes = Vec::new();
+        self.serialize_into(obj, &mut bytes)?;
+        Ok(bytes)
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let start = buf.len();
+        self.format.serialize_into(obj, buf)?;
+        let checksum = crc32fast::hash(&buf[start..]);
+        buf.extend_from_slice(&checksum.to_be_bytes());
+        Ok(buf.len() - start)
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn fragment_len(&self) -> Option<usize> {
+        self.format.fragment_len()
     }
 }
 
-#[cfg(feature = "json_ser")]
-impl ReadFormat for Json {
-    #[inline]
+#[cfg(feature = "checksum")]
+impl<F: ReadFormat> ReadFormat for Checked<F> {
     fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
     where
-        T: serde::de::DeserializeOwned,
+        T: DeserializeOwned,
+    {
+        self.format.deserialize(verify_checksum(bytes)?)
+    }
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> crate::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.format
+            .deserialize_limited(verify_checksum(bytes)?, limit)
+    }
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
     {
-        serde_json::from_slice(bytes).map_err(err!(@invalid_data))
+        self.format.deserialize_borrowed(verify_checksum(bytes)?)
+    }
+    fn is_self_describing(&self) -> bool {
+        self.format.is_self_describing()
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        self.format.transcode(verify_checksum(bytes)?, to)
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn max_len(&self) -> Option<usize> {
+        // the checksum follows the message
+        self.format.max_len().map(|len| len.saturating_add(4))
+    }
+    fn is_encrypted(&self) -> bool {
+        self.format.is_encrypted()
     }
 }
 
-#[cfg(feature = "bson_ser")]
-impl SendFormat for Bson {
-    #[inline]
+#[cfg(feature = "checksum")]
+// split the checksum off the message, checking that it matches
+fn verify_checksum(bytes: &[u8]) -> crate::Result<&[u8]> {
+    let len = match bytes.len().checked_sub(4) {
+        Some(len) => len,
+        None => return err!((invalid_data, "message is too short to carry a checksum")),
+    };
+    let (bytes, checksum) = bytes.split_at(len);
+    if crc32fast::hash(bytes).to_be_bytes() != checksum {
+        return err!((invalid_data, "checksum mismatch"));
+    }
+    Ok(bytes)
+}
+
+/// format adapter that rejects messages longer than `MAX` bytes before the
+/// inner format deserializes them. Bincode also stops deserializing once it
+/// has read `MAX` bytes worth of data, so a message claiming a huge
+/// collection fails quickly instead of exhausting memory.
+/// Messages over the limit fail with an `InvalidData` error.
+pub struct Limited<F, const MAX: usize> {
+    /// inner serialization format
+    pub format: F,
+}
+
+/// Bincode limited to messages of 16 MiB
+pub type SafeBincode = Limited<Bincode, { 16 * 1024 * 1024 }>;
+
+impl<F, const MAX: usize> Limited<F, MAX> {
+    /// limit the messages the format deserializes to `MAX` bytes
+    pub fn new(format: F) -> Self {
+        Limited { format }
+    }
+}
+
+impl<F: SendFormat, const MAX: usize> SendFormat for Limited<F, MAX> {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        bson::to_vec(obj).map_err(err!(@invalid_data))
+        self.format.serialize(obj)
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        self.format.serialize_into(obj, buf)
+    }
+    fn is_text(&self) -> bool {
+        self.format.is_text()
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn fragment_len(&self) -> Option<usize> {
+        self.format.fragment_len()
     }
 }
 
-#[cfg(feature = "bson_ser")]
-impl ReadFormat for Bson {
-    #[inline]
+impl<F: ReadFormat, const MAX: usize> ReadFormat for Limited<F, MAX> {
     fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
     where
-        T: serde::de::DeserializeOwned,
+        T: DeserializeOwned,
     {
-        bson::from_slice(bytes).map_err(err!(@invalid_data))
+        check_len(bytes, MAX)?;
+        self.format.deserialize_limited(bytes, MAX as u64)
+    }
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        check_len(bytes, MAX)?;
+        self.format.deserialize_borrowed(bytes)
+    }
+    fn is_self_describing(&self) -> bool {
+        self.format.is_self_describing()
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        check_len(bytes, MAX)?;
+        self.format.transcode(bytes, to)
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn max_len(&self) -> Option<usize> {
+        Some(self.format.max_len().map_or(MAX, |len| len.min(MAX)))
+    }
+    fn is_encrypted(&self) -> bool {
+        self.format.is_encrypted()
     }
 }
-#[cfg(feature = "postcard_ser")]
-impl SendFormat for Postcard {
-    #[inline]
+
+// reject messages longer than `limit` bytes, for formats that can't stop at it
+fn check_limit(bytes: &[u8], limit: u64) -> crate::Result<()> {
+    check_len(bytes, usize::try_from(limit).unwrap_or(usize::MAX))
+}
+
+// reject messages longer than `max` bytes
+fn check_len(bytes: &[u8], max: usize) -> crate::Result<()> {
+    if bytes.len() > max {
+        return err!((
+            invalid_data,
+            format!(
+                "message of {} bytes is over the limit of {}",
+                bytes.len(),
+                max
+            )
+        ));
+    }
+    Ok(())
+}
+
+/// format adapter that sets the length prefix of the messages of the inner
+/// format, see `Channel::with_framing`
+pub struct Framed<F = Format> {
+    /// inner serialization format
+    pub format: F,
+    /// length prefix of the messages
+    pub framing: Framing,
+}
+
+impl<F> Framed<F> {
+    /// frame the messages of the format with `framing`
+    pub fn new(format: F, framing: Framing) -> Self {
+        Framed { format, framing }
+    }
+}
+
+impl<F: SendFormat> SendFormat for Framed<F> {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        postcard::to_allocvec(obj).map_err(err!(@invalid_data))
+        self.format.serialize(obj)
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        self.format.serialize_into(obj, buf)
+    }
+    fn is_text(&self) -> bool {
+        self.format.is_text()
+    }
+    fn framing(&self) -> Framing {
+        self.framing
+    }
+    fn fragment_len(&self) -> Option<usize> {
+        self.format.fragment_len()
     }
 }
-#[cfg(feature = "postcard_ser")]
-impl ReadFormat for Postcard {
-    #[inline]
+
+impl<F: ReadFormat> ReadFormat for Framed<F> {
     fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
     where
-        T: serde::de::DeserializeOwned,
+        T: DeserializeOwned,
     {
-        postcard::from_bytes(bytes).map_err(err!(@invalid_data))
+        self.format.deserialize(bytes)
+    }
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> crate::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.format.deserialize_limited(bytes, limit)
+    }
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        self.format.deserialize_borrowed(bytes)
+    }
+    fn is_self_describing(&self) -> bool {
+        self.format.is_self_describing()
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        self.format.transcode(bytes, to)
+    }
+    fn framing(&self) -> Framing {
+        self.framing
+    }
+    fn max_len(&self) -> Option<usize> {
+        self.format.max_len()
+    }
+    fn is_encrypted(&self) -> bool {
+        self.format.is_encrypted()
     }
 }
 
-#[cfg(feature = "messagepack_ser")]
-impl SendFormat for MessagePack {
-    #[inline]
+/// format adapter that splits websocket messages over `len` bytes into
+/// continuation frames, for proxies that limit the size of single frames.
+/// Peers reassemble the frames into a single message, so they don't need it.
+/// Byte streams are unaffected. See `Channel::with_fragmentation`.
+pub struct Fragmented<F = Format> {
+    /// inner serialization format
+    pub format: F,
+    /// largest frame payload
+    pub len: usize,
+}
+
+impl<F> Fragmented<F> {
+    /// split the messages of the format into frames of at most `len` bytes
+    pub fn new(format: F, len: usize) -> Self {
+        Fragmented { format, len }
+    }
+}
+
+impl<F: SendFormat> SendFormat for Fragmented<F> {
     fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
-        rmp_serde::to_vec(obj).map_err(err!(@invalid_data))
+        self.format.serialize(obj)
+    }
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        self.format.serialize_into(obj, buf)
+    }
+    fn is_text(&self) -> bool {
+        self.format.is_text()
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn fragment_len(&self) -> Option<usize> {
+        Some(self.len)
     }
 }
-#[cfg(feature = "messagepack_ser")]
-impl ReadFormat for MessagePack {
-    #[inline]
+
+impl<F: ReadFormat> ReadFormat for Fragmented<F> {
     fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
     where
-        T: serde::de::DeserializeOwned,
+        T: DeserializeOwned,
+    {
+        self.format.deserialize(bytes)
+    }
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> crate::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.format.deserialize_limited(bytes, limit)
+    }
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
     {
-        rmp_serde::from_slice(bytes).map_err(err!(@invalid_data))
+        self.format.deserialize_borrowed(bytes)
+    }
+    fn is_self_describing(&self) -> bool {
+        self.format.is_self_describing()
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        self.format.transcode(bytes, to)
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn max_len(&self) -> Option<usize> {
+        self.format.max_len()
+    }
+    fn is_encrypted(&self) -> bool {
+        self.format.is_encrypted()
+    }
+}
+
+#[cfg(feature = "text_safe")]
+/// format adapter that base64 encodes the messages serialized by the inner
+/// format, so they can go through transports that only carry text.
+/// Websocket channels send them as text messages, which browser peers can
+/// read as strings.
+/// Messages that aren't valid base64 fail with an `InvalidData` error.
+/// ```no_run
+/// # use canary::serialization::formats::{Json, TextSafe};
+/// # use canary::Channel;
+/// # fn run(chan: Channel) {
+/// let chan = chan.with_formats(TextSafe::new(Json), TextSafe::new(Json));
+/// # }
+/// ```
+pub struct TextSafe<F = Format> {
+    /// inner serialization format
+    pub format: F,
+}
+
+#[cfg(feature = "text_safe")]
+impl<F> TextSafe<F> {
+    /// base64 encode the messages of the format
+    pub fn new(format: F) -> Self {
+        TextSafe { format }
+    }
+}
+
+#[cfg(feature = "text_safe")]
+impl<F: SendFormat> SendFormat for TextSafe<F> {
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        let bytes = self.format.serialize(obj)?;
+        Ok(BASE64.encode(bytes).into_bytes())
+    }
+    fn is_text(&self) -> bool {
+        true
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn fragment_len(&self) -> Option<usize> {
+        self.format.fragment_len()
+    }
+}
+
+#[cfg(feature = "text_safe")]
+impl<F: ReadFormat> ReadFormat for TextSafe<F> {
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let bytes = BASE64.decode(bytes).map_err(err!(@invalid_data))?;
+        self.format.deserialize(&bytes)
+    }
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> crate::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let bytes = BASE64.decode(bytes).map_err(err!(@invalid_data))?;
+        self.format.deserialize_limited(&bytes, limit)
+    }
+    fn is_self_describing(&self) -> bool {
+        self.format.is_self_describing()
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        let bytes = BASE64.decode(bytes).map_err(err!(@invalid_data))?;
+        self.format.transcode(&bytes, to)
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn max_len(&self) -> Option<usize> {
+        // messages are base64 encoded on the wire
+        self.format
+            .max_len()
+            .map(|len| base64::encoded_len(len, true).unwrap_or(usize::MAX))
+    }
+    fn is_encrypted(&self) -> bool {
+        self.format.is_encrypted()
+    }
+}
+
+/// trait that represents the serialize side of a format
+pub trait SendFormat {
+    /// serialize object in this format
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>>;
+    /// serialize object in this format at the end of `buf`,
+    /// returning the number of bytes written
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let bytes = self.serialize(obj)?;
+        buf.extend_from_slice(&bytes);
+        Ok(bytes.len())
+    }
+    /// whether the serialized objects are always valid UTF-8, so transports
+    /// that tell text from binary can send them as text
+    fn is_text(&self) -> bool {
+        false
+    }
+    /// length prefix of the messages sent through byte streams
+    fn framing(&self) -> Framing {
+        Framing::U64
+    }
+    /// largest websocket frame the messages are sent in, larger messages
+    /// are split into continuation frames
+    fn fragment_len(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// trait that represents the deserialize side of a format
+pub trait ReadFormat {
+    /// deserialize object in this format
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned;
+    /// deserialize object in this format, reading at most `limit` bytes
+    /// worth of data. Formats that can't stop reading at the limit, which
+    /// are all of them but bincode, reject messages over `limit` bytes instead
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        check_limit(bytes, limit)?;
+        self.deserialize(bytes)
+    }
+    /// deserialize an object that borrows from `bytes`, so fields such as
+    /// `&str` or `&[u8]` don't allocate. Formats that can't borrow, such as
+    /// the ones that decode the bytes first, fail with an `Unsupported` error
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        let _ = bytes;
+        err!((
+            unsupported,
+            "the format can't deserialize objects borrowed from the message"
+        ))
+    }
+    /// whether messages describe their own structure, so they can be read
+    /// without knowing their type, as `transcode` does
+    fn is_self_describing(&self) -> bool {
+        false
+    }
+    #[cfg(feature = "transcode")]
+    /// re-encode a message into `to` without knowing its type, see `transcode`.
+    /// formats that aren't self-describing fail with an `Unsupported` error
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        let _ = (bytes, to);
+        err!((
+            unsupported,
+            "messages of formats that aren't self-describing can't be read without their type"
+        ))
+    }
+    /// length prefix of the messages received through byte streams
+    fn framing(&self) -> Framing {
+        Framing::U64
+    }
+    /// largest message the format accepts, if it has a limit. Byte streams
+    /// check it against the length prefix, before the message is read
+    fn max_len(&self) -> Option<usize> {
+        None
+    }
+    /// whether messages are decrypted before they are deserialized, in which
+    /// case heartbeats have to be encrypted too
+    fn is_encrypted(&self) -> bool {
+        false
+    }
+}
+
+/// trait that represents a format that can serialize and deserialize
+pub trait CompleteFormat: SendFormat + ReadFormat {}
+
+/// format used to send bytes that are already serialized, the object is ignored.
+/// the bytes are framed and fragmented like the messages of the given format
+pub(crate) struct Preformatted<'a>(pub &'a [u8], pub Framing, pub Option<usize>);
+
+impl<'a> Preformatted<'a> {
+    /// send `bytes` the way `format` sends its messages
+    pub fn new(bytes: &'a [u8], format: &impl SendFormat) -> Self {
+        Preformatted(bytes, format.framing(), format.fragment_len())
+    }
+}
+
+impl SendFormat for Preformatted<'_> {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, _: &O) -> crate::Result<Vec<u8>> {
+        Ok(self.0.to_vec())
+    }
+    fn framing(&self) -> Framing {
+        self.1
+    }
+    fn fragment_len(&self) -> Option<usize> {
+        self.2
+    }
+}
+
+/// format adapter that records the length of the bytes it deserializes
+pub(crate) struct Counted<'a, F> {
+    pub format: &'a mut F,
+    pub len: usize,
+}
+
+impl<F: ReadFormat> ReadFormat for Counted<'_, F> {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.len = bytes.len();
+        self.format.deserialize(bytes)
+    }
+    fn framing(&self) -> Framing {
+        self.format.framing()
+    }
+    fn max_len(&self) -> Option<usize> {
+        self.format.max_len()
+    }
+    fn is_encrypted(&self) -> bool {
+        self.format.is_encrypted()
+    }
+}
+
+/// format used to receive the bytes of a message without deserializing them,
+/// can only deserialize `()`. Messages over the limit are rejected if there is one
+pub(crate) struct Captured(pub Vec<u8>, pub Framing, pub Option<usize>);
+
+impl Captured {
+    /// capture the messages of `format`, rejecting the ones over `max` bytes
+    /// or over the limit of the format, whichever is lower
+    pub fn new(format: &impl ReadFormat, max: Option<usize>) -> Self {
+        let max = match (max, format.max_len()) {
+            (Some(max), Some(len)) => Some(max.min(len)),
+            (max, len) => max.or(len),
+        };
+        Captured(Vec::new(), format.framing(), max)
+    }
+}
+
+impl ReadFormat for Captured {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        if let Some(max) = self.2 {
+            check_len(bytes, max)?;
+        }
+        self.0 = bytes.to_vec();
+        T::deserialize(serde::de::value::UnitDeserializer::<serde::de::value::Error>::new())
+            .map_err(err!(@invalid_data))
+    }
+    fn framing(&self) -> Framing {
+        self.1
+    }
+    fn max_len(&self) -> Option<usize> {
+        self.2
+    }
+}
+
+impl SendFormat for Bincode {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        let obj = bincode::DefaultOptions::new()
+            .allow_trailing_bytes()
+            .serialize(obj)
+            .map_err(bincode_error)?;
+        Ok(obj.into())
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let start = buf.len();
+        bincode::DefaultOptions::new()
+            .allow_trailing_bytes()
+            .serialize_into(&mut *buf, obj)
+            .map_err(bincode_error)?;
+        Ok(buf.len() - start)
+    }
+}
+impl ReadFormat for Bincode {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        bincode::DefaultOptions::new()
+            .allow_trailing_bytes()
+            .deserialize(bytes)
+            .map_err(bincode_error)
+    }
+    #[inline]
+    fn deserialize_limited<T>(&mut self, bytes: &[u8], limit: u64) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        // deserializing from a slice drops the limit, reading it keeps it
+        bincode::DefaultOptions::new()
+            .with_limit(limit)
+            .allow_trailing_bytes()
+            .deserialize_from(bytes)
+            .map_err(bincode_error)
+    }
+    #[inline]
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        bincode::DefaultOptions::new()
+            .allow_trailing_bytes()
+            .deserialize(bytes)
+            .map_err(bincode_error)
+    }
+}
+
+#[cfg(feature = "json_ser")]
+impl SendFormat for Json {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        serde_json::to_vec(obj).map_err(json_error)
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let start = buf.len();
+        serde_json::to_writer(&mut *buf, obj).map_err(json_error)?;
+        Ok(buf.len() - start)
+    }
+}
+
+#[cfg(feature = "json_ser")]
+impl ReadFormat for Json {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        serde_json::from_slice(bytes).map_err(json_error)
+    }
+    #[inline]
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        serde_json::from_slice(bytes).map_err(json_error)
+    }
+    fn is_self_describing(&self) -> bool {
+        true
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        let mut de = serde_json::Deserializer::from_slice(bytes);
+        let bytes = to.serialize(&serde_transcode::Transcoder::new(&mut de))?;
+        de.end().map_err(json_error)?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(feature = "bson_ser")]
+impl SendFormat for Bson {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        bson::to_vec(obj).map_err(format_error("bson"))
+    }
+}
+
+#[cfg(feature = "bson_ser")]
+impl ReadFormat for Bson {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        bson::from_slice(bytes).map_err(format_error("bson"))
+    }
+    #[inline]
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        bson::from_slice(bytes).map_err(format_error("bson"))
+    }
+    fn is_self_describing(&self) -> bool {
+        true
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        // documents are read whole, keeping the order of their keys
+        let doc = bson::Document::from_reader(bytes).map_err(format_error("bson"))?;
+        to.serialize(&serde_transcode::Transcoder::new(bson::Deserializer::new(
+            doc.into(),
+        )))
+    }
+}
+#[cfg(feature = "postcard_ser")]
+impl SendFormat for Postcard {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        postcard::to_allocvec(obj).map_err(postcard_error)
+    }
+}
+#[cfg(feature = "postcard_ser")]
+impl ReadFormat for Postcard {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        postcard::from_bytes(bytes).map_err(postcard_error)
+    }
+    #[inline]
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        postcard::from_bytes(bytes).map_err(postcard_error)
+    }
+}
+
+#[cfg(feature = "postcard_ser")]
+impl SendFormat for PostcardCobs {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        postcard::to_allocvec_cobs(obj).map_err(postcard_error)
+    }
+}
+#[cfg(feature = "postcard_ser")]
+impl ReadFormat for PostcardCobs {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        // cobs is decoded in place
+        postcard::from_bytes_cobs(&mut bytes.to_vec()).map_err(postcard_error)
+    }
+}
+
+#[cfg(feature = "messagepack_ser")]
+impl SendFormat for MessagePack {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        rmp_serde::to_vec(obj).map_err(format_error("messagepack"))
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let start = buf.len();
+        rmp_serde::encode::write(buf, obj).map_err(format_error("messagepack"))?;
+        Ok(buf.len() - start)
+    }
+}
+#[cfg(feature = "messagepack_ser")]
+impl ReadFormat for MessagePack {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        rmp_serde::from_slice(bytes).map_err(messagepack_error)
+    }
+    #[inline]
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        rmp_serde::from_slice(bytes).map_err(messagepack_error)
+    }
+    fn is_self_describing(&self) -> bool {
+        true
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        let mut de = rmp_serde::Deserializer::new(bytes);
+        to.serialize(&serde_transcode::Transcoder::new(&mut de))
+    }
+}
+
+#[cfg(feature = "cbor_ser")]
+impl SendFormat for Cbor {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(obj, &mut bytes).map_err(format_error("cbor"))?;
+        Ok(bytes)
+    }
+    #[inline]
+    fn serialize_into<O: Serialize>(&mut self, obj: &O, buf: &mut Vec<u8>) -> crate::Result<usize> {
+        let start = buf.len();
+        ciborium::ser::into_writer(obj, &mut *buf).map_err(format_error("cbor"))?;
+        Ok(buf.len() - start)
+    }
+}
+#[cfg(feature = "cbor_ser")]
+impl ReadFormat for Cbor {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        ciborium::de::from_reader(bytes).map_err(format_error("cbor"))
+    }
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        let _ = bytes;
+        err!((
+            unsupported,
+            "the CBOR format can't deserialize borrowed objects"
+        ))
+    }
+    fn is_self_describing(&self) -> bool {
+        true
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        // ciborium doesn't expose its deserializer, its values keep the
+        // order of map keys and serialize like the message was
+        let value: ciborium::value::Value =
+            ciborium::de::from_reader(bytes).map_err(format_error("cbor"))?;
+        to.serialize(&value)
+    }
+}
+
+#[cfg(feature = "ron_ser")]
+impl SendFormat for Ron {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        let string = ron::to_string(obj).map_err(format_error("ron"))?;
+        Ok(string.into_bytes())
+    }
+}
+#[cfg(feature = "ron_ser")]
+impl ReadFormat for Ron {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        ron::de::from_bytes(bytes).map_err(format_error("ron"))
+    }
+    #[inline]
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        ron::de::from_bytes(bytes).map_err(format_error("ron"))
+    }
+    fn is_self_describing(&self) -> bool {
+        true
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        let mut de = ron::Deserializer::from_bytes(bytes).map_err(format_error("ron"))?;
+        let bytes = to.serialize(&serde_transcode::Transcoder::new(&mut de))?;
+        de.end().map_err(format_error("ron"))?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(feature = "flexbuffers_ser")]
+impl SendFormat for Flexbuffers {
+    #[inline]
+    fn serialize<O: Serialize>(&mut self, obj: &O) -> crate::Result<Vec<u8>> {
+        flexbuffers::to_vec(obj).map_err(format_error("flexbuffers"))
+    }
+}
+#[cfg(feature = "flexbuffers_ser")]
+impl ReadFormat for Flexbuffers {
+    #[inline]
+    fn deserialize<T>(&mut self, bytes: &[u8]) -> crate::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        flexbuffers::from_slice(bytes).map_err(format_error("flexbuffers"))
+    }
+    #[inline]
+    fn deserialize_borrowed<'a, T>(&mut self, bytes: &'a [u8]) -> crate::Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        flexbuffers::from_slice(bytes).map_err(format_error("flexbuffers"))
+    }
+    fn is_self_describing(&self) -> bool {
+        true
+    }
+    #[cfg(feature = "transcode")]
+    fn transcode<W: SendFormat>(&mut self, bytes: &[u8], to: &mut W) -> crate::Result<Vec<u8>> {
+        let reader = flexbuffers::Reader::get_root(bytes).map_err(format_error("flexbuffers"))?;
+        to.serialize(&serde_transcode::Transcoder::new(reader))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Request {
+        Ping,
+        Echo(String),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Newer {
+        Ping,
+        Echo(String),
+        Stop,
+    }
+
+    // kind of the error of deserializing `bytes` as `T`
+    fn kind<T: DeserializeOwned + std::fmt::Debug, F: ReadFormat>(
+        format: &mut F,
+        bytes: &[u8],
+    ) -> (SerializationErrorKind, &'static str) {
+        let e = format.deserialize::<T>(bytes).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        let e = SerializationError::of(&e).unwrap();
+        (e.kind(), e.format())
+    }
+
+    #[test]
+    fn bincode_errors_are_classified() {
+        use SerializationErrorKind::*;
+        let stop = Bincode.serialize(&Newer::Stop).unwrap();
+        assert_eq!(
+            kind::<Request, _>(&mut Bincode, &stop),
+            (UnknownVariant, "bincode")
+        );
+        let echo = Bincode.serialize(&Request::Echo("hello".into())).unwrap();
+        let truncated = &echo[..echo.len() - 1];
+        assert_eq!(
+            kind::<Request, _>(&mut Bincode, truncated),
+            (Eof, "bincode")
+        );
+        assert_eq!(kind::<bool, _>(&mut Bincode, &[2]).0, Invalid);
+
+        // the error of bincode is the source
+        let e = Bincode.deserialize::<Request>(truncated).unwrap_err();
+        let source = std::error::Error::source(SerializationError::of(&e).unwrap()).unwrap();
+        assert!(source.downcast_ref::<bincode::ErrorKind>().is_some());
+    }
+
+    #[cfg(feature = "json_ser")]
+    #[test]
+    fn json_errors_are_classified() {
+        use SerializationErrorKind::*;
+        assert_eq!(
+            kind::<Request, _>(&mut Json, br#""Stop""#),
+            (UnknownVariant, "json")
+        );
+        assert_eq!(
+            kind::<Request, _>(&mut Json, br#"{"Echo":"hel"#),
+            (Eof, "json")
+        );
+        assert_eq!(
+            kind::<Request, _>(&mut Json, br#""Ping" 1"#),
+            (TrailingBytes, "json")
+        );
+        assert_eq!(kind::<Request, _>(&mut Json, b"[]").0, Invalid);
+
+        let e = Json.deserialize::<Request>(b"[]").unwrap_err();
+        let source = std::error::Error::source(SerializationError::of(&e).unwrap()).unwrap();
+        assert!(source.downcast_ref::<serde_json::Error>().is_some());
+    }
+
+    #[cfg(feature = "postcard_ser")]
+    #[test]
+    fn postcard_errors_are_classified() {
+        use SerializationErrorKind::*;
+        let echo = Postcard.serialize(&Request::Echo("hello".into())).unwrap();
+        let truncated = &echo[..echo.len() - 1];
+        assert_eq!(
+            kind::<Request, _>(&mut Postcard, truncated),
+            (Eof, "postcard")
+        );
+        assert_eq!(kind::<bool, _>(&mut Postcard, &[2]), (Invalid, "postcard"));
+    }
+
+    #[cfg(feature = "messagepack_ser")]
+    #[test]
+    fn messagepack_errors_are_classified() {
+        use SerializationErrorKind::*;
+        let stop = MessagePack.serialize(&Newer::Stop).unwrap();
+        let (kind_of_stop, format) = kind::<Request, _>(&mut MessagePack, &stop);
+        assert_eq!((kind_of_stop, format), (UnknownVariant, "messagepack"));
+        let echo = MessagePack
+            .serialize(&Request::Echo("hello".into()))
+            .unwrap();
+        let truncated = &echo[..echo.len() - 1];
+        assert_eq!(kind::<Request, _>(&mut MessagePack, truncated).0, Eof);
+        assert_eq!(kind::<bool, _>(&mut MessagePack, &[0xc1]).0, Invalid);
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn checksums_catch_corruption() {
+        let mut format = Checked::new(Bincode);
+        let bytes = format.serialize(&Request::Echo("hello".into())).unwrap();
+        assert!(matches!(
+            format.deserialize::<Request>(&bytes).unwrap(),
+            Request::Echo(echo) if echo == "hello"
+        ));
+        for bit in 0..bytes.len() * 8 {
+            let mut flipped = bytes.clone();
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            let e = format.deserialize::<Request>(&flipped).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+            assert_eq!(e.to_string(), "checksum mismatch");
+        }
+        let e = format
+            .deserialize::<Request>(&bytes[..bytes.len() - 1])
+            .unwrap_err();
+        assert_eq!(e.to_string(), "checksum mismatch");
+        let e = format.deserialize::<Request>(&bytes[..3]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "message is too short to carry a checksum");
+    }
+
+    #[test]
+    fn limits_stop_bincode_early() {
+        let long = Bincode.serialize(&vec![1u64; 100]).unwrap();
+        let e = Bincode
+            .deserialize_limited::<Vec<u64>>(&long, 16)
+            .unwrap_err();
+        assert_eq!(
+            SerializationError::of(&e).unwrap().kind(),
+            SerializationErrorKind::SizeLimit
+        );
+        let short: Vec<u64> = Bincode.deserialize_limited(&long, 1024).unwrap();
+        assert_eq!(short, vec![1u64; 100]);
+
+        // a few bytes claiming a vector of 8 GiB
+        let mut huge = vec![253];
+        huge.extend_from_slice(&(1u64 << 30).to_le_bytes());
+        huge.extend_from_slice(&[1; 32]);
+        let mut format = Limited::<Bincode, 64>::new(Bincode);
+        let e = format.deserialize::<Vec<u64>>(&huge).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        let e = format.deserialize::<Vec<u64>>(&long).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "message of 101 bytes is over the limit of 64"
+        );
+    }
+
+    #[test]
+    fn formats_parse_their_names() {
+        #[allow(unused_mut)]
+        let mut formats = vec![Format::Bincode];
+        #[cfg(feature = "json_ser")]
+        formats.push(Format::Json);
+        #[cfg(feature = "bson_ser")]
+        formats.push(Format::Bson);
+        #[cfg(feature = "postcard_ser")]
+        formats.push(Format::Postcard);
+        #[cfg(feature = "messagepack_ser")]
+        formats.push(Format::MessagePack);
+        #[cfg(feature = "cbor_ser")]
+        formats.push(Format::Cbor);
+        #[cfg(feature = "ron_ser")]
+        formats.push(Format::Ron);
+        #[cfg(feature = "flexbuffers_ser")]
+        formats.push(Format::Flexbuffers);
+        for format in formats {
+            assert_eq!(format.to_string().parse::<Format>().unwrap(), format);
+            let upper = format.to_string().to_ascii_uppercase();
+            assert_eq!(upper.parse::<Format>().unwrap(), format);
+        }
+        #[cfg(feature = "messagepack_ser")]
+        assert_eq!("msgpack".parse::<Format>().unwrap(), Format::MessagePack);
+        #[cfg(not(feature = "json_ser"))]
+        assert_eq!(
+            "json".parse::<Format>().unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+        let e = "bincod".parse::<Format>().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "unknown format \"bincod\"");
+    }
+
+    #[cfg(feature = "json_ser")]
+    #[tokio::test]
+    async fn mismatched_formats_fail_to_receive() {
+        let (a, b) = crate::Channel::pair();
+        let mut a = a.with_formats(Format::Json, Format::Json);
+        let mut b = b.with_formats(Format::Bincode, Format::Bincode);
+        a.send(Request::Echo("hello".into())).await.unwrap();
+        let e = b.receive::<Request>().await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(SerializationError::of(&e).unwrap().format(), "bincode");
+    }
+
+    #[cfg(feature = "cbor_ser")]
+    #[test]
+    fn cbor_enums_keep_their_tags() {
+        use ciborium::value::Value;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(tag = "type")]
+        enum Internal {
+            Ping,
+            Echo { text: String },
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(tag = "type", content = "value")]
+        enum Adjacent {
+            Ping,
+            Echo(String),
+        }
+
+        // externally tagged variants are maps from their name to their content
+        let echo = Cbor.serialize(&Request::Echo("a".into())).unwrap();
+        assert_eq!(echo, [0xa1, 0x64, b'E', b'c', b'h', b'o', 0x61, b'a']);
+        for request in [Request::Ping, Request::Echo("hello".into())] {
+            let bytes = Cbor.serialize(&request).unwrap();
+            assert_eq!(Cbor.deserialize::<Request>(&bytes).unwrap(), request);
+        }
+        for request in [Internal::Ping, Internal::Echo { text: "hi".into() }] {
+            let bytes = Cbor.serialize(&request).unwrap();
+            assert_eq!(Cbor.deserialize::<Internal>(&bytes).unwrap(), request);
+        }
+        for request in [Adjacent::Ping, Adjacent::Echo("hi".into())] {
+            let bytes = Cbor.serialize(&request).unwrap();
+            assert_eq!(Cbor.deserialize::<Adjacent>(&bytes).unwrap(), request);
+        }
+
+        // semantic tags of cbor, here an epoch timestamp
+        let tagged = Value::Tag(1, Box::new(Value::Integer(1.into())));
+        let bytes = Cbor.serialize(&tagged).unwrap();
+        assert_eq!(bytes, [0xc1, 0x01]);
+        assert_eq!(Cbor.deserialize::<Value>(&bytes).unwrap(), tagged);
+    }
+
+    #[cfg(feature = "cbor_ser")]
+    #[test]
+    fn cbor_byte_strings_round_trip() {
+        use ciborium::value::Value;
+
+        let bytes = Cbor.serialize(&Value::Bytes(vec![1, 2, 3])).unwrap();
+        assert_eq!(bytes, [0x43, 1, 2, 3]);
+        assert_eq!(Cbor.deserialize::<Vec<u8>>(&bytes).unwrap(), [1, 2, 3]);
+        // a byte string sent in chunks of indefinite length
+        let chunked = [0x5f, 0x42, 1, 2, 0x41, 3, 0xff];
+        assert_eq!(
+            Cbor.deserialize::<Value>(&chunked).unwrap(),
+            Value::Bytes(vec![1, 2, 3])
+        );
+    }
+
+    #[cfg(feature = "cbor_ser")]
+    #[test]
+    fn cbor_reads_indefinite_length_arrays() {
+        let array = [0x9f, 1, 2, 3, 0xff];
+        assert_eq!(Cbor.deserialize::<Vec<u32>>(&array).unwrap(), [1, 2, 3]);
+        // nested in a map of indefinite length
+        let map = [0xbf, 0x61, b'a', 0x9f, 1, 0xff, 0xff];
+        let map: std::collections::HashMap<String, Vec<u32>> = Cbor.deserialize(&map).unwrap();
+        assert_eq!(map["a"], [1]);
+        // the break byte is missing
+        let e = Cbor.deserialize::<Vec<u32>>(&array[..4]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "ron_ser")]
+    #[test]
+    fn ron_struct_variants_round_trip() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Command {
+            Move { x: i32, y: i32 },
+            Rename { from: String, to: Option<String> },
+        }
+
+        let command = Command::Move { x: 1, y: -2 };
+        let bytes = Ron.serialize(&command).unwrap();
+        assert_eq!(bytes, b"Move(x:1,y:-2)");
+        assert_eq!(Ron.deserialize::<Command>(&bytes).unwrap(), command);
+        let commands = vec![
+            command,
+            Command::Rename {
+                from: "a".into(),
+                to: None,
+            },
+        ];
+        let bytes = Ron.serialize(&commands).unwrap();
+        assert_eq!(Ron.deserialize::<Vec<Command>>(&bytes).unwrap(), commands);
+        // written by hand, with whitespace and the fields out of order
+        let command: Command = Ron
+            .deserialize(b"Rename(\n    to: Some(\"b\"),\n    from: \"a\",\n)")
+            .unwrap();
+        assert_eq!(
+            command,
+            Command::Rename {
+                from: "a".into(),
+                to: Some("b".into())
+            }
+        );
+    }
+
+    // stands in for a format that decrypts its messages
+    struct Sealed;
+
+    impl ReadFormat for Sealed {
+        fn deserialize<T: DeserializeOwned>(&mut self, bytes: &[u8]) -> crate::Result<T> {
+            Bincode.deserialize(bytes)
+        }
+        fn max_len(&self) -> Option<usize> {
+            Some(6)
+        }
+        fn is_encrypted(&self) -> bool {
+            true
+        }
+    }
+
+    // limit and encryption of a format seen through an adapter
+    fn seen<F: ReadFormat>(format: F) -> (Option<usize>, bool) {
+        (format.max_len(), format.is_encrypted())
+    }
+
+    #[test]
+    fn adapters_forward_limits_and_encryption() {
+        assert_eq!(seen(Framed::new(Sealed, Framing::VarInt)), (Some(6), true));
+        assert_eq!(seen(Fragmented::new(Sealed, 4)), (Some(6), true));
+        assert_eq!(seen(Limited::<_, 4>::new(Sealed)), (Some(4), true));
+        // the limits of the inner format hold once the adapter undid its encoding
+        #[cfg(feature = "checksum")]
+        assert_eq!(seen(Checked::new(Sealed)), (Some(10), true));
+        #[cfg(feature = "text_safe")]
+        assert_eq!(seen(TextSafe::new(Sealed)), (Some(8), true));
+        assert_eq!(seen(Framed::new(Bincode, Framing::U64)), (None, false));
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Message {
+            id: u32,
+            name: String,
+            tags: Vec<String>,
+            request: Request,
+            reply: Option<u32>,
+        }
+
+        let message = Message {
+            id: 7,
+            name: "hello!".into(),
+            tags: vec!["a".into(), "b".into()],
+            request: Request::Echo("world!".into()),
+            reply: None,
+        };
+        for &(mut format) in Format::supported() {
+            let bytes = SendFormat::serialize(&mut format, &message).unwrap();
+            assert_eq!(
+                format.deserialize::<Message>(&bytes).unwrap(),
+                message,
+                "{}",
+                format.as_str()
+            );
+            let limit = bytes.len() as u64;
+            assert_eq!(
+                format
+                    .deserialize_limited::<Message>(&bytes, limit)
+                    .unwrap(),
+                message,
+                "{}",
+                format.as_str()
+            );
+            let e = format
+                .deserialize_limited::<Message>(&bytes, limit - 1)
+                .unwrap_err();
+            assert_eq!(
+                e.kind(),
+                std::io::ErrorKind::InvalidData,
+                "{}",
+                format.as_str()
+            );
+        }
     }
 }
@@ -1,10 +1,18 @@
 mod comms;
+#[cfg(feature = "compression")]
+/// contains the compression layer of channels
+pub mod compression;
 /// contains serialization formats
 pub mod formats;
-/// contains zero-cost stream operations and more
+#[cfg(feature = "transcode")]
+mod transcode;
+/// contains zero-cost stream operations, such as the primitives used for
+/// framing, which custom protocols can build on
 /// ```no_run
-/// zc::send_u64(&mut stream, 42).await?;
+/// zc::send_u64_be(&mut stream, 42).await?;
 /// ```
 pub mod zc;
 
 pub use comms::*;
+#[cfg(feature = "transcode")]
+pub use transcode::transcode;
@@ -0,0 +1,57 @@
+use super::formats::{ReadFormat, SendFormat};
+use crate::Result;
+
+/// Re-encode a message serialized with `from` into `to`, without knowing
+/// its type. The message streams through the serde data model, so `from`
+/// must be self-describing, such as JSON, BSON, MessagePack or CBOR.
+/// Bincode and Postcard messages can't be read without their type, and fail
+/// with an `Unsupported` error instead of producing garbage.
+///
+/// The message keeps the shape it had in `from`: JSON objects become maps
+/// with their keys in order, and enum variants stay strings. Receivers of
+/// formats that aren't self-describing need types that match that shape,
+/// which structs and enums don't.
+/// ```no_run
+/// # use canary::serialization::{formats::{Json, MessagePack}, transcode};
+/// # fn run() -> canary::Result<()> {
+/// let bytes = transcode(&mut Json, &mut MessagePack, br#"{"id": 1}"#)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn transcode<R: ReadFormat, W: SendFormat>(
+    from: &mut R,
+    to: &mut W,
+    bytes: &[u8],
+) -> Result<Vec<u8>> {
+    from.transcode(bytes, to)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::transcode;
+    use crate::serialization::formats::{Bincode, ReadFormat, SendFormat};
+
+    #[test]
+    fn bincode_is_unsupported() {
+        let bytes = Bincode.serialize(&(1u32, "id")).unwrap();
+        let e = transcode(&mut Bincode, &mut Bincode, &bytes).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+    }
+
+    #[cfg(all(feature = "json_ser", feature = "messagepack_ser"))]
+    #[test]
+    fn json_becomes_messagepack() {
+        use crate::serialization::formats::{Json, MessagePack};
+        use std::collections::BTreeMap;
+
+        let bytes = transcode(&mut Json, &mut MessagePack, br#"{"id": 1, "name": "a"}"#).unwrap();
+        let map: BTreeMap<String, serde_json::Value> = MessagePack.deserialize(&bytes).unwrap();
+        assert_eq!(map["id"], 1);
+        assert_eq!(map["name"], "a");
+
+        let e = transcode(&mut Json, &mut MessagePack, br#"{"id": 1} 2"#).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,4 +1,3 @@
-#![allow(unused)]
 //! complete zero cost wrappers over network communications
 
 use crate::io::{Read, ReadExt, Write, WriteExt};
@@ -7,64 +6,259 @@ use crate::{err, Result};
 #[inline]
 pub(crate) fn try_vec<T: Default + Clone>(size: usize) -> Result<Vec<T>> {
     let mut buf = Vec::new();
-    buf.try_reserve(size as usize).map_err(|e| {
+    try_resize(&mut buf, size)?;
+    Ok(buf)
+}
+
+#[inline]
+/// clear `buf` and resize it to `size`, failing instead of aborting if
+/// it can't grow. buffers with enough capacity are reused as they are.
+pub(crate) fn try_resize<T: Default + Clone>(buf: &mut Vec<T>, size: usize) -> Result<()> {
+    buf.clear();
+    buf.try_reserve(size).map_err(|e| {
         err!(
             out_of_memory,
             format!("failed to reserve {} bytes, error: {:?}", size, e)
         )
     })?;
-    buf.resize(size as usize, T::default());
+    buf.resize(size, T::default());
+    Ok(())
+}
+
+#[inline]
+/// read exactly `len` bytes from the stream into a new buffer.
+/// lengths over `max` are rejected before allocating, so a length read
+/// from the peer can't make the program allocate unbounded memory.
+/// ```no_run
+/// # use canary::serialization::zc;
+/// # async fn run(mut stream: tokio::net::TcpStream) -> canary::Result<()> {
+/// let len = zc::read_u32_be(&mut stream).await?;
+/// let bytes = zc::read_exact_alloc(&mut stream, len as usize, 1024 * 1024).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn read_exact_alloc<T: Read + Unpin>(
+    st: &mut T,
+    len: usize,
+    max: usize,
+) -> Result<Vec<u8>> {
+    if len > max {
+        return err!((
+            invalid_data,
+            format!("length of {} bytes is over the limit of {}", len, max)
+        ));
+    }
+    let mut buf = try_vec(len)?;
+    st.read_exact(&mut buf).await?;
     Ok(buf)
 }
 
 #[inline]
-pub(crate) async fn send_u8<T: Write + Unpin>(st: &mut T, obj: u8) -> Result<()> {
+/// send a big-endian `u8` through the stream
+pub async fn send_u8<T: Write + Unpin>(st: &mut T, obj: u8) -> Result<()> {
     st.write_all(&u8::to_be_bytes(obj)).await?;
     Ok(())
 }
 
 #[inline]
-pub(crate) async fn send_u16<T: Write + Unpin>(st: &mut T, obj: u16) -> Result<()> {
-    st.write_all(&u16::to_be_bytes(obj)).await?;
-    Ok(())
+/// read a big-endian `u8` from the stream
+pub async fn read_u8<T: Read + Unpin>(st: &mut T) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    st.read_exact(&mut buf).await?;
+    Ok(u8::from_be_bytes(buf))
 }
 
-#[inline]
-pub(crate) async fn send_u32<T: Write + Unpin>(st: &mut T, obj: u32) -> Result<()> {
-    st.write_all(&u32::to_be_bytes(obj)).await?;
-    Ok(())
+macro_rules! primitive {
+    ($ty: ty, $send: ident, $read: ident, $to: ident, $from: ident, $endian: literal) => {
+        #[inline]
+        #[doc = concat!("send a ", $endian, " `", stringify!($ty), "` through the stream")]
+        pub async fn $send<T: Write + Unpin>(st: &mut T, obj: $ty) -> Result<()> {
+            st.write_all(&<$ty>::$to(obj)).await?;
+            Ok(())
+        }
+
+        #[inline]
+        #[doc = concat!("read a ", $endian, " `", stringify!($ty), "` from the stream")]
+        pub async fn $read<T: Read + Unpin>(st: &mut T) -> Result<$ty> {
+            let mut buf = [0u8; std::mem::size_of::<$ty>()];
+            st.read_exact(&mut buf).await?;
+            Ok(<$ty>::$from(buf))
+        }
+    };
+    ($ty: ty, $send_be: ident, $read_be: ident, $send_le: ident, $read_le: ident) => {
+        primitive!(
+            $ty,
+            $send_be,
+            $read_be,
+            to_be_bytes,
+            from_be_bytes,
+            "big-endian"
+        );
+        primitive!(
+            $ty,
+            $send_le,
+            $read_le,
+            to_le_bytes,
+            from_le_bytes,
+            "little-endian"
+        );
+    };
 }
 
+// single bytes have no endianness
+primitive!(
+    i8,
+    send_i8,
+    read_i8,
+    to_be_bytes,
+    from_be_bytes,
+    "single byte"
+);
+
+primitive!(u16, send_u16_be, read_u16_be, send_u16_le, read_u16_le);
+primitive!(u32, send_u32_be, read_u32_be, send_u32_le, read_u32_le);
+primitive!(u64, send_u64_be, read_u64_be, send_u64_le, read_u64_le);
+primitive!(i16, send_i16_be, read_i16_be, send_i16_le, read_i16_le);
+primitive!(i32, send_i32_be, read_i32_be, send_i32_le, read_i32_le);
+primitive!(i64, send_i64_be, read_i64_be, send_i64_le, read_i64_le);
+primitive!(f32, send_f32_be, read_f32_be, send_f32_le, read_f32_le);
+primitive!(f64, send_f64_be, read_f64_be, send_f64_le, read_f64_le);
+
+/// largest encoding of a u64 as a LEB128 varint
+pub const MAX_VARINT_LEN: usize = 10;
+
 #[inline]
-pub(crate) async fn send_u64<T: Write + Unpin>(st: &mut T, obj: u64) -> Result<()> {
-    st.write_all(&u64::to_be_bytes(obj)).await?;
-    Ok(())
+/// encode `obj` as a LEB128 varint at the start of `buf`, returning its length
+pub fn encode_varint(mut obj: u64, buf: &mut [u8; MAX_VARINT_LEN]) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = (obj & 0x7f) as u8;
+        obj >>= 7;
+        if obj == 0 {
+            buf[len] = byte;
+            return len + 1;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
 }
 
 #[inline]
-pub(crate) async fn read_u8<T: Read + Unpin>(st: &mut T) -> Result<u8> {
-    let mut buf = [0u8; 1];
-    st.read_exact(&mut buf).await?;
-    Ok(u8::from_be_bytes(buf))
+/// send `obj` through the stream as a LEB128 varint
+pub async fn send_varint<T: Write + Unpin>(st: &mut T, obj: u64) -> Result<()> {
+    let mut buf = [0u8; MAX_VARINT_LEN];
+    let len = encode_varint(obj, &mut buf);
+    st.write_all(&buf[..len]).await?;
+    Ok(())
 }
 
 #[inline]
-pub(crate) async fn read_u16<T: Read + Unpin>(st: &mut T) -> Result<u16> {
-    let mut buf = [0u8; 2];
-    st.read_exact(&mut buf).await?;
-    Ok(u16::from_be_bytes(buf))
+/// read a LEB128 varint from the stream, failing if it overflows a u64
+pub async fn read_varint<T: Read + Unpin>(st: &mut T) -> Result<u64> {
+    let mut buf = [0u8; MAX_VARINT_LEN];
+    for i in 0..MAX_VARINT_LEN {
+        buf[i] = read_u8(st).await?;
+        if buf[i] & 0x80 == 0 {
+            return decode_varint(&buf[..=i]);
+        }
+    }
+    err!((invalid_data, "varint overflows a u64"))
 }
 
 #[inline]
-pub(crate) async fn read_u32<T: Read + Unpin>(st: &mut T) -> Result<u32> {
-    let mut buf = [0u8; 4];
-    st.read_exact(&mut buf).await?;
-    Ok(u32::from_be_bytes(buf))
+/// decode the LEB128 varint in `bytes`, which ends with its last byte
+pub fn decode_varint(bytes: &[u8]) -> Result<u64> {
+    let mut obj = 0u64;
+    for (i, byte) in bytes.iter().enumerate() {
+        // the last byte only has room for the highest bit of a u64
+        if i >= MAX_VARINT_LEN || (i == MAX_VARINT_LEN - 1 && *byte > 1) {
+            return err!((invalid_data, "varint overflows a u64"));
+        }
+        obj |= ((byte & 0x7f) as u64) << (7 * i);
+    }
+    Ok(obj)
 }
 
-#[inline]
-pub(crate) async fn read_u64<T: Read + Unpin>(st: &mut T) -> Result<u64> {
-    let mut buf = [0u8; 8];
-    st.read_exact(&mut buf).await?;
-    Ok(u64::from_be_bytes(buf))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // send `value` through a duplex stream, checking the bytes and reading it back
+    macro_rules! round_trip {
+        ($send: ident, $read: ident, $value: expr, $bytes: expr) => {{
+            let (mut a, mut b) = tokio::io::duplex(64);
+            $send(&mut a, $value).await.unwrap();
+            let mut bytes = vec![0u8; $bytes.len()];
+            b.read_exact(&mut bytes).await.unwrap();
+            assert_eq!(bytes, $bytes, stringify!($send));
+            a.write_all(&bytes).await.unwrap();
+            assert_eq!($read(&mut b).await.unwrap(), $value, stringify!($read));
+        }};
+    }
+
+    #[tokio::test]
+    async fn primitives_round_trip() {
+        round_trip!(send_u8, read_u8, 0xab, [0xab]);
+        round_trip!(send_i8, read_i8, -2, [0xfe]);
+        round_trip!(send_u16_be, read_u16_be, 0x0102, [1, 2]);
+        round_trip!(send_u16_le, read_u16_le, 0x0102, [2, 1]);
+        round_trip!(send_u32_be, read_u32_be, 0x01020304, [1, 2, 3, 4]);
+        round_trip!(send_u32_le, read_u32_le, 0x01020304, [4, 3, 2, 1]);
+        let u64_be = [1, 2, 3, 4, 5, 6, 7, 8];
+        let u64_le = [8, 7, 6, 5, 4, 3, 2, 1];
+        round_trip!(send_u64_be, read_u64_be, 0x0102030405060708, u64_be);
+        round_trip!(send_u64_le, read_u64_le, 0x0102030405060708, u64_le);
+        round_trip!(send_i16_be, read_i16_be, -2, [0xff, 0xfe]);
+        round_trip!(send_i16_le, read_i16_le, -2, [0xfe, 0xff]);
+        round_trip!(send_i32_be, read_i32_be, -2, [0xff, 0xff, 0xff, 0xfe]);
+        round_trip!(send_i32_le, read_i32_le, -2, [0xfe, 0xff, 0xff, 0xff]);
+        let mut i64_be = [0xff; 8];
+        i64_be[7] = 0xfe;
+        let mut i64_le = [0xff; 8];
+        i64_le[0] = 0xfe;
+        round_trip!(send_i64_be, read_i64_be, -2, i64_be);
+        round_trip!(send_i64_le, read_i64_le, -2, i64_le);
+        round_trip!(send_f32_be, read_f32_be, 1.5, 1.5f32.to_be_bytes());
+        round_trip!(send_f32_le, read_f32_le, 1.5, 1.5f32.to_le_bytes());
+        round_trip!(send_f64_be, read_f64_be, -0.25, (-0.25f64).to_be_bytes());
+        round_trip!(send_f64_le, read_f64_le, -0.25, (-0.25f64).to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn varints_round_trip() {
+        round_trip!(send_varint, read_varint, 0, [0]);
+        round_trip!(send_varint, read_varint, 127, [0x7f]);
+        round_trip!(send_varint, read_varint, 128, [0x80, 1]);
+        let mut max = [0xff; MAX_VARINT_LEN];
+        max[MAX_VARINT_LEN - 1] = 1;
+        round_trip!(send_varint, read_varint, u64::MAX, max);
+    }
+
+    #[tokio::test]
+    async fn varint_overflows_are_rejected() {
+        let mut buf = [0u8; MAX_VARINT_LEN];
+        assert_eq!(encode_varint(u64::MAX, &mut buf), MAX_VARINT_LEN);
+        assert_eq!(decode_varint(&buf).unwrap(), u64::MAX);
+
+        // a 65th bit in the last byte
+        let mut over = buf;
+        over[MAX_VARINT_LEN - 1] = 2;
+        let e = decode_varint(&over).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        // an eleventh byte
+        let mut long = [0x80; MAX_VARINT_LEN + 1];
+        long[MAX_VARINT_LEN] = 0;
+        let e = decode_varint(&long).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+        // streams stop reading at the tenth byte
+        let (mut a, mut b) = tokio::io::duplex(64);
+        a.write_all(&long).await.unwrap();
+        let e = read_varint(&mut b).await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(read_u8(&mut b).await.unwrap(), 0);
+        a.write_all(&over).await.unwrap();
+        let e = read_varint(&mut b).await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
 }
@@ -10,30 +10,26 @@ use crate::Channel;
 /// `pipe!(send i32, receive u32)` -> `TypeIter<Tx<i32>, TypeIter<Rx<u32>>>`
 #[macro_export]
 macro_rules! pipe {
-    (send $t: ty) => {
-        $crate::type_iter::TypeIter<$crate::type_iter::Tx<$t>>
+    () => {
+        ()
     };
-    (receive $t: ty) => {
-        $crate::type_iter::TypeIter<$crate::type_iter::Rx<$t>>
+    (send $t: ty $(, $($rest: tt)*)?) => {
+        $crate::type_iter::TypeIter<$crate::type_iter::Tx<$t>, $crate::pipe!($($($rest)*)?)>
     };
-    (send $t: ty) => {
-        $crate::type_iter::TypeIter<$crate::type_iter::Tx<$t>>
+    (receive $t: ty $(, $($rest: tt)*)?) => {
+        $crate::type_iter::TypeIter<$crate::type_iter::Rx<$t>, $crate::pipe!($($($rest)*)?)>
     };
-    (receive $t: ty) => {
-        $crate::type_iter::TypeIter<$crate::type_iter::Rx<$t>>
+    (repeat { $($body: tt)* } $(, $($rest: tt)*)?) => {
+        $crate::type_iter::TypeIter<
+            $crate::type_iter::Repeat<$crate::pipe!($($body)*)>,
+            $crate::pipe!($($($rest)*)?)
+        >
     };
-
-    (send $t: ty, $($lit: ident $s: ty),*) => {
-        $crate::type_iter::TypeIter<$crate::type_iter::Tx<$t>, $crate::pipe!($($lit $s),*)>
-    };
-    (receive $t: ty, $($lit: ident $s: ty),*) => {
-        $crate::type_iter::TypeIter<$crate::type_iter::Rx<$t>, $crate::pipe!($($lit $s),*)>
-    };
-    (send $t: ty, $($lit: ident $s: ty),*) => {
-        $crate::type_iter::TypeIter<$crate::type_iter::Tx<$t>, $crate::pipe!($($lit $s),*)>
-    };
-    (receive $t: ty, $($lit: ident $s: ty),*) => {
-        $crate::type_iter::TypeIter<$crate::type_iter::Rx<$t>, $crate::pipe!($($lit $s),*)>
+    (choose { a: { $($a: tt)* }, b: { $($b: tt)* } $(,)? } $(, $($rest: tt)*)?) => {
+        $crate::type_iter::TypeIter<
+            $crate::type_iter::Either<$crate::pipe!($($a)*), $crate::pipe!($($b)*)>,
+            $crate::pipe!($($($rest)*)?)
+        >
     };
 }
 
@@ -75,18 +71,72 @@ macro_rules! receive {
 ///     }
 /// }
 /// ```
+/// `repeat { .. }` declares a loop the main channel enters with `enter_loop`
+/// as many times as it wants before `exit_loop`, and `choose { a: { .. }, b: { .. } }`
+/// a branch the main channel picks with `choose_a` or `choose_b`.
+/// The peer channel follows both with `offer`.
+/// ```no_run
+/// # use canary::type_iter::{MainChannel, Offer, PeerChannel};
+/// # use canary::{pipeline, Channel, Result};
+/// pipeline! {
+///     pub pipeline Session {
+///         receive String, // username
+///         choose {
+///             a: {
+///                 repeat {
+///                     receive String, // command
+///                     send String,    // output
+///                 },
+///             },
+///             b: { send String }, // reason the login was rejected
+///         },
+///     }
+/// }
+///
+/// async fn server(chan: Channel) -> Result<()> {
+///     let (user, chan) = MainChannel::<()>::new::<Session>(chan).receive().await?;
+///     if user != "admin" {
+///         chan.choose_b().await?.send("unknown user".into()).await?;
+///         return Ok(());
+///     }
+///     let mut chan = chan.choose_a().await?;
+///     for _ in 0..3 {
+///         let (cmd, body) = chan.enter_loop().await?.receive().await?;
+///         chan = body.send(format!("ran {}", cmd)).await?;
+///     }
+///     chan.exit_loop().await?;
+///     Ok(())
+/// }
+///
+/// async fn client(chan: Channel) -> Result<()> {
+///     let chan = PeerChannel::<()>::new::<Session>(chan).send("admin".into()).await?;
+///     let mut chan = match chan.offer().await? {
+///         Offer::A(chan) => chan,
+///         Offer::B(chan) => {
+///             let (reason, _) = chan.receive().await?;
+///             return canary::err!((permission_denied, reason));
+///         }
+///     };
+///     while let Offer::A(body) = chan.offer().await? {
+///         let body = body.send("ls".into()).await?;
+///         let (output, next) = body.receive().await?;
+///         println!("{}", output);
+///         chan = next;
+///     }
+///     Ok(())
+/// }
+/// ```
 #[macro_export]
 macro_rules! pipeline {
     () => {};
     (
         $v: vis pipeline $i: ident {
-            $($lit: ident $s: ty),*
-            $(,)?
+            $($body: tt)*
         }
     ) => {
         $v struct $i;
         impl $crate::type_iter::Pipeline for $i {
-            type Pipe = $crate::pipe!($($lit $s),*);
+            type Pipe = $crate::pipe!($($body)*);
         }
     };
 }
@@ -134,6 +184,49 @@ impl<T> Receive for Rx<T> {
 pub struct Tx<T>(T);
 /// type iterator that represents a type to be received
 pub struct Rx<T>(T);
+/// type iterator that represents a loop over its body, entered or exited by the main channel
+pub struct Repeat<B>(PhantomData<B>);
+/// type iterator that represents a branch into `A` or `B`, picked by the main channel
+pub struct Either<A, B>(PhantomData<A>, PhantomData<B>);
+
+/// trait that appends `K` to a type iterator,
+/// so `K` is reached with the same type no matter how the iterator got there
+pub trait Append<K> {
+    /// type iterator that continues with `K` once this one is exhausted
+    type Output;
+}
+impl<K> Append<K> for () {
+    type Output = K;
+}
+impl<T, L: Append<K>, K> Append<K> for TypeIter<T, L> {
+    type Output = TypeIter<T, L::Output>;
+}
+
+/// trait that represents the two ways a pipeline can continue at `T`
+pub trait Choice<T> {
+    /// continuation of `choose_a` or of entering the loop
+    type A;
+    /// continuation of `choose_b` or of exiting the loop
+    type B;
+}
+impl<B: Append<T>, T: TypeIterT> Choice<T> for Repeat<B> {
+    // the loop is back at `T` once the body is exhausted
+    type A = B::Output;
+    type B = T::Next;
+}
+impl<A: Append<T::Next>, B: Append<T::Next>, T: TypeIterT> Choice<T> for Either<A, B> {
+    type A = A::Output;
+    type B = B::Output;
+}
+
+/// Continuation the main channel picked, received by `PeerChannel::offer`.
+/// `A` is also the loop being entered, and `B` the loop being exited.
+pub enum Offer<A, B> {
+    /// the main channel picked `choose_a` or `enter_loop`
+    A(A),
+    /// the main channel picked `choose_b` or `exit_loop`
+    B(B),
+}
 
 /// used for constructing pipelines
 pub trait Pipeline {
@@ -189,6 +282,42 @@ impl<T: TypeIterT> MainChannel<T> {
         let chan = MainChannel(PhantomData, self.1);
         Ok((res, chan))
     }
+    /// pick the first branch of a `choose`
+    pub async fn choose_a(mut self) -> crate::Result<MainChannel<<T::Type as Choice<T>>::A>>
+    where
+        T::Type: Choice<T>,
+        <T::Type as Choice<T>>::A: TypeIterT,
+    {
+        self.1.send(true).await?;
+        Ok(MainChannel(PhantomData, self.1))
+    }
+    /// pick the second branch of a `choose`
+    pub async fn choose_b(mut self) -> crate::Result<MainChannel<<T::Type as Choice<T>>::B>>
+    where
+        T::Type: Choice<T>,
+        <T::Type as Choice<T>>::B: TypeIterT,
+    {
+        self.1.send(false).await?;
+        Ok(MainChannel(PhantomData, self.1))
+    }
+    /// run the body of a `repeat` once, the channel is back at the loop after it
+    pub async fn enter_loop<B>(self) -> crate::Result<MainChannel<B::Output>>
+    where
+        T: TypeIterT<Type = Repeat<B>>,
+        B: Append<T>,
+        B::Output: TypeIterT,
+    {
+        self.choose_a().await
+    }
+    /// leave a `repeat` and continue with the rest of the pipeline
+    pub async fn exit_loop<B>(self) -> crate::Result<MainChannel<T::Next>>
+    where
+        T: TypeIterT<Type = Repeat<B>>,
+        B: Append<T>,
+        T::Next: TypeIterT,
+    {
+        self.choose_b().await
+    }
     /// coerce into a different kind of channel:
     pub fn coerce(self) -> Channel {
         self.1
@@ -256,6 +385,23 @@ impl<T: TypeIterT> PeerChannel<T> {
         let chan = PeerChannel(PhantomData, self.1);
         Ok((res, chan))
     }
+    /// receive the continuation the main channel picked at a `choose` or `repeat`
+    #[allow(clippy::type_complexity)]
+    pub async fn offer(
+        mut self,
+    ) -> crate::Result<
+        Offer<PeerChannel<<T::Type as Choice<T>>::A>, PeerChannel<<T::Type as Choice<T>>::B>>,
+    >
+    where
+        T::Type: Choice<T>,
+        <T::Type as Choice<T>>::A: TypeIterT,
+        <T::Type as Choice<T>>::B: TypeIterT,
+    {
+        Ok(match self.1.receive().await? {
+            true => Offer::A(PeerChannel(PhantomData, self.1)),
+            false => Offer::B(PeerChannel(PhantomData, self.1)),
+        })
+    }
     /// coerce into a different kind of channel:
     pub fn channel(self) -> Channel {
         self.1
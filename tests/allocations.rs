@@ -0,0 +1,144 @@
+//! allocation counts of the paths that send and receive messages. They live in
+//! their own test binary since they replace the global allocator.
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use canary::async_snow::{self, Snow};
+use canary::channel::raw::unified::unformatted::UnformattedRawUnifiedChannel;
+use canary::serialization::formats::{Format, SendFormat};
+use canary::serialization::{tx, tx_into};
+use canary::Channel;
+use serde::Serialize;
+use tokio::io::DuplexStream;
+
+// counts allocations per thread, so tests running at the same time don't add up
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count() {
+    // the counter may be gone while the thread shuts down
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// allocations made so far on this thread
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+#[derive(Serialize)]
+struct Message {
+    text: &'static str,
+    bytes: [u8; 32],
+    id: u32,
+}
+
+const MESSAGE: Message = Message {
+    text: "hello!",
+    bytes: [7; 32],
+    id: 42,
+};
+
+#[test]
+fn serializing_into_a_buffer_reuses_it() {
+    let mut buf = Vec::new();
+    for mut format in Format::supported().iter().copied() {
+        format.serialize_into(&MESSAGE, &mut buf).unwrap();
+        buf.clear();
+        let before = allocations();
+        for _ in 0..100 {
+            format.serialize_into(&MESSAGE, &mut buf).unwrap();
+            buf.clear();
+        }
+        // the other formats serialize into their own buffer first
+        if matches!(format.as_str(), "bincode" | "json" | "messagepack" | "cbor") {
+            assert_eq!(allocations() - before, 0, "{}", format.as_str());
+        }
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn sends_allocate_at_most_once() {
+    let mut sink = tokio::io::sink();
+    let message = [7u8; 32];
+    // past the budget of the runtime, whose first yield allocates
+    for _ in 0..200 {
+        tx(&mut sink, message, &mut Format::Bincode).await.unwrap();
+    }
+    let before = allocations();
+    for _ in 0..100 {
+        tx(&mut sink, message, &mut Format::Bincode).await.unwrap();
+    }
+    assert_eq!(allocations() - before, 100);
+
+    // the frame buffer is the one allocation, which callers can keep
+    let mut buf = Vec::new();
+    tx_into(&mut sink, message, &mut Format::Bincode, &mut buf)
+        .await
+        .unwrap();
+    let before = allocations();
+    for _ in 0..100 {
+        tx_into(&mut sink, message, &mut Format::Bincode, &mut buf)
+            .await
+            .unwrap();
+    }
+    assert_eq!(allocations() - before, 0);
+}
+
+fn stream(chan: Channel) -> DuplexStream {
+    match chan.into_inner().unwrap() {
+        UnformattedRawUnifiedChannel::Mem(stream) => stream,
+        _ => unreachable!(),
+    }
+}
+
+async fn snow_pair() -> (Snow<DuplexStream>, Snow<DuplexStream>) {
+    let (mut a, mut b) = Channel::pair();
+    let (initiator, responder) = tokio::join!(
+        async_snow::new_initiator(&mut a),
+        async_snow::new_responder(&mut b)
+    );
+    let a = Snow::new(stream(a), initiator.unwrap());
+    let b = Snow::new(stream(b), responder.unwrap());
+    (a, b)
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn snow_messages_reuse_their_buffers() {
+    let (mut a, mut b) = snow_pair().await;
+    let message = [7u8; 32];
+    // the first messages grow the buffers, the ones of the stream included
+    for _ in 0..64 {
+        a.tx(message, &mut Format::Bincode).await.unwrap();
+        let received: [u8; 32] = b.rx(&mut Format::Bincode).await.unwrap();
+        assert_eq!(received, message);
+    }
+    let before = allocations();
+    for _ in 0..100 {
+        a.tx(message, &mut Format::Bincode).await.unwrap();
+        let received: [u8; 32] = b.rx(&mut Format::Bincode).await.unwrap();
+        assert_eq!(received, message);
+    }
+    assert_eq!(allocations() - before, 0);
+}